@@ -10,14 +10,28 @@ pub struct CameraUniforms {
     pub v: [f32; 3],
     _pad3: f32,
     pub w: [f32; 3],
-    _pad4: f32,
+    /// Lens radius in world units; 0 renders a pinhole camera.
+    pub aperture: f32,
+    pub focus_distance: f32,
+    /// Number of aperture blades; fewer than 3 gives a circular aperture.
+    pub aperture_blades: u32,
+    /// Rotation of the aperture polygon in radians.
+    pub aperture_rotation: f32,
+    _pad5: f32,
 }
 
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub lookfrom: Vec3,
     pub lookat: Vec3,
     pub vup: Vec3,
     pub vfov: f32, 
+    pub aperture: f32,
+    /// Distance to the plane in focus; `None` focuses on `lookat`.
+    pub focus_distance: Option<f32>,
+    pub aperture_blades: u32,
+    /// Degrees.
+    pub aperture_rotation: f32,
 }
 
 impl Camera {
@@ -27,6 +41,10 @@ impl Camera {
             lookat,
             vup,
             vfov,
+            aperture: 0.0,
+            focus_distance: None,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
         }
     }
 
@@ -52,14 +70,16 @@ impl Camera {
             v: [v_scaled.x(), v_scaled.y(), v_scaled.z()],
             _pad3: 0.0,
             w: [w_forward.x(), w_forward.y(), w_forward.z()],
-            _pad4: 0.0,
+            aperture: self.aperture,
+            focus_distance: self.focus_distance.unwrap_or_else(|| (self.lookat - self.lookfrom).length()),
+            aperture_blades: self.aperture_blades,
+            aperture_rotation: self.aperture_rotation.to_radians(),
+            _pad5: 0.0,
         }
     }
 
     pub fn zoom(&mut self, delta: f32) {
-        self.vfov -= delta * 10.0;
-        if self.vfov < 1.0 { self.vfov = 1.0; }
-        if self.vfov > 179.0 { self.vfov = 179.0; }
+        self.vfov = (self.vfov - delta * 10.0).clamp(1.0, 179.0);
     }
 
     pub fn move_along_w(&mut self, delta: f32) {
@@ -77,6 +97,27 @@ impl Camera {
         self.lookat += move_vec;
     }
 
+    /// Eases the focus distance towards `target`, closing about 63% of the gap
+    /// every `time_constant` seconds. Returns whether the focus visibly moved.
+    pub fn pull_focus(&mut self, target: f32, dt: f32, time_constant: f32) -> bool {
+        let current = self
+            .focus_distance
+            .unwrap_or_else(|| (self.lookat - self.lookfrom).length());
+        let blend = 1.0 - (-dt / time_constant.max(1e-3)).exp();
+        let next = current + (target - current) * blend;
+        self.focus_distance = Some(next);
+        (next - current).abs() > 1e-4 * current
+    }
+
+    /// Swings the camera around its target about the vertical axis.
+    pub fn orbit(&mut self, angle: f32) {
+        let offset = self.lookfrom - self.lookat;
+        let (sin, cos) = angle.sin_cos();
+        let x = offset.x() * cos - offset.z() * sin;
+        let z = offset.x() * sin + offset.z() * cos;
+        self.lookfrom = self.lookat + Vec3::new(x, offset.y(), z);
+    }
+
     pub fn rotate(&mut self, dx: f32, dy: f32) {
         let mut forward = self.lookat - self.lookfrom;
         
@@ -86,9 +127,6 @@ impl Camera {
         let new_z = forward.x() * sin_yaw + forward.z() * cos_yaw;
         forward = Vec3::new(new_x, forward.y(), new_z);
 
-        let w = -forward.normalized();
-        let u = self.vup.cross(&w).normalized();
-        
         let new_y = forward.y() + dy;
         forward = Vec3::new(forward.x(), new_y, forward.z());
 
@@ -0,0 +1,32 @@
+//! Convergence estimates from two independent halves of the sample set.
+
+/// Mean linear radiance of every pixel over all samples and over the odd and
+/// even samples alone. The halves are independent estimates of the same image,
+/// so their difference measures the remaining noise.
+pub struct SplitRadiance {
+    pub width: u32,
+    pub height: u32,
+    pub full: Vec<[f32; 3]>,
+    pub odd: Vec<[f32; 3]>,
+    pub even: Vec<[f32; 3]>,
+}
+
+impl SplitRadiance {
+    /// Per-pixel error metric of Dammertz et al., `|A - B| / sqrt(A + B)` on the
+    /// luminance of the two halves. It scales like the relative standard error
+    /// but does not blow up for dark pixels.
+    pub fn pixel_error(&self, index: usize) -> f32 {
+        let (odd, even) = (luminance(self.odd[index]), luminance(self.even[index]));
+        (odd - even).abs() / (odd + even).max(0.0).sqrt().max(1e-4)
+    }
+
+    /// Mean relative error over the image; halves for every 4x the samples.
+    pub fn error(&self) -> f32 {
+        let total: f64 = (0..self.full.len()).map(|i| self.pixel_error(i) as f64).sum();
+        (total / self.full.len().max(1) as f64) as f32
+    }
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
@@ -0,0 +1,135 @@
+//! Crash reports with enough context to act on GPU-specific bug reports.
+
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    fs,
+    io::{self, IsTerminal},
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Named sections of context included in every report, in insertion order.
+static CONTEXT: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+
+/// How many of the most recent log lines a report includes.
+pub const LOG_LINES: usize = 100;
+
+/// The last [`LOG_LINES`] lines passed to [`log!`], oldest first.
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Like `eprintln!`, but also keeps the line for crash reports.
+///
+/// ```
+/// raytracer::crash::log!("warning: {} textures are left white", 2);
+/// ```
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __crash_log {
+    ($($arg:tt)*) => {
+        $crate::crash::log_line(format_args!($($arg)*))
+    };
+}
+pub use __crash_log as log;
+
+/// Prints `args` as a line to stderr and keeps it for crash reports; what
+/// [`log!`] expands to.
+#[doc(hidden)]
+pub fn log_line(args: fmt::Arguments) {
+    let line = args.to_string();
+    eprintln!("{line}");
+    let mut log = LOG.lock().unwrap_or_else(|it| it.into_inner());
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    // Lines that start with a newline end a progress line on the terminal.
+    log.push_back(line.trim_start_matches('\n').to_owned());
+}
+
+/// Installs a panic hook that, after the default message, writes a crash
+/// report to `<dir>/crash-<unix time>.txt` and prints its path. When run
+/// from a terminal it then asks whether to open the report.
+pub fn install(dir: PathBuf) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |it| it.as_secs());
+        let path = dir.join(format!("crash-{secs}.txt"));
+        match fs::write(&path, report(info)) {
+            Ok(()) => {
+                eprintln!("crash report written to {}", path.display());
+                offer_to_open(&path);
+            }
+            Err(err) => eprintln!("failed to write crash report {}: {err}", path.display()),
+        }
+    }));
+}
+
+/// Adds or replaces a section of the crash report.
+pub fn record(section: &'static str, details: String) {
+    let mut context = CONTEXT.lock().unwrap_or_else(|it| it.into_inner());
+    match context.iter_mut().find(|(name, _)| *name == section) {
+        Some((_, existing)) => *existing = details,
+        None => context.push((section, details)),
+    }
+}
+
+/// The environment and every recorded section, as included in crash reports.
+pub fn context() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let args: Vec<String> = std::env::args().collect();
+    let _ = writeln!(out, "command line: {}", args.join(" "));
+
+    // A panic may have happened while the lock was held; report what we can.
+    let context = CONTEXT.lock().unwrap_or_else(|it| it.into_inner());
+    for (section, details) in context.iter() {
+        let _ = writeln!(out, "\n[{section}]\n{details}");
+    }
+    out
+}
+
+fn report(info: &PanicHookInfo) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "raytracer {} crashed", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(out, "{info}");
+    out.push_str(&context());
+    let log = LOG.lock().unwrap_or_else(|it| it.into_inner());
+    let _ = writeln!(out, "\n[log]");
+    for line in log.iter() {
+        let _ = writeln!(out, "{line}");
+    }
+    let _ = writeln!(out, "\n[backtrace]\n{}", Backtrace::force_capture());
+    out
+}
+
+/// Asks on the terminal whether to open the report at `path`, and opens it
+/// with the system's default application if the answer is yes.
+fn offer_to_open(path: &Path) {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return;
+    }
+    eprint!("open it now? [y/N] ");
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+        return;
+    }
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    if let Err(err) = command.arg(path).spawn() {
+        eprintln!("failed to open {}: {err}", path.display());
+    }
+}
@@ -0,0 +1,124 @@
+//! State snapshots that make rendering bug reports reproducible.
+
+use {
+    crate::{camera::Camera, burn_in, crash, export, render::PathTracer, scene::Scene},
+    anyhow::{Context, Result},
+    std::{
+        fmt::Write as _,
+        fs,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Samples accumulated before the snapshot is taken.
+pub const DUMP_SAMPLES: u32 = 16;
+
+/// Downscale factor of the preview render.
+const PREVIEW_SCALE: u32 = 4;
+
+/// Writes `state.txt` (camera, uniforms, scene hash, and the settings and
+/// adapter recorded for crash reports) and a low-resolution `preview.png`
+/// into an uncompressed zip.
+pub fn write(path: &Path, renderer: &PathTracer, camera: &Camera, scene: &Scene) -> Result<()> {
+    let mut state = String::new();
+    let _ = writeln!(state, "raytracer {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(state, "samples: {}", renderer.sample_count());
+    let _ = writeln!(
+        state,
+        "scene: {:08x} ({} triangles, {} materials)",
+        scene.content_hash(),
+        scene.triangles.len(),
+        scene.materials.len()
+    );
+    let _ = writeln!(state, "\n[camera]\n{camera:#?}");
+    let _ = writeln!(state, "\n[uniforms]\n{}", renderer.uniforms_report());
+    state.push_str(&crash::context());
+
+    let mut preview = Vec::new();
+    export::encode_png(&mut preview, &downsampled_preview(renderer), &[])?;
+
+    let zip = zip_stored(&[("state.txt", state.as_bytes()), ("preview.png", &preview)]);
+    fs::write(path, zip).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn downsampled_preview(renderer: &PathTracer) -> export::Image {
+    let (width, height) = renderer.size();
+    let radiance = renderer.read_radiance();
+    let (small_width, small_height) = ((width / PREVIEW_SCALE).max(1), (height / PREVIEW_SCALE).max(1));
+    let mut small = Vec::with_capacity((small_width * small_height) as usize);
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for sy in y * PREVIEW_SCALE..((y + 1) * PREVIEW_SCALE).min(height) {
+                for sx in x * PREVIEW_SCALE..((x + 1) * PREVIEW_SCALE).min(width) {
+                    let rgb = radiance[(sy * width + sx) as usize];
+                    (0..3).for_each(|i| sum[i] += rgb[i]);
+                    count += 1.0;
+                }
+            }
+            small.push(sum.map(|c| c / count));
+        }
+    }
+    export::develop(renderer, &small, small_width, small_height)
+}
+
+/// Builds a zip archive of uncompressed entries.
+fn zip_stored(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let (time, date) = dos_timestamp();
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for &(name, data) in entries {
+        let offset = out.len() as u32;
+        let crc = export::crc32(0, data);
+        // Fields shared by the local and central headers, from "version needed".
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // flags
+        common.extend_from_slice(&0u16.to_le_bytes()); // stored
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&common);
+        central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    out
+}
+
+/// Current UTC time in MS-DOS (time, date) format.
+fn dos_timestamp() -> (u16, u16) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |it| it.as_secs());
+    let (year, month, day) = burn_in::civil_from_days((secs / 86400) as i64);
+    let of_day = secs % 86400;
+    let time = (of_day / 3600) << 11 | (of_day / 60 % 60) << 5 | (of_day % 60 / 2);
+    let date = ((year - 1980).clamp(0, 127) as u32) << 9 | month << 5 | day;
+    (time as u16, date as u16)
+}
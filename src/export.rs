@@ -0,0 +1,393 @@
+use {
+    crate::{
+        burn_in, color,
+        render::PathTracer,
+        settings::{ImageFormat, RenderSettings},
+        timecode,
+    },
+    anyhow::{Context, Result},
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        ops::Div,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Integer channel type of a display-referred image.
+pub trait Channel: Copy + From<u8> + Div<Output = Self> {
+    const BITS: u8;
+    const MAX: Self;
+
+    /// Quantizes a value in `0..=1`.
+    fn from_unit(x: f32) -> Self;
+
+    fn extend_be(self, out: &mut Vec<u8>);
+}
+
+impl Channel for u8 {
+    const BITS: u8 = 8;
+    const MAX: Self = u8::MAX;
+
+    fn from_unit(x: f32) -> Self {
+        (x * 255.0 + 0.5) as u8
+    }
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+}
+
+impl Channel for u16 {
+    const BITS: u8 = 16;
+    const MAX: Self = u16::MAX;
+
+    fn from_unit(x: f32) -> Self {
+        (x * 65535.0 + 0.5) as u16
+    }
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+pub struct Image<T = u8> {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[T; 3]>,
+}
+
+impl<T: Copy> Image<T> {
+    pub fn put(&mut self, x: u32, y: u32, color: [T; 3]) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = color;
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [T; 3] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// Reads back the current accumulation and writes it as the `index`th
+/// exported frame, numbered from `start_frame` and named by
+/// [`frame_path`], in the configured format. Display-referred formats get
+/// the same display transform as the shader, and record the frame rate and
+/// timecode if `fps` is set.
+pub fn save_frame(renderer: &PathTracer, settings: &RenderSettings, index: u32) -> Result<PathBuf> {
+    let radiance = renderer.read_radiance();
+    let frame = settings.start_frame.saturating_add(index);
+    let path = frame_path(settings, frame);
+    write_frame(renderer, settings, &radiance, &path, frame, None)?;
+    Ok(path)
+}
+
+/// Like [`save_frame`], but writes the accumulation read back once at
+/// `bracket_stops` under, at and over the renderer's exposure, with the
+/// offset in stops appended to each file name, as in `_ev-2`.
+pub fn save_bracket(renderer: &PathTracer, settings: &RenderSettings, index: u32) -> Result<Vec<PathBuf>> {
+    let radiance = renderer.read_radiance();
+    let frame = settings.start_frame.saturating_add(index);
+    let path = frame_path(settings, frame);
+    [-settings.bracket_stops, 0.0, settings.bracket_stops]
+        .into_iter()
+        .map(|stops| {
+            let mut name = path.file_stem().unwrap_or_default().to_os_string();
+            name.push(format!("_ev{stops:+}"));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            let path = path.with_file_name(name);
+            write_frame(renderer, settings, &radiance, &path, frame, Some(stops))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Writes `radiance` as frame number `frame`, `stops` over the renderer's
+/// exposure if it is part of a bracket.
+fn write_frame(
+    renderer: &PathTracer,
+    settings: &RenderSettings,
+    radiance: &[[f32; 3]],
+    path: &Path,
+    frame: u32,
+    stops: Option<f32>,
+) -> Result<()> {
+    let (width, height) = renderer.size();
+    let text: Vec<(&str, String)> = settings
+        .fps
+        .map(|fps| vec![("Timecode", timecode::timecode(frame, fps)), ("Frame Rate", format!("{fps:.3}"))])
+        .unwrap_or_default();
+    let text: Vec<(&str, &str)> = text.iter().map(|(key, value)| (*key, value.as_str())).collect();
+    match settings.image_format {
+        ImageFormat::Png => {
+            let image = develop_stamped::<u8>(renderer, settings, radiance, frame, stops);
+            write_file(path, |out| encode_png(out, &image, &text))
+        }
+        ImageFormat::Png16 => {
+            let image = develop_stamped::<u16>(renderer, settings, radiance, frame, stops);
+            write_file(path, |out| encode_png(out, &image, &text))
+        }
+        ImageFormat::Tiff16 => {
+            let image = develop_stamped::<u16>(renderer, settings, radiance, frame, stops);
+            write_file(path, |out| encode_tiff(out, &image, &text))
+        }
+        ImageFormat::Pfm => {
+            let linear = expose(renderer, radiance, stops.unwrap_or(0.0));
+            write_file(path, |out| encode_pfm(out, &linear, width, height))
+        }
+    }
+}
+
+/// `output_pattern` within `output_dir` for frame number `frame`.
+pub fn frame_path(settings: &RenderSettings, frame: u32) -> PathBuf {
+    let pattern = settings.output_pattern.replace("{scene}", &settings.scene_name);
+    let start = pattern.find('#').unwrap_or(pattern.len());
+    let digits = pattern[start..].len() - pattern[start..].trim_start_matches('#').len();
+    let mut name = format!("{}{frame:0digits$}{}", &pattern[..start], &pattern[start + digits..]);
+    if Path::new(&name).extension().is_none() {
+        name = format!("{name}.{}", settings.image_format.extension());
+    }
+    settings.output_dir.join(name)
+}
+
+fn develop_stamped<T: Channel>(
+    renderer: &PathTracer,
+    settings: &RenderSettings,
+    radiance: &[[f32; 3]],
+    frame: u32,
+    stops: Option<f32>,
+) -> Image<T> {
+    let (width, height) = renderer.size();
+    let mut image = develop_at(renderer, radiance, width, height, stops.unwrap_or(0.0));
+    if settings.burn_in {
+        let spp = renderer.sample_count();
+        let timecode = settings
+            .fps
+            .map(|fps| format!("  TC {}", timecode::timecode(frame, fps)))
+            .unwrap_or_default();
+        let bracket = stops.map(|stops| format!("  EV {stops:+}")).unwrap_or_default();
+        burn_in::stamp(
+            &mut image,
+            &format!(
+                "{}  FRAME {:04}{}{}  {} SPP  {}",
+                settings.scene_name,
+                frame,
+                timecode,
+                bracket,
+                spp,
+                burn_in::utc_date()
+            ),
+        );
+    }
+    image
+}
+
+/// Applies the renderer's exposure, `stops` brighter, and white balance to
+/// linear radiance.
+fn expose(renderer: &PathTracer, radiance: &[[f32; 3]], stops: f32) -> Vec<[f32; 3]> {
+    let exposure = renderer.exposure() * stops.exp2();
+    let white_balance = renderer.white_balance();
+    radiance
+        .iter()
+        .map(|&rgb| color::apply(&white_balance, rgb.map(|c| c * exposure)))
+        .collect()
+}
+
+/// Applies the renderer's exposure, white balance and display transform to
+/// linear radiance.
+pub fn develop<T: Channel>(renderer: &PathTracer, radiance: &[[f32; 3]], width: u32, height: u32) -> Image<T> {
+    develop_at(renderer, radiance, width, height, 0.0)
+}
+
+fn develop_at<T: Channel>(
+    renderer: &PathTracer,
+    radiance: &[[f32; 3]],
+    width: u32,
+    height: u32,
+    stops: f32,
+) -> Image<T> {
+    Image {
+        width,
+        height,
+        pixels: expose(renderer, radiance, stops).into_iter().map(display_transform).collect(),
+    }
+}
+
+/// ACES tone mapping followed by gamma correction, matching `fs_main`.
+fn display_transform<T: Channel>(rgb: [f32; 3]) -> [T; 3] {
+    rgb.map(|x| {
+        let x = if x.is_finite() { x.max(0.0) } else { 0.0 };
+        let mapped = ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0);
+        T::from_unit(mapped.powf(1.0 / 2.2))
+    })
+}
+
+fn write_file(path: &Path, encode: impl FnOnce(&mut BufWriter<File>) -> Result<()>) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    encode(&mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_png<T: Channel>(path: &Path, image: &Image<T>) -> Result<()> {
+    write_file(path, |out| encode_png(out, image, &[]))
+}
+
+/// Writes an uncompressed PNG, with `text` as `tEXt` keyword and value
+/// pairs.
+pub fn encode_png<T: Channel>(out: &mut impl Write, image: &Image<T>, text: &[(&str, &str)]) -> Result<()> {
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 or 16 bits per channel, truecolor, default compression/filter, no
+    // interlace.
+    header.extend_from_slice(&[T::BITS, 2, 0, 0, 0]);
+
+    let row_bytes = image.width as usize * 3 * usize::from(T::BITS / 8) + 1;
+    let mut scanlines = Vec::with_capacity(row_bytes * image.height as usize);
+    for row in image.pixels.chunks_exact(image.width as usize) {
+        scanlines.push(0);
+        row.iter().flatten().for_each(|&c| c.extend_be(&mut scanlines));
+    }
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(out, b"IHDR", &header)?;
+    for (keyword, value) in text {
+        write_chunk(out, b"tEXt", &[keyword.as_bytes(), &[0], value.as_bytes()].concat())?;
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(out, b"IEND", &[])?;
+    Ok(())
+}
+
+/// Writes a big-endian baseline TIFF with one uncompressed strip. `text`
+/// goes into the ImageDescription as `keyword: value` lines.
+pub fn encode_tiff<T: Channel>(out: &mut impl Write, image: &Image<T>, text: &[(&str, &str)]) -> Result<()> {
+    let mut data = Vec::with_capacity(image.pixels.len() * 3 * usize::from(T::BITS / 8));
+    image.pixels.iter().flatten().for_each(|&c| c.extend_be(&mut data));
+    let description: Vec<u8> = match text {
+        [] => Vec::new(),
+        _ => {
+            let lines: Vec<String> = text.iter().map(|(keyword, value)| format!("{keyword}: {value}")).collect();
+            format!("{}\0", lines.join("\n")).into_bytes()
+        }
+    };
+
+    // Header, then the pixel data, the BitsPerSample values, the
+    // description and the IFD, each starting on a word boundary.
+    let bits_offset = 8 + data.len() as u32 + (data.len() as u32 % 2);
+    let description_offset = bits_offset + 6;
+    let ifd_offset = description_offset + description.len() as u32 + (description.len() as u32 % 2);
+    let short = |tag: u16, value: u16| (tag, 3u16, 1u32, u32::from(value) << 16);
+    let long = |tag: u16, value: u32| (tag, 4u16, 1u32, value);
+    let mut entries = vec![
+        long(256, image.width),
+        long(257, image.height),
+        (258, 3, 3, bits_offset),
+        short(259, 1),
+        short(262, 2),
+    ];
+    if !text.is_empty() {
+        entries.push((270, 2, description.len() as u32, description_offset));
+    }
+    entries.extend([
+        long(273, 8),
+        short(277, 3),
+        long(278, image.height),
+        long(279, data.len() as u32),
+        short(284, 1),
+    ]);
+
+    out.write_all(b"MM\0*")?;
+    out.write_all(&ifd_offset.to_be_bytes())?;
+    out.write_all(&data)?;
+    if data.len() % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    for _ in 0..3 {
+        out.write_all(&u16::from(T::BITS).to_be_bytes())?;
+    }
+    out.write_all(&description)?;
+    if description.len() % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    out.write_all(&(entries.len() as u16).to_be_bytes())?;
+    for (tag, kind, count, value) in entries {
+        out.write_all(&tag.to_be_bytes())?;
+        out.write_all(&kind.to_be_bytes())?;
+        out.write_all(&count.to_be_bytes())?;
+        out.write_all(&value.to_be_bytes())?;
+    }
+    out.write_all(&0u32.to_be_bytes())?;
+    Ok(())
+}
+
+/// Writes linear RGB as a little-endian PFM, whose rows run bottom to top.
+pub fn encode_pfm(out: &mut impl Write, rgb: &[[f32; 3]], width: u32, height: u32) -> Result<()> {
+    write!(out, "PF\n{width} {height}\n-1.0\n")?;
+    let mut row_bytes = Vec::with_capacity(width as usize * 12);
+    for row in rgb.chunks_exact(width as usize).rev() {
+        row_bytes.clear();
+        row.iter().flatten().for_each(|c| row_bytes.extend_from_slice(&c.to_le_bytes()));
+        out.write_all(&row_bytes)?;
+    }
+    Ok(())
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(0, kind), data);
+    out.write_all(&crc.to_be_bytes())?;
+    Ok(())
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 16);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
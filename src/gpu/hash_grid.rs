@@ -0,0 +1,270 @@
+use {
+    super::{
+        buffer_entry, create_pipeline, create_storage, dispatch,
+        sort::{PrefixScan, RadixSort},
+    },
+    bytemuck::{Pod, Zeroable},
+    wgpu::{util::DeviceExt, Buffer, CommandEncoder, ComputePipeline, Device},
+};
+
+const WORKGROUP_SIZE: u32 = 256;
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Params {
+    count: u32,
+    table_mask: u32,
+    query_count: u32,
+    max_neighbors: u32,
+    cell_size: f32,
+    radius: f32,
+    _pad: [u32; 2],
+}
+
+/// Uniform spatial hash over a point set, built and queried on the GPU.
+///
+/// Points are `vec4<f32>` (xyz plus an unused w, the layout of [`Vec3`]) and
+/// are bucketed by `hash(floor(p / cell_size))` into `2^table_bits` buckets.
+///
+/// Checked against a brute-force search wherever an adapter is found;
+/// without one the example returns early:
+///
+/// ```
+/// use raytracer::gpu::hash_grid::HashGrid;
+/// use wgpu::util::DeviceExt;
+///
+/// let instance = wgpu::Instance::default();
+/// let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default())) else {
+///     return;
+/// };
+/// let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None)).unwrap();
+///
+/// // Points scattered through a 10-unit cube, about one within reach of each
+/// // query, over more cells than the table has buckets so that some collide.
+/// let mut state = 1u32;
+/// let mut random = move || {
+///     state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+///     (state >> 8) as f32 / (1 << 24) as f32 * 10.0
+/// };
+/// let points: Vec<[f32; 4]> = (0..2000).map(|_| [random(), random(), random(), 0.0]).collect();
+/// let queries: Vec<[f32; 4]> = (0..200).map(|_| [random(), random(), random(), 0.0]).collect();
+/// let (radius, max_neighbors) = (0.5, 64);
+/// let upload = |contents: &[[f32; 4]]| {
+///     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+///         label: None,
+///         contents: bytemuck::cast_slice(contents),
+///         usage: wgpu::BufferUsages::STORAGE,
+///     })
+/// };
+/// let (point_buffer, query_buffer) = (upload(&points), upload(&queries));
+/// let output = |size: u64| {
+///     device.create_buffer(&wgpu::BufferDescriptor {
+///         label: None,
+///         size,
+///         usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+///         mapped_at_creation: false,
+///     })
+/// };
+/// let neighbors = output(4 * max_neighbors as u64 * queries.len() as u64);
+/// let neighbor_count = output(4 * queries.len() as u64);
+/// let readback = device.create_buffer(&wgpu::BufferDescriptor {
+///     label: None,
+///     size: neighbors.size() + neighbor_count.size(),
+///     usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+///     mapped_at_creation: false,
+/// });
+///
+/// let grid = HashGrid::new(&device, radius, 10);
+/// let mut encoder = device.create_command_encoder(&Default::default());
+/// let buffers = grid.build(&device, &mut encoder, &point_buffer, points.len() as u32);
+/// grid.query(
+///     &device,
+///     &mut encoder,
+///     &buffers,
+///     &point_buffer,
+///     &query_buffer,
+///     queries.len() as u32,
+///     radius,
+///     max_neighbors,
+///     &neighbors,
+///     &neighbor_count,
+/// );
+/// encoder.copy_buffer_to_buffer(&neighbors, 0, &readback, 0, neighbors.size());
+/// encoder.copy_buffer_to_buffer(&neighbor_count, 0, &readback, neighbors.size(), neighbor_count.size());
+/// queue.submit([encoder.finish()]);
+/// readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+/// device.poll(wgpu::Maintain::Wait);
+/// let found: Vec<u32> = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+/// let (found, counts) = found.split_at(max_neighbors as usize * queries.len());
+///
+/// for (q, query) in queries.iter().enumerate() {
+///     let within = |p: &[f32; 4]| (0..3).map(|i| (p[i] - query[i]).powi(2)).sum::<f32>() <= radius * radius;
+///     let expected: Vec<u32> = (0..points.len() as u32).filter(|&i| within(&points[i as usize])).collect();
+///     let start = q * max_neighbors as usize;
+///     let mut gathered = found[start..start + counts[q] as usize].to_vec();
+///     gathered.sort();
+///     assert_eq!(gathered, expected, "query {q}");
+/// }
+/// ```
+///
+/// [`Vec3`]: crate::math::Vec3
+pub struct HashGrid {
+    cell_size: f32,
+    table_bits: u32,
+    hash_points: ComputePipeline,
+    query_neighbors: ComputePipeline,
+    scan: PrefixScan,
+    sort: RadixSort,
+}
+
+/// Output of [`HashGrid::build`]: the point indices of bucket `h` are
+/// `indices[cell_start[h]..cell_start[h] + cell_count[h]]`.
+pub struct HashGridBuffers {
+    pub point_count: u32,
+    pub indices: Buffer,
+    pub cell_start: Buffer,
+    pub cell_count: Buffer,
+}
+
+impl HashGrid {
+    pub fn new(device: &Device, cell_size: f32, table_bits: u32) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("hash_grid.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(concat!(include_str!("spatial_hash.wgsl"), include_str!("hash_grid.wgsl")).into()),
+        });
+        Self {
+            cell_size,
+            table_bits: table_bits.clamp(1, 22),
+            hash_points: create_pipeline(device, &module, "hash_points"),
+            query_neighbors: create_pipeline(device, &module, "query_neighbors"),
+            scan: PrefixScan::new(device),
+            sort: RadixSort::new(device),
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Records the construction of a grid over the first `count` entries of
+    /// `points`, a `STORAGE` buffer of `vec4<f32>`.
+    pub fn build(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        points: &Buffer,
+        count: u32,
+    ) -> HashGridBuffers {
+        let table_size = 1 << self.table_bits;
+        let grid = HashGridBuffers {
+            point_count: count,
+            indices: create_storage(device, "hash grid indices", count),
+            cell_start: create_storage(device, "hash grid cell start", table_size),
+            cell_count: create_storage(device, "hash grid cell count", table_size),
+        };
+        let keys = create_storage(device, "hash grid keys", count);
+        encoder.clear_buffer(&grid.cell_count, 0, None);
+
+        let params = self.create_params(device, count, 0, 0, 0.0);
+        dispatch(
+            device,
+            encoder,
+            "hash_points",
+            &self.hash_points,
+            &[
+                buffer_entry(0, &params),
+                buffer_entry(1, points),
+                buffer_entry(2, &keys),
+                buffer_entry(3, &grid.indices),
+                buffer_entry(4, &grid.cell_count),
+            ],
+            count.div_ceil(WORKGROUP_SIZE),
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &grid.cell_count,
+            0,
+            &grid.cell_start,
+            0,
+            table_size as u64 * 4,
+        );
+        self.scan
+            .encode(device, encoder, &grid.cell_start, table_size);
+        self.sort.encode(
+            device,
+            encoder,
+            &keys,
+            &grid.indices,
+            count,
+            self.table_bits,
+        );
+        grid
+    }
+
+    /// Records a gather of up to `max_neighbors` points within `radius` of each
+    /// of the first `query_count` entries of `queries`. Results for query `q`
+    /// land in `neighbors[q * max_neighbors..]` with their number in
+    /// `neighbor_count[q]`. `radius` is clamped to the cell size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn query(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        grid: &HashGridBuffers,
+        points: &Buffer,
+        queries: &Buffer,
+        query_count: u32,
+        radius: f32,
+        max_neighbors: u32,
+        neighbors: &Buffer,
+        neighbor_count: &Buffer,
+    ) {
+        let params = self.create_params(
+            device,
+            grid.point_count,
+            query_count,
+            max_neighbors,
+            radius.min(self.cell_size),
+        );
+        dispatch(
+            device,
+            encoder,
+            "query_neighbors",
+            &self.query_neighbors,
+            &[
+                buffer_entry(0, &params),
+                buffer_entry(1, points),
+                buffer_entry(3, &grid.indices),
+                buffer_entry(4, &grid.cell_count),
+                buffer_entry(5, &grid.cell_start),
+                buffer_entry(6, queries),
+                buffer_entry(7, neighbors),
+                buffer_entry(8, neighbor_count),
+            ],
+            query_count.div_ceil(WORKGROUP_SIZE),
+        );
+    }
+
+    fn create_params(
+        &self,
+        device: &Device,
+        count: u32,
+        query_count: u32,
+        max_neighbors: u32,
+        radius: f32,
+    ) -> Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("hash grid params"),
+            contents: bytemuck::bytes_of(&Params {
+                count,
+                table_mask: (1 << self.table_bits) - 1,
+                query_count,
+                max_neighbors,
+                cell_size: self.cell_size,
+                radius,
+                _pad: [0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+}
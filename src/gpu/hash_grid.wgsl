@@ -0,0 +1,86 @@
+struct Params {
+    count: u32,
+    table_mask: u32,
+    query_count: u32,
+    max_neighbors: u32,
+    cell_size: f32,
+    radius: f32,
+    _pad: vec2<u32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> points: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> keys: array<u32>;
+@group(0) @binding(3) var<storage, read_write> indices: array<u32>;
+@group(0) @binding(4) var<storage, read_write> cell_count: array<atomic<u32>>;
+@group(0) @binding(5) var<storage, read> cell_start: array<u32>;
+@group(0) @binding(6) var<storage, read> queries: array<vec4<f32>>;
+@group(0) @binding(7) var<storage, read_write> neighbors: array<u32>;
+@group(0) @binding(8) var<storage, read_write> neighbor_count: array<u32>;
+
+fn cell_of(p: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor(p / params.cell_size));
+}
+
+fn hash_cell(cell: vec3<i32>) -> u32 {
+    return spatial_hash(cell) & params.table_mask;
+}
+
+// Tags every point with its cell hash and counts the points per hash bucket.
+@compute @workgroup_size(256)
+fn hash_points(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let i = gid.x;
+    if (i >= params.count) {
+        return;
+    }
+    let hash = hash_cell(cell_of(points[i].xyz));
+    keys[i] = hash;
+    indices[i] = i;
+    atomicAdd(&cell_count[hash], 1u);
+}
+
+// Collects up to `max_neighbors` points within `radius` of every query. The
+// radius must not exceed the cell size so the 27 surrounding cells suffice.
+@compute @workgroup_size(256)
+fn query_neighbors(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let q = gid.x;
+    if (q >= params.query_count) {
+        return;
+    }
+    let center = queries[q].xyz;
+    let base = cell_of(center);
+    let radius_sq = params.radius * params.radius;
+
+    // Neighbouring cells can collide in the table; visit each bucket once.
+    var visited: array<u32, 27>;
+    var visited_len = 0u;
+    var found = 0u;
+    for (var dz = -1; dz <= 1; dz++) {
+        for (var dy = -1; dy <= 1; dy++) {
+            for (var dx = -1; dx <= 1; dx++) {
+                let hash = hash_cell(base + vec3<i32>(dx, dy, dz));
+                var seen = false;
+                for (var v = 0u; v < visited_len; v++) {
+                    seen = seen || visited[v] == hash;
+                }
+                if (seen) {
+                    continue;
+                }
+                visited[visited_len] = hash;
+                visited_len++;
+
+                let start = cell_start[hash];
+                let end = start + atomicLoad(&cell_count[hash]);
+                for (var s = start; s < end && found < params.max_neighbors; s++) {
+                    let index = indices[s];
+                    let d = points[index].xyz - center;
+                    if (dot(d, d) <= radius_sq) {
+                        neighbors[q * params.max_neighbors + found] = index;
+                        found++;
+                    }
+                }
+            }
+        }
+    }
+    neighbor_count[q] = found;
+}
@@ -0,0 +1,137 @@
+use {
+    super::{create_pipeline, dispatch_grid},
+    crate::color,
+    std::ops::Range,
+    wgpu::{BindGroupEntry, BindingResource, CommandEncoder, ComputePipeline, Device, Texture, TextureFormat},
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Levels in a full mip chain down to 1x1 for a `width` by `height` image.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// The mip chain of a `size` by `size` image on the CPU, `level0` first and
+/// then each level down to 1x1 a 2x2 box-filtered reduction of the one
+/// above, as [`MipGenerator`] makes them. `size` must be a power of two.
+///
+/// ```
+/// use raytracer::gpu::mipmap::mip_chain;
+///
+/// let levels = mip_chain(vec![[0, 0, 0, 0], [255, 255, 255, 255], [0; 4], [255; 4]], 2, false);
+/// assert_eq!(levels[1], [[128, 128, 128, 128]]);
+/// // sRGB colors are averaged as the linear values they encode; alpha
+/// // is linear either way.
+/// let levels = mip_chain(vec![[0, 0, 0, 0], [255, 255, 255, 255], [0; 4], [255; 4]], 2, true);
+/// assert_eq!(levels[1], [[188, 188, 188, 128]]);
+/// ```
+pub fn mip_chain(level0: Vec<[u8; 4]>, size: u32, srgb: bool) -> Vec<Vec<[u8; 4]>> {
+    let decode: Vec<f32> = (0..=255u8)
+        .map(|c| match srgb {
+            true => color::srgb_to_linear(c as f32 / 255.0),
+            false => c as f32 / 255.0,
+        })
+        .collect();
+    let encode = |c: f32| match srgb {
+        true => color::linear_to_srgb(c),
+        false => c,
+    };
+    let mut levels = vec![level0];
+    let mut size = size as usize;
+    while size > 1 {
+        let source = levels.last().unwrap();
+        let half = size / 2;
+        let mut level = Vec::with_capacity(half * half);
+        for y in 0..half {
+            for x in 0..half {
+                let quad = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| source[(2 * y + dy) * size + 2 * x + dx]);
+                level.push(std::array::from_fn(|i| {
+                    let mean = match i {
+                        3 => quad.iter().map(|texel| texel[i] as f32 / 255.0).sum::<f32>() / 4.0,
+                        _ => encode(quad.iter().map(|texel| decode[texel[i] as usize]).sum::<f32>() / 4.0),
+                    };
+                    (mean.clamp(0.0, 1.0) * 255.0).round() as u8
+                }));
+            }
+        }
+        levels.push(level);
+        size = half;
+    }
+    levels
+}
+
+/// Fills the mip chains of 2D textures and texture arrays on the GPU, each
+/// level a box-filtered reduction of the one above.
+pub struct MipGenerator {
+    downsample_srgb: ComputePipeline,
+    downsample_unorm: ComputePipeline,
+    downsample_float: ComputePipeline,
+}
+
+impl MipGenerator {
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("mipmap.wgsl"));
+        Self {
+            downsample_srgb: create_pipeline(device, &module, "downsample_srgb"),
+            downsample_unorm: create_pipeline(device, &module, "downsample_unorm"),
+            downsample_float: create_pipeline(device, &module, "downsample_float"),
+        }
+    }
+
+    /// Records filling levels 1 and up of `layers` of `texture` from level
+    /// 0. The texture needs `TEXTURE_BINDING` and `STORAGE_BINDING`
+    /// usage and an `Rgba8Unorm` or `Rgba16Float` format; with `srgb`, 8-bit
+    /// colors are averaged as sRGB-encoded ones, for textures viewed as
+    /// `Rgba8UnormSrgb`. Fails, recording nothing, for other formats.
+    pub fn encode(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        layers: Range<u32>,
+        srgb: bool,
+    ) -> anyhow::Result<()> {
+        let (pipeline, dest_binding) = match texture.format() {
+            TextureFormat::Rgba8Unorm if srgb => (&self.downsample_srgb, 1),
+            TextureFormat::Rgba8Unorm => (&self.downsample_unorm, 1),
+            TextureFormat::Rgba16Float => (&self.downsample_float, 2),
+            format => anyhow::bail!("cannot generate mipmaps for {format:?} textures"),
+        };
+        let level_view = |level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip level"),
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                base_array_layer: layers.start,
+                array_layer_count: Some(layers.len() as u32),
+                ..Default::default()
+            })
+        };
+        for level in 1..texture.mip_level_count() {
+            let source = level_view(level - 1);
+            let dest = level_view(level);
+            let width = (texture.width() >> level).max(1);
+            let height = (texture.height() >> level).max(1);
+            dispatch_grid(
+                device,
+                encoder,
+                "downsample mip level",
+                pipeline,
+                &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&source),
+                    },
+                    BindGroupEntry {
+                        binding: dest_binding,
+                        resource: BindingResource::TextureView(&dest),
+                    },
+                ],
+                [width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), layers.len() as u32],
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+const WORKGROUP_SIZE: u32 = 8u;
+
+@group(0) @binding(0) var source: texture_2d_array<f32>;
+@group(0) @binding(1) var dest_rgba8: texture_storage_2d_array<rgba8unorm, write>;
+@group(0) @binding(2) var dest_rgba16f: texture_storage_2d_array<rgba16float, write>;
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+// Mean of the source texels under destination texel `texel`, weighted by
+// how much of each the texel covers, so odd sizes lose no rows or columns.
+// With `srgb`, colors are decoded before averaging; alpha is always linear.
+fn box_filter(texel: vec2<u32>, layer: u32, dest_size: vec2<u32>, srgb: bool) -> vec4<f32> {
+    let source_size = textureDimensions(source);
+    let scale = vec2<f32>(source_size) / vec2<f32>(dest_size);
+    let lo = vec2<f32>(texel) * scale;
+    let hi = lo + scale;
+    var sum = vec4<f32>(0.0);
+    for (var y = u32(lo.y); f32(y) < hi.y && y < source_size.y; y++) {
+        let wy = min(hi.y, f32(y + 1u)) - max(lo.y, f32(y));
+        for (var x = u32(lo.x); f32(x) < hi.x && x < source_size.x; x++) {
+            let wx = min(hi.x, f32(x + 1u)) - max(lo.x, f32(x));
+            var c = textureLoad(source, vec2<u32>(x, y), layer, 0);
+            if (srgb) {
+                c = vec4<f32>(srgb_to_linear(c.rgb), c.a);
+            }
+            sum += wx * wy * c;
+        }
+    }
+    let mean = sum / (scale.x * scale.y);
+    if (srgb) {
+        return vec4<f32>(linear_to_srgb(mean.rgb), mean.a);
+    }
+    return mean;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn downsample_srgb(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest_rgba8);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    textureStore(dest_rgba8, id.xy, id.z, box_filter(id.xy, id.z, size, true));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn downsample_unorm(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest_rgba8);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    textureStore(dest_rgba8, id.xy, id.z, box_filter(id.xy, id.z, size, false));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn downsample_float(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest_rgba16f);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    textureStore(dest_rgba16f, id.xy, id.z, box_filter(id.xy, id.z, size, false));
+}
@@ -0,0 +1,67 @@
+use wgpu::{BindGroupEntry, Buffer, CommandEncoder, ComputePipeline, Device};
+
+pub mod hash_grid;
+pub mod mipmap;
+pub(crate) mod readback;
+pub mod resample;
+pub mod sort;
+
+pub(crate) fn create_pipeline(device: &Device, module: &wgpu::ShaderModule, entry_point: &str) -> ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: None,
+        module,
+        entry_point,
+    })
+}
+
+pub(crate) fn create_storage(device: &Device, label: &str, len: u32) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: len.max(1) as u64 * 4,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+pub(crate) fn buffer_entry(binding: u32, buffer: &Buffer) -> BindGroupEntry<'_> {
+    BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
+
+pub(crate) fn dispatch(
+    device: &Device,
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &ComputePipeline,
+    entries: &[BindGroupEntry],
+    workgroups: u32,
+) {
+    dispatch_grid(device, encoder, label, pipeline, entries, [workgroups, 1, 1]);
+}
+
+pub(crate) fn dispatch_grid(
+    device: &Device,
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &ComputePipeline,
+    entries: &[BindGroupEntry],
+    [x, y, z]: [u32; 3],
+) {
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries,
+    });
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+        label: Some(label),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, &bind_group, &[]);
+    pass.dispatch_workgroups(x, y, z);
+}
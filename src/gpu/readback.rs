@@ -0,0 +1,98 @@
+use {
+    bytemuck::Pod,
+    std::sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    wgpu::{Buffer, CommandEncoder, Device},
+};
+
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+/// Copies a small GPU value of a finished frame into a staging buffer and maps
+/// it without stalling; frames rendered while a copy is in flight are skipped.
+pub(crate) struct Readback<T> {
+    staging: Buffer,
+    map_state: Arc<AtomicU8>,
+    in_flight: bool,
+    copied: bool,
+    /// Values of the latest completed copy, empty before the first.
+    latest: Vec<T>,
+    /// Whether `latest` arrived after the last call to `poll_fresh`.
+    fresh: bool,
+}
+
+impl<T: Pod> Readback<T> {
+    pub fn new(device: &Device, label: &str) -> Self {
+        Self::with_len(device, label, 1)
+    }
+
+    /// Reads back the first `len` values of the source buffer instead of one.
+    pub fn with_len(device: &Device, label: &str, len: usize) -> Self {
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (std::mem::size_of::<T>() * len) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            staging,
+            map_state: Arc::new(AtomicU8::new(MAP_PENDING)),
+            in_flight: false,
+            copied: false,
+            latest: Vec::new(),
+            fresh: false,
+        }
+    }
+
+    pub fn copy(&mut self, encoder: &mut CommandEncoder, source: &Buffer) {
+        if self.in_flight {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(source, 0, &self.staging, 0, self.staging.size());
+        self.copied = true;
+    }
+
+    /// Starts mapping the staging buffer; call after the copy was submitted.
+    pub fn map(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.in_flight = true;
+        let map_state = self.map_state.clone();
+        self.staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let state = if result.is_ok() { MAP_DONE } else { MAP_FAILED };
+                map_state.store(state, Ordering::Release);
+            });
+    }
+
+    pub fn poll(&mut self, device: &Device) -> Option<T> {
+        self.receive(device);
+        self.latest.first().copied()
+    }
+
+    /// All values of a copy that completed since the last call, if any.
+    pub fn poll_fresh(&mut self, device: &Device) -> Option<&[T]> {
+        self.receive(device);
+        std::mem::take(&mut self.fresh).then_some(&self.latest[..])
+    }
+
+    fn receive(&mut self, device: &Device) {
+        device.poll(wgpu::Maintain::Poll);
+        match self.map_state.swap(MAP_PENDING, Ordering::Acquire) {
+            MAP_DONE => {
+                self.latest = bytemuck::cast_slice(&self.staging.slice(..).get_mapped_range()).to_vec();
+                self.staging.unmap();
+                self.in_flight = false;
+                self.fresh = true;
+            }
+            MAP_FAILED => self.in_flight = false,
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use {
+    super::{create_pipeline, dispatch_grid},
+    wgpu::{BindGroupEntry, BindingResource, CommandEncoder, ComputePipeline, Device, Sampler, TextureView},
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Resamples textures the CPU cannot read, such as block-compressed ones
+/// that only the GPU decodes, into layers of an 8-bit texture.
+pub struct Resampler {
+    resample: ComputePipeline,
+    resample_grey: ComputePipeline,
+    resample_unorm: ComputePipeline,
+    resample_grey_unorm: ComputePipeline,
+    sampler: Sampler,
+}
+
+impl Resampler {
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("resample.wgsl"));
+        Self {
+            resample: create_pipeline(device, &module, "resample"),
+            resample_grey: create_pipeline(device, &module, "resample_grey"),
+            resample_unorm: create_pipeline(device, &module, "resample_unorm"),
+            resample_grey_unorm: create_pipeline(device, &module, "resample_grey_unorm"),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("resample sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Records filling `dest`, a `width` by `height` single-level 2D view of
+    /// an `Rgba8Unorm` texture with `STORAGE_BINDING` usage, from `source`.
+    /// Colors are sampled bilinearly from the source's nearest mip level,
+    /// and with `srgb` encoded as sRGB; with `grey`, red is used for all
+    /// three.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        dest: &TextureView,
+        [width, height]: [u32; 2],
+        grey: bool,
+        srgb: bool,
+    ) {
+        let pipeline = match (grey, srgb) {
+            (false, true) => &self.resample,
+            (true, true) => &self.resample_grey,
+            (false, false) => &self.resample_unorm,
+            (true, false) => &self.resample_grey_unorm,
+        };
+        dispatch_grid(
+            device,
+            encoder,
+            "resample texture",
+            pipeline,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(dest),
+                },
+            ],
+            [width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1],
+        );
+    }
+}
@@ -0,0 +1,66 @@
+const WORKGROUP_SIZE: u32 = 8u;
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var dest: texture_storage_2d<rgba8unorm, write>;
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+// Bilinear sample of the source at the center of destination texel `texel`,
+// from the mip level nearest the destination's resolution.
+fn resampled(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    let uv = (vec2<f32>(texel) + 0.5) / vec2<f32>(size);
+    let ratio = vec2<f32>(textureDimensions(source)) / vec2<f32>(size);
+    let level = max(log2(max(ratio.x, ratio.y)), 0.0);
+    return textureSampleLevel(source, source_sampler, uv, level);
+}
+
+// Stores `c` at destination texel `texel`, the color of a grey source's red
+// channel with `grey`, and sRGB-encoded with `srgb`.
+fn store(texel: vec2<u32>, c: vec4<f32>, grey: bool, srgb: bool) {
+    var rgb = clamp(select(c.rgb, vec3<f32>(c.r), grey), vec3<f32>(0.0), vec3<f32>(1.0));
+    if (srgb) {
+        rgb = linear_to_srgb(rgb);
+    }
+    textureStore(dest, texel, vec4<f32>(rgb, c.a));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn resample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    store(id.xy, resampled(id.xy, size), false, true);
+}
+
+// For one-channel sources, whose red channel becomes grey.
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn resample_grey(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    store(id.xy, resampled(id.xy, size), true, true);
+}
+
+// For data such as normal maps, whose values are stored as they are.
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn resample_unorm(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    store(id.xy, resampled(id.xy, size), false, false);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn resample_grey_unorm(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dest);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    store(id.xy, resampled(id.xy, size), true, false);
+}
@@ -0,0 +1,255 @@
+use {
+    super::{buffer_entry, create_pipeline, create_storage, dispatch},
+    bytemuck::{Pod, Zeroable},
+    wgpu::{util::DeviceExt, Buffer, CommandEncoder, ComputePipeline, Device},
+};
+
+const WORKGROUP_SIZE: u32 = 256;
+const RADIX_BITS: u32 = 4;
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Params {
+    count: u32,
+    shift: u32,
+    num_blocks: u32,
+    _pad: u32,
+}
+
+/// Exclusive prefix sums over `u32` storage buffers.
+pub struct PrefixScan {
+    scan_blocks: ComputePipeline,
+    add_block_offsets: ComputePipeline,
+}
+
+/// Stable least-significant-digit radix sort of `u32` keys with `u32` payloads.
+///
+/// Checked against [`reference_sort`] wherever an adapter is found; without
+/// one the example returns early:
+///
+/// ```
+/// use raytracer::gpu::sort::{reference_sort, RadixSort};
+/// use wgpu::util::DeviceExt;
+///
+/// let instance = wgpu::Instance::default();
+/// let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default())) else {
+///     return;
+/// };
+/// let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None)).unwrap();
+///
+/// // Several workgroups' worth, with repeated keys to exercise stability.
+/// let count = 3000;
+/// let keys: Vec<u32> = (0..count).map(|i: u32| i.wrapping_mul(2_654_435_761) >> 20).collect();
+/// let values: Vec<u32> = (0..count).collect();
+/// let usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC;
+/// let upload = |contents: &[u32]| {
+///     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+///         label: None,
+///         contents: bytemuck::cast_slice(contents),
+///         usage,
+///     })
+/// };
+/// let (key_buffer, value_buffer) = (upload(&keys), upload(&values));
+/// let readback = device.create_buffer(&wgpu::BufferDescriptor {
+///     label: None,
+///     size: 8 * count as u64,
+///     usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+///     mapped_at_creation: false,
+/// });
+/// let mut encoder = device.create_command_encoder(&Default::default());
+/// RadixSort::new(&device).encode(&device, &mut encoder, &key_buffer, &value_buffer, count, 12);
+/// encoder.copy_buffer_to_buffer(&key_buffer, 0, &readback, 0, 4 * count as u64);
+/// encoder.copy_buffer_to_buffer(&value_buffer, 0, &readback, 4 * count as u64, 4 * count as u64);
+/// queue.submit([encoder.finish()]);
+/// readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+/// device.poll(wgpu::Maintain::Wait);
+/// let sorted: Vec<u32> = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+///
+/// let (mut expected_keys, mut expected_values) = (keys, values);
+/// reference_sort(&mut expected_keys, &mut expected_values, 12);
+/// assert_eq!(sorted[..count as usize], expected_keys);
+/// assert_eq!(sorted[count as usize..], expected_values);
+/// ```
+pub struct RadixSort {
+    scan: PrefixScan,
+    histogram: ComputePipeline,
+    scatter: ComputePipeline,
+}
+
+impl PrefixScan {
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("sort.wgsl"));
+        Self {
+            scan_blocks: create_pipeline(device, &module, "scan_blocks"),
+            add_block_offsets: create_pipeline(device, &module, "add_block_offsets"),
+        }
+    }
+
+    /// Records an in-place exclusive scan of the first `count` elements of
+    /// `data`, which needs `STORAGE` usage. Temporaries are allocated per call.
+    pub fn encode(&self, device: &Device, encoder: &mut CommandEncoder, data: &Buffer, count: u32) {
+        if count == 0 {
+            return;
+        }
+        let num_blocks = count.div_ceil(WORKGROUP_SIZE);
+        let block_sums = create_storage(device, "scan block sums", num_blocks);
+        let params = create_params(device, count, 0, num_blocks);
+        let entries = [
+            buffer_entry(0, &params),
+            buffer_entry(1, data),
+            buffer_entry(2, &block_sums),
+        ];
+
+        dispatch(device, encoder, "scan_blocks", &self.scan_blocks, &entries, num_blocks);
+        if num_blocks > 1 {
+            self.encode(device, encoder, &block_sums, num_blocks);
+            dispatch(
+                device,
+                encoder,
+                "add_block_offsets",
+                &self.add_block_offsets,
+                &entries,
+                num_blocks,
+            );
+        }
+    }
+}
+
+impl RadixSort {
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("sort.wgsl"));
+        Self {
+            scan: PrefixScan::new(device),
+            histogram: create_pipeline(device, &module, "radix_histogram"),
+            scatter: create_pipeline(device, &module, "radix_scatter"),
+        }
+    }
+
+    /// Records a sort of the first `count` entries of `keys` (and the matching
+    /// `values`) by their low `key_bits` bits. Both buffers need `STORAGE` and
+    /// `COPY_DST` usage; the sorted result is left in place.
+    pub fn encode(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        keys: &Buffer,
+        values: &Buffer,
+        count: u32,
+        key_bits: u32,
+    ) {
+        if count < 2 {
+            return;
+        }
+        let num_blocks = count.div_ceil(WORKGROUP_SIZE);
+        let histograms = create_storage(device, "radix histograms", num_blocks << RADIX_BITS);
+        let temp_keys = create_storage(device, "radix keys", count);
+        let temp_values = create_storage(device, "radix values", count);
+
+        let passes = key_bits.min(32).div_ceil(RADIX_BITS);
+        for pass in 0..passes {
+            let (keys_in, values_in, keys_out, values_out) = if pass % 2 == 0 {
+                (keys, values, &temp_keys, &temp_values)
+            } else {
+                (&temp_keys, &temp_values, keys, values)
+            };
+            let params = create_params(device, count, pass * RADIX_BITS, num_blocks);
+
+            dispatch(
+                device,
+                encoder,
+                "radix_histogram",
+                &self.histogram,
+                &[
+                    buffer_entry(0, &params),
+                    buffer_entry(3, keys_in),
+                    buffer_entry(7, &histograms),
+                ],
+                num_blocks,
+            );
+            self.scan
+                .encode(device, encoder, &histograms, num_blocks << RADIX_BITS);
+            dispatch(
+                device,
+                encoder,
+                "radix_scatter",
+                &self.scatter,
+                &[
+                    buffer_entry(0, &params),
+                    buffer_entry(3, keys_in),
+                    buffer_entry(4, values_in),
+                    buffer_entry(5, keys_out),
+                    buffer_entry(6, values_out),
+                    buffer_entry(7, &histograms),
+                ],
+                num_blocks,
+            );
+        }
+
+        if passes % 2 == 1 {
+            let size = count as u64 * 4;
+            encoder.copy_buffer_to_buffer(&temp_keys, 0, keys, 0, size);
+            encoder.copy_buffer_to_buffer(&temp_values, 0, values, 0, size);
+        }
+    }
+}
+
+fn create_params(device: &Device, count: u32, shift: u32, num_blocks: u32) -> Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("sort params"),
+        contents: bytemuck::bytes_of(&Params {
+            count,
+            shift,
+            num_blocks,
+            _pad: 0,
+        }),
+        usage: wgpu::BufferUsages::UNIFORM,
+    })
+}
+
+/// Exclusive prefix sum of `data` in place on the CPU, wrapping on
+/// overflow as [`PrefixScan`] does.
+///
+/// ```
+/// let mut data = [3, 1, 4, 1, 5];
+/// raytracer::gpu::sort::reference_scan(&mut data);
+/// assert_eq!(data, [0, 3, 4, 8, 9]);
+/// ```
+pub fn reference_scan(data: &mut [u32]) {
+    let mut sum = 0u32;
+    for x in data {
+        let value = *x;
+        *x = sum;
+        sum = sum.wrapping_add(value);
+    }
+}
+
+/// Sorts `keys`, and `values` along with them, by the low `key_bits` bits
+/// of the keys on the CPU, in the same digit passes as [`RadixSort`]: a
+/// histogram of the digit, its exclusive scan and a stable scatter.
+///
+/// ```
+/// let mut keys = [0x31, 0x12, 0x21, 0x12, 0x1ff];
+/// let mut values = [0, 1, 2, 3, 4];
+/// raytracer::gpu::sort::reference_sort(&mut keys, &mut values, 8);
+/// // Bit 8 of the last key is above `key_bits`.
+/// assert_eq!(keys, [0x12, 0x12, 0x21, 0x31, 0x1ff]);
+/// assert_eq!(values, [1, 3, 2, 0, 4]);
+/// ```
+pub fn reference_sort(keys: &mut [u32], values: &mut [u32], key_bits: u32) {
+    let radix = 1 << RADIX_BITS;
+    for pass in 0..key_bits.min(32).div_ceil(RADIX_BITS) {
+        let digit = |key: u32| (key >> (pass * RADIX_BITS) & (radix - 1)) as usize;
+        let mut offsets = vec![0; radix as usize];
+        for &key in keys.iter() {
+            offsets[digit(key)] += 1;
+        }
+        reference_scan(&mut offsets);
+        let pairs: Vec<(u32, u32)> = keys.iter().copied().zip(values.iter().copied()).collect();
+        for (key, value) in pairs {
+            let slot = &mut offsets[digit(key)];
+            keys[*slot as usize] = key;
+            values[*slot as usize] = value;
+            *slot += 1;
+        }
+    }
+}
@@ -0,0 +1,147 @@
+struct Params {
+    count: u32,
+    shift: u32,
+    num_blocks: u32,
+    _pad: u32,
+}
+
+const WORKGROUP_SIZE: u32 = 256u;
+const RADIX: u32 = 16u;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> scan_data: array<u32>;
+@group(0) @binding(2) var<storage, read_write> block_sums: array<u32>;
+@group(0) @binding(3) var<storage, read> keys_in: array<u32>;
+@group(0) @binding(4) var<storage, read> values_in: array<u32>;
+@group(0) @binding(5) var<storage, read_write> keys_out: array<u32>;
+@group(0) @binding(6) var<storage, read_write> values_out: array<u32>;
+@group(0) @binding(7) var<storage, read_write> histograms: array<u32>;
+
+var<workgroup> scratch: array<u32, WORKGROUP_SIZE>;
+var<workgroup> digit_counts: array<atomic<u32>, RADIX>;
+var<workgroup> digit_start: array<u32, RADIX>;
+var<workgroup> local_keys: array<u32, WORKGROUP_SIZE>;
+var<workgroup> local_values: array<u32, WORKGROUP_SIZE>;
+var<workgroup> local_digits: array<u32, WORKGROUP_SIZE>;
+
+// Hillis-Steele scan over the workgroup. Returns (exclusive prefix, total).
+fn workgroup_scan(local: u32, value: u32) -> vec2<u32> {
+    workgroupBarrier();
+    scratch[local] = value;
+    workgroupBarrier();
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset <<= 1u) {
+        var add = 0u;
+        if (local >= offset) {
+            add = scratch[local - offset];
+        }
+        workgroupBarrier();
+        scratch[local] += add;
+        workgroupBarrier();
+    }
+    let inclusive = scratch[local];
+    let total = scratch[WORKGROUP_SIZE - 1u];
+    return vec2<u32>(inclusive - value, total);
+}
+
+// Exclusive scan of each block of `scan_data`; block totals go to `block_sums`.
+@compute @workgroup_size(256)
+fn scan_blocks(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+) {
+    var value = 0u;
+    if (gid.x < params.count) {
+        value = scan_data[gid.x];
+    }
+    let scan = workgroup_scan(lid.x, value);
+    if (gid.x < params.count) {
+        scan_data[gid.x] = scan.x;
+    }
+    if (lid.x == 0u) {
+        block_sums[wid.x] = scan.y;
+    }
+}
+
+// Adds the (already scanned) block totals back onto every block.
+@compute @workgroup_size(256)
+fn add_block_offsets(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+) {
+    if (gid.x < params.count) {
+        scan_data[gid.x] += block_sums[wid.x];
+    }
+}
+
+// Counts the digits of one block. Counts are stored digit-major so that an
+// exclusive scan over `histograms` yields each block's output offset per digit.
+@compute @workgroup_size(256)
+fn radix_histogram(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+) {
+    if (lid.x < RADIX) {
+        atomicStore(&digit_counts[lid.x], 0u);
+    }
+    workgroupBarrier();
+    if (gid.x < params.count) {
+        let digit = (keys_in[gid.x] >> params.shift) & (RADIX - 1u);
+        atomicAdd(&digit_counts[digit], 1u);
+    }
+    workgroupBarrier();
+    if (lid.x < RADIX) {
+        histograms[lid.x * params.num_blocks + wid.x] = atomicLoad(&digit_counts[lid.x]);
+    }
+}
+
+// Stable scatter of one block to its scanned digit offsets. The block is first
+// sorted locally with four 1-bit splits so ranks within a digit keep input order.
+@compute @workgroup_size(256)
+fn radix_scatter(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+) {
+    // Padding past `count` only ever occurs at the tail of the last block, so
+    // giving it the largest digit keeps it behind every real element.
+    var key = 0u;
+    var value = 0u;
+    var digit = RADIX - 1u;
+    if (gid.x < params.count) {
+        key = keys_in[gid.x];
+        value = values_in[gid.x];
+        digit = (key >> params.shift) & (RADIX - 1u);
+    }
+
+    for (var bit = 0u; bit < 4u; bit++) {
+        let bit_set = (digit >> bit) & 1u;
+        let scan = workgroup_scan(lid.x, 1u - bit_set);
+        var dest = scan.x;
+        if (bit_set == 1u) {
+            dest = scan.y + lid.x - scan.x;
+        }
+        local_keys[dest] = key;
+        local_values[dest] = value;
+        local_digits[dest] = digit;
+        workgroupBarrier();
+        key = local_keys[lid.x];
+        value = local_values[lid.x];
+        digit = local_digits[lid.x];
+        workgroupBarrier();
+    }
+
+    if (lid.x == 0u || local_digits[lid.x - 1u] != digit) {
+        digit_start[digit] = lid.x;
+    }
+    workgroupBarrier();
+
+    let valid = min(WORKGROUP_SIZE, params.count - wid.x * WORKGROUP_SIZE);
+    if (lid.x < valid) {
+        let rank = lid.x - digit_start[digit];
+        let dest = histograms[digit * params.num_blocks + wid.x] + rank;
+        keys_out[dest] = key;
+        values_out[dest] = value;
+    }
+}
@@ -0,0 +1,7 @@
+// Hash of an integer grid cell, from Teschner et al., "Optimized Spatial
+// Hashing for Collision Detection of Deformable Objects". Prepended to the
+// shaders that key tables on cells, so that they all agree.
+fn spatial_hash(cell: vec3<i32>) -> u32 {
+    let c = bitcast<vec3<u32>>(cell);
+    return (c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u);
+}
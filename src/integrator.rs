@@ -0,0 +1,72 @@
+//! Integrators written outside the crate. A [`CustomIntegrator`] draws
+//! each frame with its own pipelines, reading the renderer's uniforms,
+//! accumulation images and scene buffers through the same bind group as
+//! shader.wgsl, and is registered with
+//! [`PathTracer::register_integrator`](crate::render::PathTracer::register_integrator)
+//! so that the viewer can switch to it alongside the built-in ones.
+//!
+//! ```no_run
+//! use raytracer::{
+//!     integrator::{CustomIntegrator, IntegratorFrame, IntegratorSetup},
+//!     render::PathTracer,
+//! };
+//! use wgpu::{CommandEncoder, TextureView};
+//!
+//! /// Shades every pixel with the number of samples so far.
+//! struct SampleCount {
+//!     pipeline: wgpu::RenderPipeline,
+//! }
+//!
+//! impl CustomIntegrator for SampleCount {
+//!     fn encode(&mut self, frame: &IntegratorFrame, encoder: &mut CommandEncoder, target: &TextureView) {
+//!         // Begin a render pass on `target`, set `self.pipeline`, bind
+//!         // `frame.scene` at group 0 and draw a full-screen triangle.
+//!     }
+//! }
+//!
+//! fn register(renderer: &mut PathTracer, pipeline: impl Fn(&IntegratorSetup) -> wgpu::RenderPipeline) {
+//!     renderer
+//!         .register_integrator("sample-count", |setup| Ok(Box::new(SampleCount { pipeline: pipeline(setup) })))
+//!         .unwrap();
+//!     renderer.set_integrator("sample-count").unwrap();
+//! }
+//! ```
+
+use {
+    crate::camera::Camera,
+    wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, Queue, TextureView},
+};
+
+/// What an integrator is created for.
+pub struct IntegratorSetup<'a> {
+    pub device: &'a Device,
+    /// Layout of [`IntegratorFrame::scene`], for the integrator's pipeline
+    /// layouts. Its bindings are those of group 0 in shader.wgsl, visible to
+    /// fragment shaders, so a WGSL integrator declares the ones it uses just
+    /// as shader.wgsl does.
+    pub layout: &'a BindGroupLayout,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The frame an integrator draws.
+pub struct IntegratorFrame<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    /// The renderer's bind group, with the frame's uniforms already written.
+    pub scene: &'a BindGroup,
+    pub camera: &'a Camera,
+    /// Frames accumulated since the last restart, this one included.
+    pub frame_count: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A way of shading the image in place of shader.wgsl's.
+pub trait CustomIntegrator: Send {
+    /// Records drawing the frame into `target`, a render attachment of
+    /// [`post::FORMAT`](crate::post::FORMAT) as large as the image.
+    /// Accumulating across frames is up to the integrator, for instance in
+    /// the accumulation images of the bind group.
+    fn encode(&mut self, frame: &IntegratorFrame, encoder: &mut CommandEncoder, target: &TextureView);
+}
@@ -0,0 +1,24 @@
+pub mod burn_in;
+pub mod camera;
+pub mod color;
+pub mod convergence;
+pub mod crash;
+pub mod dump;
+pub mod export;
+pub mod gpu;
+pub mod integrator;
+pub mod json;
+pub mod math;
+pub mod photometry;
+pub mod post;
+pub mod render;
+pub mod scene;
+pub mod settings;
+pub mod shake;
+#[cfg(feature = "debug-stats")]
+pub mod stats;
+pub mod streaming;
+pub mod sun;
+pub mod texture_cache;
+pub mod timecode;
+pub mod viewer;
@@ -0,0 +1,24 @@
+//! Conversions between photometric inputs and the radiometric quantities the
+//! tracer integrates. Photometric values are converted at the 555 nm peak
+//! efficacy, which treats every light as if it were monochromatic green; this
+//! is the usual convention for RGB renderers.
+
+/// Lumens per watt at 555 nm.
+pub const LUMINOUS_EFFICACY: f32 = 683.0;
+
+/// Illuminance in lux to irradiance in W/m².
+pub fn lux_to_irradiance(lux: f32) -> f32 {
+    lux / LUMINOUS_EFFICACY
+}
+
+/// Luminance in nits (cd/m²) to radiance in W/(sr·m²).
+pub fn nits_to_radiance(nits: f32) -> f32 {
+    nits / LUMINOUS_EFFICACY
+}
+
+/// Scale from radiance to display-referred values for a camera at the given
+/// EV100, using the saturation-based sensitivity model (K = 1.2 · 2^EV100 cd/m²
+/// maps to white).
+pub fn exposure_from_ev100(ev100: f32) -> f32 {
+    LUMINOUS_EFFICACY / (1.2 * 2f32.powf(ev100))
+}
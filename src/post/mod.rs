@@ -0,0 +1,224 @@
+//! Post passes, run on the displayed image after the path tracer draws it.
+//! Each kind of pass is registered under a name in a [`PostRegistry`], and
+//! `--post` lists the passes to run in order, so library users can add
+//! their own effects:
+//!
+//! ```no_run
+//! use raytracer::post::{PostFrame, PostPass, PostRegistry, PostSetup};
+//! use wgpu::{Buffer, CommandEncoder, Device, TextureView};
+//!
+//! /// Copies its input unchanged.
+//! struct Passthrough;
+//!
+//! impl PostPass for Passthrough {
+//!     fn encode(
+//!         &self,
+//!         device: &Device,
+//!         encoder: &mut CommandEncoder,
+//!         input: &TextureView,
+//!         output: &TextureView,
+//!         uniforms: &Buffer,
+//!     ) {
+//!         // Bind `input` and `uniforms`, then draw a full-screen triangle
+//!         // into `output`.
+//!     }
+//! }
+//!
+//! let mut registry = PostRegistry::with_builtins();
+//! registry.register("passthrough", |_setup: &PostSetup| Ok(Box::new(Passthrough)));
+//! ```
+//!
+//! The viewer offers its registry to
+//! [`ViewerHooks::register_post_passes`](crate::viewer::ViewerHooks::register_post_passes)
+//! before creating the passes.
+//!
+//! Exported frames are developed from the accumulated radiance and do not
+//! go through the post passes.
+
+pub mod vignette;
+
+use {
+    anyhow::{bail, Result},
+    wgpu::{Buffer, CommandEncoder, Device, Queue, TextureView},
+};
+
+/// Format of the images passes read and write, that of the display.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+
+/// What a pass is created for.
+pub struct PostSetup<'a> {
+    pub device: &'a Device,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The frame a pass runs on.
+#[derive(Copy, Clone, Debug)]
+pub struct PostFrame {
+    pub width: u32,
+    pub height: u32,
+    /// Samples accumulated into the image so far.
+    pub sample_count: u32,
+}
+
+/// One effect applied to the displayed image.
+pub trait PostPass: Send {
+    /// Contents of the pass's uniform buffer for `frame`, written before it
+    /// runs. Passes without parameters leave the buffer zeroed.
+    fn uniforms(&self, frame: &PostFrame) -> Vec<u8> {
+        let _ = frame;
+        Vec::new()
+    }
+
+    /// Records the pass into `encoder`. `input` is the image so far, a view
+    /// of a [`FORMAT`] texture with `TEXTURE_BINDING` usage, and the pass
+    /// draws every pixel of `output`, a render attachment of the same format
+    /// and size. `uniforms` is a uniform buffer holding what
+    /// [`uniforms`](Self::uniforms) returned, padded to 16 bytes.
+    fn encode(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        input: &TextureView,
+        output: &TextureView,
+        uniforms: &Buffer,
+    );
+}
+
+type Factory = Box<dyn Fn(&PostSetup) -> Result<Box<dyn PostPass>>>;
+
+/// Kinds of pass by name.
+#[derive(Default)]
+pub struct PostRegistry {
+    factories: Vec<(String, Factory)>,
+}
+
+impl PostRegistry {
+    /// The passes that come with the crate: `vignette`.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry.register("vignette", |setup| Ok(Box::new(vignette::Vignette::new(setup.device))));
+        registry
+    }
+
+    /// Makes `factory` create the pass named `name`, in place of any
+    /// registered before under that name.
+    pub fn register(&mut self, name: &str, factory: impl Fn(&PostSetup) -> Result<Box<dyn PostPass>> + 'static) {
+        self.factories.retain(|(existing, _)| existing != name);
+        self.factories.push((name.to_string(), Box::new(factory)));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn create(&self, name: &str, setup: &PostSetup) -> Result<Box<dyn PostPass>> {
+        match self.factories.iter().find(|(existing, _)| existing == name) {
+            Some((_, factory)) => factory(setup),
+            None => {
+                let names: Vec<_> = self.names().collect();
+                bail!("unknown post pass `{name}` (expected one of {})", names.join(", "))
+            }
+        }
+    }
+}
+
+/// The passes in the order they run, with the images between them.
+pub(crate) struct PostStack {
+    passes: Vec<(Box<dyn PostPass>, Buffer)>,
+    /// Two images the passes alternate between. The path tracer draws into
+    /// the first, and the last pass into the display.
+    images: [TextureView; 2],
+    width: u32,
+    height: u32,
+}
+
+impl PostStack {
+    /// The passes of `registry` named by `names`, or `None` if there are
+    /// none.
+    pub(crate) fn new(
+        device: &Device,
+        registry: &PostRegistry,
+        names: &[String],
+        [width, height]: [u32; 2],
+    ) -> Result<Option<Self>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let setup = PostSetup { device, width, height };
+        let mut passes = Vec::with_capacity(names.len());
+        for name in names {
+            let pass = registry.create(name, &setup)?;
+            let uniforms = uniform_buffer(device, 16);
+            passes.push((pass, uniforms));
+        }
+        let image = |label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(label),
+                    ..Default::default()
+                })
+        };
+        Ok(Some(Self {
+            passes,
+            images: [image("post image 0"), image("post image 1")],
+            width,
+            height,
+        }))
+    }
+
+    /// Where the path tracer draws the image the passes start from.
+    pub(crate) fn input(&self) -> &TextureView {
+        &self.images[0]
+    }
+
+    /// Records every pass, the last drawing into `target`.
+    pub(crate) fn encode(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        sample_count: u32,
+    ) {
+        let frame = PostFrame {
+            width: self.width,
+            height: self.height,
+            sample_count,
+        };
+        let last = self.passes.len() - 1;
+        for (i, (pass, uniforms)) in self.passes.iter_mut().enumerate() {
+            let mut bytes = pass.uniforms(&frame);
+            bytes.resize(bytes.len().next_multiple_of(16).max(16), 0);
+            if bytes.len() as u64 > uniforms.size() {
+                *uniforms = uniform_buffer(device, bytes.len() as u64);
+            }
+            queue.write_buffer(uniforms, 0, &bytes);
+            let output = if i == last { target } else { &self.images[(i + 1) % 2] };
+            pass.encode(device, encoder, &self.images[i % 2], output, uniforms);
+        }
+    }
+}
+
+fn uniform_buffer(device: &Device, size: u64) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("post uniforms"),
+        size,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,84 @@
+use {
+    super::{PostFrame, PostPass, FORMAT},
+    wgpu::{BindGroupEntry, BindingResource, Buffer, CommandEncoder, Device, RenderPipeline, TextureView},
+};
+
+/// Corner darkening in a fixed proportion.
+const STRENGTH: f32 = 0.4;
+
+/// Darkens the image towards its corners, as lenses do.
+pub struct Vignette {
+    pipeline: RenderPipeline,
+}
+
+impl Vignette {
+    pub fn new(device: &Device) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("vignette.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("vignette"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(FORMAT.into())],
+            }),
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+}
+
+impl PostPass for Vignette {
+    fn uniforms(&self, frame: &PostFrame) -> Vec<u8> {
+        bytemuck::cast_slice(&[frame.width as f32, frame.height as f32, STRENGTH, 0.0]).to_vec()
+    }
+
+    fn encode(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        input: &TextureView,
+        output: &TextureView,
+        uniforms: &Buffer,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("vignette"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: uniforms.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("vignette"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
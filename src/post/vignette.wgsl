@@ -0,0 +1,25 @@
+struct Params {
+    size: vec2<f32>,
+    // Darkening at the corners, from 0 (none) to 1 (black).
+    strength: f32,
+    _pad: f32,
+}
+
+@group(0) @binding(0) var input: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+// One triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32(index & 1u), f32(index >> 1u)) * 2.0;
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let color = textureLoad(input, vec2<i32>(position.xy), 0);
+    // Squared distance from the center, 1 at the corners.
+    let d = position.xy / params.size * 2.0 - 1.0;
+    let falloff = 1.0 - params.strength * dot(d, d) * 0.5;
+    return vec4<f32>(color.rgb * clamp(falloff, 0.0, 1.0), color.a);
+}
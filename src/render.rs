@@ -1,87 +1,895 @@
-use crate::camera::{Camera, CameraUniforms}; 
+use crate::camera::{Camera, CameraUniforms};
+use crate::color::Mat3;
+use crate::crash;
+use crate::convergence::SplitRadiance;
+use crate::integrator::{CustomIntegrator, IntegratorFrame, IntegratorSetup};
+use crate::post::{PostRegistry, PostStack};
+use crate::settings::{Integrator, RenderSettings};
+use crate::streaming::{self, Residency, POOLS};
+use crate::sun::Sun;
+use crate::texture_cache;
+use crate::gpu::{
+    mipmap::{self, MipGenerator},
+    readback::Readback,
+    resample::Resampler,
+};
+use crate::scene::{
+    bvh::{Bvh, BvhNode},
+    curve::{Curve, CurveShape},
+    splat::{Splat, SplatShape},
+    bcn::BlockFormat,
+    gltf::IDENTITY,
+    texture::{self, BlockImage},
+    light::{AreaLight, LightShape, MAX_LIGHTS},
+    lod::{self, BoundingSphere, Lod, LodView},
+    procedural,
+    sdf::{SdfNode, SdfOp, SdfPrimitive, MAX_SDF_NODES},
+    edit::SceneChanges,
+    volume::MAX_VOLUMES,
+    Instance, MaterialKind, Scene, ShapeKind, SphereMaterial, Triangle, Visibility,
+};
+#[cfg(feature = "debug-stats")]
+use crate::stats::TraversalStats;
 use bytemuck::{Pod, Zeroable};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use wgpu::util::DeviceExt;
 use wgpu::{
     BindGroup, BindGroupLayout, Buffer, Device, Queue, RenderPipeline, ShaderModule, Texture,
     TextureView,
 };
 
+/// Must match `IRRADIANCE_CACHE_CELLS` in shader.wgsl.
+const IRRADIANCE_CACHE_CELLS: u64 = 1 << 18;
+
+/// Storage buffers bound to the fragment stage, six more than wgpu's
+/// default limit.
+pub const STORAGE_BUFFERS_PER_STAGE: u32 = 14;
+
+/// Must match `MATERIAL_SCENE` in shader.wgsl.
+const MATERIAL_SCENE: u32 = 4;
+
+/// Must match the `PRIMITIVE_` constants in shader.wgsl.
+const PRIMITIVE_TRIANGLES: u32 = 0;
+const PRIMITIVE_CURVES: u32 = 1;
+const PRIMITIVE_SPLATS: u32 = 2;
+
+/// Edge lengths of the texture arrays that scene textures are resampled
+/// into, each texture into the smallest that holds its longer side, or the
+/// largest. Must match the `textures_` bindings in shader.wgsl.
+const TEXTURE_SIZES: [u32; 5] = [256, 512, 1024, 2048, 4096];
+
+/// Must match the `VISIBLE_` constants in shader.wgsl.
+const VISIBLE_CAMERA: u32 = 1;
+const VISIBLE_SHADOW: u32 = 2;
+const VISIBLE_DIFFUSE: u32 = 4;
+const VISIBLE_SPECULAR: u32 = 8;
+
+/// Must match `NOT_RESIDENT` in shader.wgsl.
+const NOT_RESIDENT: u32 = u32::MAX;
+
+/// Most triangles in one chunk of a streamed mesh.
+const STREAM_CHUNK_TRIANGLES: usize = 1 << 16;
+
+/// Cells along the longest side of a streamed chunk's bounds whose vertices
+/// merge into one in the chunk's proxy.
+const PROXY_CELLS: u32 = 16;
+
+/// Bytes of a vertex with its normal, texture coordinates and tangent, a
+/// triangle record and a hierarchy node, the elements of the streaming pools.
+const POOL_ELEMENT_BYTES: [u64; POOLS] = [56, 16, 32];
+
 pub struct PathTracer {
     device: Device,
     queue: Queue,
     uniforms: Uniforms,
     uniform_buffer: Buffer,
     display_pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
     display_bind_group: BindGroup,
     vertex_buffer: Buffer,
+    radiance_samples: Texture,
+    odd_samples: Texture,
+    irradiance_cache: Buffer,
+    debug_stats: Buffer,
+    #[cfg(feature = "debug-stats")]
+    stats_readback: Readback<TraversalStats>,
+    focus_probe: Buffer,
+    /// Present when autofocus is enabled.
+    focus_readback: Option<Readback<f32>>,
+    bvh_leaf_size: usize,
+    geometry_budget: Option<u64>,
+    /// Names of the procedural primitives the shader was assembled with.
+    procedural_kinds: Vec<&'static str>,
+    texture_tools: TextureTools,
+    scene_buffers: SceneBuffers,
+    /// Present when post passes are set.
+    post: Option<PostStack>,
+    /// Integrators registered by name.
+    integrators: Vec<(String, Box<dyn CustomIntegrator>)>,
+    /// Index into `integrators` of the one drawing frames in place of the
+    /// built-in one named by the uniforms.
+    custom_integrator: Option<usize>,
+    #[cfg(feature = "renderdoc")]
+    capture_next: bool,
+}
+
+/// Compute passes that prepare scene textures.
+struct TextureTools {
+    mip_generator: MipGenerator,
+    resampler: Resampler,
+}
+
+/// Read-only storage buffers holding the loaded scene, each rebuilt on its
+/// own when only its part of the scene changes.
+struct SceneBuffers {
+    geometry: SceneGeometry,
+    materials: Buffer,
+    instances: SceneInstances,
+    /// Present when a placed mesh has levels of detail.
+    lods: Option<LodSelection>,
+    /// Spheres followed by the other analytic shapes and the SDF shapes.
+    shapes: Buffer,
+    /// Shapes in `shapes`, leaving out the placeholder of an empty scene.
+    shape_count: u32,
+    /// A uniform buffer of `MAX_SDF_NODES` entries.
+    sdf_nodes: Buffer,
+    /// A uniform buffer of `MAX_LIGHTS` entries.
+    lights: Buffer,
+    /// Emissive triangles, sampled in proportion to their power.
+    emitters: Buffer,
+    /// Emitters in `emitters`, leaving out the placeholder of a scene
+    /// without any.
+    emitter_count: u32,
+    /// A uniform buffer of `MAX_VOLUMES` entries.
+    volumes: Buffer,
+    volume_count: u32,
+    /// Densities of every volume, stacked along z.
+    volume_densities: TextureView,
+    textures: SceneTextures,
+    sampler: wgpu::Sampler,
+}
+
+/// One array per entry of `TEXTURE_SIZES`, with a layer per scene texture
+/// of that size, each with a full mip chain.
+struct SceneTextures {
+    /// The arrays viewed as sRGB, for colors.
+    color: [TextureView; TEXTURE_SIZES.len()],
+    /// The same arrays viewed as plain 8-bit, for data such as normal maps,
+    /// whose layers hold the values in their files and mips averaged as
+    /// they are.
+    data: [TextureView; TEXTURE_SIZES.len()],
+    /// Indices of the scene textures that hold data.
+    data_indices: HashSet<usize>,
+}
+
+/// Meshes, curves and splats, cut into chunks of one hierarchy each, with
+/// what placing them again takes without rebuilding their hierarchies.
+struct SceneGeometry {
+    vertices: Buffer,
+    normals: Buffer,
+    uvs: Buffer,
+    tangents: Buffer,
+    triangles: Buffer,
+    /// Hierarchies of the resident chunks, with child and triangle offsets
+    /// that hold once the top level is in front of them.
+    mesh_nodes: Vec<BvhNode>,
+    /// Root node within `mesh_nodes` or `NOT_RESIDENT`, object-space bounds
+    /// and primitive kind of every chunk.
+    roots: Vec<(u32, Bounds, u32)>,
+    /// Chunks of every mesh, then of the world-space triangles, curves and
+    /// splats.
+    mesh_chunks: Vec<Range<u32>>,
+    /// Instances of the world-space triangles, curves and splats.
+    placements: Vec<Instance>,
+    /// Present when triangle meshes are streamed through a memory budget.
+    stream: Option<GeometryStream>,
+}
+
+/// Streamed chunks, kept on the CPU to be paged in again. They come first
+/// among the chunks; the curve and splat chunks after them stay resident.
+struct GeometryStream {
+    chunks: Vec<GeometryChunk>,
+    residency: Residency,
+    /// Root node within `SceneGeometry::mesh_nodes` of each chunk's proxy,
+    /// which stays resident, or `NOT_RESIDENT` if it has none.
+    proxies: Vec<u32>,
+    /// Where each pool starts in `vertices`, `triangles` and `mesh_nodes`,
+    /// after the chunks that stay resident.
+    pool_base: [u32; POOLS],
+    /// Ray counts of the streamed chunks, from the chunk table.
+    readback: Readback<GpuChunk>,
+}
+
+/// Placements of meshes, kept with the meshes' levels of detail to choose
+/// the levels again as the camera moves.
+struct LodSelection {
+    /// The scene's instances and node instances, placing their finest meshes.
+    placed: Vec<Instance>,
+    /// Levels of every mesh, with its object-space bounding sphere.
+    chains: Vec<(Vec<Lod>, BoundingSphere)>,
+    /// Mesh drawn for each of `placed`.
+    chosen: Vec<u32>,
+    /// Where `chosen` was chosen from; the finest meshes are drawn before a
+    /// view is given.
+    view: Option<LodView>,
+}
+
+/// The top-level hierarchy and the instances it holds.
+struct SceneInstances {
+    /// The top-level hierarchy over instances from node 0, followed by
+    /// `SceneGeometry::mesh_nodes`.
+    bvh_nodes: Buffer,
+    top_nodes: u32,
+    instances: Buffer,
+    /// Instances in `instances`, one per chunk of each placed mesh.
+    instance_count: u32,
+    /// A `GpuChunk` for every chunk.
+    chunks: Buffer,
+    /// Every placed chunk with its world-space bounds, for streaming by
+    /// camera nearness.
+    placed_bounds: Vec<(u32, Bounds)>,
+}
+
+/// Must match `SceneMaterial` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuMaterial {
+    base_color: [f32; 3],
+    metallic: f32,
+    emission: [f32; 3],
+    roughness: f32,
+    ior: f32,
+    /// One of the `SHADING_` codes in shader.wgsl.
+    kind: u32,
+    fuzz: f32,
+    /// One more than the slot of the base color texture (see
+    /// `texture_slots`), or 0 for none.
+    base_color_texture: u32,
+    /// Likewise for the normal map.
+    normal_texture: u32,
+    normal_scale: f32,
+    _pad: [u32; 2],
+}
+
+/// Must match `Shape` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuShape {
+    /// Sphere center, quad corner, box minimum, a point on a plane or the
+    /// minimum of an SDF shape's bounds.
+    position: [f32; 3],
+    kind: u32,
+    /// Quad edge, box maximum, plane normal or SDF bounds maximum.
+    u: [f32; 3],
+    radius: f32,
+    /// Second quad edge, or the first SDF node and the node count.
+    v: [f32; 3],
+    /// Fixed material code, or `MATERIAL_SCENE` plus a scene material index.
+    material: u32,
+}
+
+/// Must match `Instance` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuInstance {
+    world_to_object: [[f32; 4]; 3],
+    /// The placed chunk, an index into the chunk table.
+    chunk: u32,
+    /// `MATERIAL_SCENE` plus the material replacing the mesh's, or 0.
+    material: u32,
+    /// One of the `PRIMITIVE_` kinds held by the hierarchy.
+    primitive: u32,
+    /// `VISIBLE_` bits of the rays that see the instance.
+    visibility: u32,
+}
+
+/// Must match `Chunk` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuChunk {
+    min: [f32; 3],
+    /// Root node of the chunk's hierarchy, or `NOT_RESIDENT`.
+    root: u32,
+    max: [f32; 3],
+    /// Rays that reached the bounds, counted only while streaming.
+    traffic: u32,
+}
+
+/// Must match `Emitter` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuEmitter {
+    /// First corner of the triangle.
+    position: [f32; 3],
+    /// Scene material index.
+    material: u32,
+    /// Edges from the first corner to the other two.
+    e1: [f32; 3],
+    /// Chance of picking this emitter or one before it.
+    cdf: f32,
+    e2: [f32; 3],
+    /// Chance of picking this emitter, in proportion to its power.
+    probability: f32,
+}
+
+/// Must match `SdfNode` in shader.wgsl. Shapes are stored in postfix
+/// order: primitives push their distance and operations combine the top two.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuSdfNode {
+    center: [f32; 3],
+    kind: u32,
+    /// Sphere radius in x, box half size, torus major and minor radii, or
+    /// the smoothness of an operation in x.
+    size: [f32; 3],
+    _pad: u32,
+    /// Torus axis.
+    axis: [f32; 3],
+    _pad2: u32,
+}
+
+impl GpuSdfNode {
+    /// Appends `node` in postfix order.
+    fn flatten(node: &SdfNode, out: &mut Vec<GpuSdfNode>) {
+        let (kind, center, size, axis) = match node {
+            SdfNode::Primitive(SdfPrimitive::Sphere { center, radius }) => (0, *center, [*radius, 0.0, 0.0], [0.0; 3]),
+            SdfNode::Primitive(SdfPrimitive::Box { center, half_size }) => (1, *center, *half_size, [0.0; 3]),
+            SdfNode::Primitive(SdfPrimitive::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            }) => (2, *center, [*major_radius, *minor_radius, 0.0], *axis),
+            SdfNode::Operation { op, children, smoothness } => {
+                let kind = match op {
+                    SdfOp::Union => 3,
+                    SdfOp::Intersection => 4,
+                    SdfOp::Difference => 5,
+                };
+                for (i, child) in children.iter().enumerate() {
+                    GpuSdfNode::flatten(child, out);
+                    if i > 0 {
+                        out.push(GpuSdfNode {
+                            kind,
+                            size: [*smoothness, 0.0, 0.0],
+                            ..GpuSdfNode::zeroed()
+                        });
+                    }
+                }
+                return;
+            }
+        };
+        out.push(GpuSdfNode {
+            center,
+            kind,
+            size,
+            _pad: 0,
+            axis,
+            _pad2: 0,
+        });
+    }
 }
 
+/// Must match `Volume` in shader.wgsl.
 #[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
+struct GpuVolume {
+    world_to_grid: [[f32; 4]; 3],
+    size: [u32; 3],
+    /// Slice of `volume_densities` holding the grid's first z layer.
+    first_slice: u32,
+    albedo: [f32; 3],
+    majorant: f32,
+    density_scale: f32,
+    _pad: [u32; 3],
+}
+
+/// Must match `AreaLight` in shader.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct GpuLight {
+    position: [f32; 3],
+    shape: u32,
+    /// Rect edge, disc normal or tube axis.
+    u: [f32; 3],
+    radius: f32,
+    /// Second rect edge.
+    v: [f32; 3],
+    /// One more than the texture slot of the gobo, or 0 for none.
+    gobo: u32,
+    emission: [f32; 3],
+    _pad2: u32,
+}
+
+impl GpuLight {
+    fn new(light: &AreaLight, slots: &[u32]) -> Self {
+        let (shape, position, u, v, radius) = match light.shape {
+            LightShape::Rect { corner, u, v } => (0, corner, u, v, 0.0),
+            LightShape::Disc { center, normal, radius } => (1, center, normal, [0.0; 3], radius),
+            LightShape::Sphere { center, radius } => (2, center, [0.0; 3], [0.0; 3], radius),
+            LightShape::Tube { a, b, radius } => (3, a, [0, 1, 2].map(|i| b[i] - a[i]), [0.0; 3], radius),
+        };
+        GpuLight {
+            position,
+            shape,
+            u,
+            radius,
+            v,
+            gobo: slot(slots, light.gobo),
+            emission: light.emission,
+            _pad2: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[repr(C)]
 struct Uniforms {
     width: u32,
     height: u32,
     frame_count: u32,
-    _pad: u32, 
+    filter_type: u32,
+    filter_radius: f32,
+    cache_cell_size: f32,
+    preview_frames: u32,
+    light_count: u32,
     camera: CameraUniforms,
+    /// Direction towards the sun in xyz, irradiance in w (0 disables it).
+    sun: [f32; 4],
+    exposure: f32,
+    sky_radiance: f32,
+    instance_count: u32,
+    shape_count: u32,
+    /// Columns of the display white-balance matrix, padded to vec4.
+    white_balance: [[f32; 4]; 3],
+    sun_color: [f32; 3],
+    integrator: u32,
+    /// Occlusion ray length for the sky AO integrator.
+    ao_distance: f32,
+    /// Seed of the render, mixed into every pixel's random numbers.
+    seed: u32,
+    /// Animation frame being rendered, mixed in with `seed`.
+    animation_frame: u32,
+    volume_count: u32,
+    emitter_count: u32,
+    _pad: [u32; 3],
 }
 
 impl PathTracer {
-    pub fn new(device: Device, queue: Queue, width: u32, height: u32) -> Self {
+    pub fn new(
+        device: Device,
+        queue: Queue,
+        width: u32,
+        height: u32,
+        settings: &RenderSettings,
+        scene: &Scene,
+    ) -> anyhow::Result<Self> {
         device.on_uncaptured_error(Box::new(|err| {
             panic!("Unhandled error: {err}");
         }));
 
-        let shader_mod = compile_shader_module(&device);
-        let (display_pipeline, bind_group_layout) = create_display_pipeline(&device, &shader_mod);
+        let procedural_kinds = procedural::kinds(&scene.procedurals);
+        let shader_mod = compile_shader_module(&device, settings.geometry_budget.is_some(), &procedural_kinds)?;
+        let bind_group_layout = create_display_layout(&device);
+        let display_pipeline = create_display_pipeline(&device, &shader_mod, &bind_group_layout);
+        let procedural_kinds = procedural_kinds.iter().map(|kind| kind.name()).collect();
 
-        let uniforms = Uniforms {
+        let mut uniforms = Uniforms {
             camera: CameraUniforms::zeroed(),
             width,
             height,
             frame_count: 0,
-            _pad: 0,
+            filter_type: settings.filter as u32,
+            filter_radius: settings.filter_radius(),
+            cache_cell_size: settings.cache_cell_size,
+            preview_frames: if settings.irradiance_cache {
+                settings.preview_frames
+            } else {
+                0
+            },
+            light_count: scene.lights.len().min(MAX_LIGHTS) as u32,
+            sun: sun_uniform(settings.sun.as_ref()),
+            exposure: settings.exposure(),
+            sky_radiance: settings.sky_radiance,
+            instance_count: 0,
+            shape_count: 0,
+            white_balance: matrix_columns(&settings.white_balance()),
+            sun_color: sun_color(settings.sun.as_ref()),
+            integrator: settings.integrator as u32,
+            ao_distance: settings.ao_distance.unwrap_or(1e30),
+            seed: settings.seed,
+            animation_frame: settings.start_frame,
+            volume_count: 0,
+            emitter_count: 0,
+            _pad: [0; 3],
         };
 
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             mapped_at_creation: false,
             size: std::mem::size_of::<Uniforms>() as u64,
-            label: Some("uniform buffers"),
+            label: Some("uniforms"),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
         let indices: [u32; 6] = [0, 1, 2, 3, 4, 5];
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
+            label: Some("fullscreen vertices"),
             contents: bytemuck::cast_slice(&indices),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let radiance_samples = create_sample_texture(&device, width, height);
-    
+        let radiance_samples = create_sample_texture(&device, "radiance samples", width, height);
+        let odd_samples = create_sample_texture(&device, "odd samples", width, height);
+
+        // Four u32 per cell: fixed-point RGB sums and a sample count.
+        let irradiance_cache = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("irradiance cache"),
+            size: IRRADIANCE_CACHE_CELLS * 16,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Only written when the shader is built with the `debug-stats` feature.
+        let debug_stats = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debug stats"),
+            size: 12,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Distance along the central camera ray to the first hit, or -1.
+        let focus_probe = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("focus probe"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let texture_tools = TextureTools {
+            mip_generator: MipGenerator::new(&device),
+            resampler: Resampler::new(&device),
+        };
+        let scene_buffers = create_scene_buffers(
+            &device,
+            &queue,
+            &texture_tools,
+            scene,
+            settings.bvh_leaf_size,
+            settings.geometry_budget,
+        );
+        uniforms.instance_count = scene_buffers.instances.instance_count;
+        uniforms.shape_count = scene_buffers.shape_count;
+        uniforms.volume_count = scene_buffers.volume_count;
+        uniforms.emitter_count = scene_buffers.emitter_count;
+
         let display_bind_group = create_display_bindgroup(
             &device,
             &bind_group_layout,
             &radiance_samples,
+            &odd_samples,
             &uniform_buffer,
+            &irradiance_cache,
+            &debug_stats,
+            &focus_probe,
+            &scene_buffers,
         );
 
-        Self {
+        #[cfg(feature = "debug-stats")]
+        let stats_readback = Readback::new(&device, "debug stats readback");
+        let focus_readback = settings
+            .autofocus
+            .then(|| Readback::new(&device, "focus probe readback"));
+
+        Ok(Self {
             device,
             queue,
             uniforms,
             uniform_buffer,
             display_pipeline,
+            bind_group_layout,
             display_bind_group,
             vertex_buffer,
+            radiance_samples,
+            odd_samples,
+            irradiance_cache,
+            debug_stats,
+            #[cfg(feature = "debug-stats")]
+            stats_readback,
+            focus_probe,
+            focus_readback,
+            bvh_leaf_size: settings.bvh_leaf_size,
+            geometry_budget: settings.geometry_budget,
+            procedural_kinds,
+            texture_tools,
+            scene_buffers,
+            post: None,
+            integrators: Vec::new(),
+            custom_integrator: None,
+            #[cfg(feature = "renderdoc")]
+            capture_next: false,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.uniforms.width, self.uniforms.height)
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.uniforms.frame_count
+    }
+
+    /// Scale applied to radiance before the display transform.
+    pub fn exposure(&self) -> f32 {
+        self.uniforms.exposure
+    }
+
+    /// The uniform block as last uploaded, for bug reports.
+    pub fn uniforms_report(&self) -> String {
+        format!("{:#?}", self.uniforms)
+    }
+
+    /// White-balance matrix applied to exposed radiance before tone mapping.
+    pub fn white_balance(&self) -> Mat3 {
+        let [c0, c1, c2] = self.uniforms.white_balance;
+        [0, 1, 2].map(|row| [c0[row], c1[row], c2[row]])
+    }
+
+    /// Copies the accumulation texture back to the CPU and returns the mean
+    /// linear radiance of every pixel, row by row.
+    pub fn read_radiance(&self) -> Vec<[f32; 3]> {
+        let scale = (self.uniforms.frame_count.max(1) as f32).recip();
+        self.read_sums(&self.radiance_samples)
+            .into_iter()
+            .map(|sum| sum.map(|c| c * scale))
+            .collect()
+    }
+
+    /// Reads back the mean radiance of all samples and of the odd and even
+    /// halves separately. Needs at least two samples so neither half is empty.
+    pub fn read_split_radiance(&self) -> Option<SplitRadiance> {
+        let n = self.uniforms.frame_count;
+        if n < 2 {
+            return None;
+        }
+        let full = self.read_sums(&self.radiance_samples);
+        let odd = self.read_sums(&self.odd_samples);
+        let odd_scale = (n.div_ceil(2) as f32).recip();
+        let even_scale = ((n / 2) as f32).recip();
+        let (width, height) = self.size();
+        Some(SplitRadiance {
+            width,
+            height,
+            even: full
+                .iter()
+                .zip(&odd)
+                .map(|(f, o)| [0, 1, 2].map(|i| (f[i] - o[i]) * even_scale))
+                .collect(),
+            odd: odd.iter().map(|o| o.map(|c| c * odd_scale)).collect(),
+            full: full.iter().map(|f| f.map(|c| c / n as f32)).collect(),
+        })
+    }
+
+    /// Raw RGB sums of an accumulation texture, row by row.
+    fn read_sums(&self, texture: &Texture) -> Vec<[f32; 3]> {
+        let (width, height) = self.size();
+        let unpadded_row = width * 16;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(align) * align;
+
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("radiance readback"),
+            size: (padded_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("read radiance"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let data = slice.get_mapped_range();
+        data.chunks_exact(padded_row as usize)
+            .flat_map(|row| row[..unpadded_row as usize].chunks_exact(16))
+            .map(|texel| {
+                let channel = |i: usize| f32::from_ne_bytes(texel[i * 4..i * 4 + 4].try_into().unwrap());
+                [channel(0), channel(1), channel(2)]
+            })
+            .collect()
+    }
+
+    /// Runs the passes of `registry` named by `names` on every displayed
+    /// frame, in order, in place of any set before. No names turns post
+    /// processing off.
+    pub fn set_post_passes(&mut self, registry: &PostRegistry, names: &[String]) -> anyhow::Result<()> {
+        let size = [self.uniforms.width, self.uniforms.height];
+        self.post = PostStack::new(&self.device, registry, names, size)?;
+        Ok(())
+    }
+
+    /// Adds the integrator `create` makes for this renderer under `name`,
+    /// for [`set_integrator`](Self::set_integrator) to switch to.
+    pub fn register_integrator(
+        &mut self,
+        name: &str,
+        create: impl FnOnce(&IntegratorSetup) -> anyhow::Result<Box<dyn CustomIntegrator>>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.integrator_names().contains(&name), "integrator `{name}` already exists");
+        let setup = IntegratorSetup {
+            device: &self.device,
+            layout: &self.bind_group_layout,
+            width: self.uniforms.width,
+            height: self.uniforms.height,
+        };
+        let integrator = create(&setup)?;
+        self.integrators.push((name.to_string(), integrator));
+        Ok(())
+    }
+
+    /// Names [`set_integrator`](Self::set_integrator) takes: the built-in
+    /// integrators, then the registered ones.
+    pub fn integrator_names(&self) -> Vec<&str> {
+        let builtins = Integrator::ALL.iter().map(|integrator| integrator.name());
+        builtins.chain(self.integrators.iter().map(|(name, _)| name.as_str())).collect()
+    }
+
+    pub fn integrator_name(&self) -> &str {
+        match self.custom_integrator {
+            Some(i) => &self.integrators[i].0,
+            None => Integrator::ALL[self.uniforms.integrator as usize].name(),
+        }
+    }
+
+    /// Switches to the integrator named `name` and restarts accumulation.
+    pub fn set_integrator(&mut self, name: &str) -> anyhow::Result<()> {
+        if let Some(integrator) = Integrator::ALL.into_iter().find(|integrator| integrator.name() == name) {
+            self.uniforms.integrator = integrator as u32;
+            self.custom_integrator = None;
+        } else {
+            let i = self
+                .integrators
+                .iter()
+                .position(|(existing, _)| existing == name)
+                .ok_or_else(|| anyhow::anyhow!("unknown integrator `{name}`"))?;
+            self.custom_integrator = Some(i);
+        }
+        self.reset_samples();
+        Ok(())
+    }
+
+    pub fn set_sun(&mut self, sun: Option<&Sun>) {
+        self.uniforms.sun = sun_uniform(sun);
+        self.uniforms.sun_color = sun_color(sun);
+        self.reset_samples();
+    }
+
+    /// Replaces the scene, for example with a procedural one generated again
+    /// with new parameters, and restarts accumulation.
+    pub fn set_scene(&mut self, scene: &Scene) -> anyhow::Result<()> {
+        self.update_scene(scene, SceneChanges::ALL)
+    }
+
+    /// Uploads again the parts of `scene` named by `changes`, after it was
+    /// edited in place, and restarts accumulation. Buffers of the other parts
+    /// are kept, so moving an instance, for example, rebuilds only the top
+    /// level of the hierarchy. Fails, changing nothing, if the WGSL of a new
+    /// kind of procedural primitive does not compile.
+    pub fn update_scene(&mut self, scene: &Scene, changes: SceneChanges) -> anyhow::Result<()> {
+        let (device, queue) = (&self.device, &self.queue);
+        // New kinds of procedural primitive need their functions in the
+        // shader.
+        let kinds = procedural::kinds(&scene.procedurals);
+        if changes.shapes && !kinds.iter().map(|kind| kind.name()).eq(self.procedural_kinds.iter().copied()) {
+            let shader_mod = compile_shader_module(device, self.geometry_budget.is_some(), &kinds)?;
+            self.display_pipeline = create_display_pipeline(device, &shader_mod, &self.bind_group_layout);
+            self.procedural_kinds = kinds.iter().map(|kind| kind.name()).collect();
+        }
+        let buffers = &mut self.scene_buffers;
+        if changes.geometry {
+            buffers.geometry = create_geometry(device, scene, self.bvh_leaf_size, self.geometry_budget);
+        }
+        if changes.geometry || changes.instances {
+            let view = buffers.lods.as_ref().and_then(|lods| lods.view);
+            buffers.lods = LodSelection::new(scene, view);
+            let placed = placed_instances(scene, buffers.lods.as_ref());
+            buffers.instances = create_instances(device, &placed, &buffers.geometry, self.bvh_leaf_size);
+        }
+        // Materials and lights name textures by where they were placed.
+        if changes.materials || changes.textures {
+            buffers.materials = create_materials(device, scene);
+        }
+        if changes.geometry || changes.instances || changes.materials {
+            (buffers.emitters, buffers.emitter_count) = create_emitters(device, scene);
+        }
+        if changes.shapes {
+            (buffers.shapes, buffers.shape_count, buffers.sdf_nodes) = create_shapes(device, scene);
+        }
+        if changes.lights || changes.textures {
+            buffers.lights = create_lights(device, scene);
+        }
+        if changes.volumes {
+            (buffers.volumes, buffers.volume_count, buffers.volume_densities) = create_volumes(device, queue, scene);
+        }
+        // A texture that becomes or stops being a normal map is prepared
+        // again as data or as colors.
+        if changes.textures || (changes.materials && data_textures(scene) != buffers.textures.data_indices) {
+            buffers.textures = create_textures(device, queue, &self.texture_tools, scene);
         }
+        self.uniforms.light_count = scene.lights.len().min(MAX_LIGHTS) as u32;
+        self.uniforms.instance_count = buffers.instances.instance_count;
+        self.uniforms.shape_count = buffers.shape_count;
+        self.uniforms.volume_count = buffers.volume_count;
+        self.uniforms.emitter_count = buffers.emitter_count;
+        self.rebind_scene();
+        self.reset_samples();
+        Ok(())
+    }
+
+    /// Binds the scene buffers again after some were replaced.
+    fn rebind_scene(&mut self) {
+        self.display_bind_group = create_display_bindgroup(
+            &self.device,
+            &self.bind_group_layout,
+            &self.radiance_samples,
+            &self.odd_samples,
+            &self.uniform_buffer,
+            &self.irradiance_cache,
+            &self.debug_stats,
+            &self.focus_probe,
+            &self.scene_buffers,
+        );
     }
 
     pub fn reset_samples(&mut self) {
         self.uniforms.frame_count = 0;
     }
 
+    /// Sets the number of the animation frame being rendered, which seeds
+    /// its noise together with the job's seed. The same frame of the same
+    /// job renders the same noise however many restarts came before it.
+    pub fn set_animation_frame(&mut self, frame: u32) {
+        if self.uniforms.animation_frame != frame {
+            self.uniforms.animation_frame = frame;
+            self.reset_samples();
+        }
+    }
+
+    /// Records the next rendered frame with RenderDoc, if the application was
+    /// launched from it or has it injected.
+    #[cfg(feature = "renderdoc")]
+    pub fn capture_next_frame(&mut self) {
+        self.capture_next = true;
+    }
+
     pub fn render_frame(&mut self, target: &TextureView, camera: &Camera) {
+        #[cfg(feature = "renderdoc")]
+        let capture = std::mem::take(&mut self.capture_next);
+        #[cfg(feature = "renderdoc")]
+        if capture {
+            self.device.start_capture();
+        }
+
+        self.choose_lods(camera);
+        self.stream_geometry(camera);
         self.uniforms.frame_count += 1;
         self.uniforms.camera = camera.get_uniforms(); 
         
@@ -95,11 +903,29 @@ impl PathTracer {
             label: Some("render frame"),
         });
 
-        {
+        if self.uniforms.frame_count == 1 && self.uniforms.preview_frames > 0 {
+            encoder.clear_buffer(&self.irradiance_cache, 0, None);
+        }
+        #[cfg(feature = "debug-stats")]
+        encoder.clear_buffer(&self.debug_stats, 0, None);
+
+        let display_target = self.post.as_ref().map_or(target, PostStack::input);
+        if let Some(i) = self.custom_integrator {
+            let frame = IntegratorFrame {
+                device: &self.device,
+                queue: &self.queue,
+                scene: &self.display_bind_group,
+                camera,
+                frame_count: self.uniforms.frame_count,
+                width: self.uniforms.width,
+                height: self.uniforms.height,
+            };
+            self.integrators[i].1.encode(&frame, &mut encoder, display_target);
+        } else {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render pass"),
+                label: Some("display pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: target,
+                    view: display_target,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -116,21 +942,181 @@ impl PathTracer {
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             render_pass.draw(0..6, 0..1);
         }
+        if let Some(post) = &mut self.post {
+            post.encode(&self.device, &self.queue, &mut encoder, target, self.uniforms.frame_count);
+        }
+
+        #[cfg(feature = "debug-stats")]
+        self.stats_readback.copy(&mut encoder, &self.debug_stats);
+        if let Some(readback) = &mut self.focus_readback {
+            readback.copy(&mut encoder, &self.focus_probe);
+        }
+        if let Some(stream) = &mut self.scene_buffers.geometry.stream {
+            stream.readback.copy(&mut encoder, &self.scene_buffers.instances.chunks);
+        }
 
         self.queue.submit(Some(encoder.finish()));
+
+        #[cfg(feature = "renderdoc")]
+        if capture {
+            self.device.stop_capture();
+        }
+
+        #[cfg(feature = "debug-stats")]
+        self.stats_readback.map();
+        if let Some(readback) = &mut self.focus_readback {
+            readback.map();
+        }
+        if let Some(stream) = &mut self.scene_buffers.geometry.stream {
+            stream.readback.map();
+        }
+    }
+
+    /// Chooses the levels of detail of placed meshes again for `camera`, and
+    /// rebuilds the top level of the hierarchy and restarts accumulation if
+    /// any changed.
+    fn choose_lods(&mut self, camera: &Camera) {
+        let Some(lods) = &mut self.scene_buffers.lods else {
+            return;
+        };
+        let eye = camera.lookfrom;
+        let view = LodView {
+            eye: [eye.x(), eye.y(), eye.z()],
+            vfov: camera.vfov,
+        };
+        if !lods.choose(view) {
+            return;
+        }
+        let placed = lods.instances();
+        let buffers = &mut self.scene_buffers;
+        buffers.instances = create_instances(&self.device, &placed, &buffers.geometry, self.bvh_leaf_size);
+        self.uniforms.instance_count = buffers.instances.instance_count;
+        self.rebind_scene();
+        self.reset_samples();
+    }
+
+    /// Pages streamed chunks in and out following the ray counts of the
+    /// latest readback and the nearness of `camera`, and restarts
+    /// accumulation if any moved.
+    fn stream_geometry(&mut self, camera: &Camera) {
+        let SceneBuffers { geometry, instances, .. } = &mut self.scene_buffers;
+        let Some(stream) = &mut geometry.stream else {
+            return;
+        };
+        let Some(chunks) = stream.readback.poll_fresh(&self.device) else {
+            return;
+        };
+        let counts: Vec<u32> = chunks.iter().map(|chunk| chunk.traffic).collect();
+        let eye = camera.lookfrom;
+        let eye = [eye.x(), eye.y(), eye.z()];
+        // A chunk placed more than once is as near as its nearest placement.
+        let mut nearness = vec![0.0; stream.chunks.len()];
+        for &(chunk, bounds) in &instances.placed_bounds {
+            if let Some(near) = nearness.get_mut(chunk as usize) {
+                *near = streaming::nearness(eye, bounds).max(*near);
+            }
+        }
+        let paging = stream.residency.update(&counts, &nearness);
+        if paging.paged_in.is_empty() && paging.paged_out.is_empty() {
+            return;
+        }
+        let queue = &self.queue;
+        let top = instances.top_nodes;
+        let write_root = |chunk: usize, root: u32| {
+            let offset = (chunk * std::mem::size_of::<GpuChunk>() + std::mem::offset_of!(GpuChunk, root)) as u64;
+            queue.write_buffer(&instances.chunks, offset, bytemuck::bytes_of(&root));
+        };
+        for chunk in paging.paged_out {
+            let proxy = stream.proxies[chunk];
+            geometry.roots[chunk].0 = proxy;
+            write_root(chunk, if proxy == NOT_RESIDENT { proxy } else { top + proxy });
+        }
+        for chunk in paging.paged_in {
+            let slot = stream.residency.slot(chunk).unwrap();
+            let first: [u32; POOLS] = std::array::from_fn(|p| stream.pool_base[p] + slot[p]);
+            let data = &stream.chunks[chunk];
+            let (records, nodes) = data.placed(first);
+            let at = |index: u32, size: usize| index as u64 * size as u64;
+            queue.write_buffer(&geometry.vertices, at(first[0], 16), bytemuck::cast_slice(&data.vertices));
+            queue.write_buffer(&geometry.normals, at(first[0], 16), bytemuck::cast_slice(&data.normals));
+            queue.write_buffer(&geometry.uvs, at(first[0], 8), bytemuck::cast_slice(&data.uvs));
+            queue.write_buffer(&geometry.tangents, at(first[0], 16), bytemuck::cast_slice(&data.tangents));
+            queue.write_buffer(&geometry.triangles, at(first[1], 16), bytemuck::cast_slice(&records));
+            let gpu_nodes: Vec<BvhNode> = nodes
+                .iter()
+                .map(|node| BvhNode {
+                    first: node.first + if node.count > 0 { 0 } else { top },
+                    ..*node
+                })
+                .collect();
+            queue.write_buffer(
+                &instances.bvh_nodes,
+                at(top + first[2], std::mem::size_of::<BvhNode>()),
+                bytemuck::cast_slice(&gpu_nodes),
+            );
+            geometry.mesh_nodes[first[2] as usize..][..nodes.len()].copy_from_slice(&nodes);
+            geometry.roots[chunk].0 = first[2];
+            write_root(chunk, top + first[2]);
+        }
+        self.reset_samples();
+    }
+
+    /// Latest depth of the surfaces around the center of the screen, a
+    /// center-weighted mean over a few probes, if autofocus is enabled and
+    /// any probe hit anything.
+    pub fn focus_probe(&mut self) -> Option<f32> {
+        let readback = self.focus_readback.as_mut()?;
+        readback.poll(&self.device).filter(|&distance| distance > 0.0)
+    }
+
+    /// Counters of the most recent frame whose readback has completed.
+    #[cfg(feature = "debug-stats")]
+    pub fn stats(&mut self) -> Option<TraversalStats> {
+        self.stats_readback.poll(&self.device)
     }
 }
 
 
+fn sun_uniform(sun: Option<&Sun>) -> [f32; 4] {
+    let Some(sun) = sun else {
+        return [0.0; 4];
+    };
+    let dir = sun.direction();
+    // Below the horizon the sun contributes nothing.
+    let irradiance = if dir.y() > 0.0 { sun.irradiance } else { 0.0 };
+    [dir.x(), dir.y(), dir.z(), irradiance]
+}
+
+fn sun_color(sun: Option<&Sun>) -> [f32; 3] {
+    sun.map_or([1.0; 3], |sun| sun.color)
+}
+
+fn matrix_columns(m: &Mat3) -> [[f32; 4]; 3] {
+    [0, 1, 2].map(|col| [m[0][col], m[1][col], m[2][col], 0.0])
+}
+
+#[allow(clippy::too_many_arguments)]
 fn create_display_bindgroup(
     device: &Device,
     layout: &BindGroupLayout,
     texture: &Texture,
+    odd_texture: &Texture,
     uniform_buffer: &Buffer,
+    irradiance_cache: &Buffer,
+    debug_stats: &Buffer,
+    focus_probe: &Buffer,
+    scene: &SceneBuffers,
 ) -> BindGroup {
-    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("radiance samples view"),
+        ..Default::default()
+    });
+    let odd_view = odd_texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("odd samples view"),
+        ..Default::default()
+    });
     device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("bind groups"),
+        label: Some("display bind group"),
         layout,
         entries: &[
             wgpu::BindGroupEntry {
@@ -145,38 +1131,1381 @@ fn create_display_bindgroup(
                 binding: 1,
                 resource: wgpu::BindingResource::TextureView(&view),
             },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: irradiance_cache.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: debug_stats.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&odd_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: focus_probe.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: scene.geometry.vertices.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: scene.geometry.triangles.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: scene.materials.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: scene.geometry.normals.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: scene.instances.bvh_nodes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: scene.lights.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: scene.shapes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.color[0]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::Sampler(&scene.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: scene.instances.instances.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: scene.sdf_nodes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 17,
+                resource: scene.volumes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 18,
+                resource: wgpu::BindingResource::TextureView(&scene.volume_densities),
+            },
+            wgpu::BindGroupEntry {
+                binding: 19,
+                resource: scene.instances.chunks.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 20,
+                resource: scene.geometry.tangents.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 21,
+                resource: scene.emitters.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 22,
+                resource: scene.geometry.uvs.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 23,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.color[1]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 24,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.color[2]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 25,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.color[3]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 26,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.color[4]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 27,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.data[0]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 28,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.data[1]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 29,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.data[2]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 30,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.data[3]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 31,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.data[4]),
+            },
         ],
     })
 }
 
-fn create_sample_texture(device: &Device, width: u32, height: u32) -> Texture {
-    let desc = wgpu::TextureDescriptor {
-        label: Some("radiance samples"),
-        format: wgpu::TextureFormat::Rgba32Float,
-        size: wgpu::Extent3d {
+fn visibility_mask(visibility: Visibility) -> u32 {
+    [
+        (visibility.camera, VISIBLE_CAMERA),
+        (visibility.shadow, VISIBLE_SHADOW),
+        (visibility.diffuse, VISIBLE_DIFFUSE),
+        (visibility.specular, VISIBLE_SPECULAR),
+    ]
+    .into_iter()
+    .filter(|&(visible, _)| visible)
+    .fold(0, |mask, (_, bit)| mask | bit)
+}
+
+/// A linear vertex color as the `w` of its position: the square roots of
+/// its channels in 8 bits each, or -1 for none. Must match `vertex_color` in
+/// shader.wgsl.
+fn pack_color(color: Option<[f32; 3]>) -> f32 {
+    color.map_or(-1.0, |rgb| {
+        let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0).sqrt() * 255.0).round() as u32);
+        (r << 16 | g << 8 | b) as f32
+    })
+}
+
+/// Per-vertex arrays of a mesh, as in `Scene`.
+#[derive(Copy, Clone)]
+struct VertexArrays<'a> {
+    positions: &'a [[f32; 3]],
+    normals: &'a [[f32; 3]],
+    colors: &'a [Option<[f32; 3]>],
+    uvs: &'a [[f32; 2]],
+    tangents: &'a [[f32; 4]],
+}
+
+/// Axis-aligned `(min, max)` bounds.
+type Bounds = ([f32; 3], [f32; 3]);
+
+/// One hierarchy with the vertices and records it indexes, numbered from 0
+/// until the chunk is placed among the others.
+struct GeometryChunk {
+    vertices: Vec<[f32; 4]>,
+    normals: Vec<[f32; 4]>,
+    /// Zero for vertices of curves and splats, as are the tangents.
+    uvs: Vec<[f32; 2]>,
+    tangents: Vec<[f32; 4]>,
+    /// Triangles, or curve or splat records, in leaf order.
+    records: Vec<[u32; 4]>,
+    nodes: Vec<BvhNode>,
+    /// One of the `PRIMITIVE_` kinds.
+    primitive: u32,
+}
+
+impl GeometryChunk {
+    /// All of `triangles` with every vertex of the mesh.
+    fn triangles(mesh: VertexArrays, triangles: &[Triangle], bvh_leaf_size: usize) -> Self {
+        let bvh = Bvh::build(mesh.positions, triangles, bvh_leaf_size);
+        GeometryChunk {
+            vertices: mesh
+                .positions
+                .iter()
+                .enumerate()
+                .map(|(i, &[x, y, z])| [x, y, z, pack_color(mesh.colors.get(i).copied().flatten())])
+                .collect(),
+            normals: mesh.normals.iter().map(|&[x, y, z]| [x, y, z, 0.0]).collect(),
+            uvs: (0..mesh.positions.len())
+                .map(|i| mesh.uvs.get(i).copied().unwrap_or_default())
+                .collect(),
+            tangents: (0..mesh.positions.len())
+                .map(|i| mesh.tangents.get(i).copied().unwrap_or_default())
+                .collect(),
+            records: triangle_records(&bvh, triangles),
+            nodes: bvh.nodes,
+            primitive: PRIMITIVE_TRIANGLES,
+        }
+    }
+
+    /// A coarse stand-in for a triangle chunk, drawn while the chunk is not
+    /// resident. Vertices within one of `PROXY_CELLS` cells along the
+    /// longest side of the bounds merge into their average, taking the
+    /// color, texture coordinates and tangent of the first, and triangles
+    /// left without area are dropped. `None` if none are left.
+    fn proxy(&self, bvh_leaf_size: usize) -> Option<GeometryChunk> {
+        let (min, max) = self.bounds();
+        let cell = (0..3).map(|i| max[i] - min[i]).fold(0.0, f32::max) / PROXY_CELLS as f32;
+        if cell <= 0.0 {
+            return None;
+        }
+        let mut cells: HashMap<[u32; 3], u32> = HashMap::new();
+        // Position and normal sums and vertex count of each merged vertex.
+        let mut sums: Vec<([f32; 3], [f32; 3], u32)> = Vec::new();
+        let mut vertices: Vec<[f32; 4]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut tangents: Vec<[f32; 4]> = Vec::new();
+        let merged: Vec<u32> = self
+            .vertices
+            .iter()
+            .zip(&self.normals)
+            .zip(self.uvs.iter().zip(&self.tangents))
+            .map(|((&[x, y, z, color], &[nx, ny, nz, _]), (&uv, &tangent))| {
+                let key = [x - min[0], y - min[1], z - min[2]].map(|d| ((d / cell) as u32).min(PROXY_CELLS - 1));
+                let i = *cells.entry(key).or_insert_with(|| {
+                    sums.push(([0.0; 3], [0.0; 3], 0));
+                    vertices.push([0.0, 0.0, 0.0, color]);
+                    uvs.push(uv);
+                    tangents.push(tangent);
+                    sums.len() as u32 - 1
+                });
+                let (position, normal, count) = &mut sums[i as usize];
+                *position = [position[0] + x, position[1] + y, position[2] + z];
+                *normal = [normal[0] + nx, normal[1] + ny, normal[2] + nz];
+                *count += 1;
+                i
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        let triangles: Vec<Triangle> = self
+            .records
+            .iter()
+            .filter_map(|&[a, b, c, material]| {
+                let indices = [a, b, c].map(|v| merged[v as usize]);
+                let [a, b, c] = indices;
+                let mut key = indices;
+                key.sort_unstable();
+                (a != b && b != c && c != a && seen.insert((key, material))).then_some(Triangle { indices, material })
+            })
+            .collect();
+        if triangles.is_empty() {
+            return None;
+        }
+        let mut normals = Vec::with_capacity(sums.len());
+        for (vertex, &(position, normal, count)) in vertices.iter_mut().zip(&sums) {
+            let [x, y, z] = position.map(|p| p / count as f32);
+            *vertex = [x, y, z, vertex[3]];
+            // Vertices of flat-shaded triangles keep a zero normal.
+            let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+            let [nx, ny, nz] = normal.map(|n| if length > 0.0 { n / length } else { 0.0 });
+            normals.push([nx, ny, nz, 0.0]);
+        }
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|&[x, y, z, _]| [x, y, z]).collect();
+        let bvh = Bvh::build(&positions, &triangles, bvh_leaf_size);
+        Some(GeometryChunk {
+            vertices,
+            normals,
+            uvs,
+            tangents,
+            records: triangle_records(&bvh, &triangles),
+            nodes: bvh.nodes,
+            primitive: PRIMITIVE_TRIANGLES,
+        })
+    }
+
+    /// A hierarchy over `boxes`, with `record` pushing the vertices of each
+    /// box in leaf order and returning its record.
+    fn primitives(
+        boxes: &[Bounds],
+        primitive: u32,
+        bvh_leaf_size: usize,
+        mut record: impl FnMut(u32, &mut Vec<[f32; 4]>, &mut Vec<[f32; 4]>) -> [u32; 4],
+    ) -> Self {
+        let bvh = Bvh::over_boxes(boxes, bvh_leaf_size);
+        let (mut vertices, mut normals) = (Vec::new(), Vec::new());
+        let records = bvh.order.iter().map(|&i| record(i, &mut vertices, &mut normals)).collect();
+        GeometryChunk {
+            uvs: vec![[0.0; 2]; vertices.len()],
+            tangents: vec![[0.0; 4]; vertices.len()],
+            vertices,
+            normals,
+            records,
+            nodes: bvh.nodes,
+            primitive,
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        (self.nodes[0].min, self.nodes[0].max)
+    }
+
+    /// Vertices, records and nodes, the elements the chunk takes in each
+    /// streaming pool.
+    fn pool_sizes(&self) -> [u32; POOLS] {
+        [self.vertices.len(), self.records.len(), self.nodes.len()].map(|len| len as u32)
+    }
+
+    /// Records and nodes with the offsets that hold once the chunk's first
+    /// vertex, record and node are at `first`.
+    fn placed(&self, first: [u32; POOLS]) -> (Vec<[u32; 4]>, Vec<BvhNode>) {
+        let [vertex_base, record_base, node_base] = first;
+        let records = self
+            .records
+            .iter()
+            .map(|&[a, b, c, d]| match self.primitive {
+                PRIMITIVE_TRIANGLES => [a + vertex_base, b + vertex_base, c + vertex_base, d],
+                _ => [a + vertex_base, b, c, d],
+            })
+            .collect();
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| BvhNode {
+                first: node.first + if node.count > 0 { record_base } else { node_base },
+                ..*node
+            })
+            .collect();
+        (records, nodes)
+    }
+}
+
+/// Records of `triangles`, uploaded in the leaf order of `bvh`.
+fn triangle_records(bvh: &Bvh, triangles: &[Triangle]) -> Vec<[u32; 4]> {
+    bvh.order
+        .iter()
+        .map(|&i| {
+            let tri = &triangles[i as usize];
+            let [a, b, c] = tri.indices;
+            [a, b, c, tri.material]
+        })
+        .collect()
+}
+
+/// Cuts a mesh into spatially compact chunks of at most `max` triangles, each
+/// with its own copy of the vertices it uses.
+fn split_triangles(
+    mesh: VertexArrays,
+    triangles: &[Triangle],
+    max: usize,
+    bvh_leaf_size: usize,
+    chunks: &mut Vec<GeometryChunk>,
+) {
+    let bvh = Bvh::build(mesh.positions, triangles, bvh_leaf_size);
+    // Index of each mesh vertex within the chunk being cut, if it uses it.
+    let mut local = vec![u32::MAX; mesh.positions.len()];
+    for run in bvh.subtree_runs(max) {
+        let mut used: Vec<u32> = Vec::new();
+        let chunk_triangles: Vec<Triangle> = bvh.order[run]
+            .iter()
+            .map(|&i| {
+                let tri = triangles[i as usize];
+                let indices = tri.indices.map(|v| {
+                    if local[v as usize] == u32::MAX {
+                        local[v as usize] = used.len() as u32;
+                        used.push(v);
+                    }
+                    local[v as usize]
+                });
+                Triangle { indices, ..tri }
+            })
+            .collect();
+        let pick = |values: &[[f32; 3]]| -> Vec<[f32; 3]> { used.iter().map(|&v| values[v as usize]).collect() };
+        let colors: Vec<_> = used.iter().map(|&v| mesh.colors.get(v as usize).copied().flatten()).collect();
+        let uvs: Vec<_> = used.iter().map(|&v| mesh.uvs.get(v as usize).copied().unwrap_or_default()).collect();
+        let tangents: Vec<_> = used
+            .iter()
+            .map(|&v| mesh.tangents.get(v as usize).copied().unwrap_or_default())
+            .collect();
+        let chunk_mesh = VertexArrays {
+            positions: &pick(mesh.positions),
+            normals: &pick(mesh.normals),
+            colors: &colors,
+            uvs: &uvs,
+            tangents: &tangents,
+        };
+        chunks.push(GeometryChunk::triangles(chunk_mesh, &chunk_triangles, bvh_leaf_size));
+        for v in used {
+            local[v as usize] = u32::MAX;
+        }
+    }
+}
+
+fn create_scene_buffers(
+    device: &Device,
+    queue: &Queue,
+    texture_tools: &TextureTools,
+    scene: &Scene,
+    bvh_leaf_size: usize,
+    geometry_budget: Option<u64>,
+) -> SceneBuffers {
+    let geometry = create_geometry(device, scene, bvh_leaf_size, geometry_budget);
+    let lods = LodSelection::new(scene, None);
+    let instances = create_instances(device, &placed_instances(scene, lods.as_ref()), &geometry, bvh_leaf_size);
+    let (shapes, shape_count, sdf_nodes) = create_shapes(device, scene);
+    let (volumes, volume_count, volume_densities) = create_volumes(device, queue, scene);
+    let (emitters, emitter_count) = create_emitters(device, scene);
+    SceneBuffers {
+        geometry,
+        materials: create_materials(device, scene),
+        instances,
+        lods,
+        shapes,
+        shape_count,
+        sdf_nodes,
+        lights: create_lights(device, scene),
+        emitters,
+        emitter_count,
+        volumes,
+        volume_count,
+        volume_densities,
+        textures: create_textures(device, queue, texture_tools, scene),
+        sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("scene texture sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }),
+    }
+}
+
+fn storage(device: &Device, label: &str, contents: &[u8]) -> Buffer {
+    storage_with(device, label, contents, wgpu::BufferUsages::empty())
+}
+
+/// A storage buffer with `usage` as well.
+fn storage_with(device: &Device, label: &str, contents: &[u8], usage: wgpu::BufferUsages) -> Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents,
+        usage: wgpu::BufferUsages::STORAGE | usage,
+    })
+}
+
+/// Cuts the scene's meshes, world-space triangles, curves and splats into
+/// chunks and uploads them. With a `geometry_budget`, triangle meshes are cut
+/// into chunks of at most `STREAM_CHUNK_TRIANGLES`, of which as many are
+/// uploaded as fit the budget.
+fn create_geometry(device: &Device, scene: &Scene, bvh_leaf_size: usize, geometry_budget: Option<u64>) -> SceneGeometry {
+    // The world-space triangles are drawn as one more instance, of a mesh
+    // that shares the scene's vertex arrays.
+    let meshes = scene
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let arrays = VertexArrays {
+                positions: &mesh.positions,
+                normals: &mesh.normals,
+                colors: &mesh.colors,
+                uvs: &mesh.uvs,
+                tangents: &mesh.tangents,
+            };
+            (arrays, &mesh.triangles[..])
+        })
+        .chain([(
+            VertexArrays {
+                positions: &scene.positions,
+                normals: &scene.normals,
+                colors: &scene.colors,
+                uvs: &scene.uvs,
+                tangents: &scene.tangents,
+            },
+            &scene.triangles[..],
+        )]);
+    let mut chunks = Vec::new();
+    let mut mesh_chunks = Vec::new();
+    for (arrays, triangles) in meshes {
+        let first = chunks.len() as u32;
+        match geometry_budget {
+            _ if triangles.is_empty() => (),
+            Some(_) if triangles.len() > STREAM_CHUNK_TRIANGLES => {
+                split_triangles(arrays, triangles, STREAM_CHUNK_TRIANGLES, bvh_leaf_size, &mut chunks)
+            }
+            _ => chunks.push(GeometryChunk::triangles(arrays, triangles, bvh_leaf_size)),
+        }
+        mesh_chunks.push(first..chunks.len() as u32);
+    }
+    let mut placements = vec![Instance {
+        mesh: scene.meshes.len() as u32,
+        transform: IDENTITY,
+        material: None,
+        visibility: Visibility::ALL,
+    }];
+    let streamed = if geometry_budget.is_some() { chunks.len() } else { 0 };
+
+    // Curves and splats are one more chunk each, whose records sit among the
+    // triangles. Curve records are `[first control point, shape, 0,
+    // material]`, with the control points among the vertices and the radius
+    // in w. Splat records are `[first vertex, shape, has color, material]`,
+    // with the center and radius in one vertex, the color in the next and
+    // the normal alongside the first.
+    let mut push_placed = |chunk: GeometryChunk, chunks: &mut Vec<GeometryChunk>| {
+        chunks.push(chunk);
+        mesh_chunks.push(chunks.len() as u32 - 1..chunks.len() as u32);
+        placements.push(Instance {
+            mesh: mesh_chunks.len() as u32 - 1,
+            transform: IDENTITY,
+            material: None,
+            visibility: Visibility::ALL,
+        });
+    };
+    if !scene.curves.is_empty() {
+        let boxes: Vec<_> = scene.curves.iter().map(Curve::bounds).collect();
+        let chunk = GeometryChunk::primitives(&boxes, PRIMITIVE_CURVES, bvh_leaf_size, |i, vertices, normals| {
+            let curve = &scene.curves[i as usize];
+            let shape = match curve.shape {
+                CurveShape::Round => 0,
+                CurveShape::Ribbon => 1,
+            };
+            let record = [vertices.len() as u32, shape, 0, curve.material];
+            for (k, [x, y, z]) in curve.points.into_iter().enumerate() {
+                let t = k as f32 / 3.0;
+                vertices.push([x, y, z, curve.radius[0] * (1.0 - t) + curve.radius[1] * t]);
+                normals.push([0.0; 4]);
+            }
+            record
+        });
+        push_placed(chunk, &mut chunks);
+    }
+    if !scene.splats.is_empty() {
+        let boxes: Vec<_> = scene.splats.iter().map(Splat::bounds).collect();
+        let chunk = GeometryChunk::primitives(&boxes, PRIMITIVE_SPLATS, bvh_leaf_size, |i, vertices, normals| {
+            let splat = &scene.splats[i as usize];
+            let shape = match splat.shape {
+                SplatShape::Disk => 0,
+                SplatShape::Gaussian => 1,
+            };
+            let record = [vertices.len() as u32, shape, u32::from(splat.color.is_some()), splat.material];
+            let ([x, y, z], [r, g, b], [nx, ny, nz]) = (splat.center, splat.color.unwrap_or_default(), splat.normal);
+            vertices.extend([[x, y, z, splat.radius], [r, g, b, 0.0]]);
+            normals.extend([[nx, ny, nz, 0.0], [0.0; 4]]);
+            record
+        });
+        push_placed(chunk, &mut chunks);
+    }
+
+    // Chunks that stay resident come first in every array, followed by the
+    // pools that streamed chunks are paged into.
+    let mut vertices: Vec<[f32; 4]> = Vec::new();
+    let mut normals: Vec<[f32; 4]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut tangents: Vec<[f32; 4]> = Vec::new();
+    let mut triangles: Vec<[u32; 4]> = Vec::new();
+    let mut mesh_nodes: Vec<BvhNode> = Vec::new();
+    let mut roots: Vec<(u32, Bounds, u32)> = chunks
+        .iter()
+        .map(|chunk| (NOT_RESIDENT, chunk.bounds(), chunk.primitive))
+        .collect();
+    for (chunk, root) in chunks.iter().zip(&mut roots).skip(streamed) {
+        let (records, nodes) = chunk.placed([vertices.len(), triangles.len(), mesh_nodes.len()].map(|len| len as u32));
+        root.0 = mesh_nodes.len() as u32;
+        vertices.extend(&chunk.vertices);
+        normals.extend(&chunk.normals);
+        uvs.extend(&chunk.uvs);
+        tangents.extend(&chunk.tangents);
+        triangles.extend(records);
+        mesh_nodes.extend(nodes);
+    }
+    let stream = geometry_budget.map(|budget| {
+        chunks.truncate(streamed);
+        let proxies: Vec<u32> = chunks
+            .iter()
+            .zip(&mut roots)
+            .map(|(chunk, root)| {
+                let Some(proxy) = chunk.proxy(bvh_leaf_size) else {
+                    return NOT_RESIDENT;
+                };
+                let (records, nodes) =
+                    proxy.placed([vertices.len(), triangles.len(), mesh_nodes.len()].map(|len| len as u32));
+                root.0 = mesh_nodes.len() as u32;
+                vertices.extend(&proxy.vertices);
+                normals.extend(&proxy.normals);
+                uvs.extend(&proxy.uvs);
+                tangents.extend(&proxy.tangents);
+                triangles.extend(records);
+                mesh_nodes.extend(nodes);
+                root.0
+            })
+            .collect();
+        let capacity = pool_capacity(&chunks, budget);
+        let residency = Residency::new(chunks.iter().map(GeometryChunk::pool_sizes).collect(), capacity);
+        if residency.resident_count() == 0 && !chunks.is_empty() {
+            crash::log!("warning: the geometry budget is too small to hold any mesh chunk");
+        }
+        let pool_base = [vertices.len(), triangles.len(), mesh_nodes.len()].map(|len| len as u32);
+        vertices.resize(vertices.len() + capacity[0] as usize, [0.0; 4]);
+        normals.resize(normals.len() + capacity[0] as usize, [0.0; 4]);
+        uvs.resize(uvs.len() + capacity[0] as usize, [0.0; 2]);
+        tangents.resize(tangents.len() + capacity[0] as usize, [0.0; 4]);
+        triangles.resize(triangles.len() + capacity[1] as usize, [0; 4]);
+        mesh_nodes.resize(mesh_nodes.len() + capacity[2] as usize, BvhNode::zeroed());
+        for (i, chunk) in chunks.iter().enumerate() {
+            let Some(slot) = residency.slot(i) else { continue };
+            let first: [u32; POOLS] = std::array::from_fn(|p| pool_base[p] + slot[p]);
+            let (records, nodes) = chunk.placed(first);
+            let [vertex, record, node] = first.map(|first| first as usize);
+            vertices[vertex..][..chunk.vertices.len()].copy_from_slice(&chunk.vertices);
+            normals[vertex..][..chunk.normals.len()].copy_from_slice(&chunk.normals);
+            uvs[vertex..][..chunk.uvs.len()].copy_from_slice(&chunk.uvs);
+            tangents[vertex..][..chunk.tangents.len()].copy_from_slice(&chunk.tangents);
+            triangles[record..][..records.len()].copy_from_slice(&records);
+            mesh_nodes[node..][..nodes.len()].copy_from_slice(&nodes);
+            roots[i].0 = first[2];
+        }
+        GeometryStream {
+            readback: Readback::with_len(device, "chunk readback", chunks.len().max(1)),
+            chunks,
+            residency,
+            proxies,
+            pool_base,
+        }
+    });
+
+    // Storage bindings cannot be empty, so an empty scene gets one dummy element
+    // of each; `instance_count` keeps the shader from reading it.
+    if vertices.is_empty() {
+        vertices.push([0.0; 4]);
+        normals.push([0.0; 4]);
+        uvs.push([0.0; 2]);
+        tangents.push([0.0; 4]);
+    }
+    if triangles.is_empty() {
+        triangles.push([0; 4]);
+    }
+    // Streamed chunks are written into the arrays while rendering.
+    let usage = match stream {
+        Some(_) => wgpu::BufferUsages::COPY_DST,
+        None => wgpu::BufferUsages::empty(),
+    };
+    SceneGeometry {
+        vertices: storage_with(device, "scene vertices", bytemuck::cast_slice(&vertices), usage),
+        normals: storage_with(device, "scene normals", bytemuck::cast_slice(&normals), usage),
+        uvs: storage_with(device, "scene texture coordinates", bytemuck::cast_slice(&uvs), usage),
+        tangents: storage_with(device, "scene tangents", bytemuck::cast_slice(&tangents), usage),
+        triangles: storage_with(device, "scene triangles", bytemuck::cast_slice(&triangles), usage),
+        mesh_nodes,
+        roots,
+        mesh_chunks,
+        placements,
+        stream,
+    }
+}
+
+/// Pool sizes that share `budget` bytes between vertices, records and nodes
+/// in the proportions that `chunks` need them.
+fn pool_capacity(chunks: &[GeometryChunk], budget: u64) -> [u32; POOLS] {
+    let totals: [u64; POOLS] = std::array::from_fn(|p| chunks.iter().map(|chunk| chunk.pool_sizes()[p] as u64).sum());
+    let bytes: u64 = (0..POOLS).map(|p| totals[p] * POOL_ELEMENT_BYTES[p]).sum();
+    let scale = (budget as f64 / bytes.max(1) as f64).min(1.0);
+    totals.map(|total| (total as f64 * scale) as u32)
+}
+
+impl LodSelection {
+    /// Chooses from `view`, if given. `None` when no placed mesh has levels.
+    fn new(scene: &Scene, view: Option<LodView>) -> Option<LodSelection> {
+        let placed = placed_instances(scene, None);
+        if placed.iter().all(|instance| scene.meshes[instance.mesh as usize].lods.is_empty()) {
+            return None;
+        }
+        let chains = scene
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let sphere = mesh.bounds().map_or(([0.0; 3], 0.0), lod::bounding_sphere);
+                (mesh.lods.clone(), sphere)
+            })
+            .collect();
+        let chosen = placed.iter().map(|instance| instance.mesh).collect();
+        let mut lods = LodSelection { placed, chains, chosen, view: None };
+        if let Some(view) = view {
+            lods.choose(view);
+        }
+        Some(lods)
+    }
+
+    /// Chooses the levels seen from `view` and tells whether any changed.
+    fn choose(&mut self, view: LodView) -> bool {
+        if self.view == Some(view) {
+            return false;
+        }
+        self.view = Some(view);
+        let mut changed = false;
+        for (instance, chosen) in self.placed.iter().zip(&mut self.chosen) {
+            let (chain, sphere) = &self.chains[instance.mesh as usize];
+            let mesh = instance.choose_lod(chain, *sphere, &view);
+            changed |= mesh != *chosen;
+            *chosen = mesh;
+        }
+        changed
+    }
+
+    /// The placements with their chosen meshes.
+    fn instances(&self) -> Vec<Instance> {
+        self.placed
+            .iter()
+            .zip(&self.chosen)
+            .map(|(instance, &mesh)| Instance { mesh, ..*instance })
+            .collect()
+    }
+}
+
+/// The scene's instances and node instances, placing the meshes chosen by
+/// `lods` if given.
+fn placed_instances(scene: &Scene, lods: Option<&LodSelection>) -> Vec<Instance> {
+    match lods {
+        Some(lods) => lods.instances(),
+        None => scene.instances.iter().copied().chain(scene.node_instances()).collect(),
+    }
+}
+
+/// The top-level hierarchy in front of the chunk hierarchies, over the
+/// `placed` meshes and the world-space geometry, the instances it holds and
+/// the chunk table they point into.
+fn create_instances(
+    device: &Device,
+    placed: &[Instance],
+    geometry: &SceneGeometry,
+    bvh_leaf_size: usize,
+) -> SceneInstances {
+    let SceneGeometry { mesh_nodes, roots, mesh_chunks, placements, stream, .. } = geometry;
+    // Every chunk of every placed mesh; meshes without triangles have none.
+    let placed: Vec<(&Instance, u32)> = placed
+        .iter()
+        .chain(placements)
+        .flat_map(|instance| mesh_chunks[instance.mesh as usize].clone().map(move |chunk| (instance, chunk)))
+        .collect();
+    let boxes: Vec<([f32; 3], [f32; 3])> = placed
+        .iter()
+        .map(|(instance, chunk)| instance.world_bounds(roots[*chunk as usize].1))
+        .collect();
+    let top = Bvh::over_boxes(&boxes, bvh_leaf_size);
+    let top_len = top.nodes.len() as u32;
+    let mut bvh_nodes = top.nodes;
+    bvh_nodes.extend(mesh_nodes.iter().map(|node| BvhNode {
+        first: node.first + if node.count > 0 { 0 } else { top_len },
+        ..*node
+    }));
+    // Uploaded in top-level leaf order.
+    let mut instances: Vec<GpuInstance> = top
+        .order
+        .iter()
+        .map(|&i| {
+            let (instance, chunk) = placed[i as usize];
+            GpuInstance {
+                world_to_object: instance.world_to_object(),
+                chunk,
+                material: instance.material.map_or(0, |m| MATERIAL_SCENE + m),
+                primitive: roots[chunk as usize].2,
+                visibility: visibility_mask(instance.visibility),
+            }
+        })
+        .collect();
+    let instance_count = instances.len() as u32;
+    if instances.is_empty() {
+        instances.push(GpuInstance::zeroed());
+    }
+    let mut chunks: Vec<GpuChunk> = roots
+        .iter()
+        .map(|&(root, (min, max), _)| GpuChunk {
+            min,
+            root: if root == NOT_RESIDENT { root } else { top_len + root },
+            max,
+            traffic: 0,
+        })
+        .collect();
+    if chunks.is_empty() {
+        chunks.push(GpuChunk::zeroed());
+    }
+    // Nodes of streamed chunks and the chunks' roots are written while
+    // rendering, and the chunks read back for their ray counts.
+    let node_usage = match stream {
+        Some(_) => wgpu::BufferUsages::COPY_DST,
+        None => wgpu::BufferUsages::empty(),
+    };
+    SceneInstances {
+        bvh_nodes: storage_with(device, "bvh nodes", bytemuck::cast_slice(&bvh_nodes), node_usage),
+        top_nodes: top_len,
+        instances: storage(device, "scene instances", bytemuck::cast_slice(&instances)),
+        instance_count,
+        chunks: storage_with(
+            device,
+            "scene chunks",
+            bytemuck::cast_slice(&chunks),
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        ),
+        placed_bounds: placed.iter().map(|&(_, chunk)| chunk).zip(boxes).collect(),
+    }
+}
+
+fn create_materials(device: &Device, scene: &Scene) -> Buffer {
+    let slots = texture_slots(&scene.textures);
+    let mut materials: Vec<GpuMaterial> = scene
+        .materials
+        .iter()
+        .map(|m| GpuMaterial {
+            base_color: m.base_color,
+            metallic: m.metallic,
+            emission: m.emission,
+            roughness: m.roughness,
+            ior: m.ior,
+            kind: match m.kind {
+                MaterialKind::MetallicRoughness => 0,
+                MaterialKind::Lambertian => 1,
+                MaterialKind::Metal { .. } => 2,
+                MaterialKind::Dielectric => 3,
+            },
+            fuzz: match m.kind {
+                MaterialKind::Metal { fuzz } => fuzz,
+                _ => 0.0,
+            },
+            base_color_texture: slot(&slots, m.base_color_texture),
+            normal_texture: slot(&slots, m.normal_texture),
+            normal_scale: m.normal_scale,
+            _pad: [0; 2],
+        })
+        .collect();
+    if materials.is_empty() {
+        materials.push(GpuMaterial::zeroed());
+    }
+    storage(device, "scene materials", bytemuck::cast_slice(&materials))
+}
+
+/// Spheres, the other analytic shapes and the SDF shapes, how many there
+/// are and the SDF nodes.
+fn create_shapes(device: &Device, scene: &Scene) -> (Buffer, u32, Buffer) {
+    let spheres = scene.spheres.iter().map(|sphere| GpuShape {
+        position: sphere.center,
+        kind: 0,
+        u: [0.0; 3],
+        radius: sphere.radius,
+        v: [0.0; 3],
+        material: match sphere.material {
+            SphereMaterial::Checker => 0,
+            SphereMaterial::Brass => 1,
+            SphereMaterial::RedDiffuse => 2,
+            SphereMaterial::Glass => 3,
+            SphereMaterial::Scene(i) => MATERIAL_SCENE + i,
+        },
+    });
+    let others = scene.shapes.iter().map(|shape| {
+        let (kind, position, u, v) = match shape.kind {
+            ShapeKind::Quad { corner, u, v } => (1, corner, u, v),
+            ShapeKind::Box { min, max } => (2, min, max, [0.0; 3]),
+            ShapeKind::Plane { point, normal } => (3, point, normal, [0.0; 3]),
+        };
+        GpuShape {
+            position,
+            kind,
+            u,
+            radius: 0.0,
+            v,
+            material: MATERIAL_SCENE + shape.material,
+        }
+    });
+    let mut sdf_nodes = Vec::new();
+    let sdfs = scene.sdfs.iter().filter_map(|sdf| {
+        // An empty intersection has nothing to trace.
+        let (min, max) = sdf.root.bounds()?;
+        let first = sdf_nodes.len();
+        GpuSdfNode::flatten(&sdf.root, &mut sdf_nodes);
+        Some(GpuShape {
+            position: min,
+            kind: 4,
+            u: max,
+            radius: 0.0,
+            // Small integers are exact in f32.
+            v: [first as f32, (sdf_nodes.len() - first) as f32, 0.0],
+            material: MATERIAL_SCENE + sdf.material,
+        })
+    });
+    let kinds = procedural::kinds(&scene.procedurals);
+    let procedurals = scene.procedurals.iter().map(|placed| {
+        let parameters = placed.primitive.parameters();
+        let kind = kinds.iter().position(|kind| kind.name() == placed.primitive.name()).unwrap();
+        GpuShape {
+            position: parameters.position,
+            kind: 5 + kind as u32,
+            u: parameters.u,
+            radius: parameters.radius,
+            v: parameters.v,
+            material: MATERIAL_SCENE + placed.material,
+        }
+    });
+    let mut shapes: Vec<GpuShape> = spheres.chain(others).chain(sdfs).chain(procedurals).collect();
+    let shape_count = shapes.len() as u32;
+    sdf_nodes.resize(MAX_SDF_NODES, GpuSdfNode::zeroed());
+    if shapes.is_empty() {
+        shapes.push(GpuShape::zeroed());
+    }
+    (
+        storage(device, "scene shapes", bytemuck::cast_slice(&shapes)),
+        shape_count,
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sdf nodes"),
+            contents: bytemuck::cast_slice(&sdf_nodes),
+            usage: wgpu::BufferUsages::UNIFORM,
+        }),
+    )
+}
+
+fn create_lights(device: &Device, scene: &Scene) -> Buffer {
+    let slots = texture_slots(&scene.textures);
+    let mut lights = [GpuLight::zeroed(); MAX_LIGHTS];
+    for (gpu, light) in lights.iter_mut().zip(&scene.lights) {
+        *gpu = GpuLight::new(light, &slots);
+    }
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("area lights"),
+        contents: bytemuck::cast_slice(&lights),
+        usage: wgpu::BufferUsages::UNIFORM,
+    })
+}
+
+/// The emissive triangles of `scene` and how many there are.
+fn create_emitters(device: &Device, scene: &Scene) -> (Buffer, u32) {
+    let triangles = scene.emissive_triangles();
+    let mut emitters: Vec<GpuEmitter> = triangles
+        .iter()
+        .map(|tri| {
+            let [p0, p1, p2] = tri.positions;
+            let e1 = [0, 1, 2].map(|i| p1[i] - p0[i]);
+            let e2 = [0, 1, 2].map(|i| p2[i] - p0[i]);
+            let normal = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            let area = 0.5 * normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+            let [r, g, b] = scene.materials[tri.material as usize].emission;
+            GpuEmitter {
+                position: p0,
+                material: tri.material,
+                e1,
+                cdf: 0.0,
+                e2,
+                probability: area * (0.2126 * r + 0.7152 * g + 0.0722 * b).max(0.0),
+            }
+        })
+        .collect();
+    let total: f32 = emitters.iter().map(|emitter| emitter.probability).sum();
+    if total > 0.0 && total.is_finite() {
+        let mut cdf = 0.0;
+        for emitter in &mut emitters {
+            emitter.probability /= total;
+            cdf += emitter.probability;
+            emitter.cdf = cdf;
+        }
+        emitters.last_mut().unwrap().cdf = 1.0;
+    } else {
+        // Triangles without area leave nothing to sample.
+        emitters.clear();
+    }
+    let count = emitters.len() as u32;
+    // Storage bindings cannot be empty.
+    if emitters.is_empty() {
+        emitters.push(GpuEmitter::zeroed());
+    }
+    (storage(device, "emissive triangles", bytemuck::cast_slice(&emitters)), count)
+}
+
+/// Where each scene texture is placed on the GPU: `layer << 3 | class`,
+/// with `class` indexing `TEXTURE_SIZES`. Must match `sample_texture` in
+/// shader.wgsl.
+fn texture_slots(textures: &[texture::Texture]) -> Vec<u32> {
+    let mut layers = [0; TEXTURE_SIZES.len()];
+    textures
+        .iter()
+        .map(|texture| {
+            let class = texture_class(texture);
+            layers[class] += 1;
+            (layers[class] - 1) << 3 | class as u32
+        })
+        .collect()
+}
+
+/// Index into `TEXTURE_SIZES` of the array that `texture` is placed in.
+fn texture_class(texture: &texture::Texture) -> usize {
+    let side = texture.width.max(texture.height);
+    TEXTURE_SIZES.iter().position(|&size| size >= side).unwrap_or(TEXTURE_SIZES.len() - 1)
+}
+
+/// One more than the slot of the texture at `index` among `slots`, or 0 for
+/// none.
+fn slot(slots: &[u32], index: Option<u32>) -> u32 {
+    index.and_then(|i| slots.get(i as usize)).map_or(0, |slot| slot + 1)
+}
+
+fn create_textures(device: &Device, queue: &Queue, tools: &TextureTools, scene: &Scene) -> SceneTextures {
+    let largest = TEXTURE_SIZES[TEXTURE_SIZES.len() - 1];
+    for (i, texture) in scene.textures.iter().enumerate() {
+        if texture.width.max(texture.height) > largest {
+            crash::log!(
+                "warning: texture {i} is {}x{} and is downscaled to {largest}x{largest}",
+                texture.width, texture.height
+            );
+        }
+    }
+    let data_indices = data_textures(scene);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("scene texture mips"),
+    });
+    let arrays: [Texture; TEXTURE_SIZES.len()] = std::array::from_fn(|class| {
+        let textures: Vec<(usize, &texture::Texture, bool)> = scene
+            .textures
+            .iter()
+            .enumerate()
+            .filter(|(_, texture)| texture_class(texture) == class)
+            .map(|(index, texture)| (index, texture, !data_indices.contains(&index)))
+            .collect();
+        create_texture_array(device, queue, tools, &mut encoder, TEXTURE_SIZES[class], &textures)
+    });
+    queue.submit([encoder.finish()]);
+    let view = |array: &Texture, format| {
+        array.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("scene textures view"),
+            format: Some(format),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    };
+    SceneTextures {
+        color: arrays.each_ref().map(|array| view(array, wgpu::TextureFormat::Rgba8UnormSrgb)),
+        data: arrays.each_ref().map(|array| view(array, wgpu::TextureFormat::Rgba8Unorm)),
+        data_indices,
+    }
+}
+
+/// Indices of the scene textures that materials use as normal maps, whose
+/// texels are data rather than colors.
+fn data_textures(scene: &Scene) -> HashSet<usize> {
+    scene.materials.iter().filter_map(|m| m.normal_texture).map(|i| i as usize).collect()
+}
+
+/// One array of `size` by `size` layers holding `textures`, numbered by
+/// their index in the scene and marked sRGB unless they hold data, with
+/// mips recorded into `encoder`.
+fn create_texture_array(
+    device: &Device,
+    queue: &Queue,
+    tools: &TextureTools,
+    encoder: &mut wgpu::CommandEncoder,
+    size: u32,
+    textures: &[(usize, &texture::Texture, bool)],
+) -> Texture {
+    // Block-compressed textures go to the GPU as they are where it samples
+    // them, and are resampled into their layer there. Elsewhere they are
+    // decoded on the CPU like the rest.
+    let sample_blocks = device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+    let mut gpu_layers = Vec::new();
+    // An empty array cannot be bound either, so it gets one white texel.
+    let size = if textures.is_empty() { 1 } else { size };
+    let layers = textures.len().max(1) as u32;
+    let mip_levels = mipmap::mip_level_count(size, size);
+    let white = || mipmap::mip_chain(vec![[255; 4]; (size * size) as usize], size, false);
+    // Each level of every layer, uploaded a level at a time.
+    let mut levels: Vec<Vec<[u8; 4]>> = vec![Vec::new(); mip_levels as usize];
+    for (layer, &(index, texture, srgb)) in textures.iter().enumerate() {
+        let prepared = match &texture.blocks {
+            Some(blocks) if sample_blocks && texture.width % 4 == 0 && texture.height % 4 == 0 => {
+                gpu_layers.push((layer as u32, create_block_texture(device, queue, texture, blocks), blocks, srgb));
+                white()
+            }
+            _ => prepare_layer(texture, size, srgb).unwrap_or_else(|err| {
+                crash::log!("warning: texture {index} is left white: {err:#}");
+                white()
+            }),
+        };
+        for (level, texels) in levels.iter_mut().zip(prepared) {
+            level.extend(texels);
+        }
+    }
+    if textures.is_empty() {
+        levels = white();
+    }
+    // Stored as plain 8-bit so that the mip generator can write it, and
+    // viewed as sRGB by the shader where it holds colors.
+    let array = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("scene textures"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: mip_levels,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
+    });
+    for (level, texels) in levels.iter().enumerate() {
+        let level_size = size >> level;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                mip_level: level as u32,
+                ..array.as_image_copy()
+            },
+            bytemuck::cast_slice(texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * level_size),
+                rows_per_image: Some(level_size),
+            },
+            wgpu::Extent3d {
+                width: level_size,
+                height: level_size,
+                depth_or_array_layers: layers,
+            },
+        );
+    }
+    // Layers resampled on the GPU get their mips there too.
+    for (layer, source, blocks, srgb) in gpu_layers {
+        let dest = array.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("scene texture layer"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: 0,
+            mip_level_count: Some(1),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        // Sampling decodes sRGB blocks, which data layers encode back to
+        // keep the values stored.
+        let grey = blocks.format == BlockFormat::Bc4;
+        tools.resampler.encode(device, encoder, &source, &dest, [size; 2], grey, srgb || blocks.srgb);
+        if let Err(err) = tools.mip_generator.encode(device, encoder, &array, layer..layer + 1, srgb) {
+            crash::log!("warning: scene texture layer {layer} has no mipmaps: {err:#}");
+        }
+    }
+    array
+}
+
+/// The pixels of a texture layer and its mips, decoded on the CPU if the
+/// texture is block-compressed and resampled to `size` by `size`, or read
+/// back from the installed cache if it holds them. With `srgb` they are
+/// colors, and otherwise data.
+fn prepare_layer(texture: &texture::Texture, size: u32, srgb: bool) -> anyhow::Result<Vec<Vec<[u8; 4]>>> {
+    let cache = texture_cache::installed().zip(texture.source);
+    if let Some(cached) = cache.as_ref().and_then(|(cache, key)| cache.get_layer(*key, size, srgb)) {
+        return Ok(cached);
+    }
+    let resized = match texture.blocks {
+        Some(_) => texture.decoded(srgb)?.resized(size, size),
+        None => texture.resized(size, size),
+    };
+    let levels = mipmap::mip_chain(resized.pixels, size, srgb);
+    if let Some((cache, key)) = cache {
+        cache.put_layer(key, size, srgb, &levels);
+    }
+    Ok(levels)
+}
+
+/// Uploads the blocks of a compressed texture with their mip chain. Its
+/// size must be whole blocks.
+fn create_block_texture(device: &Device, queue: &Queue, texture: &texture::Texture, blocks: &BlockImage) -> TextureView {
+    use wgpu::TextureFormat as F;
+    let format = match (blocks.format, blocks.srgb) {
+        (BlockFormat::Bc1, false) => F::Bc1RgbaUnorm,
+        (BlockFormat::Bc1, true) => F::Bc1RgbaUnormSrgb,
+        (BlockFormat::Bc2, false) => F::Bc2RgbaUnorm,
+        (BlockFormat::Bc2, true) => F::Bc2RgbaUnormSrgb,
+        (BlockFormat::Bc3, false) => F::Bc3RgbaUnorm,
+        (BlockFormat::Bc3, true) => F::Bc3RgbaUnormSrgb,
+        (BlockFormat::Bc4, _) => F::Bc4RUnorm,
+        (BlockFormat::Bc5, _) => F::Bc5RgUnorm,
+        (BlockFormat::Bc6h, _) => F::Bc6hRgbUfloat,
+        (BlockFormat::Bc7, false) => F::Bc7RgbaUnorm,
+        (BlockFormat::Bc7, true) => F::Bc7RgbaUnormSrgb,
+    };
+    let levels = (blocks.levels.len() as u32).min(mipmap::mip_level_count(texture.width, texture.height));
+    let gpu_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("compressed scene texture"),
+        size: wgpu::Extent3d {
+            width: texture.width,
+            height: texture.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: levels,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    for (level, data) in blocks.levels.iter().take(levels as usize).enumerate() {
+        // Levels smaller than a block still take a whole one.
+        let blocks_wide = (texture.width >> level).max(1).div_ceil(4);
+        let blocks_high = (texture.height >> level).max(1).div_ceil(4);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &gpu_texture,
+                mip_level: level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(blocks_wide * blocks.format.block_size() as u32),
+                rows_per_image: Some(blocks_high),
+            },
+            wgpu::Extent3d {
+                width: blocks_wide * 4,
+                height: blocks_high * 4,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    gpu_texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("compressed scene texture view"),
+        ..Default::default()
+    })
+}
+
+/// The volume table, how many volumes there are and their densities.
+fn create_volumes(device: &Device, queue: &Queue, scene: &Scene) -> (Buffer, u32, TextureView) {
+    // Grids are stacked along z in one texture, which again cannot be empty.
+    let mut volumes = [GpuVolume::zeroed(); MAX_VOLUMES];
+    let mut atlas = [1, 1, 0];
+    for (gpu, volume) in volumes.iter_mut().zip(&scene.volumes) {
+        *gpu = GpuVolume {
+            world_to_grid: volume.world_to_grid(),
+            size: volume.size,
+            first_slice: atlas[2],
+            albedo: volume.albedo,
+            majorant: volume.majorant(),
+            density_scale: volume.density_scale,
+            _pad: [0; 3],
+        };
+        atlas = [atlas[0].max(volume.size[0]), atlas[1].max(volume.size[1]), atlas[2] + volume.size[2]];
+    }
+    let volume_densities = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("volume densities"),
+        size: wgpu::Extent3d {
+            width: atlas[0],
+            height: atlas[1],
+            depth_or_array_layers: atlas[2].max(1),
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    for (gpu, volume) in volumes.iter().zip(&scene.volumes) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &volume_densities,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: gpu.first_slice },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&volume.densities),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * volume.size[0]),
+                rows_per_image: Some(volume.size[1]),
+            },
+            wgpu::Extent3d {
+                width: volume.size[0],
+                height: volume.size[1],
+                depth_or_array_layers: volume.size[2],
+            },
+        );
+    }
+    (
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("volumes"),
+            contents: bytemuck::cast_slice(&volumes),
+            usage: wgpu::BufferUsages::UNIFORM,
+        }),
+        scene.volumes.len().min(MAX_VOLUMES) as u32,
+        volume_densities.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("volume densities view"),
+            ..Default::default()
+        }),
+    )
+}
+
+fn create_sample_texture(device: &Device, label: &str, width: u32, height: u32) -> Texture {
+    let desc = wgpu::TextureDescriptor {
+        label: Some(label),
+        format: wgpu::TextureFormat::Rgba32Float,
+        size: wgpu::Extent3d {
             width,
             height,
             depth_or_array_layers: 1,
         },
         dimension: wgpu::TextureDimension::D2,
         sample_count: 1,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
         mip_level_count: 1,
         view_formats: &[],
     };
     device.create_texture(&desc)
 }
 
-fn compile_shader_module(device: &Device) -> ShaderModule {
-    device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"))
+/// Compiles shader.wgsl with the functions of `procedural_kinds` spliced
+/// in. WGSL that does not compile fails with an error naming the primitive
+/// it came from, rather than through the device's uncaptured-error handler.
+fn compile_shader_module(
+    device: &Device,
+    stream_geometry: bool,
+    procedural_kinds: &[&dyn procedural::ProceduralPrimitive],
+) -> anyhow::Result<ShaderModule> {
+    let error = match try_compile(device, &shader_source(stream_geometry, procedural_kinds)) {
+        Ok(module) => return Ok(module),
+        Err(error) => error,
+    };
+    // Compiling each primitive alone finds the one at fault.
+    for &kind in procedural_kinds {
+        if let Err(error) = try_compile(device, &shader_source(stream_geometry, &[kind])) {
+            anyhow::bail!("WGSL of procedural primitive `{}` does not compile: {error}", kind.name());
+        }
+    }
+    anyhow::bail!("shader.wgsl does not compile: {error}")
 }
 
-fn create_display_pipeline(
-    device: &Device,
-    shader_mod: &ShaderModule,
-) -> (RenderPipeline, BindGroupLayout) {
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("bind group"),
+/// `source` compiled, or the validation error it raised.
+fn try_compile(device: &Device, source: &str) -> Result<ShaderModule, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("shader.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error),
+        None => Ok(module),
+    }
+}
+
+/// shader.wgsl with the features and procedural primitives it is built with.
+fn shader_source(stream_geometry: bool, procedural_kinds: &[&dyn procedural::ProceduralPrimitive]) -> String {
+    let mut source = concat!(include_str!("gpu/spatial_hash.wgsl"), include_str!("shader.wgsl")).to_string();
+    #[cfg(feature = "debug-stats")]
+    {
+        source = source.replace(
+            "const DEBUG_STATS: bool = false;",
+            "const DEBUG_STATS: bool = true;",
+        );
+    }
+    if stream_geometry {
+        source = source.replace(
+            "const STREAM_GEOMETRY: bool = false;",
+            "const STREAM_GEOMETRY: bool = true;",
+        );
+    }
+    if !procedural_kinds.is_empty() {
+        source = source.replace(
+            "fn hit_procedural(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {\n    var rec: HitRecord;\n    rec.hit = false;\n    return rec;\n}\n",
+            &procedural::hit_function(procedural_kinds),
+        );
+    }
+    source
+}
+
+/// Layout of the display bind group, the same whatever the shader was
+/// assembled with, so that it outlives recompiles.
+fn create_display_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("display bind group layout"),
         entries: &[
             wgpu::BindGroupLayoutEntry {
                 binding: 0,
@@ -198,9 +2527,311 @@ fn create_display_pipeline(
                     format: wgpu::TextureFormat::Rgba32Float,
                 },
             },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::StorageTexture {
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    access: wgpu::StorageTextureAccess::ReadWrite,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 11,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 12,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 13,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 14,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 15,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 16,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 17,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 18,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 19,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 20,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 21,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 22,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 23,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 24,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 25,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 26,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 27,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 28,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 29,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 30,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 31,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
         ],
-    });
+    })
+}
 
+fn create_display_pipeline(
+    device: &Device,
+    shader_mod: &ShaderModule,
+    bind_group_layout: &BindGroupLayout,
+) -> RenderPipeline {
     let vertex_buffer_layout = wgpu::VertexBufferLayout {
         array_stride: std::mem::size_of::<u32>() as wgpu::BufferAddress,
         step_mode: wgpu::VertexStepMode::Vertex,
@@ -211,11 +2842,12 @@ fn create_display_pipeline(
         }],
     };
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("display"),
         layout: Some(
             &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                bind_group_layouts: &[&bind_group_layout],
+                label: Some("display pipeline layout"),
+                bind_group_layouts: &[bind_group_layout],
                 ..Default::default()
             }),
         ),
@@ -242,6 +2874,5 @@ fn create_display_pipeline(
             buffers: &[vertex_buffer_layout],
         },
         multisample: wgpu::MultisampleState::default(),
-    });
-    (pipeline, bind_group_layout)
+    })
 }
@@ -0,0 +1,923 @@
+//! Alembic archives (`.abc`) in the Ogawa layout, such as simulation and
+//! animation caches baked out of Houdini or Maya.
+//!
+//! `PolyMesh` and `SubD` objects are flattened into world space through the
+//! `Xform`s above them, their clockwise polygons turned counterclockwise and
+//! triangulated, and shaded with one grey material. Normals `N` are kept
+//! where they are given per face, per point or per face corner. Meshes whose
+//! points or transforms are animated keep the positions of every sample in
+//! `Scene::vertex_animations`, timed by the archive's time samplings; the
+//! scene itself shows the first. Meshes whose point count changes over time
+//! keep only their first sample. Cameras, curves, points, face sets, UVs and
+//! archives in the older HDF5 layout are not read.
+
+use {
+    super::{
+        gltf::{self, Mat4, IDENTITY},
+        ktx2, obj, Material, MaterialKind, Scene, Triangle, VertexAnimation,
+    },
+    crate::{crash, math::Vec3},
+    anyhow::{bail, ensure, Context, Result},
+    std::{fs, path::Path},
+};
+
+const OGAWA_MAGIC: &[u8] = b"Ogawa";
+const HDF5_MAGIC: &[u8] = b"\x89HDF\r\n\x1a\n";
+
+/// Set on the offsets of a group's children that are data rather than
+/// groups.
+const DATA_BIT: u64 = 1 << 63;
+
+/// Bytes of the content key stored in front of every property sample.
+const SAMPLE_KEY_SIZE: usize = 16;
+
+/// Bytes of the hashes stored after an object's child headers.
+const OBJECT_HASHES_SIZE: usize = 32;
+
+/// Object hierarchies deeper than this are rejected rather than recursed
+/// into.
+const MAX_OBJECT_DEPTH: usize = 256;
+
+/// Time per cycle of a time sampling that lists the time of every sample.
+const ACYCLIC_TIME_PER_CYCLE: f64 = f64::MAX / 32.0;
+
+/// Slack when matching sample times, which are accumulated in seconds.
+const TIME_EPSILON: f64 = 1e-6;
+
+/// Stands in for the materials Alembic does not carry.
+const GREY: Material = Material {
+    base_color: [0.5; 3],
+    metallic: 0.0,
+    roughness: 0.5,
+    emission: [0.0; 3],
+    ior: 1.5,
+    kind: MaterialKind::MetallicRoughness,
+    base_color_texture: None,
+    normal_texture: None,
+    normal_scale: 1.0,
+};
+
+/// Reads the meshes of an archive.
+///
+/// ```
+/// use raytracer::scene::alembic;
+///
+/// // Ogawa groups list the offsets of their children, with the top bit set
+/// // on data, and data is its size and then its bytes.
+/// enum Node {
+///     Group(Vec<Node>),
+///     Data(Vec<u8>),
+/// }
+/// fn write(node: &Node, file: &mut Vec<u8>) -> u64 {
+///     match node {
+///         Node::Data(bytes) => {
+///             let offset = file.len() as u64;
+///             file.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+///             file.extend_from_slice(bytes);
+///             offset | 1 << 63
+///         }
+///         Node::Group(children) => {
+///             let children: Vec<u64> = children.iter().map(|child| write(child, file)).collect();
+///             let offset = file.len() as u64;
+///             file.extend_from_slice(&(children.len() as u64).to_le_bytes());
+///             children.iter().for_each(|child| file.extend_from_slice(&child.to_le_bytes()));
+///             offset
+///         }
+///     }
+/// }
+/// // A property with one sample, behind a 16-byte key; array samples are
+/// // followed by their dimensions.
+/// fn scalar(values: Vec<u8>) -> Node {
+///     Node::Group(vec![Node::Data([vec![0; 16], values].concat())])
+/// }
+/// fn array(values: Vec<u8>) -> Node {
+///     Node::Group(vec![Node::Data([vec![0; 16], values].concat()), Node::Data(Vec::new())])
+/// }
+/// // A compound property: its properties, then their headers. Each header
+/// // packs kind, value type and extent into `info`, then a sample count
+/// // for all but compounds, and the name.
+/// fn compound(properties: Vec<(u32, &str, Node)>) -> Node {
+///     let mut headers = Vec::new();
+///     for (info, name, _) in &properties {
+///         headers.extend_from_slice(&info.to_le_bytes());
+///         if info & 3 != 0 {
+///             headers.push(1);
+///         }
+///         headers.push(name.len() as u8);
+///         headers.extend_from_slice(name.as_bytes());
+///     }
+///     let mut children: Vec<Node> = properties.into_iter().map(|(_, _, node)| node).collect();
+///     children.push(Node::Data(headers));
+///     Node::Group(children)
+/// }
+/// // An object: its properties, its children, then their headers with
+/// // their schemas and 32 bytes of hashes.
+/// fn object(properties: Node, children: Vec<(&str, &str, Node)>) -> Node {
+///     let mut headers = Vec::new();
+///     for (name, schema, _) in &children {
+///         let metadata = format!("schema={schema}");
+///         headers.extend_from_slice(&(name.len() as u32).to_le_bytes());
+///         headers.extend_from_slice(name.as_bytes());
+///         headers.push(0xff);
+///         headers.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+///         headers.extend_from_slice(metadata.as_bytes());
+///     }
+///     headers.extend_from_slice(&[0; 32]);
+///     let mut nodes = vec![properties];
+///     nodes.extend(children.into_iter().map(|(_, _, node)| node));
+///     nodes.push(Node::Data(headers));
+///     Node::Group(nodes)
+/// }
+/// let bytes = |values: &[f32]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+///
+/// // A unit quad, wound clockwise, under a transform moving it 5 along -z.
+/// const ARRAY_F32_3: u32 = 2 | 10 << 4 | 3 << 16;
+/// const ARRAY_I32: u32 = 2 | 6 << 4 | 1 << 16;
+/// const SCALAR_U8: u32 = 1 | 1 << 4 | 1 << 16;
+/// const SCALAR_F64_3: u32 = 1 | 11 << 4 | 3 << 16;
+/// let geom = compound(vec![
+///     (ARRAY_F32_3, "P", array(bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0]))),
+///     (ARRAY_I32, ".faceIndices", array([0i32, 3, 2, 1].iter().flat_map(|i| i.to_le_bytes()).collect())),
+///     (ARRAY_I32, ".faceCounts", array(4i32.to_le_bytes().to_vec())),
+/// ]);
+/// let mesh = object(compound(vec![(0, ".geom", geom)]), vec![]);
+/// let xform = compound(vec![
+///     // One translation, then its values.
+///     (SCALAR_U8, ".ops", scalar(vec![1 << 4])),
+///     (SCALAR_F64_3, ".vals", scalar([0.0f64, 0.0, -5.0].iter().flat_map(|v| v.to_le_bytes()).collect())),
+/// ]);
+/// let xform = object(compound(vec![(0, ".xform", xform)]), vec![("quad", "AbcGeom_PolyMesh_v1", mesh)]);
+/// let top = object(compound(vec![]), vec![("move", "AbcGeom_Xform_v3", xform)]);
+/// // Versions, the top object, archive metadata, time samplings and
+/// // indexed metadata.
+/// let empty = || Node::Data(Vec::new());
+/// let root = Node::Group(vec![empty(), empty(), top, empty(), empty(), empty()]);
+/// let mut file = b"Ogawa\xff\x00\x01".to_vec();
+/// file.extend_from_slice(&[0; 8]);
+/// let root = write(&root, &mut file);
+/// file[8..16].copy_from_slice(&root.to_le_bytes());
+///
+/// let path = std::env::temp_dir().join(format!("quad-{}.abc", std::process::id()));
+/// std::fs::write(&path, &file).unwrap();
+/// let scene = alembic::load(&path).unwrap();
+/// std::fs::remove_file(&path).unwrap();
+/// assert_eq!(scene.positions, [[0.0, 0.0, -5.0], [0.0, 1.0, -5.0], [1.0, 1.0, -5.0], [1.0, 0.0, -5.0]]);
+/// assert_eq!(scene.triangles.len(), 2);
+/// // Turned counterclockwise, facing +z.
+/// let [a, b, c] = scene.triangles[0].indices.map(|i| scene.positions[i as usize]);
+/// let [u, v] = [b, c].map(|p| [p[0] - a[0], p[1] - a[1]]);
+/// assert!(u[0] * v[1] - u[1] * v[0] > 0.0);
+/// ```
+pub fn load(path: &Path) -> Result<Scene> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    import(&bytes).with_context(|| format!("failed to import {}", path.display()))
+}
+
+fn import(bytes: &[u8]) -> Result<Scene> {
+    ensure!(
+        !bytes.starts_with(HDF5_MAGIC),
+        "HDF5 Alembic archives are not supported; export the cache again as Ogawa"
+    );
+    ensure!(bytes.starts_with(OGAWA_MAGIC), "not an Alembic archive");
+    let file = Ogawa(bytes);
+    // Versions, the top object, archive metadata, time samplings and the
+    // metadata that headers refer to by index.
+    let root = file.children(file.u64(8)?)?;
+    ensure!(root.len() >= 6, "archive has {} top-level entries, expected 6", root.len());
+    let archive = Archive {
+        file,
+        time_samplings: parse_time_samplings(file.data(root[4])?)?,
+        metadata: parse_indexed_metadata(file.data(root[5])?)?,
+    };
+    let mut importer = Importer {
+        archive: &archive,
+        scene: Scene {
+            materials: vec![GREY],
+            ..Scene::default()
+        },
+        transforms: Vec::new(),
+    };
+    let top = ObjectHeader {
+        name: String::new(),
+        metadata: String::new(),
+        group: file.group(root[2])?,
+    };
+    importer.visit(&top, "", 0)?;
+    Ok(importer.scene)
+}
+
+/// The Ogawa container: a tree of groups whose leaves are byte strings,
+/// each found by its offset in the file.
+#[derive(Copy, Clone)]
+struct Ogawa<'a>(&'a [u8]);
+
+#[derive(Copy, Clone)]
+enum Node {
+    Group(u64),
+    Data(u64),
+}
+
+impl<'a> Ogawa<'a> {
+    fn u64(&self, at: u64) -> Result<u64> {
+        usize::try_from(at)
+            .ok()
+            .and_then(|at| self.0.get(at..at.checked_add(8)?))
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .context("offset out of range")
+    }
+
+    /// Children of the group at `offset`, where offset 0 is an empty group.
+    fn children(&self, offset: u64) -> Result<Vec<Node>> {
+        if offset == 0 {
+            return Ok(Vec::new());
+        }
+        let count = self.u64(offset)?;
+        ensure!(count <= self.0.len() as u64 / 8, "group has more children than fit the file");
+        (0..count)
+            .map(|i| {
+                let child = self.u64(offset + 8 + 8 * i)?;
+                Ok(match child & DATA_BIT {
+                    0 => Node::Group(child),
+                    _ => Node::Data(child & !DATA_BIT),
+                })
+            })
+            .collect()
+    }
+
+    /// Bytes of a data node, where offset 0 is empty data.
+    fn data(&self, node: Node) -> Result<&'a [u8]> {
+        let Node::Data(offset) = node else {
+            bail!("expected data, found a group");
+        };
+        if offset == 0 {
+            return Ok(&[]);
+        }
+        let size = self.u64(offset)?;
+        let start = offset + 8;
+        start
+            .checked_add(size)
+            .and_then(|end| self.0.get(usize::try_from(start).ok()?..usize::try_from(end).ok()?))
+            .context("data out of range")
+    }
+
+    fn group(&self, node: Node) -> Result<u64> {
+        match node {
+            Node::Group(offset) => Ok(offset),
+            Node::Data(_) => bail!("expected a group, found data"),
+        }
+    }
+}
+
+/// Little-endian reads moving through a byte string.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Cursor { bytes, pos: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.pos..end))
+            .context("truncated header")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A count stored in 1, 2 or 4 bytes, as `hint` 0, 1 or 2 says.
+    fn sized(&mut self, hint: u32) -> Result<u32> {
+        Ok(match hint {
+            0 => self.u8()? as u32,
+            1 => u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u32,
+            _ => self.u32()?,
+        })
+    }
+
+    fn string(&mut self, len: u32) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.take(len as usize)?).into_owned())
+    }
+}
+
+/// When each sample of a property was taken.
+struct TimeSampling {
+    /// Seconds between cycles, or `ACYCLIC_TIME_PER_CYCLE`.
+    per_cycle: f64,
+    /// Times of the samples of the first cycle, or of every sample if
+    /// acyclic.
+    times: Vec<f64>,
+}
+
+/// One sample per second from 0, which time sampling 0 always is.
+static DEFAULT_TIME_SAMPLING: TimeSampling = TimeSampling {
+    per_cycle: 1.0,
+    times: Vec::new(),
+};
+
+impl TimeSampling {
+    fn time(&self, index: u32) -> f64 {
+        let count = self.times.len() as u32;
+        if count == 0 {
+            return index as f64 * self.per_cycle;
+        }
+        if self.per_cycle == ACYCLIC_TIME_PER_CYCLE {
+            return self.times[index.min(count - 1) as usize];
+        }
+        self.times[(index % count) as usize] + (index / count) as f64 * self.per_cycle
+    }
+}
+
+fn parse_time_samplings(bytes: &[u8]) -> Result<Vec<TimeSampling>> {
+    let mut r = Cursor::new(bytes);
+    let mut samplings = Vec::new();
+    while !r.done() {
+        let _max_sample = r.u32()?;
+        let per_cycle = r.f64()?;
+        let count = r.u32()?;
+        ensure!(count as usize <= bytes.len() / 8, "time sampling lists more times than fit");
+        let times = (0..count).map(|_| r.f64()).collect::<Result<_>>()?;
+        samplings.push(TimeSampling { per_cycle, times });
+    }
+    Ok(samplings)
+}
+
+/// Metadata strings that headers refer to by index; index 0 is empty.
+fn parse_indexed_metadata(bytes: &[u8]) -> Result<Vec<String>> {
+    let mut r = Cursor::new(bytes);
+    let mut metadata = vec![String::new()];
+    while !r.done() {
+        let len = r.u8()?;
+        metadata.push(r.string(len as u32)?);
+    }
+    Ok(metadata)
+}
+
+/// Value of `key` in serialized metadata, which is `key=value` pairs
+/// separated by semicolons.
+fn meta<'m>(metadata: &'m str, key: &str) -> Option<&'m str> {
+    metadata.split(';').find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+}
+
+struct ObjectHeader {
+    name: String,
+    metadata: String,
+    group: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PropertyKind {
+    Compound,
+    Scalar,
+    Array,
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+    /// Alembic's `PlainOldDataType` of each value.
+    pod: u32,
+    /// Values per element, such as 3 for points.
+    extent: u32,
+    /// Samples written, of which only those from `first_changed` to
+    /// `last_changed` differ from the one before and are stored after the
+    /// first.
+    samples: u32,
+    first_changed: u32,
+    last_changed: u32,
+    time_sampling: u32,
+    metadata: String,
+    node: Node,
+}
+
+impl Property {
+    /// Which stored sample holds sample `index`.
+    fn stored(&self, index: u32) -> u32 {
+        let constant = self.first_changed == 0 && self.last_changed == 0;
+        if constant || index < self.first_changed {
+            0
+        } else if index >= self.last_changed {
+            self.last_changed - self.first_changed + 1
+        } else {
+            index - self.first_changed + 1
+        }
+    }
+}
+
+struct Archive<'a> {
+    file: Ogawa<'a>,
+    time_samplings: Vec<TimeSampling>,
+    metadata: Vec<String>,
+}
+
+impl<'a> Archive<'a> {
+    fn indexed_metadata(&self, index: u32) -> String {
+        self.metadata.get(index as usize).cloned().unwrap_or_default()
+    }
+
+    /// The group of an object's properties, if it has any, and the headers
+    /// of its children. Child `i` is the object's group child `i + 1`.
+    fn object(&self, group: u64) -> Result<(Option<u64>, Vec<ObjectHeader>)> {
+        let children = self.file.children(group)?;
+        let properties = match children.first() {
+            Some(&Node::Group(offset)) => Some(offset),
+            _ => None,
+        };
+        let mut headers = Vec::new();
+        if let Some(&last @ Node::Data(_)) = children.last() {
+            let bytes = self.file.data(last)?;
+            let mut r = Cursor::new(&bytes[..bytes.len().saturating_sub(OBJECT_HASHES_SIZE)]);
+            while !r.done() {
+                let len = r.u32()?;
+                let name = r.string(len)?;
+                let metadata = match r.u8()? {
+                    0xff => {
+                        let len = r.u32()?;
+                        r.string(len)?
+                    }
+                    index => self.indexed_metadata(index as u32),
+                };
+                let node = *children.get(headers.len() + 1).context("object header without an object")?;
+                headers.push(ObjectHeader {
+                    name,
+                    metadata,
+                    group: self.file.group(node)?,
+                });
+            }
+        }
+        Ok((properties, headers))
+    }
+
+    /// Headers of the properties in a compound property's group.
+    fn properties(&self, group: u64) -> Result<Vec<Property>> {
+        let children = self.file.children(group)?;
+        let Some(&last @ Node::Data(_)) = children.last() else {
+            return Ok(Vec::new());
+        };
+        let mut r = Cursor::new(self.file.data(last)?);
+        let mut properties = Vec::new();
+        while !r.done() {
+            // Bits 0-1 kind, 2-3 size of the counts that follow, 4-7 value
+            // type, 8 has a time sampling, 9 lists its changed samples, 11
+            // never changes, 16-23 extent and 24-31 metadata index.
+            let info = r.u32()?;
+            let hint = info >> 2 & 3;
+            let kind = match info & 3 {
+                0 => PropertyKind::Compound,
+                1 => PropertyKind::Scalar,
+                _ => PropertyKind::Array,
+            };
+            let (mut samples, mut first_changed, mut last_changed, mut time_sampling) = (0, 0, 0, 0);
+            if kind != PropertyKind::Compound {
+                samples = r.sized(hint)?;
+                (first_changed, last_changed) = if info & 0x200 != 0 {
+                    (r.sized(hint)?, r.sized(hint)?)
+                } else if info & 0x800 != 0 {
+                    (0, 0)
+                } else {
+                    (1, samples.saturating_sub(1))
+                };
+                if info & 0x100 != 0 {
+                    time_sampling = r.sized(hint)?;
+                }
+            }
+            let len = r.sized(hint)?;
+            let name = r.string(len)?;
+            let metadata = match info >> 24 {
+                0xff => {
+                    let len = r.sized(hint)?;
+                    r.string(len)?
+                }
+                index => self.indexed_metadata(index),
+            };
+            let node = *children.get(properties.len()).context("property header without a property")?;
+            properties.push(Property {
+                name,
+                kind,
+                pod: info >> 4 & 0xf,
+                extent: (info >> 16 & 0xff).max(1),
+                samples,
+                first_changed,
+                last_changed,
+                time_sampling,
+                metadata,
+                node,
+            });
+        }
+        Ok(properties)
+    }
+
+    /// Headers of the properties of a compound property.
+    fn compound(&self, property: &Property) -> Result<Vec<Property>> {
+        ensure!(property.kind == PropertyKind::Compound, "`{}` is not a compound property", property.name);
+        self.properties(self.file.group(property.node)?)
+    }
+
+    fn time_sampling(&self, property: &Property) -> &TimeSampling {
+        self.time_samplings
+            .get(property.time_sampling as usize)
+            .unwrap_or(&DEFAULT_TIME_SAMPLING)
+    }
+
+    /// Last sample of `property` taken at or before `time`, or its first.
+    fn sample_at(&self, property: &Property, time: f64) -> u32 {
+        let sampling = self.time_sampling(property);
+        let (mut lo, mut hi) = (0, property.samples.max(1));
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if sampling.time(mid) <= time + TIME_EPSILON {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    /// Values of sample `index` of a scalar or array property, widened to
+    /// `f64`.
+    fn numbers(&self, property: &Property, index: u32) -> Result<Vec<f64>> {
+        ensure!(property.kind != PropertyKind::Compound, "`{}` is a compound property", property.name);
+        let children = self.file.children(self.file.group(property.node)?)?;
+        let stored = property.stored(index.min(property.samples.saturating_sub(1))) as usize;
+        // Array samples are each followed by their dimensions.
+        let at = match property.kind {
+            PropertyKind::Array => 2 * stored,
+            _ => stored,
+        };
+        let node = *children.get(at).with_context(|| format!("`{}` is missing a sample", property.name))?;
+        let bytes = self.file.data(node)?.get(SAMPLE_KEY_SIZE..).unwrap_or(&[]);
+        let (size, decode): (usize, fn(&[u8]) -> f64) = match property.pod {
+            0 | 1 => (1, |b| b[0] as f64),
+            2 => (1, |b| b[0] as i8 as f64),
+            3 => (2, |b| u16::from_le_bytes([b[0], b[1]]) as f64),
+            4 => (2, |b| i16::from_le_bytes([b[0], b[1]]) as f64),
+            5 => (4, |b| u32::from_le_bytes(b.try_into().unwrap()) as f64),
+            6 => (4, |b| i32::from_le_bytes(b.try_into().unwrap()) as f64),
+            7 => (8, |b| u64::from_le_bytes(b.try_into().unwrap()) as f64),
+            8 => (8, |b| i64::from_le_bytes(b.try_into().unwrap()) as f64),
+            9 => (2, |b| ktx2::half_to_f32(u16::from_le_bytes([b[0], b[1]])) as f64),
+            10 => (4, |b| f32::from_le_bytes(b.try_into().unwrap()) as f64),
+            11 => (8, |b| f64::from_le_bytes(b.try_into().unwrap())),
+            pod => bail!("`{}` holds values of unsupported type {pod}", property.name),
+        };
+        Ok(bytes.chunks_exact(size).map(decode).collect())
+    }
+
+    fn vec3s(&self, property: &Property, index: u32) -> Result<Vec<[f32; 3]>> {
+        ensure!(property.extent == 3, "`{}` does not hold 3-vectors", property.name);
+        let values = self.numbers(property, index)?;
+        Ok(values.chunks_exact(3).map(|v| [v[0] as f32, v[1] as f32, v[2] as f32]).collect())
+    }
+
+    fn indices(&self, property: &Property, index: u32) -> Result<Vec<u32>> {
+        self.numbers(property, index)?
+            .into_iter()
+            .map(|i| {
+                ensure!(i >= 0.0 && i <= u32::MAX as f64, "`{}` holds a negative index", property.name);
+                Ok(i as u32)
+            })
+            .collect()
+    }
+}
+
+fn find<'p>(properties: &'p [Property], name: &str) -> Option<&'p Property> {
+    properties.iter().find(|property| property.name == name)
+}
+
+/// One sample of an `Xform`'s transform relative to its parent.
+struct XformSample {
+    time: f64,
+    local: Mat4,
+    /// Whether the parent's transform applies as well.
+    inherits: bool,
+}
+
+#[derive(Copy, Clone)]
+enum Scope {
+    Face,
+    Point,
+    Corner,
+}
+
+/// Normals given per face, per point or per face corner, possibly through
+/// indices.
+struct Normals {
+    values: Vec<[f32; 3]>,
+    indices: Option<Vec<u32>>,
+    scope: Scope,
+}
+
+impl Normals {
+    /// The normal at corner `corner` of face `face`, which is point `point`.
+    fn at(&self, face: usize, corner: usize, point: usize) -> Option<[f32; 3]> {
+        let i = match self.scope {
+            Scope::Face => face,
+            Scope::Point => point,
+            Scope::Corner => corner,
+        };
+        let i = match &self.indices {
+            Some(indices) => *indices.get(i)? as usize,
+            None => i,
+        };
+        self.values.get(i).copied()
+    }
+}
+
+struct Importer<'a> {
+    archive: &'a Archive<'a>,
+    scene: Scene,
+    /// Samples of the `Xform`s above the object visited, outermost first.
+    transforms: Vec<Vec<XformSample>>,
+}
+
+impl Importer<'_> {
+    fn visit(&mut self, object: &ObjectHeader, path: &str, depth: usize) -> Result<()> {
+        ensure!(depth <= MAX_OBJECT_DEPTH, "objects nest more than {MAX_OBJECT_DEPTH} deep");
+        let (properties, children) = self.archive.object(object.group)?;
+        let properties = match properties {
+            Some(group) => self.archive.properties(group)?,
+            None => Vec::new(),
+        };
+        let schema = meta(&object.metadata, "schema").unwrap_or("");
+        let is_xform = schema.starts_with("AbcGeom_Xform_");
+        if is_xform {
+            let samples = self.xform(find(&properties, ".xform")).with_context(|| format!("xform `{path}`"))?;
+            self.transforms.push(samples);
+        }
+        if schema.starts_with("AbcGeom_PolyMesh_") || schema.starts_with("AbcGeom_SubD_") {
+            if let Some(geom) = find(&properties, ".geom") {
+                self.mesh(geom, path).with_context(|| format!("mesh `{path}`"))?;
+            }
+        }
+        for child in &children {
+            self.visit(child, &format!("{path}/{}", child.name), depth + 1)?;
+        }
+        if is_xform {
+            self.transforms.pop();
+        }
+        Ok(())
+    }
+
+    /// Every sample of an `Xform`'s local transform. A constant identity
+    /// leaves out the properties altogether.
+    fn xform(&self, schema: Option<&Property>) -> Result<Vec<XformSample>> {
+        let identity = vec![XformSample {
+            time: 0.0,
+            local: IDENTITY,
+            inherits: true,
+        }];
+        let Some(schema) = schema else {
+            return Ok(identity);
+        };
+        let properties = self.archive.compound(schema)?;
+        let (Some(ops), Some(vals)) = (find(&properties, ".ops"), find(&properties, ".vals")) else {
+            return Ok(identity);
+        };
+        let ops = self.archive.numbers(ops, 0)?;
+        let inherits = find(&properties, ".inherits");
+        let sampling = self.archive.time_sampling(vals);
+        (0..vals.samples.max(1))
+            .map(|i| {
+                let inherits = match inherits {
+                    Some(inherits) => self.archive.numbers(inherits, i)?.first() != Some(&0.0),
+                    None => true,
+                };
+                Ok(XformSample {
+                    time: sampling.time(i),
+                    local: compose(&ops, &self.archive.numbers(vals, i)?)?,
+                    inherits,
+                })
+            })
+            .collect()
+    }
+
+    /// Transform from object to world space at `time`.
+    fn world(&self, time: f64) -> Mat4 {
+        self.transforms.iter().fold(IDENTITY, |world, samples| {
+            let i = samples.partition_point(|sample| sample.time <= time + TIME_EPSILON);
+            let sample = &samples[i.saturating_sub(1)];
+            match sample.inherits {
+                true => gltf::mat_mul(&world, &sample.local),
+                false => sample.local,
+            }
+        })
+    }
+
+    fn mesh(&mut self, schema: &Property, path: &str) -> Result<()> {
+        let properties = self.archive.compound(schema)?;
+        let property = |name: &str| find(&properties, name).with_context(|| format!("missing `{name}`"));
+        let points = property("P")?;
+        let counts = self.archive.indices(property(".faceCounts")?, 0)?;
+        let indices = self.archive.indices(property(".faceIndices")?, 0)?;
+        ensure!(
+            counts.iter().map(|&n| n as usize).sum::<usize>() == indices.len(),
+            "`.faceCounts` does not add up to the {} face indices",
+            indices.len()
+        );
+
+        // Points are sampled at their own times if they move, or else at
+        // those of the transform above them that changes most often.
+        let times: Vec<f64> = if points.samples > 1 {
+            let sampling = self.archive.time_sampling(points);
+            (0..points.samples).map(|i| sampling.time(i)).collect()
+        } else {
+            let samples = self.transforms.iter().max_by_key(|samples| samples.len());
+            samples.map_or(vec![0.0], |samples| samples.iter().map(|sample| sample.time).collect())
+        };
+        let first_points = self.archive.vec3s(points, self.archive.sample_at(points, times[0]))?;
+        if let Some(&i) = indices.iter().find(|&&i| i as usize >= first_points.len()) {
+            bail!("face index {i} is out of range for {} points", first_points.len());
+        }
+        let normals = self.normals(&properties, &counts, &indices, first_points.len())?;
+        // Vertices are shared between faces unless normals vary per face or
+        // per corner.
+        let shared = normals.as_ref().is_none_or(|it| matches!(it.scope, Scope::Point));
+
+        let world = self.world(times[0]);
+        let first_vertex = self.scene.positions.len() as u32;
+        let mut point_vertices = vec![u32::MAX; first_points.len()];
+        let mut vertex_points = Vec::new();
+        let mut start = 0;
+        for (face, &count) in counts.iter().enumerate() {
+            let range = start..start + count as usize;
+            start = range.end;
+            if count < 3 {
+                continue;
+            }
+            let mut corners: Vec<u32> = range
+                .map(|corner| {
+                    let point = indices[corner] as usize;
+                    if shared && point_vertices[point] != u32::MAX {
+                        return point_vertices[point];
+                    }
+                    let vertex = self.scene.positions.len() as u32;
+                    self.scene.positions.push(gltf::transform_point(&world, first_points[point]));
+                    let normal = normals.as_ref().and_then(|it| it.at(face, corner, point));
+                    self.scene
+                        .normals
+                        .push(normal.map_or([0.0; 3], |it| gltf::transform_normal(&world, it)));
+                    point_vertices[point] = vertex;
+                    vertex_points.push(point as u32);
+                    vertex
+                })
+                .collect();
+            // Alembic polygons wind clockwise.
+            corners.reverse();
+            let positions: Vec<Vec3> = corners
+                .iter()
+                .map(|&i| {
+                    let [x, y, z] = self.scene.positions[i as usize];
+                    Vec3::new(x, y, z)
+                })
+                .collect();
+            self.scene.triangles.extend(obj::triangulate(&positions).into_iter().map(|[a, b, c]| Triangle {
+                indices: [a, b, c].map(|k| corners[k]),
+                material: 0,
+            }));
+        }
+
+        if times.len() > 1 {
+            let mut positions = Vec::with_capacity(times.len());
+            for &time in &times {
+                let world = self.world(time);
+                let points = self.archive.vec3s(points, self.archive.sample_at(points, time))?;
+                if points.len() != first_points.len() {
+                    crash::log!("warning: mesh `{path}` changes its point count over time; only its first sample is kept");
+                    return Ok(());
+                }
+                positions.push(
+                    vertex_points
+                        .iter()
+                        .map(|&point| gltf::transform_point(&world, points[point as usize]))
+                        .collect(),
+                );
+            }
+            self.scene.vertex_animations.push(VertexAnimation {
+                first: first_vertex,
+                times: times.iter().map(|&time| time as f32).collect(),
+                positions,
+            });
+        }
+        Ok(())
+    }
+
+    /// The mesh's first sample of `N`, either an array or a compound of
+    /// `.vals` and `.indices`, with its scope from its `geoScope` or else
+    /// from how many there are.
+    fn normals(&self, properties: &[Property], counts: &[u32], indices: &[u32], points: usize) -> Result<Option<Normals>> {
+        let Some(property) = find(properties, "N") else {
+            return Ok(None);
+        };
+        let (values, indexed) = match property.kind {
+            PropertyKind::Compound => {
+                let parts = self.archive.compound(property)?;
+                let values = find(&parts, ".vals").context("`N` has no `.vals`")?;
+                let indexed = match find(&parts, ".indices") {
+                    Some(it) => Some(self.archive.indices(it, 0)?),
+                    None => None,
+                };
+                (self.archive.vec3s(values, 0)?, indexed)
+            }
+            _ => (self.archive.vec3s(property, 0)?, None),
+        };
+        let count = indexed.as_ref().map_or(values.len(), Vec::len);
+        let scope = match meta(&property.metadata, "geoScope") {
+            Some("uni") => Scope::Face,
+            Some("var" | "vtx") => Scope::Point,
+            Some("fvr") => Scope::Corner,
+            _ if count == indices.len() => Scope::Corner,
+            _ if count == points => Scope::Point,
+            _ if count == counts.len() => Scope::Face,
+            _ => return Ok(None),
+        };
+        Ok(Some(Normals {
+            values,
+            indices: indexed,
+            scope,
+        }))
+    }
+}
+
+/// Local transform of an `Xform` from its operations, each with its kind in
+/// the high nibble of a byte, and their values in order.
+fn compose(ops: &[f64], values: &[f64]) -> Result<Mat4> {
+    let mut m = IDENTITY;
+    let mut rest = values;
+    for &op in ops {
+        let kind = op as u8 >> 4;
+        let count = match kind {
+            // Scale, translate, rotate about an axis and matrix.
+            0 | 1 => 3,
+            2 => 4,
+            3 => 16,
+            // Rotate about X, Y and Z.
+            4..=6 => 1,
+            _ => bail!("unknown xform operation {kind}"),
+        };
+        ensure!(rest.len() >= count, "xform has fewer values than its operations take");
+        let (v, tail) = rest.split_at(count);
+        rest = tail;
+        let v: Vec<f32> = v.iter().map(|&it| it as f32).collect();
+        let mut op_matrix = IDENTITY;
+        match kind {
+            0 => {
+                for (i, &s) in v.iter().enumerate() {
+                    op_matrix[i][i] = s;
+                }
+            }
+            1 => op_matrix[3] = [v[0], v[1], v[2], 1.0],
+            2 => op_matrix = axis_angle([v[0], v[1], v[2]], v[3]),
+            // Rows of a matrix that transforms row vectors, which are the
+            // columns of one that transforms column vectors.
+            3 => {
+                for (column, row) in op_matrix.iter_mut().zip(v.chunks_exact(4)) {
+                    column.copy_from_slice(row);
+                }
+            }
+            _ => {
+                let mut axis = [0.0; 3];
+                axis[kind as usize - 4] = 1.0;
+                op_matrix = axis_angle(axis, v[0]);
+            }
+        }
+        m = gltf::mat_mul(&m, &op_matrix);
+    }
+    Ok(m)
+}
+
+/// Rotation about `axis` by `degrees`, counterclockwise looking down it.
+fn axis_angle(axis: [f32; 3], degrees: f32) -> Mat4 {
+    let length = Vec3::new(axis[0], axis[1], axis[2]).length();
+    if length == 0.0 {
+        return IDENTITY;
+    }
+    let [x, y, z] = axis.map(|c| c / length);
+    let (s, c) = degrees.to_radians().sin_cos();
+    let t = 1.0 - c;
+    [
+        [t * x * x + c, t * x * y + s * z, t * x * z - s * y, 0.0],
+        [t * x * y - s * z, t * y * y + c, t * y * z + s * x, 0.0],
+        [t * x * z + s * y, t * y * z - s * x, t * z * z + c, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
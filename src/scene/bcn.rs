@@ -0,0 +1,163 @@
+//! The BC1 to BC7 block-compressed formats, which store 4x4 texel blocks
+//! of 8 or 16 bytes in rows of blocks, and CPU decoders for BC1 to BC5.
+
+use anyhow::{ensure, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// RGB with optional 1-bit alpha.
+    Bc1,
+    /// RGB with explicit 4-bit alpha.
+    Bc2,
+    /// RGB with interpolated alpha.
+    Bc3,
+    /// One unsigned channel, decoded into red.
+    Bc4,
+    /// Two unsigned channels, decoded into red and green.
+    Bc5,
+    /// Unsigned half-float RGB.
+    Bc6h,
+    Bc7,
+}
+
+impl BlockFormat {
+    pub fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    /// Bytes of a `width` by `height` image, padded out to whole blocks.
+    pub fn image_size(self, width: u32, height: u32) -> usize {
+        width.div_ceil(4) as usize * height.div_ceil(4) as usize * self.block_size()
+    }
+}
+
+/// Decodes a `width` by `height` image to RGBA texels, row by row from the
+/// top. Channels a format lacks are 0, and alpha is 255. BC6H and BC7 are
+/// left to GPUs that sample them.
+///
+/// ```
+/// use raytracer::scene::bcn::{decode, BlockFormat};
+///
+/// // BC1 endpoints red and blue, and indices 0 to 3 in the first row.
+/// let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0, 0, 0];
+/// let texels = decode(BlockFormat::Bc1, 4, 4, &block).unwrap();
+/// assert_eq!(texels[..4], [[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]);
+/// // With the endpoints the other way round, the palette has three colors
+/// // and transparent black.
+/// let block = [0x1f, 0x00, 0x00, 0xf8, 0xe4, 0, 0, 0];
+/// let texels = decode(BlockFormat::Bc1, 4, 4, &block).unwrap();
+/// assert_eq!(texels[..4], [[0, 0, 255, 255], [255, 0, 0, 255], [128, 0, 128, 255], [0, 0, 0, 0]]);
+///
+/// // BC4 and BC5 endpoints 200 and 60 give eight values between them, and
+/// // 60 and 200 six, then 0 and 255. Indices 0, 1, 2 and 7, then 2, 5, 6
+/// // and 7.
+/// let eight = [200, 60, 0x88, 0x0e, 0, 0, 0, 0];
+/// let six = [60, 200, 0xaa, 0x0f, 0, 0, 0, 0];
+/// let texels = decode(BlockFormat::Bc4, 4, 4, &eight).unwrap();
+/// assert_eq!(texels[..4], [[200, 0, 0, 255], [60, 0, 0, 255], [180, 0, 0, 255], [80, 0, 0, 255]]);
+/// let texels = decode(BlockFormat::Bc5, 4, 4, &[eight, six].concat()).unwrap();
+/// assert_eq!(texels[..4], [[200, 88, 0, 255], [60, 172, 0, 255], [180, 0, 0, 255], [80, 255, 0, 255]]);
+///
+/// // Sizes are padded out to whole blocks, which must all be there.
+/// assert_eq!(decode(BlockFormat::Bc4, 2, 3, &eight).unwrap().len(), 6);
+/// assert!(decode(BlockFormat::Bc4, 5, 4, &eight).is_err());
+/// ```
+pub fn decode(format: BlockFormat, width: u32, height: u32, data: &[u8]) -> Result<Vec<[u8; 4]>> {
+    ensure!(
+        !matches!(format, BlockFormat::Bc6h | BlockFormat::Bc7),
+        "{format:?} textures need a GPU that samples them"
+    );
+    ensure!(
+        data.len() >= format.image_size(width, height),
+        "truncated {format:?} data"
+    );
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4);
+    let mut pixels = vec![[0, 0, 0, 255]; width * height];
+    for (i, block) in data.chunks_exact(format.block_size()).take(blocks_wide * height.div_ceil(4)).enumerate() {
+        let (bx, by) = (i % blocks_wide * 4, i / blocks_wide * 4);
+        for (j, texel) in decode_block(format, block).into_iter().enumerate() {
+            let (x, y) = (bx + j % 4, by + j / 4);
+            if x < width && y < height {
+                pixels[y * width + x] = texel;
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+fn decode_block(format: BlockFormat, block: &[u8]) -> [[u8; 4]; 16] {
+    match format {
+        BlockFormat::Bc1 => color_block(block, false),
+        BlockFormat::Bc2 => {
+            let mut texels = color_block(&block[8..], true);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = (alpha >> (4 * i) & 0xf) as u8 * 17;
+            }
+            texels
+        }
+        BlockFormat::Bc3 => {
+            let mut texels = color_block(&block[8..], true);
+            for (texel, alpha) in texels.iter_mut().zip(value_block(&block[..8])) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        BlockFormat::Bc4 => value_block(block).map(|r| [r, 0, 0, 255]),
+        BlockFormat::Bc5 => {
+            let (red, green) = (value_block(&block[..8]), value_block(&block[8..]));
+            std::array::from_fn(|i| [red[i], green[i], 0, 255])
+        }
+        BlockFormat::Bc6h | BlockFormat::Bc7 => unreachable!(),
+    }
+}
+
+/// Two RGB565 endpoints and 2-bit indices into a palette interpolated
+/// between them. BC1 blocks whose first endpoint is not the larger one have
+/// a three-color palette and transparent black instead, unless
+/// `four_color`, as in BC2 and BC3.
+fn color_block(block: &[u8], four_color: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let rgb = |c: u16| {
+        let (r, g, b) = ((c >> 11 & 31) as u32, (c >> 5 & 63) as u32, (c & 31) as u32);
+        [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+    };
+    let (a, b) = (rgb(c0), rgb(c1));
+    let mix = |wa: u32, wb: u32| {
+        let [r, g, b] = [0, 1, 2].map(|i| ((wa * a[i] + wb * b[i] + (wa + wb) / 2) / (wa + wb)) as u8);
+        [r, g, b, 255]
+    };
+    let palette = if four_color || c0 > c1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    };
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    std::array::from_fn(|i| palette[(indices >> (2 * i) & 3) as usize])
+}
+
+/// Two 8-bit endpoints and 3-bit indices into eight values between them,
+/// or six and the extremes 0 and 255 if the first endpoint is not larger.
+fn value_block(block: &[u8]) -> [u8; 16] {
+    let (v0, v1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [v0, v1, 0, 0, 0, 0, 0, 255];
+    if v0 > v1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as u32) * v0 + i as u32 * v1 + 3) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as u32) * v0 + i as u32 * v1 + 2) / 5;
+        }
+        palette[6] = 0;
+    }
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[(indices >> (3 * i) & 7) as usize] as u8)
+}
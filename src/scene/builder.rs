@@ -0,0 +1,285 @@
+//! Builds scenes in code, for generated content such as sphere fields, grids,
+//! grooms and stress tests. A generator is an ordinary function returning a
+//! [`Scene`], so it can be run again and handed to
+//! [`PathTracer::set_scene`](crate::render::PathTracer::set_scene) whenever
+//! its parameters change.
+//!
+//! ```no_run
+//! use raytracer::scene::{builder::SceneBuilder, Material};
+//!
+//! let mut builder = SceneBuilder::new();
+//! let red = builder.add_material(Material {
+//!     base_color: [0.8, 0.1, 0.1],
+//!     metallic: 0.0,
+//!     ..Material::default()
+//! });
+//! builder.add_plane([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+//! for x in -5..=5 {
+//!     for z in -5..=5 {
+//!         builder
+//!             .add_sphere([x as f32, 0.3, z as f32], 0.3)
+//!             .with_material(red);
+//!     }
+//! }
+//! let scene = builder.build().unwrap();
+//! ```
+
+use {
+    super::{
+        curve::{self, Curve, CurveShape},
+        gltf::Mat4,
+        light::AreaLight,
+        procedural::{Procedural, ProceduralPrimitive},
+        sdf::{Sdf, SdfNode},
+        splat::Splat,
+        Instance, Material, Mesh, Scene, SceneCamera, Shape, ShapeKind, Sphere, SphereMaterial, Triangle,
+        Visibility,
+    },
+    anyhow::{ensure, Result},
+    std::sync::Arc,
+};
+
+/// Accumulates objects into a [`Scene`]. Material 0 is the glTF default
+/// material, which every object uses until given another.
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+/// An object just added to a [`SceneBuilder`].
+pub struct Object<'a> {
+    target: Target<'a>,
+}
+
+enum Target<'a> {
+    Sphere(&'a mut Sphere),
+    Shape(&'a mut Shape),
+    Sdf(&'a mut Sdf),
+    Procedural(&'a mut Procedural),
+    Curves(&'a mut [Curve]),
+    Splat(&'a mut Splat),
+    Triangles(&'a mut [Triangle]),
+    Instance(&'a mut Instance),
+}
+
+impl Object<'_> {
+    /// Index into the builder's materials. For an instance this replaces the
+    /// materials of the mesh's triangles.
+    pub fn with_material(mut self, material: u32) -> Self {
+        match &mut self.target {
+            Target::Sphere(sphere) => sphere.material = SphereMaterial::Scene(material),
+            Target::Shape(shape) => shape.material = material,
+            Target::Sdf(sdf) => sdf.material = material,
+            Target::Procedural(procedural) => procedural.material = material,
+            Target::Curves(curves) => curves.iter_mut().for_each(|curve| curve.material = material),
+            Target::Splat(splat) => splat.material = material,
+            Target::Triangles(triangles) => triangles.iter_mut().for_each(|tri| tri.material = material),
+            Target::Instance(instance) => instance.material = Some(material),
+        }
+        self
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self {
+            scene: Scene {
+                materials: vec![Material::default()],
+                ..Scene::default()
+            },
+        }
+    }
+
+    /// Returns the index objects refer to the material by.
+    pub fn add_material(&mut self, material: Material) -> u32 {
+        self.scene.materials.push(material);
+        self.scene.materials.len() as u32 - 1
+    }
+
+    pub fn add_sphere(&mut self, center: [f32; 3], radius: f32) -> Object<'_> {
+        self.scene.spheres.push(Sphere {
+            center,
+            radius,
+            material: SphereMaterial::Scene(0),
+        });
+        Object {
+            target: Target::Sphere(self.scene.spheres.last_mut().unwrap()),
+        }
+    }
+
+    /// Parallelogram spanned by `u` and `v` from `corner`.
+    pub fn add_quad(&mut self, corner: [f32; 3], u: [f32; 3], v: [f32; 3]) -> Object<'_> {
+        self.add_shape(ShapeKind::Quad { corner, u, v })
+    }
+
+    /// Axis-aligned box between two opposite corners.
+    pub fn add_box(&mut self, a: [f32; 3], b: [f32; 3]) -> Object<'_> {
+        self.add_shape(ShapeKind::Box {
+            min: [0, 1, 2].map(|i| a[i].min(b[i])),
+            max: [0, 1, 2].map(|i| a[i].max(b[i])),
+        })
+    }
+
+    pub fn add_plane(&mut self, point: [f32; 3], normal: [f32; 3]) -> Object<'_> {
+        self.add_shape(ShapeKind::Plane { point, normal })
+    }
+
+    fn add_shape(&mut self, kind: ShapeKind) -> Object<'_> {
+        self.scene.shapes.push(Shape { kind, material: 0 });
+        Object {
+            target: Target::Shape(self.scene.shapes.last_mut().unwrap()),
+        }
+    }
+
+    pub fn add_sdf(&mut self, root: SdfNode) -> Object<'_> {
+        self.scene.sdfs.push(Sdf { root, material: 0 });
+        Object {
+            target: Target::Sdf(self.scene.sdfs.last_mut().unwrap()),
+        }
+    }
+
+    /// Shape intersected by its own WGSL function; see
+    /// [`procedural`](super::procedural).
+    pub fn add_procedural(&mut self, primitive: impl ProceduralPrimitive + 'static) -> Object<'_> {
+        self.scene.procedurals.push(Procedural {
+            primitive: Arc::new(primitive),
+            material: 0,
+        });
+        Object {
+            target: Target::Procedural(self.scene.procedurals.last_mut().unwrap()),
+        }
+    }
+
+    /// Cubic Bézier curve with radius `radius[0]` at its start and
+    /// `radius[1]` at its end.
+    pub fn add_curve(&mut self, points: [[f32; 3]; 4], radius: [f32; 2], shape: CurveShape) -> Object<'_> {
+        self.scene.curves.push(Curve {
+            points,
+            radius,
+            shape,
+            material: 0,
+        });
+        let last = self.scene.curves.len() - 1;
+        Object {
+            target: Target::Curves(&mut self.scene.curves[last..]),
+        }
+    }
+
+    /// Smooth strand through `points`, with the radius given at each point.
+    /// Fails if there is not one radius per point.
+    ///
+    /// ```
+    /// use raytracer::scene::{builder::SceneBuilder, curve::CurveShape};
+    ///
+    /// let mut builder = SceneBuilder::new();
+    /// let points = [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.2, 2.0, 0.0]];
+    /// assert!(builder.add_strand(&points, &[0.02, 0.01, 0.0], CurveShape::Round).is_ok());
+    /// assert!(builder.add_strand(&points, &[0.02], CurveShape::Round).is_err());
+    /// ```
+    pub fn add_strand(&mut self, points: &[[f32; 3]], radii: &[f32], shape: CurveShape) -> Result<Object<'_>> {
+        ensure!(
+            points.len() == radii.len(),
+            "a strand needs one radius per point, not {} radii for {} points",
+            radii.len(),
+            points.len()
+        );
+        let first = self.scene.curves.len();
+        self.scene.curves.extend(curve::strand(points, radii, shape, 0));
+        Ok(Object {
+            target: Target::Curves(&mut self.scene.curves[first..]),
+        })
+    }
+
+    /// Point of a cloud, drawn as a disk or Gaussian. Its `material` is
+    /// replaced like any other object's.
+    pub fn add_splat(&mut self, splat: Splat) -> Object<'_> {
+        self.scene.splats.push(Splat { material: 0, ..splat });
+        Object {
+            target: Target::Splat(self.scene.splats.last_mut().unwrap()),
+        }
+    }
+
+    /// World-space triangles indexing into `positions`. Without `normals` the
+    /// mesh is flat-shaded.
+    pub fn add_mesh(
+        &mut self,
+        positions: &[[f32; 3]],
+        normals: Option<&[[f32; 3]]>,
+        indices: &[[u32; 3]],
+    ) -> Object<'_> {
+        let first = self.scene.triangles.len();
+        self.scene.add_mesh(positions, normals, indices, 0);
+        Object {
+            target: Target::Triangles(&mut self.scene.triangles[first..]),
+        }
+    }
+
+    /// Stores a mesh to be placed with [`SceneBuilder::add_instance`] and
+    /// returns its index.
+    pub fn add_instanced_mesh(&mut self, mesh: Mesh) -> u32 {
+        self.scene.meshes.push(mesh);
+        self.scene.meshes.len() as u32 - 1
+    }
+
+    /// Places mesh `mesh` with the object-to-world `transform`, which must be
+    /// invertible. The mesh keeps its own materials unless given one.
+    pub fn add_instance(&mut self, mesh: u32, transform: Mat4) -> Object<'_> {
+        self.scene.instances.push(Instance {
+            mesh,
+            transform,
+            material: None,
+            visibility: Visibility::ALL,
+        });
+        Object {
+            target: Target::Instance(self.scene.instances.last_mut().unwrap()),
+        }
+    }
+
+    pub fn add_light(&mut self, light: AreaLight) -> &mut Self {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn camera(&mut self, camera: SceneCamera) -> &mut Self {
+        self.scene.camera = Some(camera);
+        self
+    }
+
+    /// Checks that every reference resolves and the scene fits the
+    /// renderer's fixed-size tables.
+    pub fn build(self) -> Result<Scene> {
+        let scene = self.scene;
+        let materials = scene.materials.len() as u32;
+        let meshes = scene.meshes.len() as u32;
+        ensure!(
+            scene.triangles.iter().all(|tri| tri.material < materials)
+                && scene.spheres.iter().all(|sphere| match sphere.material {
+                    SphereMaterial::Scene(m) => m < materials,
+                    _ => true,
+                })
+                && scene.shapes.iter().all(|shape| shape.material < materials)
+                && scene.sdfs.iter().all(|sdf| sdf.material < materials)
+                && scene.procedurals.iter().all(|procedural| procedural.material < materials)
+                && scene.curves.iter().all(|curve| curve.material < materials)
+                && scene.splats.iter().all(|splat| splat.material < materials)
+                && scene.instances.iter().all(|instance| instance.material.is_none_or(|m| m < materials))
+                && scene.meshes.iter().flat_map(|mesh| &mesh.triangles).all(|tri| tri.material < materials),
+            "an object uses a material that was never added"
+        );
+        ensure!(
+            scene.instances.iter().all(|instance| instance.mesh < meshes),
+            "an instance places a mesh that was never added"
+        );
+        ensure!(
+            scene.meshes.iter().flat_map(|mesh| &mesh.lods).all(|lod| lod.mesh < meshes),
+            "a mesh has a LOD that was never added"
+        );
+        scene.check_limits()?;
+        Ok(scene)
+    }
+}
@@ -0,0 +1,271 @@
+//! Bounding volume hierarchies over triangles or instance boxes, built on the
+//! CPU with the surface area heuristic and flattened for traversal in
+//! shader.wgsl. Hierarchies over triangles also cut their leaf order into
+//! [meshlets](super::meshlet).
+
+use {
+    super::{meshlet::{self, Meshlet}, Triangle},
+    bytemuck::{Pod, Zeroable},
+    std::ops::Range,
+};
+
+/// Deepest a node may be. Must not exceed the traversal stack in shader.wgsl.
+pub const MAX_DEPTH: usize = 64;
+
+/// Centroid bins per axis when searching for a split.
+const BINS: usize = 16;
+
+/// Cost of visiting a node relative to testing one triangle.
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// Must match `BvhNode` in shader.wgsl.
+///
+/// Interior nodes have `count == 0` and their children at `first` and
+/// `first + 1`; leaves hold `count` primitives starting at `first` in
+/// [`Bvh::order`].
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+pub struct Bvh {
+    /// The root is node 0.
+    pub nodes: Vec<BvhNode>,
+    /// Primitive indices, ordered so that every leaf is a contiguous run.
+    pub order: Vec<u32>,
+    /// Clusters of consecutive triangles in `order`, for hierarchies over
+    /// triangles.
+    pub meshlets: Vec<Meshlet>,
+}
+
+#[derive(Copy, Clone)]
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Aabb {
+    const EMPTY: Aabb = Aabb {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    fn grow(&mut self, p: [f32; 3]) {
+        self.min = [0, 1, 2].map(|i| self.min[i].min(p[i]));
+        self.max = [0, 1, 2].map(|i| self.max[i].max(p[i]));
+    }
+
+    fn union(&mut self, other: &Aabb) {
+        self.min = [0, 1, 2].map(|i| self.min[i].min(other.min[i]));
+        self.max = [0, 1, 2].map(|i| self.max[i].max(other.max[i]));
+    }
+
+    fn half_area(&self) -> f32 {
+        let [x, y, z] = [0, 1, 2].map(|i| (self.max[i] - self.min[i]).max(0.0));
+        x * y + y * z + z * x
+    }
+}
+
+impl Bvh {
+    /// Builds the hierarchy over `triangles` indexing into `positions`.
+    pub fn build(positions: &[[f32; 3]], triangles: &[Triangle], leaf_size: usize) -> Bvh {
+        let bounds = triangles
+            .iter()
+            .map(|tri| {
+                let mut aabb = Aabb::EMPTY;
+                for i in tri.indices {
+                    aabb.grow(positions[i as usize]);
+                }
+                aabb
+            })
+            .collect();
+        let mut bvh = Bvh::from_bounds(bounds, leaf_size);
+        bvh.meshlets = meshlet::build(positions, triangles, &bvh);
+        bvh
+    }
+
+    /// Builds the hierarchy over `(min, max)` boxes, such as placed instances.
+    pub fn over_boxes(boxes: &[([f32; 3], [f32; 3])], leaf_size: usize) -> Bvh {
+        let bounds = boxes.iter().map(|&(min, max)| Aabb { min, max }).collect();
+        Bvh::from_bounds(bounds, leaf_size)
+    }
+
+    /// Splits [`Bvh::order`] into runs of at most `max` primitives, each
+    /// holding the primitives of one subtree, so that every run is spatially
+    /// compact. Leaves larger than `max` stay whole.
+    ///
+    /// ```
+    /// use raytracer::scene::bvh::Bvh;
+    ///
+    /// // Sixteen unit boxes in a row, listed out of order.
+    /// let place = |i: u32| (i * 7 % 16) as f32 * 2.0;
+    /// let boxes: Vec<_> = (0..16).map(|i| ([place(i), 0.0, 0.0], [place(i) + 1.0, 1.0, 1.0])).collect();
+    /// let bvh = Bvh::over_boxes(&boxes, 1);
+    /// let runs = bvh.subtree_runs(4);
+    /// // The runs cover the order from start to end without overlapping.
+    /// assert_eq!(runs.first().unwrap().start, 0);
+    /// assert_eq!(runs.last().unwrap().end, 16);
+    /// assert!(runs.windows(2).all(|pair| pair[0].end == pair[1].start));
+    /// for run in runs {
+    ///     // Each holds at most four boxes, next to each other in the row.
+    ///     assert!((1..=4).contains(&run.len()));
+    ///     let xs: Vec<f32> = bvh.order[run.clone()].iter().map(|&i| place(i)).collect();
+    ///     let span = xs.iter().copied().fold(f32::MIN, f32::max) - xs.iter().copied().fold(f32::MAX, f32::min);
+    ///     assert_eq!(span, 2.0 * (run.len() - 1) as f32);
+    /// }
+    /// assert!(Bvh::over_boxes(&[], 1).subtree_runs(4).is_empty());
+    /// ```
+    pub fn subtree_runs(&self, max: usize) -> Vec<Range<usize>> {
+        // An empty hierarchy's root looks like an interior node.
+        if self.order.is_empty() {
+            return Vec::new();
+        }
+        // Children come after their parent, and the left child's run comes
+        // before the right child's.
+        let mut runs = vec![0..0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate().rev() {
+            let first = node.first as usize;
+            runs[i] = match node.count {
+                0 => runs[first].start..runs[first + 1].end,
+                count => first..first + count as usize,
+            };
+        }
+        let mut out = Vec::new();
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if node.count > 0 || runs[i].len() <= max {
+                out.push(runs[i].clone());
+            } else {
+                let first = node.first as usize;
+                stack.extend([first + 1, first]);
+            }
+        }
+        out
+    }
+
+    /// Binned SAH splits. Nodes with at most `leaf_size` primitives become
+    /// leaves once splitting stops paying off; larger nodes are always split.
+    fn from_bounds(bounds: Vec<Aabb>, leaf_size: usize) -> Bvh {
+        let centroids: Vec<[f32; 3]> = bounds
+            .iter()
+            .map(|b| [0, 1, 2].map(|i| (b.min[i] + b.max[i]) * 0.5))
+            .collect();
+
+        let mut order: Vec<u32> = (0..bounds.len() as u32).collect();
+        let mut nodes = vec![BvhNode::zeroed()];
+        // (node, first, count, depth) still to be filled in.
+        let mut pending = vec![(0, 0, order.len(), 0)];
+        while let Some((node, first, count, depth)) = pending.pop() {
+            let range = &mut order[first..first + count];
+            let mut aabb = Aabb::EMPTY;
+            let mut centroid_bounds = Aabb::EMPTY;
+            for &tri in range.iter() {
+                aabb.union(&bounds[tri as usize]);
+                centroid_bounds.grow(centroids[tri as usize]);
+            }
+            nodes[node] = BvhNode {
+                min: aabb.min,
+                first: first as u32,
+                max: aabb.max,
+                count: count as u32,
+            };
+            if count <= 1 || depth + 1 >= MAX_DEPTH {
+                continue;
+            }
+
+            let left_count = match best_split(range, &bounds, &centroids, &centroid_bounds) {
+                Some(split) if count > leaf_size || split.cost / aabb.half_area() + TRAVERSAL_COST < count as f32 => {
+                    partition(range, |tri| {
+                        bin_index(&centroid_bounds, split.axis, centroids[tri as usize]) < split.bin
+                    })
+                }
+                // No plane separates coincident centroids, so halve the run.
+                None if count > leaf_size => count / 2,
+                _ => continue,
+            };
+
+            let left = nodes.len();
+            nodes.push(BvhNode::zeroed());
+            nodes.push(BvhNode::zeroed());
+            nodes[node].first = left as u32;
+            nodes[node].count = 0;
+            pending.push((left, first, left_count, depth + 1));
+            pending.push((left + 1, first + left_count, count - left_count, depth + 1));
+        }
+
+        Bvh {
+            nodes,
+            order,
+            meshlets: Vec::new(),
+        }
+    }
+}
+
+struct Split {
+    /// Child half areas weighted by their primitive counts.
+    cost: f32,
+    axis: usize,
+    /// First bin on the right-hand side.
+    bin: usize,
+}
+
+fn bin_index(centroid_bounds: &Aabb, axis: usize, centroid: [f32; 3]) -> usize {
+    let (lo, hi) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+    (((centroid[axis] - lo) * (BINS as f32 / (hi - lo))) as usize).min(BINS - 1)
+}
+
+/// Cheapest binned split of `range`, or `None` if the centroids do not spread
+/// along any axis.
+fn best_split(range: &[u32], bounds: &[Aabb], centroids: &[[f32; 3]], centroid_bounds: &Aabb) -> Option<Split> {
+    let mut best: Option<Split> = None;
+    for axis in 0..3 {
+        if centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+            continue;
+        }
+        let mut bins = [(Aabb::EMPTY, 0usize); BINS];
+        for &tri in range {
+            let bin = &mut bins[bin_index(centroid_bounds, axis, centroids[tri as usize])];
+            bin.0.union(&bounds[tri as usize]);
+            bin.1 += 1;
+        }
+
+        // Sweep from the right to get the cost of every right-hand side.
+        let mut right_costs = [0.0; BINS];
+        let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+        for bin in (1..BINS).rev() {
+            aabb.union(&bins[bin].0);
+            count += bins[bin].1;
+            right_costs[bin] = aabb.half_area() * count as f32;
+        }
+        let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+        for bin in 1..BINS {
+            aabb.union(&bins[bin - 1].0);
+            count += bins[bin - 1].1;
+            if count == 0 || count == range.len() {
+                continue;
+            }
+            let cost = aabb.half_area() * count as f32 + right_costs[bin];
+            if best.as_ref().is_none_or(|best| cost < best.cost) {
+                best = Some(Split { cost, axis, bin });
+            }
+        }
+    }
+    best
+}
+
+/// Moves the elements matching `left` to the front and returns their count.
+fn partition(range: &mut [u32], left: impl Fn(u32) -> bool) -> usize {
+    let mut split = 0;
+    for i in 0..range.len() {
+        if left(range[i]) {
+            range.swap(i, split);
+            split += 1;
+        }
+    }
+    split
+}
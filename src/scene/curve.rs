@@ -0,0 +1,80 @@
+//! Cubic Bézier curves for hair and fur, intersected in shader.wgsl by
+//! subdividing them in the space of each ray.
+
+use super::gltf::{self, Mat4};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Curve {
+    /// Control points of a cubic Bézier; the curve passes through the first
+    /// and last.
+    pub points: [[f32; 3]; 4],
+    /// Radius at the start and end, interpolated linearly in between.
+    pub radius: [f32; 2],
+    pub shape: CurveShape,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CurveShape {
+    /// Shaded as a tube.
+    Round,
+    /// A flat strip turned towards every ray, cheaper to light for very fine
+    /// strands.
+    Ribbon,
+}
+
+impl Curve {
+    /// Axis-aligned `(min, max)` bounds, from the hull of the control points.
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let radius = self.radius[0].max(self.radius[1]);
+        let (lo, hi) = self.points[1..].iter().fold((self.points[0], self.points[0]), |(lo, hi), p| {
+            ([0, 1, 2].map(|i| lo[i].min(p[i])), [0, 1, 2].map(|i| hi[i].max(p[i])))
+        });
+        (lo.map(|c| c - radius), hi.map(|c| c + radius))
+    }
+
+    /// Applies `rotate`, which must preserve lengths, followed by a uniform
+    /// `scale`.
+    pub fn transformed(self, rotate: impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> Curve {
+        Curve {
+            points: self.points.map(|p| rotate(p).map(|c| c * scale)),
+            radius: self.radius.map(|r| r * scale.abs()),
+            ..self
+        }
+    }
+
+    /// Applies an affine transform. Radii scale with the cube root of the
+    /// volume change, so non-uniform scales only approximately keep them.
+    pub fn transformed_by(self, m: &Mat4) -> Curve {
+        let scale = gltf::determinant(m).abs().cbrt();
+        Curve {
+            points: self.points.map(|p| gltf::transform_point(m, p)),
+            radius: self.radius.map(|r| r * scale),
+            ..self
+        }
+    }
+}
+
+/// Curves through every point of a strand, as a uniform Catmull-Rom spline
+/// with one Bézier segment per pair of neighboring points. `radii` holds the
+/// radius at each point.
+pub fn strand(points: &[[f32; 3]], radii: &[f32], shape: CurveShape, material: u32) -> Vec<Curve> {
+    let at = |i: isize| points[i.clamp(0, points.len() as isize - 1) as usize];
+    (0..points.len().saturating_sub(1) as isize)
+        .map(|i| {
+            let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+            Curve {
+                points: [
+                    p1,
+                    [0, 1, 2].map(|k| p1[k] + (p2[k] - p0[k]) / 6.0),
+                    [0, 1, 2].map(|k| p2[k] - (p3[k] - p1[k]) / 6.0),
+                    p2,
+                ],
+                radius: [radii[i as usize], radii[i as usize + 1]],
+                shape,
+                material,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,229 @@
+//! DirectDraw Surface textures (`.dds`), as written by game asset pipelines.
+//!
+//! Only the first surface is read, so arrays and cube maps give their first
+//! layer or face. BC1 to BC7 blocks, named by a legacy FourCC or a DX10
+//! header, are kept with their mip chain for the renderer to upload as they
+//! are. Legacy BC1 to BC3 files carry no color space and are taken as sRGB,
+//! like most color textures. Uncompressed 8-bit RGBA and BGRA DX10 formats
+//! and legacy RGB, luminance and alpha bit masks are decoded to pixels.
+
+use {
+    super::{
+        bcn::BlockFormat,
+        texture::{self, BlockImage, Texture},
+    },
+    anyhow::{bail, ensure, Context, Result},
+};
+
+pub const MAGIC: &[u8; 4] = b"DDS ";
+
+const HEADER_SIZE: usize = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+
+const DXGI_R8G8B8A8_UNORM: u32 = 28;
+const DXGI_R8G8B8A8_UNORM_SRGB: u32 = 29;
+const DXGI_R8_UNORM: u32 = 61;
+const DXGI_B8G8R8A8_UNORM: u32 = 87;
+const DXGI_B8G8R8X8_UNORM: u32 = 88;
+const DXGI_B8G8R8A8_UNORM_SRGB: u32 = 91;
+const DXGI_B8G8R8X8_UNORM_SRGB: u32 = 93;
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+
+/// How the first surface's texels are stored.
+enum Layout {
+    /// Uncompressed pixels of `bytes` bytes each, whose `rgba` bit masks
+    /// select their channels. Grey pixels keep their luminance in red.
+    Pixels {
+        bytes: usize,
+        rgba: [u32; 4],
+        grey: bool,
+        srgb: bool,
+    },
+    Blocks {
+        format: BlockFormat,
+        srgb: bool,
+    },
+}
+
+/// Reads the first surface of a DDS file.
+///
+/// ```
+/// use raytracer::scene::{bcn::BlockFormat, dds};
+///
+/// // A 4x4 texture with a DX10 header and `mips` levels of `format`.
+/// fn dds(format: u32, mips: u32, data: &[u8]) -> Vec<u8> {
+///     let mut file = vec![0; 148];
+///     file[..4].copy_from_slice(b"DDS ");
+///     file[84..88].copy_from_slice(b"DX10");
+///     // Header size, height, width, mip count, pixel format flags (FourCC),
+///     // DXGI format and resource dimension (2D).
+///     for (at, value) in [(4, 124), (12, 4), (16, 4), (28, mips), (80, 0x4), (128, format), (132, 3)] {
+///         file[at..at + 4].copy_from_slice(&u32::to_le_bytes(value));
+///     }
+///     file.extend_from_slice(data);
+///     file
+/// }
+///
+/// // BC1_UNORM_SRGB, whose 4x4, 2x2 and 1x1 levels take a block each.
+/// let texture = dds::parse(&dds(72, 3, &[0; 24])).unwrap();
+/// let blocks = texture.blocks.unwrap();
+/// assert_eq!((blocks.format, blocks.srgb, blocks.levels.len()), (BlockFormat::Bc1, true, 3));
+/// // BC5_UNORM, with a truncated last level.
+/// let err = dds::parse(&dds(83, 3, &[0; 47])).unwrap_err();
+/// assert_eq!(err.to_string(), "truncated mip level 2");
+/// // R8G8B8A8_UNORM pixels, which are linear, are encoded as sRGB.
+/// let texture = dds::parse(&dds(28, 1, &[[0x80, 0, 0xff, 0xff]; 16].concat())).unwrap();
+/// assert_eq!(texture.pixels[0], [0xbc, 0, 0xff, 0xff]);
+/// ```
+pub fn parse(bytes: &[u8]) -> Result<Texture> {
+    ensure!(bytes.starts_with(MAGIC), "not a DDS file");
+    ensure!(bytes.len() >= MAGIC.len() + HEADER_SIZE, "truncated header");
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    ensure!(u32_at(4) as usize == HEADER_SIZE, "invalid header size");
+    let (height, width, mip_count) = (u32_at(12), u32_at(16), u32_at(28));
+    ensure!(width > 0 && height > 0, "image is empty");
+    ensure!(u32_at(112) & DDSCAPS2_VOLUME == 0, "volume textures are not supported");
+
+    let (layout, data_offset) = layout(bytes)?;
+    let data = &bytes[data_offset..];
+    match layout {
+        Layout::Blocks { format, srgb } => {
+            // Mip levels follow each other, largest first.
+            let mut offset = 0;
+            let levels = (0..mip_count.clamp(1, 32))
+                .map(|i| {
+                    let size = format.image_size((width >> i).max(1), (height >> i).max(1));
+                    let level = data
+                        .get(offset..offset + size)
+                        .with_context(|| format!("truncated mip level {i}"))?;
+                    offset += size;
+                    Ok(level.to_vec())
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Texture {
+                width,
+                height,
+                pixels: Vec::new(),
+                blocks: Some(BlockImage { format, srgb, levels }),
+                source: None,
+            })
+        }
+        Layout::Pixels { bytes, rgba, grey, srgb } => {
+            let texels = width as usize * height as usize;
+            ensure!(data.len() >= texels * bytes, "truncated pixel data");
+            let channel = |pixel: u32, mask: u32| -> Option<u8> {
+                if mask == 0 {
+                    return None;
+                }
+                let max = (1u64 << mask.count_ones()) - 1;
+                let value = ((pixel & mask) >> mask.trailing_zeros()) as u64;
+                Some(((value * 255 + max / 2) / max) as u8)
+            };
+            let pixels = data
+                .chunks_exact(bytes)
+                .take(texels)
+                .map(|texel| {
+                    let mut word = [0; 4];
+                    word[..bytes].copy_from_slice(texel);
+                    let pixel = u32::from_le_bytes(word);
+                    let [r, g, b, a] = rgba.map(|mask| channel(pixel, mask));
+                    let [r, g, b] = match grey {
+                        true => [r.unwrap_or(0); 3],
+                        false => [r, g, b].map(|c| c.unwrap_or(0)),
+                    };
+                    let [r, g, b] = if srgb { [r, g, b] } else { [r, g, b].map(texture::linear_to_srgb8) };
+                    [r, g, b, a.unwrap_or(255)]
+                })
+                .collect();
+            Ok(Texture {
+                width,
+                height,
+                pixels,
+                blocks: None,
+                source: None,
+            })
+        }
+    }
+}
+
+/// The pixel format and where the texels start, after the DX10 header if
+/// there is one.
+fn layout(bytes: &[u8]) -> Result<(Layout, usize)> {
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let (pf_flags, four_cc, bit_count) = (u32_at(80), &bytes[84..88], u32_at(88));
+    let data_offset = MAGIC.len() + HEADER_SIZE;
+    let blocks = |format, srgb| Ok((Layout::Blocks { format, srgb }, data_offset));
+
+    if pf_flags & DDPF_FOURCC == 0 {
+        ensure!(
+            pf_flags & (DDPF_RGB | DDPF_LUMINANCE | DDPF_ALPHA) != 0,
+            "unsupported pixel format"
+        );
+        ensure!(matches!(bit_count, 8 | 16 | 24 | 32), "unsupported {bit_count}-bit pixels");
+        let masks = [92, 96, 100, 104].map(u32_at);
+        let alpha = if pf_flags & (DDPF_ALPHAPIXELS | DDPF_ALPHA) != 0 { masks[3] } else { 0 };
+        // Legacy headers have no color space; color images are mostly sRGB.
+        let layout = Layout::Pixels {
+            bytes: bit_count as usize / 8,
+            rgba: [masks[0], masks[1], masks[2], alpha],
+            grey: pf_flags & DDPF_RGB == 0,
+            srgb: true,
+        };
+        return Ok((layout, data_offset));
+    }
+    match four_cc {
+        b"DXT1" => return blocks(BlockFormat::Bc1, true),
+        b"DXT2" | b"DXT3" => return blocks(BlockFormat::Bc2, true),
+        b"DXT4" | b"DXT5" => return blocks(BlockFormat::Bc3, true),
+        b"ATI1" | b"BC4U" => return blocks(BlockFormat::Bc4, false),
+        b"ATI2" | b"BC5U" => return blocks(BlockFormat::Bc5, false),
+        b"DX10" => (),
+        other => bail!("unsupported FourCC `{}`", String::from_utf8_lossy(other)),
+    }
+
+    let dx10 = data_offset;
+    ensure!(bytes.len() >= dx10 + DX10_HEADER_SIZE, "truncated DX10 header");
+    ensure!(
+        u32_at(dx10 + 4) != D3D10_RESOURCE_DIMENSION_TEXTURE3D,
+        "volume textures are not supported"
+    );
+    let data_offset = dx10 + DX10_HEADER_SIZE;
+    let blocks = |format, srgb| Ok((Layout::Blocks { format, srgb }, data_offset));
+    let pixels = |bytes, rgba, srgb| {
+        let grey = bytes == 1;
+        Ok((Layout::Pixels { bytes, rgba, grey, srgb }, data_offset))
+    };
+    const RGBA: [u32; 4] = [0xff, 0xff00, 0xff_0000, 0xff00_0000];
+    const BGRA: [u32; 4] = [0xff_0000, 0xff00, 0xff, 0xff00_0000];
+    const BGRX: [u32; 4] = [0xff_0000, 0xff00, 0xff, 0];
+    // Typeless and UNORM formats hold linear colors.
+    match u32_at(dx10) {
+        70 | 71 => blocks(BlockFormat::Bc1, false),
+        72 => blocks(BlockFormat::Bc1, true),
+        73 | 74 => blocks(BlockFormat::Bc2, false),
+        75 => blocks(BlockFormat::Bc2, true),
+        76 | 77 => blocks(BlockFormat::Bc3, false),
+        78 => blocks(BlockFormat::Bc3, true),
+        79 | 80 => blocks(BlockFormat::Bc4, false),
+        82 | 83 => blocks(BlockFormat::Bc5, false),
+        94 | 95 => blocks(BlockFormat::Bc6h, false),
+        97 | 98 => blocks(BlockFormat::Bc7, false),
+        99 => blocks(BlockFormat::Bc7, true),
+        81 | 84 | 96 => bail!("signed BC4, BC5 and BC6H textures are not supported"),
+        DXGI_R8G8B8A8_UNORM => pixels(4, RGBA, false),
+        DXGI_R8G8B8A8_UNORM_SRGB => pixels(4, RGBA, true),
+        DXGI_B8G8R8A8_UNORM => pixels(4, BGRA, false),
+        DXGI_B8G8R8A8_UNORM_SRGB => pixels(4, BGRA, true),
+        DXGI_B8G8R8X8_UNORM => pixels(4, BGRX, false),
+        DXGI_B8G8R8X8_UNORM_SRGB => pixels(4, BGRX, true),
+        DXGI_R8_UNORM => pixels(1, [0xff, 0, 0, 0], false),
+        other => bail!("unsupported DXGI format {other}"),
+    }
+}
@@ -0,0 +1,765 @@
+//! Declarative JSON scene files that place meshes, materials, lights and a
+//! camera without recompiling:
+//!
+//! ```json
+//! {
+//!   "camera": { "lookfrom": [0, 1, 4], "lookat": [0, 0.5, 0], "vup": [0, 1, 0], "vfov": 40 },
+//!   "materials": {
+//!     "clay": { "base_color": [0.8, 0.6, 0.5], "roughness": 0.7 },
+//!     "chrome": { "base_color": [0.9, 0.9, 0.9], "metallic": 1, "roughness": 0.05 },
+//!     "lacquer": { "base_color": [0.6, 0.1, 0.1], "roughness": 0.2, "ior": 1.6 },
+//!     "matte": { "type": "lambertian", "base_color": [0.7, 0.3, 0.3] },
+//!     "brushed": { "type": "metal", "base_color": [0.8, 0.6, 0.2], "fuzz": 0.3 },
+//!     "glass": { "type": "dielectric", "ior": 1.5 },
+//!     "neon": { "base_color": [0, 0, 0], "emission": [4, 1, 6] },
+//!     "brick": { "base_color_texture": "brick.jpg", "normal_texture": "brick_normal.png", "roughness": 0.9 }
+//!   },
+//!   "meshes": {
+//!     "tree": { "file": "tree.glb", "lods": [
+//!       { "mesh": "tree_low", "screen_size": 0.1 },
+//!       { "mesh": "tree_card", "distance": 200 }
+//!     ] },
+//!     "tree_low": "tree_low.glb",
+//!     "tree_card": "tree_card.obj"
+//!   },
+//!   "objects": [
+//!     { "mesh": "floor.obj" },
+//!     { "mesh": "bunny.glb", "material": "clay", "translate": [0, 0, 0], "rotate": [0, 90, 0], "scale": 2 },
+//!     { "mesh": "head.obj", "subdivision": 2 },
+//!     { "mesh": "fur.hair", "material": "clay", "curve_shape": "ribbon", "scale": 0.01 },
+//!     { "mesh": "scan.ply", "splat_shape": "gaussian", "splat_radius": 0.005 },
+//!     { "mesh": "catcher.obj", "visibility": { "camera": false } },
+//!     { "sphere": { "center": [1, 0.3, 0], "radius": 0.3 }, "material": "chrome" },
+//!     { "quad": { "corner": [-2, 0, -2], "u": [4, 0, 0], "v": [0, 2, 0] }, "material": "clay" },
+//!     { "box": { "min": [-1.5, 0, -1], "max": [-1, 0.5, -0.5] }, "material": "clay" },
+//!     { "plane": { "point": [0, 0, 0], "normal": [0, 1, 0] }, "material": "clay" },
+//!     { "sdf": { "smoothness": 0.2, "primitives": [
+//!       { "shape": "sphere", "center": [2, 0.5, 0], "radius": 0.3 },
+//!       { "shape": "box", "center": [2, 0.2, 0], "half_size": [0.4, 0.2, 0.4] },
+//!       { "shape": "torus", "center": [2, 0.8, 0], "axis": [0, 1, 0], "major_radius": 0.3, "minor_radius": 0.05 }
+//!     ] }, "material": "clay" },
+//!     { "csg": { "difference": [
+//!       { "shape": "box", "center": [-2, 0.5, 0], "half_size": [0.5, 0.5, 0.5] },
+//!       { "shape": "sphere", "center": [-2, 0.5, 0], "radius": 0.65 }
+//!     ] }, "material": "chrome" },
+//!     { "instance": "tree", "translate": [-3, 0, -2] },
+//!     { "instance": "tree", "translate": [3, 0, -2], "rotate": [0, 45, 0], "material": "clay" },
+//!     { "volume": "smoke.nvdb", "density_scale": 4, "albedo": [0.9, 0.9, 0.9], "scale": 0.01 }
+//!   ],
+//!   "lights": [
+//!     { "shape": "rect", "corner": [-1, 3, -1], "u": [2, 0, 0], "v": [0, 0, 2], "emission": [8, 8, 8], "gobo": "window.pgm" },
+//!     { "shape": "disc", "center": [2, 2, 0], "normal": [-1, -1, 0], "radius": 0.3, "emission": [20, 18, 15] },
+//!     { "shape": "sphere", "center": [-2, 1, 1], "radius": 0.2, "emission": [30, 30, 30] },
+//!     { "shape": "tube", "a": [-1, 2, -2], "b": [1, 2, -2], "radius": 0.02, "emission": [40, 40, 40] }
+//!   ]
+//! }
+//! ```
+//!
+//! Materials are metallic-roughness unless their `type` is `lambertian`,
+//! `metal`, whose reflections `fuzz` blurs from 0 to 1, or `dielectric`,
+//! which refracts by `ior`; all three take their color from `base_color`.
+//! A `base_color_texture` image, relative to the scene file, multiplies
+//! `base_color` (white with a texture) where triangles have texture
+//! coordinates; it is read like a `gobo`. A `normal_texture`, a
+//! tangent-space normal map with +Y up the image as in glTF, bends the
+//! shading normals of textured triangles, its X and Y scaled by
+//! `normal_scale` (1 by default). An `emission` makes a material glow, and
+//! meshes with it light the scene like area lights.
+//! Mesh paths are relative to the scene file. Meshes without a `material`
+//! keep the materials of their file. A glTF file loads as all the meshes of
+//! its node hierarchy, flattened together. A `.usda` or `.usdz` stage loads as
+//! all of its meshes, with their preview materials. An Alembic `.abc` cache
+//! loads as all of its polygon meshes, keeping every sample of animated
+//! ones. A `.hair` file loads as
+//! round curves unless `curve_shape` is `ribbon`. A `.ply` file with faces
+//! loads as a mesh, colored by its vertex colors if it has them. `.ply`
+//! files without faces and `.xyz` files are point clouds, which load as
+//! disks facing their normals, or the ray where they have none; a
+//! `splat_shape` of `gaussian` fades them out towards the rim and
+//! `splat_radius` sets the size of every point, in the file's units. A
+//! `subdivision` level smooths a mesh before it is placed, by Catmull-Clark
+//! where it has quads or larger faces and by Loop where it is all
+//! triangles, up to level 6, dropping vertex colors. Named `meshes` are
+//! loaded once and placed by any number of `instance` objects, which share
+//! their triangles. A named mesh given with its `file` may list `lods`,
+//! other named meshes from finest to coarsest; an instance draws the
+//! coarsest whose `distance` from the camera it is beyond, or whose
+//! `screen_size`, a fraction of the view height, its bounding sphere spans
+//! less than, and the level is chosen again as the camera moves. A `mesh`
+//! or `instance` object's `visibility` can hide it from `camera` rays,
+//! `shadow` rays towards lights, or rays leaving `diffuse` or `specular`
+//! surfaces, all of which see it by default; a mesh that hides from any is
+//! placed as an instance of itself. A `volume` is a NanoVDB float density grid, whose
+//! densities are multiplied by `density_scale` (1 by default) to give
+//! extinction per unit of length and which scatters `albedo` (white by
+//! default) of what it stops.
+//! Rotations of meshes, instances and volumes are in degrees about X, then Y, then Z,
+//! applied after `scale` and before `translate`. Spheres, quads, boxes,
+//! planes and SDF shapes need a `material` and take no transform. An `sdf`
+//! blends its primitives with a smooth union whose blend radius is
+//! `smoothness` (0 by default). A `csg` is a tree whose leaves are the same
+//! primitives and whose nodes hold a `union`, `intersection` or `difference`
+//! (the first child minus the rest) of their children, each with an
+//! optional `smoothness`. Rect lights emit towards `u × v` and
+//! discs towards their `normal`. A rect `gobo` is a PNG, JPEG, binary PPM or PGM
+//! image or a KTX2 or DDS texture that tints the light, with its columns along `u` and its rows along `v`.
+
+use {
+    super::{
+        alembic,
+        curve::{Curve, CurveShape},
+        gltf::{self, Mat4, IDENTITY},
+        light::{AreaLight, LightShape},
+        lod::{Lod, LodSwitch},
+        hair, nanovdb, obj, ply,
+        sdf::{Sdf, SdfNode, SdfOp, SdfPrimitive, SDF_STACK_SIZE},
+        splat::{Splat, SplatShape},
+        subdivide::{self, Polygon},
+        texture::Texture,
+        usd,
+        volume::Volume,
+        xyz,
+        Instance, Material, MaterialKind, Mesh, Scene, SceneCamera, Shape, ShapeKind, Sphere, SphereMaterial, Triangle,
+        Visibility,
+    },
+    crate::{json::Json, math::Vec3},
+    anyhow::{bail, ensure, Context, Result},
+    std::{collections::HashMap, fs, path::Path},
+};
+
+pub fn load(path: &Path) -> Result<Scene> {
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let doc = Json::parse(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    import(&doc, base_dir).with_context(|| format!("failed to load {}", path.display()))
+}
+
+fn import(doc: &Json, base_dir: &Path) -> Result<Scene> {
+    let mut scene = Scene::default();
+
+    let mut material_ids = HashMap::new();
+    for (name, material) in doc.get("materials").and_then(Json::as_object).unwrap_or(&[]) {
+        let material = parse_material(&mut scene, material, base_dir).with_context(|| format!("in material `{name}`"))?;
+        material_ids.insert(name.as_str(), scene.materials.len() as u32);
+        scene.materials.push(material);
+    }
+
+    let meshes = doc.get("meshes").and_then(Json::as_object).unwrap_or(&[]);
+    let mut mesh_ids = HashMap::new();
+    for (name, entry) in meshes {
+        let file = entry.get("file").unwrap_or(entry);
+        let file = file
+            .as_str()
+            .with_context(|| format!("mesh `{name}` must be a file name or have a `file`"))?;
+        let mesh = load_mesh(base_dir, file).with_context(|| format!("in mesh `{name}`"))?;
+        ensure!(
+            mesh.curves.is_empty() && mesh.splats.is_empty(),
+            "mesh `{name}` has curves or points, which cannot be instanced"
+        );
+        let material_base = scene.materials.len() as u32;
+        let texture_base = scene.textures.len() as u32;
+        scene.materials.extend(mesh.materials.into_iter().map(|m| Material {
+            base_color_texture: m.base_color_texture.map(|i| texture_base + i),
+            normal_texture: m.normal_texture.map(|i| texture_base + i),
+            ..m
+        }));
+        scene.textures.extend(mesh.textures);
+        mesh_ids.insert(name.as_str(), scene.meshes.len() as u32);
+        scene.meshes.push(Mesh {
+            positions: mesh.positions,
+            normals: mesh.normals,
+            colors: mesh.colors,
+            uvs: mesh.uvs,
+            tangents: mesh.tangents,
+            triangles: mesh
+                .triangles
+                .into_iter()
+                .map(|tri| Triangle {
+                    material: material_base + tri.material,
+                    ..tri
+                })
+                .collect(),
+            lods: Vec::new(),
+        });
+    }
+    // Levels name meshes that may come after them.
+    for (name, entry) in meshes {
+        let lods = entry
+            .items("lods")
+            .iter()
+            .enumerate()
+            .map(|(i, lod)| parse_lod(lod, &mesh_ids).with_context(|| format!("in LOD {i}")))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("in mesh `{name}`"))?;
+        scene.meshes[mesh_ids[name.as_str()] as usize].lods = lods;
+    }
+
+    for (i, object) in doc.items("objects").iter().enumerate() {
+        add_object(&mut scene, object, base_dir, &material_ids, &mesh_ids).with_context(|| format!("in object {i}"))?;
+    }
+    for (i, light) in doc.items("lights").iter().enumerate() {
+        let light = parse_light(&mut scene, light, base_dir).with_context(|| format!("in light {i}"))?;
+        scene.lights.push(light);
+    }
+    scene.check_limits()?;
+
+    if let Some(camera) = doc.get("camera") {
+        scene.camera = Some(parse_camera(camera).context("in camera")?);
+    }
+    Ok(scene)
+}
+
+/// Unset fields default to a grey diffuse with the specular layer of an index
+/// of refraction of 1.5. Textures are added to `scene`.
+fn parse_material(scene: &mut Scene, json: &Json, base_dir: &Path) -> Result<Material> {
+    let ior = f32_or(json, "ior", 1.5)?;
+    ensure!(ior >= 1.0, "`ior` must be at least 1");
+    let kind = match json.get("type").map(|kind| kind.as_str()) {
+        None | Some(Some("metallic_roughness")) => MaterialKind::MetallicRoughness,
+        Some(Some("lambertian")) => MaterialKind::Lambertian,
+        Some(Some("metal")) => {
+            let fuzz = f32_or(json, "fuzz", 0.0)?;
+            ensure!((0.0..=1.0).contains(&fuzz), "`fuzz` must be between 0 and 1");
+            MaterialKind::Metal { fuzz }
+        }
+        Some(Some("dielectric")) => MaterialKind::Dielectric,
+        Some(_) => bail!("`type` must be `metallic_roughness`, `lambertian`, `metal` or `dielectric`"),
+    };
+    ensure!(
+        json.get("fuzz").is_none() || matches!(kind, MaterialKind::Metal { .. }),
+        "`fuzz` is only for `metal` materials"
+    );
+    let mut texture = |key: &str| -> Result<Option<u32>> {
+        let Some(file) = json.get(key) else { return Ok(None) };
+        let file = file.as_str().with_context(|| format!("`{key}` must be a file name"))?;
+        scene.textures.push(Texture::load(&base_dir.join(file))?);
+        Ok(Some(scene.textures.len() as u32 - 1))
+    };
+    let base_color_texture = texture("base_color_texture")?;
+    let normal_texture = texture("normal_texture")?;
+    let base_color = if base_color_texture.is_some() { [1.0; 3] } else { [0.8; 3] };
+    Ok(Material {
+        base_color: vec3_or(json, "base_color", base_color)?,
+        metallic: f32_or(json, "metallic", 0.0)?,
+        roughness: f32_or(json, "roughness", 1.0)?,
+        emission: vec3_or(json, "emission", [0.0; 3])?,
+        ior,
+        kind,
+        base_color_texture,
+        normal_texture,
+        normal_scale: f32_or(json, "normal_scale", 1.0)?,
+    })
+}
+
+fn parse_lod(json: &Json, mesh_ids: &HashMap<&str, u32>) -> Result<Lod> {
+    let name = json.get("mesh").and_then(Json::as_str).context("LOD has no `mesh` name")?;
+    let mesh = *mesh_ids.get(name).with_context(|| format!("unknown mesh `{name}`"))?;
+    let switch = match (json.get("distance"), json.get("screen_size")) {
+        (Some(_), None) => {
+            let distance = f32_or(json, "distance", 0.0)?;
+            ensure!(distance > 0.0, "LOD `distance` must be positive");
+            LodSwitch::Distance(distance)
+        }
+        (None, Some(_)) => {
+            let size = f32_or(json, "screen_size", 0.0)?;
+            ensure!(size > 0.0, "LOD `screen_size` must be positive");
+            LodSwitch::ScreenSize(size)
+        }
+        _ => bail!("LOD needs one of `distance` and `screen_size`"),
+    };
+    Ok(Lod { mesh, switch })
+}
+
+fn add_object(
+    scene: &mut Scene,
+    object: &Json,
+    base_dir: &Path,
+    material_ids: &HashMap<&str, u32>,
+    mesh_ids: &HashMap<&str, u32>,
+) -> Result<()> {
+    let material = match object.get("material") {
+        Some(name) => {
+            let name = name.as_str().context("`material` must be a name")?;
+            Some(*material_ids.get(name).with_context(|| format!("unknown material `{name}`"))?)
+        }
+        None => None,
+    };
+    let visibility = parse_visibility(object)?;
+    ensure!(
+        visibility == Visibility::ALL || object.get("mesh").is_some() || object.get("instance").is_some(),
+        "`visibility` applies only to `mesh` and `instance` objects"
+    );
+
+    if let Some(sphere) = object.get("sphere") {
+        let radius = f32_or(sphere, "radius", 0.0)?;
+        ensure!(radius > 0.0, "sphere `radius` must be positive");
+        scene.spheres.push(Sphere {
+            center: vec3(sphere, "center")?,
+            radius,
+            material: SphereMaterial::Scene(material.context("sphere has no `material`")?),
+        });
+        return Ok(());
+    }
+    let root = match (object.get("sdf"), object.get("csg")) {
+        (Some(sdf), _) => {
+            let children = sdf
+                .items("primitives")
+                .iter()
+                .enumerate()
+                .map(|(i, primitive)| {
+                    let primitive = parse_sdf_primitive(primitive).with_context(|| format!("in SDF primitive {i}"))?;
+                    Ok(SdfNode::Primitive(primitive))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            ensure!(!children.is_empty(), "SDF shape has no `primitives`");
+            Some(SdfNode::Operation {
+                op: SdfOp::Union,
+                children,
+                smoothness: smoothness(sdf)?,
+            })
+        }
+        (None, Some(csg)) => Some(parse_csg(csg)?),
+        (None, None) => None,
+    };
+    if let Some(root) = root {
+        ensure!(
+            root.stack_depth() <= SDF_STACK_SIZE,
+            "SDF shape nests too deeply (at most {SDF_STACK_SIZE} pending distances)"
+        );
+        scene.sdfs.push(Sdf {
+            root,
+            material: material.context("SDF shape has no `material`")?,
+        });
+        return Ok(());
+    }
+    if let Some(kind) = parse_shape(object)? {
+        scene.shapes.push(Shape {
+            kind,
+            material: material.context("shape has no `material`")?,
+        });
+        return Ok(());
+    }
+
+    if let Some(name) = object.get("instance") {
+        let name = name.as_str().context("`instance` must be a mesh name")?;
+        let transform = parse_transform(object)?;
+        ensure!(gltf::determinant(&transform) != 0.0, "instance `scale` must be nonzero");
+        scene.instances.push(Instance {
+            mesh: *mesh_ids.get(name).with_context(|| format!("unknown mesh `{name}`"))?,
+            transform,
+            material,
+            visibility,
+        });
+        return Ok(());
+    }
+
+    if let Some(file) = object.get("volume") {
+        let file = file.as_str().context("`volume` must be a file name")?;
+        let transform = parse_transform(object)?;
+        ensure!(gltf::determinant(&transform) != 0.0, "volume `scale` must be nonzero");
+        let density_scale = f32_or(object, "density_scale", 1.0)?;
+        ensure!(density_scale >= 0.0, "`density_scale` must not be negative");
+        let albedo = vec3_or(object, "albedo", [1.0; 3])?;
+        ensure!(
+            albedo.iter().all(|&a| (0.0..=1.0).contains(&a)),
+            "`albedo` must be between 0 and 1"
+        );
+        let path = base_dir.join(file);
+        let volume = nanovdb::load(&path).with_context(|| format!("failed to load {}", path.display()))?;
+        scene.volumes.push(Volume {
+            density_scale,
+            albedo,
+            ..volume.transformed(&transform)
+        });
+        return Ok(());
+    }
+
+    let file = object
+        .get("mesh")
+        .and_then(Json::as_str)
+        .context("object has none of `mesh`, `instance`, `volume`, `sphere`, `quad`, `box`, `plane`, `sdf` or `csg`")?;
+    let mut mesh = match object.get("subdivision") {
+        Some(level) => {
+            let level = level.as_usize().context("`subdivision` must be a whole number")?;
+            ensure!(
+                level as u32 <= subdivide::MAX_LEVEL,
+                "`subdivision` must be at most {}",
+                subdivide::MAX_LEVEL
+            );
+            load_subdivided(base_dir, file, level as u32)?
+        }
+        None => load_mesh(base_dir, file)?,
+    };
+
+    let transform = parse_transform(object)?;
+    for p in &mut mesh.positions {
+        *p = gltf::transform_point(&transform, *p);
+    }
+    for p in mesh.vertex_animations.iter_mut().flat_map(|it| &mut it.positions).flatten() {
+        *p = gltf::transform_point(&transform, *p);
+    }
+    for n in &mut mesh.normals {
+        *n = gltf::transform_normal(&transform, *n);
+    }
+    for t in &mut mesh.tangents {
+        *t = gltf::transform_tangent(&transform, *t);
+    }
+    let shape = match object.get("curve_shape").map(|shape| shape.as_str()) {
+        None => None,
+        Some(Some("round")) => Some(CurveShape::Round),
+        Some(Some("ribbon")) => Some(CurveShape::Ribbon),
+        Some(_) => bail!("`curve_shape` must be `round` or `ribbon`"),
+    };
+    for curve in &mut mesh.curves {
+        *curve = Curve {
+            shape: shape.unwrap_or(curve.shape),
+            ..curve.transformed_by(&transform)
+        };
+    }
+    let splat_shape = match object.get("splat_shape").map(|shape| shape.as_str()) {
+        None => None,
+        Some(Some("disk")) => Some(SplatShape::Disk),
+        Some(Some("gaussian")) => Some(SplatShape::Gaussian),
+        Some(_) => bail!("`splat_shape` must be `disk` or `gaussian`"),
+    };
+    let splat_radius = match object.get("splat_radius") {
+        Some(_) => {
+            let radius = f32_or(object, "splat_radius", 0.0)?;
+            ensure!(radius > 0.0, "`splat_radius` must be positive");
+            Some(radius)
+        }
+        None => None,
+    };
+    for splat in &mut mesh.splats {
+        *splat = Splat {
+            radius: splat_radius.unwrap_or(splat.radius),
+            shape: splat_shape.unwrap_or(splat.shape),
+            ..*splat
+        }
+        .transformed_by(&transform);
+    }
+    if visibility != Visibility::ALL {
+        mesh = placed_once(mesh, visibility)?;
+    }
+    scene.append(mesh, material);
+    Ok(())
+}
+
+/// Every kind of ray sees an object unless its `visibility` says otherwise.
+fn parse_visibility(object: &Json) -> Result<Visibility> {
+    let Some(json) = object.get("visibility") else {
+        return Ok(Visibility::ALL);
+    };
+    let mut visibility = Visibility::ALL;
+    for (key, value) in json.as_object().context("`visibility` must be an object")? {
+        let visible = match key.as_str() {
+            "camera" => &mut visibility.camera,
+            "shadow" => &mut visibility.shadow,
+            "diffuse" => &mut visibility.diffuse,
+            "specular" => &mut visibility.specular,
+            _ => bail!("unknown `visibility` ray `{key}` (expected camera, shadow, diffuse or specular)"),
+        };
+        *visible = value.as_bool().with_context(|| format!("`visibility` `{key}` must be true or false"))?;
+    }
+    Ok(visibility)
+}
+
+/// `mesh` with its world-space triangles moved into a mesh placed once,
+/// since only instances have a visibility.
+fn placed_once(mut mesh: Scene, visibility: Visibility) -> Result<Scene> {
+    ensure!(
+        mesh.curves.is_empty() && mesh.splats.is_empty() && mesh.vertex_animations.is_empty(),
+        "`visibility` applies only to triangle meshes, without curves, points or animation"
+    );
+    for instance in &mut mesh.instances {
+        instance.visibility = visibility;
+    }
+    if !mesh.triangles.is_empty() {
+        let placed = Mesh {
+            positions: std::mem::take(&mut mesh.positions),
+            normals: std::mem::take(&mut mesh.normals),
+            colors: std::mem::take(&mut mesh.colors),
+            uvs: std::mem::take(&mut mesh.uvs),
+            tangents: std::mem::take(&mut mesh.tangents),
+            triangles: std::mem::take(&mut mesh.triangles),
+            lods: Vec::new(),
+        };
+        mesh.meshes.push(placed);
+        mesh.instances.push(Instance {
+            mesh: mesh.meshes.len() as u32 - 1,
+            transform: IDENTITY,
+            material: None,
+            visibility,
+        });
+    }
+    Ok(mesh)
+}
+
+fn parse_shape(object: &Json) -> Result<Option<ShapeKind>> {
+    let vec = |[x, y, z]: [f32; 3]| Vec3::new(x, y, z);
+    if let Some(quad) = object.get("quad") {
+        let [u, v] = [vec3(quad, "u")?, vec3(quad, "v")?];
+        ensure!(vec(u).cross(&vec(v)).length() > 0.0, "quad has no area");
+        return Ok(Some(ShapeKind::Quad {
+            corner: vec3(quad, "corner")?,
+            u,
+            v,
+        }));
+    }
+    if let Some(aabb) = object.get("box") {
+        let [min, max] = [vec3(aabb, "min")?, vec3(aabb, "max")?];
+        ensure!((0..3).all(|i| min[i] < max[i]), "box `min` must be below `max` on every axis");
+        return Ok(Some(ShapeKind::Box { min, max }));
+    }
+    if let Some(plane) = object.get("plane") {
+        let normal = vec(vec3(plane, "normal")?);
+        ensure!(normal.length() > 0.0, "plane `normal` must be nonzero");
+        let normal = normal.normalized();
+        return Ok(Some(ShapeKind::Plane {
+            point: vec3(plane, "point")?,
+            normal: [normal.x(), normal.y(), normal.z()],
+        }));
+    }
+    Ok(None)
+}
+
+fn smoothness(json: &Json) -> Result<f32> {
+    let smoothness = f32_or(json, "smoothness", 0.0)?;
+    ensure!(smoothness >= 0.0, "`smoothness` must not be negative");
+    Ok(smoothness)
+}
+
+/// A primitive, or a node with one of the keys `union`, `intersection` or
+/// `difference` listing its children.
+fn parse_csg(json: &Json) -> Result<SdfNode> {
+    let Some((op, key)) = [
+        (SdfOp::Union, "union"),
+        (SdfOp::Intersection, "intersection"),
+        (SdfOp::Difference, "difference"),
+    ]
+    .into_iter()
+    .find(|(_, key)| json.get(key).is_some()) else {
+        return Ok(SdfNode::Primitive(parse_sdf_primitive(json)?));
+    };
+    let children = json
+        .items(key)
+        .iter()
+        .enumerate()
+        .map(|(i, child)| parse_csg(child).with_context(|| format!("in `{key}` child {i}")))
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(!children.is_empty(), "`{key}` has no children");
+    Ok(SdfNode::Operation {
+        op,
+        children,
+        smoothness: smoothness(json)?,
+    })
+}
+
+fn parse_sdf_primitive(primitive: &Json) -> Result<SdfPrimitive> {
+    let shape = primitive.get("shape").and_then(Json::as_str).context("primitive has no `shape`")?;
+    let positive = |key: &str| -> Result<f32> {
+        let value = f32_or(primitive, key, 0.0)?;
+        ensure!(value > 0.0, "`{key}` must be positive");
+        Ok(value)
+    };
+    let center = vec3(primitive, "center")?;
+    Ok(match shape {
+        "sphere" => SdfPrimitive::Sphere {
+            center,
+            radius: positive("radius")?,
+        },
+        "box" => {
+            let half_size = vec3(primitive, "half_size")?;
+            ensure!(half_size.iter().all(|&h| h > 0.0), "`half_size` must be positive");
+            SdfPrimitive::Box { center, half_size }
+        }
+        "torus" => {
+            let [x, y, z] = vec3_or(primitive, "axis", [0.0, 1.0, 0.0])?;
+            let axis = Vec3::new(x, y, z);
+            ensure!(axis.length() > 0.0, "torus `axis` must be nonzero");
+            let axis = axis.normalized();
+            SdfPrimitive::Torus {
+                center,
+                axis: [axis.x(), axis.y(), axis.z()],
+                major_radius: positive("major_radius")?,
+                minor_radius: positive("minor_radius")?,
+            }
+        }
+        _ => bail!("unknown SDF primitive `{shape}` (expected sphere, box or torus)"),
+    })
+}
+
+fn load_mesh(base_dir: &Path, file: &str) -> Result<Scene> {
+    let path = base_dir.join(file);
+    let extension = path.extension().and_then(|it| it.to_str()).map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gltf" | "glb") => {
+            let mut scene = gltf::load(&path)?;
+            scene.flatten_nodes();
+            Ok(scene)
+        }
+        Some("obj") => obj::load(&path),
+        Some("hair") => hair::load(&path),
+        Some("ply") => ply::load(&path),
+        Some("xyz") => xyz::load(&path),
+        Some("usda" | "usd" | "usdz") => usd::load(&path),
+        Some("abc") => alembic::load(&path),
+        _ => bail!("unsupported mesh format `{file}` (expected .gltf, .glb, .obj, .hair, .ply, .xyz, .usda, .usdz or .abc)"),
+    }
+}
+
+/// Loads `file` with its faces subdivided `level` times. OBJ, PLY and USD
+/// faces keep their quads; other formats only have triangles.
+fn load_subdivided(base_dir: &Path, file: &str, level: u32) -> Result<Scene> {
+    let path = base_dir.join(file);
+    let extension = path.extension().and_then(|it| it.to_str()).map(str::to_ascii_lowercase);
+    let (mut mesh, polygons) = if extension.as_deref() == Some("obj") {
+        obj::load_polygons(&path)?
+    } else if extension.as_deref() == Some("ply") {
+        ply::load_polygons(&path)?
+    } else if matches!(extension.as_deref(), Some("usda" | "usd" | "usdz")) {
+        usd::load_polygons(&path)?
+    } else {
+        let mesh = load_mesh(base_dir, file)?;
+        let polygons = mesh
+            .triangles
+            .iter()
+            .map(|tri| Polygon {
+                corners: tri.indices.to_vec(),
+                material: tri.material,
+            })
+            .collect();
+        (mesh, polygons)
+    };
+    ensure!(!polygons.is_empty(), "`subdivision` needs a mesh with faces");
+    // Vertex colors, texture coordinates, tangents and animation are not
+    // carried through subdivision.
+    mesh.colors.clear();
+    mesh.uvs.clear();
+    mesh.tangents.clear();
+    mesh.vertex_animations.clear();
+    (mesh.positions, mesh.normals, mesh.triangles) = subdivide::subdivide(&mesh.positions, &polygons, level);
+    Ok(mesh)
+}
+
+fn parse_light(scene: &mut Scene, light: &Json, base_dir: &Path) -> Result<AreaLight> {
+    let shape = light.get("shape").and_then(Json::as_str).context("light has no `shape`")?;
+    let radius = || -> Result<f32> {
+        let radius = f32_or(light, "radius", 0.0)?;
+        ensure!(radius > 0.0, "`radius` must be positive");
+        Ok(radius)
+    };
+    let shape = match shape {
+        "rect" => {
+            let [u, v] = [vec3(light, "u")?, vec3(light, "v")?];
+            let [u_vec, v_vec] = [u, v].map(|[x, y, z]| Vec3::new(x, y, z));
+            ensure!(
+                u_vec.dot(&v_vec).abs() <= 1e-4 * u_vec.length() * v_vec.length(),
+                "rect edges `u` and `v` must be perpendicular"
+            );
+            ensure!(u_vec.cross(&v_vec).length() > 0.0, "rect has no area");
+            LightShape::Rect {
+                corner: vec3(light, "corner")?,
+                u,
+                v,
+            }
+        }
+        "disc" => {
+            let [x, y, z] = vec3(light, "normal")?;
+            let normal = Vec3::new(x, y, z);
+            ensure!(normal.length() > 0.0, "disc `normal` must be nonzero");
+            let normal = normal.normalized();
+            LightShape::Disc {
+                center: vec3(light, "center")?,
+                normal: [normal.x(), normal.y(), normal.z()],
+                radius: radius()?,
+            }
+        }
+        "sphere" => LightShape::Sphere {
+            center: vec3(light, "center")?,
+            radius: radius()?,
+        },
+        "tube" => {
+            let [a, b] = [vec3(light, "a")?, vec3(light, "b")?];
+            ensure!(a != b, "tube ends `a` and `b` must differ");
+            LightShape::Tube { a, b, radius: radius()? }
+        }
+        _ => bail!("unknown light shape `{shape}` (expected rect, disc, sphere or tube)"),
+    };
+    let gobo = match light.get("gobo") {
+        Some(file) => {
+            ensure!(matches!(shape, LightShape::Rect { .. }), "only rect lights take a `gobo`");
+            let file = file.as_str().context("`gobo` must be a file name")?;
+            scene.textures.push(Texture::load(&base_dir.join(file))?);
+            Some(scene.textures.len() as u32 - 1)
+        }
+        None => None,
+    };
+    Ok(AreaLight {
+        shape,
+        emission: vec3(light, "emission")?,
+        gobo,
+    })
+}
+
+fn parse_camera(json: &Json) -> Result<SceneCamera> {
+    let vec = |[x, y, z]: [f32; 3]| Vec3::new(x, y, z);
+    Ok(SceneCamera {
+        lookfrom: vec(vec3(json, "lookfrom")?),
+        lookat: vec(vec3(json, "lookat")?),
+        vup: vec(vec3_or(json, "vup", [0.0, 1.0, 0.0])?),
+        vfov: f32_or(json, "vfov", 40.0)?,
+    })
+}
+
+/// `translate * rotate_z * rotate_y * rotate_x * scale`.
+fn parse_transform(object: &Json) -> Result<Mat4> {
+    let scale = match object.get("scale") {
+        None => [1.0; 3],
+        Some(Json::Number(s)) => [*s as f32; 3],
+        Some(_) => vec3(object, "scale")?,
+    };
+    let [rx, ry, rz] = vec3_or(object, "rotate", [0.0; 3])?.map(f32::to_radians);
+    let [tx, ty, tz] = vec3_or(object, "translate", [0.0; 3])?;
+
+    let rotation = |axis: usize, angle: f32| {
+        let (sin, cos) = angle.sin_cos();
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut m = IDENTITY;
+        m[a][a] = cos;
+        m[a][b] = sin;
+        m[b][a] = -sin;
+        m[b][b] = cos;
+        m
+    };
+    let mut m = IDENTITY;
+    for (i, s) in scale.into_iter().enumerate() {
+        m[i][i] = s;
+    }
+    m = gltf::mat_mul(&rotation(0, rx), &m);
+    m = gltf::mat_mul(&rotation(1, ry), &m);
+    m = gltf::mat_mul(&rotation(2, rz), &m);
+    m[3] = [tx, ty, tz, 1.0];
+    Ok(m)
+}
+
+fn vec3(json: &Json, key: &str) -> Result<[f32; 3]> {
+    let value = json.get(key).with_context(|| format!("missing `{key}`"))?;
+    value
+        .as_f32_array::<3>()
+        .with_context(|| format!("`{key}` must be an array of three numbers"))
+}
+
+fn vec3_or(json: &Json, key: &str, default: [f32; 3]) -> Result<[f32; 3]> {
+    match json.get(key) {
+        Some(_) => vec3(json, key),
+        None => Ok(default),
+    }
+}
+
+fn f32_or(json: &Json, key: &str, default: f32) -> Result<f32> {
+    match json.get(key) {
+        Some(value) => value.as_f32().with_context(|| format!("`{key}` must be a number")),
+        None => Ok(default),
+    }
+}
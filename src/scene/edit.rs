@@ -0,0 +1,233 @@
+//! Changes to a scene that is already being rendered. Every object is named
+//! by an [`ObjectId`], which also tells which of the scene's buffers editing
+//! it touches, so that
+//! [`PathTracer::update_scene`](crate::render::PathTracer::update_scene)
+//! rebuilds only those:
+//!
+//! ```no_run
+//! use raytracer::{render::PathTracer, scene::{edit::ObjectId, Scene}};
+//!
+//! fn raise(renderer: &mut PathTracer, scene: &mut Scene) -> anyhow::Result<()> {
+//!     let id = ObjectId::Sphere(0);
+//!     scene.move_object(id, [0.0, 0.1, 0.0])?;
+//!     renderer.update_scene(scene, id.changes())?;
+//!     Ok(())
+//! }
+//! ```
+
+use {
+    super::{
+        gltf::{self, Mat4, IDENTITY},
+        light::AreaLight,
+        sdf::Sdf,
+        volume::Volume,
+        Instance, Scene, Shape, Sphere, SphereMaterial,
+    },
+    anyhow::{bail, ensure, Context, Result},
+    std::ops::{BitOr, BitOrAssign},
+};
+
+/// Parts of a scene changed since it was uploaded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SceneChanges {
+    /// Meshes, world-space triangles, curves or splats, whose hierarchies
+    /// are the slowest part to rebuild.
+    pub geometry: bool,
+    /// Mesh placements, including node transforms.
+    pub instances: bool,
+    pub materials: bool,
+    /// Spheres, analytic shapes or SDF shapes.
+    pub shapes: bool,
+    pub lights: bool,
+    pub volumes: bool,
+    pub textures: bool,
+}
+
+impl SceneChanges {
+    /// Everything, as when the scene is replaced.
+    pub const ALL: SceneChanges = SceneChanges {
+        geometry: true,
+        instances: true,
+        materials: true,
+        shapes: true,
+        lights: true,
+        volumes: true,
+        textures: true,
+    };
+}
+
+impl BitOr for SceneChanges {
+    type Output = SceneChanges;
+
+    fn bitor(self, other: SceneChanges) -> SceneChanges {
+        SceneChanges {
+            geometry: self.geometry || other.geometry,
+            instances: self.instances || other.instances,
+            materials: self.materials || other.materials,
+            shapes: self.shapes || other.shapes,
+            lights: self.lights || other.lights,
+            volumes: self.volumes || other.volumes,
+            textures: self.textures || other.textures,
+        }
+    }
+}
+
+impl BitOrAssign for SceneChanges {
+    fn bitor_assign(&mut self, other: SceneChanges) {
+        *self = *self | other;
+    }
+}
+
+/// An object that can be added to or removed from a scene.
+#[derive(Clone, Debug)]
+pub enum SceneObject {
+    Instance(Instance),
+    Sphere(Sphere),
+    Shape(Shape),
+    Sdf(Sdf),
+    Light(AreaLight),
+    Volume(Volume),
+}
+
+/// Index of an object among those of its kind. Removing an object moves the
+/// later ones of its kind down by one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectId {
+    Instance(usize),
+    Sphere(usize),
+    Shape(usize),
+    Sdf(usize),
+    Light(usize),
+    Volume(usize),
+}
+
+impl ObjectId {
+    /// Parts of the scene that adding, removing or moving the object changes.
+    pub fn changes(self) -> SceneChanges {
+        let mut changes = SceneChanges::default();
+        match self {
+            ObjectId::Instance(_) => changes.instances = true,
+            ObjectId::Sphere(_) | ObjectId::Shape(_) | ObjectId::Sdf(_) => changes.shapes = true,
+            ObjectId::Light(_) => changes.lights = true,
+            ObjectId::Volume(_) => changes.volumes = true,
+        }
+        changes
+    }
+}
+
+impl Scene {
+    /// Adds `object` after the others of its kind, checking that the
+    /// materials, mesh and textures it refers to exist and that the scene
+    /// still fits the shader's tables.
+    pub fn add_object(&mut self, object: SceneObject) -> Result<ObjectId> {
+        let materials = self.materials.len() as u32;
+        let id = match object {
+            SceneObject::Instance(instance) => {
+                ensure!((instance.mesh as usize) < self.meshes.len(), "no mesh {}", instance.mesh);
+                ensure!(
+                    instance.material.is_none_or(|m| m < materials),
+                    "instance uses a missing material"
+                );
+                ensure!(gltf::determinant(&instance.transform) != 0.0, "instance transform must be invertible");
+                self.instances.push(instance);
+                ObjectId::Instance(self.instances.len() - 1)
+            }
+            SceneObject::Sphere(sphere) => {
+                if let SphereMaterial::Scene(m) = sphere.material {
+                    ensure!(m < materials, "sphere uses a missing material");
+                }
+                self.spheres.push(sphere);
+                ObjectId::Sphere(self.spheres.len() - 1)
+            }
+            SceneObject::Shape(shape) => {
+                ensure!(shape.material < materials, "shape uses a missing material");
+                self.shapes.push(shape);
+                ObjectId::Shape(self.shapes.len() - 1)
+            }
+            SceneObject::Sdf(sdf) => {
+                ensure!(sdf.material < materials, "SDF shape uses a missing material");
+                self.sdfs.push(sdf);
+                ObjectId::Sdf(self.sdfs.len() - 1)
+            }
+            SceneObject::Light(light) => {
+                ensure!(
+                    light.gobo.is_none_or(|gobo| (gobo as usize) < self.textures.len()),
+                    "light uses a missing gobo texture"
+                );
+                self.lights.push(light);
+                ObjectId::Light(self.lights.len() - 1)
+            }
+            SceneObject::Volume(volume) => {
+                ensure!(gltf::determinant(&volume.transform) != 0.0, "volume transform must be invertible");
+                self.volumes.push(volume);
+                ObjectId::Volume(self.volumes.len() - 1)
+            }
+        };
+        if let Err(err) = self.check_limits() {
+            self.remove_object(id)?;
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Takes an object out of the scene.
+    pub fn remove_object(&mut self, id: ObjectId) -> Result<SceneObject> {
+        fn take<T>(items: &mut Vec<T>, index: usize, kind: &str) -> Result<T> {
+            ensure!(index < items.len(), "no {kind} {index}");
+            Ok(items.remove(index))
+        }
+        Ok(match id {
+            ObjectId::Instance(i) => SceneObject::Instance(take(&mut self.instances, i, "instance")?),
+            ObjectId::Sphere(i) => SceneObject::Sphere(take(&mut self.spheres, i, "sphere")?),
+            ObjectId::Shape(i) => SceneObject::Shape(take(&mut self.shapes, i, "shape")?),
+            ObjectId::Sdf(i) => SceneObject::Sdf(take(&mut self.sdfs, i, "SDF shape")?),
+            ObjectId::Light(i) => SceneObject::Light(take(&mut self.lights, i, "light")?),
+            ObjectId::Volume(i) => SceneObject::Volume(take(&mut self.volumes, i, "volume")?),
+        })
+    }
+
+    /// Moves an object by `offset`.
+    pub fn move_object(&mut self, id: ObjectId, offset: [f32; 3]) -> Result<()> {
+        let moved = |p: [f32; 3]| [0, 1, 2].map(|i| p[i] + offset[i]);
+        match id {
+            ObjectId::Instance(_) | ObjectId::Volume(_) => {
+                let mut translation = IDENTITY;
+                translation[3] = [offset[0], offset[1], offset[2], 1.0];
+                return self.transform_object(id, &translation);
+            }
+            ObjectId::Sphere(i) => {
+                let sphere = self.spheres.get_mut(i).with_context(|| format!("no sphere {i}"))?;
+                sphere.center = moved(sphere.center);
+            }
+            ObjectId::Shape(i) => {
+                let shape = self.shapes.get_mut(i).with_context(|| format!("no shape {i}"))?;
+                *shape = shape.translated(offset);
+            }
+            ObjectId::Sdf(i) => {
+                let sdf = self.sdfs.get_mut(i).with_context(|| format!("no SDF shape {i}"))?;
+                sdf.root = sdf.root.translated(offset);
+            }
+            ObjectId::Light(i) => {
+                let light = self.lights.get_mut(i).with_context(|| format!("no light {i}"))?;
+                *light = light.translated(offset);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `transform` after the current placement of an instance or a
+    /// volume. Other objects have no placement of their own and can only be
+    /// moved.
+    pub fn transform_object(&mut self, id: ObjectId, transform: &Mat4) -> Result<()> {
+        ensure!(gltf::determinant(transform) != 0.0, "transform must be invertible");
+        let placement = match id {
+            ObjectId::Instance(i) => {
+                &mut self.instances.get_mut(i).with_context(|| format!("no instance {i}"))?.transform
+            }
+            ObjectId::Volume(i) => &mut self.volumes.get_mut(i).with_context(|| format!("no volume {i}"))?.transform,
+            _ => bail!("only instances and volumes can be rotated or scaled"),
+        };
+        *placement = gltf::mat_mul(transform, placement);
+        Ok(())
+    }
+}
@@ -0,0 +1,643 @@
+//! glTF 2.0 import (`.gltf` with external or embedded buffers, and `.glb`).
+//!
+//! The node hierarchy of the default scene becomes the scene's
+//! [`nodes`](Scene::nodes), each glTF mesh one object-space mesh placed by
+//! the nodes naming it. Base color and normal textures are imported along
+//! with the TEXCOORD_0 coordinates they use, and TANGENT vectors or, where
+//! a primitive has none, tangents generated from those coordinates; other
+//! textures, skins, morph targets and animations are ignored.
+
+use {
+    super::{
+        graph::{SceneNode, Trs, MAX_NODE_DEPTH},
+        texture::Texture,
+        tangent, Material, MaterialKind, Mesh, Scene, SceneCamera, Triangle,
+    },
+    crate::{json::Json, math::Vec3},
+    anyhow::{bail, ensure, Context, Result},
+    std::{collections::HashMap, fs, path::Path},
+};
+
+/// Column-major 4x4 matrix, as stored by glTF.
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4e4f_534a;
+const GLB_CHUNK_BIN: u32 = 0x004e_4942;
+
+pub fn load(path: &Path) -> Result<Scene> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+        split_glb(&bytes)?
+    } else {
+        (&bytes[..], None)
+    };
+    let json = std::str::from_utf8(json).context("glTF JSON is not valid UTF-8")?;
+    let doc = Json::parse(json)?;
+    import(&doc, path.parent().unwrap_or(Path::new(".")), bin)
+        .with_context(|| format!("failed to import {}", path.display()))
+}
+
+fn import(doc: &Json, base_dir: &Path, bin: Option<&[u8]>) -> Result<Scene> {
+    let version = doc.get("asset").and_then(|it| it.get("version")).and_then(Json::as_str);
+    ensure!(
+        version.is_some_and(|it| it.starts_with("2.")),
+        "unsupported glTF version {version:?}"
+    );
+    for extension in doc.items("extensionsRequired") {
+        let name = extension.as_str().unwrap_or_default();
+        if !matches!(name, "KHR_materials_emissive_strength" | "KHR_materials_ior") {
+            bail!("required extension `{name}` is not supported");
+        }
+    }
+
+    let buffers = doc
+        .items("buffers")
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| load_buffer(buffer, i, base_dir, bin))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut materials: Vec<Material> = doc.items("materials").iter().map(material).collect();
+    // Materials name glTF textures; each one they use becomes a scene
+    // texture.
+    let mut textures = Vec::new();
+    let mut texture_ids = HashMap::new();
+    for material in &mut materials {
+        for slot in [&mut material.base_color_texture, &mut material.normal_texture] {
+            let Some(index) = *slot else { continue };
+            let id = match texture_ids.get(&index) {
+                Some(&id) => id,
+                None => {
+                    let texture = load_texture(doc, index as usize, base_dir, &buffers)
+                        .with_context(|| format!("in texture {index}"))?;
+                    textures.push(texture);
+                    texture_ids.insert(index, textures.len() as u32 - 1);
+                    textures.len() as u32 - 1
+                }
+            };
+            *slot = Some(id);
+        }
+    }
+    // Primitives without a material use the glTF default, appended last.
+    materials.push(Material::default());
+    let mut scene = Scene {
+        materials,
+        textures,
+        ..Scene::default()
+    };
+
+    let mut importer = Importer {
+        doc,
+        buffers,
+        mesh_ids: HashMap::new(),
+    };
+    let roots: Vec<usize> = match doc.get("scene").and_then(Json::as_usize) {
+        Some(index) => node_list(doc.items("scenes").get(index).context("invalid default scene")?),
+        None => match doc.items("scenes").first() {
+            Some(first) => node_list(first),
+            None => root_nodes(doc),
+        },
+    };
+    for root in roots {
+        let node = importer.visit(&mut scene, root, IDENTITY, 0)?;
+        scene.root_nodes.push(node);
+    }
+    Ok(scene)
+}
+
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let word = |offset: usize| -> Result<u32> {
+        let bytes = bytes.get(offset..offset + 4).context("truncated GLB file")?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    ensure!(word(4)? == 2, "unsupported GLB version {}", word(4)?);
+    let length = (word(8)? as usize).min(bytes.len());
+
+    let (mut json, mut bin) = (None, None);
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = word(offset)? as usize;
+        let kind = word(offset + 4)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .context("truncated GLB chunk")?;
+        match kind {
+            GLB_CHUNK_JSON if json.is_none() => json = Some(data),
+            GLB_CHUNK_BIN if bin.is_none() => bin = Some(data),
+            _ => (),
+        }
+        offset += 8 + chunk_length.next_multiple_of(4);
+    }
+    Ok((json.context("GLB file has no JSON chunk")?, bin))
+}
+
+fn load_buffer(buffer: &Json, index: usize, base_dir: &Path, bin: Option<&[u8]>) -> Result<Vec<u8>> {
+    let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) if uri.starts_with("data:") => {
+            let (_, payload) = uri.split_once(";base64,").context("data URI is not base64")?;
+            base64_decode(payload).context("invalid base64 in data URI")?
+        }
+        Some(uri) => {
+            let path = base_dir.join(percent_decode(uri));
+            fs::read(&path).with_context(|| format!("failed to read buffer {}", path.display()))?
+        }
+        None if index == 0 => bin.context("buffer 0 has no uri and there is no GLB binary chunk")?.to_vec(),
+        None => bail!("buffer {index} has no uri"),
+    };
+    let length = buffer.get("byteLength").and_then(Json::as_usize).unwrap_or(data.len());
+    ensure!(data.len() >= length, "buffer {index} is shorter than its byteLength");
+    Ok(data)
+}
+
+/// Decodes the image of glTF texture `index`, from a file, a data URI or a
+/// buffer view.
+fn load_texture(doc: &Json, index: usize, base_dir: &Path, buffers: &[Vec<u8>]) -> Result<Texture> {
+    let texture = doc.items("textures").get(index).context("invalid texture index")?;
+    let source = texture.get("source").and_then(Json::as_usize).context("texture has no source image")?;
+    let image = doc.items("images").get(source).context("invalid image index")?;
+    match (image.get("uri").and_then(Json::as_str), image.get("bufferView").and_then(Json::as_usize)) {
+        (Some(uri), _) if uri.starts_with("data:") => {
+            let (_, payload) = uri.split_once(";base64,").context("data URI is not base64")?;
+            Texture::parse(&base64_decode(payload).context("invalid base64 in data URI")?)
+        }
+        (Some(uri), _) => Texture::load(&base_dir.join(percent_decode(uri))),
+        (None, Some(view)) => {
+            let view = doc.items("bufferViews").get(view).context("invalid bufferView index")?;
+            let buffer = view
+                .get("buffer")
+                .and_then(Json::as_usize)
+                .and_then(|i| buffers.get(i))
+                .context("invalid buffer index")?;
+            let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+            let length = view.get("byteLength").and_then(Json::as_usize).context("bufferView has no byteLength")?;
+            Texture::parse(buffer.get(offset..offset + length).context("bufferView exceeds its buffer")?)
+        }
+        (None, None) => bail!("image {source} has neither a uri nor a bufferView"),
+    }
+}
+
+/// The material's factors, with `base_color_texture` and `normal_texture`
+/// naming glTF textures rather than scene ones. Textures read through other coordinates than
+/// TEXCOORD_0 are dropped.
+fn material(material: &Json) -> Material {
+    let pbr = material.get("pbrMetallicRoughness");
+    let pbr_value = |key: &str| pbr.and_then(|it| it.get(key));
+    let base_color = pbr_value("baseColorFactor")
+        .and_then(Json::as_f32_array::<4>)
+        .map_or([1.0; 3], |[r, g, b, _]| [r, g, b]);
+    let strength = material
+        .get("extensions")
+        .and_then(|it| it.get("KHR_materials_emissive_strength"))
+        .and_then(|it| it.get("emissiveStrength"))
+        .and_then(Json::as_f32)
+        .unwrap_or(1.0);
+    let emission = material
+        .get("emissiveFactor")
+        .and_then(Json::as_f32_array::<3>)
+        .unwrap_or([0.0; 3])
+        .map(|c| c * strength);
+    let ior = material
+        .get("extensions")
+        .and_then(|it| it.get("KHR_materials_ior"))
+        .and_then(|it| it.get("ior"))
+        .and_then(Json::as_f32)
+        .unwrap_or(1.5);
+    Material {
+        base_color,
+        metallic: pbr_value("metallicFactor").and_then(Json::as_f32).unwrap_or(1.0),
+        roughness: pbr_value("roughnessFactor").and_then(Json::as_f32).unwrap_or(1.0),
+        emission,
+        ior: ior.max(1.0),
+        kind: MaterialKind::MetallicRoughness,
+        base_color_texture: pbr_value("baseColorTexture").and_then(texture_index),
+        normal_texture: material.get("normalTexture").and_then(texture_index),
+        normal_scale: material
+            .get("normalTexture")
+            .and_then(|it| it.get("scale"))
+            .and_then(Json::as_f32)
+            .unwrap_or(1.0),
+    }
+}
+
+/// The glTF texture a texture info names, if it reads TEXCOORD_0.
+fn texture_index(info: &Json) -> Option<u32> {
+    if info.get("texCoord").and_then(Json::as_usize).unwrap_or(0) != 0 {
+        return None;
+    }
+    info.get("index").and_then(Json::as_usize).map(|i| i as u32)
+}
+
+fn node_list(scene: &Json) -> Vec<usize> {
+    scene.items("nodes").iter().filter_map(Json::as_usize).collect()
+}
+
+/// Nodes that are nobody's child, for files without a `scenes` array.
+fn root_nodes(doc: &Json) -> Vec<usize> {
+    let nodes = doc.items("nodes");
+    let mut is_child = vec![false; nodes.len()];
+    for child in nodes.iter().flat_map(|node| node.items("children")) {
+        if let Some(flag) = child.as_usize().and_then(|i| is_child.get_mut(i)) {
+            *flag = true;
+        }
+    }
+    (0..nodes.len()).filter(|&i| !is_child[i]).collect()
+}
+
+struct Importer<'a> {
+    doc: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    /// Scene mesh of each glTF mesh imported so far, `None` for meshes
+    /// without triangles.
+    mesh_ids: HashMap<usize, Option<u32>>,
+}
+
+impl Importer<'_> {
+    /// Adds glTF node `index` and its descendants to the scene's nodes and
+    /// returns the scene node. `parent` is only needed to place cameras.
+    fn visit(&mut self, scene: &mut Scene, index: usize, parent: Mat4, depth: usize) -> Result<u32> {
+        ensure!(depth < MAX_NODE_DEPTH, "node hierarchy is too deep or cyclic");
+        let node = self.doc.items("nodes").get(index).with_context(|| format!("invalid node {index}"))?;
+        let local = local_transform(node);
+        let transform = mat_mul(&parent, &local.matrix());
+
+        let mesh = match node.get("mesh").and_then(Json::as_usize) {
+            Some(mesh) => self.mesh_id(scene, mesh).with_context(|| format!("in mesh {mesh}"))?,
+            None => None,
+        };
+        if scene.camera.is_none() {
+            if let Some(camera) = node.get("camera").and_then(Json::as_usize) {
+                scene.camera = self.camera(camera, &transform);
+            }
+        }
+        let id = scene.nodes.len() as u32;
+        scene.nodes.push(SceneNode {
+            name: node.get("name").and_then(Json::as_str).unwrap_or_default().to_string(),
+            transform: local,
+            mesh,
+            material: None,
+            children: Vec::new(),
+        });
+        for child in node.items("children").iter().filter_map(Json::as_usize) {
+            let child = self.visit(scene, child, transform, depth + 1)?;
+            scene.nodes[id as usize].children.push(child);
+        }
+        Ok(id)
+    }
+
+    /// Imports glTF mesh `index` the first time a node places it.
+    fn mesh_id(&mut self, scene: &mut Scene, index: usize) -> Result<Option<u32>> {
+        if let Some(&id) = self.mesh_ids.get(&index) {
+            return Ok(id);
+        }
+        let default_material = scene.materials.len() as u32 - 1;
+        let mesh = self.mesh(index, default_material)?;
+        let id = (!mesh.triangles.is_empty()).then(|| {
+            scene.meshes.push(mesh);
+            scene.meshes.len() as u32 - 1
+        });
+        self.mesh_ids.insert(index, id);
+        Ok(id)
+    }
+
+    fn mesh(&self, index: usize, default_material: u32) -> Result<Mesh> {
+        let gltf_mesh = self.doc.items("meshes").get(index).context("invalid mesh index")?;
+        let mut mesh = Mesh::default();
+        // TANGENT attributes, by the first vertex of their primitive.
+        let mut given_tangents = Vec::new();
+        for primitive in gltf_mesh.items("primitives") {
+            let mode = primitive.get("mode").and_then(Json::as_usize).unwrap_or(4);
+            if !(4..=6).contains(&mode) {
+                // Points and lines have no surface to hit.
+                continue;
+            }
+            let attribute = |name| primitive.get("attributes").and_then(|it| it.get(name)).and_then(Json::as_usize);
+            let position = attribute("POSITION").context("primitive has no POSITION attribute")?;
+            let positions = self.accessor(position)?.read_vec3();
+            let normals = match attribute("NORMAL") {
+                Some(normal) => {
+                    let normals = self.accessor(normal)?.read_vec3();
+                    ensure!(normals.len() == positions.len(), "NORMAL and POSITION counts differ");
+                    normals
+                }
+                None => vec![[0.0; 3]; positions.len()],
+            };
+            let uvs = match attribute("TEXCOORD_0") {
+                Some(uv) => {
+                    let uvs = self.accessor(uv)?.read_vec2();
+                    ensure!(uvs.len() == positions.len(), "TEXCOORD_0 and POSITION counts differ");
+                    Some(uvs)
+                }
+                None => None,
+            };
+            if let (Some(tangent), Some(_)) = (attribute("TANGENT"), &uvs) {
+                let tangents = self.accessor(tangent)?.read_vec4();
+                ensure!(tangents.len() == positions.len(), "TANGENT and POSITION counts differ");
+                given_tangents.push((mesh.positions.len(), tangents));
+            }
+            let indices = match primitive.get("indices").and_then(Json::as_usize) {
+                Some(accessor) => self.accessor(accessor)?.read_indices(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            if let Some(&bad) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                bail!("vertex index {bad} out of range");
+            }
+            let material = primitive
+                .get("material")
+                .and_then(Json::as_usize)
+                .filter(|&i| i < default_material as usize)
+                .map_or(default_material, |i| i as u32);
+            let base = mesh.positions.len() as u32;
+            mesh.triangles.extend(triangulate(&indices, mode).into_iter().map(|tri| Triangle {
+                indices: tri.map(|i| base + i),
+                material,
+            }));
+            if let Some(uvs) = uvs {
+                mesh.uvs.resize(base as usize, [0.0; 2]);
+                mesh.uvs.extend(uvs);
+            }
+            mesh.positions.extend(positions);
+            mesh.normals.extend(normals);
+        }
+        if !mesh.uvs.is_empty() {
+            mesh.tangents = tangent::generate(&mesh.positions, &mesh.normals, &mesh.uvs, &mesh.triangles);
+            for (base, tangents) in given_tangents {
+                mesh.tangents[base..][..tangents.len()].copy_from_slice(&tangents);
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn camera(&self, index: usize, transform: &Mat4) -> Option<SceneCamera> {
+        let perspective = self.doc.items("cameras").get(index)?.get("perspective")?;
+        let yfov = perspective.get("yfov").and_then(Json::as_f32)?;
+        let eye = transform_point(transform, [0.0; 3]);
+        let forward = transform_direction(transform, [0.0, 0.0, -1.0]);
+        let up = transform_direction(transform, [0.0, 1.0, 0.0]);
+        let vec = |[x, y, z]: [f32; 3]| Vec3::new(x, y, z);
+        Some(SceneCamera {
+            lookfrom: vec(eye),
+            lookat: vec(eye) + vec(forward).normalized(),
+            vup: vec(up).normalized(),
+            vfov: yfov.to_degrees(),
+        })
+    }
+
+    fn accessor(&self, index: usize) -> Result<Accessor<'_>> {
+        let accessor = self.doc.items("accessors").get(index).context("invalid accessor index")?;
+        ensure!(accessor.get("sparse").is_none(), "sparse accessors are not supported");
+        let count = accessor.get("count").and_then(Json::as_usize).context("accessor has no count")?;
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .context("accessor has no componentType")?;
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => bail!("invalid componentType {component_type}"),
+        };
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            other => bail!("unsupported accessor type {other:?}"),
+        };
+        let element_size = component_size * components;
+
+        let Some(view) = accessor.get("bufferView").and_then(Json::as_usize) else {
+            // Accessors without a buffer view are all zeros.
+            return Ok(Accessor {
+                data: &[],
+                stride: 0,
+                count,
+                components,
+                component_type,
+                normalized: false,
+            });
+        };
+        let view = self.doc.items("bufferViews").get(view).context("invalid bufferView index")?;
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|i| self.buffers.get(i))
+            .context("invalid buffer index")?;
+        let view_offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let view_length = view.get("byteLength").and_then(Json::as_usize).context("bufferView has no byteLength")?;
+        let stride = view.get("byteStride").and_then(Json::as_usize).unwrap_or(element_size);
+        let offset = accessor.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+
+        let view_data = view_offset
+            .checked_add(view_length)
+            .and_then(|end| buffer.get(view_offset..end))
+            .context("bufferView exceeds its buffer")?;
+        // Counts, strides and offsets come from the file, so their sums are
+        // checked rather than trusted not to wrap.
+        let needed = match count {
+            0 => Some(0),
+            _ => stride
+                .checked_mul(count - 1)
+                .and_then(|it| it.checked_add(offset))
+                .and_then(|it| it.checked_add(element_size)),
+        };
+        ensure!(
+            stride >= element_size && offset <= view_data.len() && needed.is_some_and(|it| it <= view_data.len()),
+            "accessor exceeds its bufferView"
+        );
+        Ok(Accessor {
+            data: &view_data[offset..],
+            stride,
+            count,
+            components,
+            component_type,
+            normalized: accessor.get("normalized").and_then(Json::as_bool).unwrap_or(false),
+        })
+    }
+}
+
+/// A validated view of accessor elements inside a buffer.
+struct Accessor<'a> {
+    data: &'a [u8],
+    stride: usize,
+    count: usize,
+    components: usize,
+    component_type: usize,
+    normalized: bool,
+}
+
+impl Accessor<'_> {
+    fn component(&self, element: usize, component: usize) -> f64 {
+        if self.data.is_empty() {
+            return 0.0;
+        }
+        let at = element * self.stride;
+        let bytes = &self.data[at..];
+        let (value, max) = match self.component_type {
+            5120 => (bytes[component] as i8 as f64, i8::MAX as f64),
+            5121 => (bytes[component] as f64, u8::MAX as f64),
+            5122 => (i16::from_le_bytes([bytes[component * 2], bytes[component * 2 + 1]]) as f64, i16::MAX as f64),
+            5123 => (u16::from_le_bytes([bytes[component * 2], bytes[component * 2 + 1]]) as f64, u16::MAX as f64),
+            5125 => (u32::from_le_bytes(bytes[component * 4..component * 4 + 4].try_into().unwrap()) as f64, u32::MAX as f64),
+            _ => (f32::from_le_bytes(bytes[component * 4..component * 4 + 4].try_into().unwrap()) as f64, 1.0),
+        };
+        if self.normalized && self.component_type != 5126 {
+            (value / max).max(-1.0)
+        } else {
+            value
+        }
+    }
+
+    fn read_vec3(&self) -> Vec<[f32; 3]> {
+        (0..self.count)
+            .map(|i| [0, 1, 2].map(|c| if c < self.components { self.component(i, c) as f32 } else { 0.0 }))
+            .collect()
+    }
+
+    fn read_vec4(&self) -> Vec<[f32; 4]> {
+        (0..self.count)
+            .map(|i| [0, 1, 2, 3].map(|c| if c < self.components { self.component(i, c) as f32 } else { 0.0 }))
+            .collect()
+    }
+
+    fn read_vec2(&self) -> Vec<[f32; 2]> {
+        (0..self.count)
+            .map(|i| [0, 1].map(|c| if c < self.components { self.component(i, c) as f32 } else { 0.0 }))
+            .collect()
+    }
+
+    fn read_indices(&self) -> Vec<u32> {
+        (0..self.count).map(|i| self.component(i, 0) as u32).collect()
+    }
+}
+
+/// Triangles of a triangle list (4), strip (5) or fan (6), dropping degenerate ones.
+fn triangulate(indices: &[u32], mode: usize) -> Vec<[u32; 3]> {
+    let triangles: Vec<[u32; 3]> = match mode {
+        5 => indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] })
+            .collect(),
+        6 if !indices.is_empty() => indices[1..].windows(2).map(|w| [indices[0], w[0], w[1]]).collect(),
+        6 => Vec::new(),
+        _ => indices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+    };
+    triangles
+        .into_iter()
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .collect()
+}
+
+fn local_transform(node: &Json) -> Trs {
+    if let Some(m) = node.get("matrix").and_then(Json::as_f32_array::<16>) {
+        return Trs::from_matrix(&[0, 1, 2, 3].map(|col| [0, 1, 2, 3].map(|row| m[col * 4 + row])));
+    }
+    let defaults = Trs::default();
+    Trs {
+        translation: node.get("translation").and_then(Json::as_f32_array::<3>).unwrap_or(defaults.translation),
+        rotation: node.get("rotation").and_then(Json::as_f32_array::<4>).unwrap_or(defaults.rotation),
+        scale: node.get("scale").and_then(Json::as_f32_array::<3>).unwrap_or(defaults.scale),
+    }
+}
+
+pub(super) fn mat_mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    b.map(|col| [0, 1, 2, 3].map(|row| (0..4).map(|k| a[k][row] * col[k]).sum()))
+}
+
+pub(super) fn transform_point(m: &Mat4, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[0][row] * x + m[1][row] * y + m[2][row] * z + m[3][row])
+}
+
+/// Determinant of the linear part of `m`.
+pub(super) fn determinant(m: &Mat4) -> f32 {
+    let col = |i: usize| Vec3::new(m[i][0], m[i][1], m[i][2]);
+    col(0).dot(&col(1).cross(&col(2)))
+}
+
+pub(super) fn transform_direction(m: &Mat4, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[0][row] * x + m[1][row] * y + m[2][row] * z)
+}
+
+/// Transforms a tangent by `m`, renormalized, flipping its handedness if
+/// `m` mirrors.
+pub(super) fn transform_tangent(m: &Mat4, [x, y, z, w]: [f32; 4]) -> [f32; 4] {
+    let [x, y, z] = transform_direction(m, [x, y, z]);
+    let out = Vec3::new(x, y, z);
+    let length = out.length();
+    if length == 0.0 {
+        return [0.0; 4];
+    }
+    let out = out / length;
+    [out.x(), out.y(), out.z(), w * determinant(m).signum()]
+}
+
+/// Transforms a normal by the inverse transpose of `m`, computed from the
+/// cofactors so that singular transforms do not divide by zero.
+pub(super) fn transform_normal(m: &Mat4, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    let col = |i: usize| Vec3::new(m[i][0], m[i][1], m[i][2]);
+    let (a, b, c) = (col(0), col(1), col(2));
+    let out = b.cross(&c) * x + c.cross(&a) * y + a.cross(&b) * z;
+    let length = out.length();
+    if length == 0.0 {
+        return [0.0; 3];
+    }
+    // Mirroring transforms flip the inverse transpose.
+    let out = out / length * a.dot(&b.cross(&c)).signum();
+    [out.x(), out.y(), out.z()]
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return None,
+        };
+        bits = (bits << 6 | value as u32) & 0xff_ffff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Decodes `%XX` escapes in relative URIs.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
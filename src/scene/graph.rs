@@ -0,0 +1,209 @@
+//! Hierarchies of placed meshes, such as the node trees of glTF files. Each
+//! node's transform is relative to its parent, so moving a node moves
+//! everything below it. The renderer flattens the hierarchy into world-space
+//! [`Instance`]s when the scene is uploaded.
+
+use {
+    super::{
+        gltf::{self, Mat4, IDENTITY},
+        Instance, Scene, Triangle, Visibility,
+    },
+    crate::math::Vec3,
+    anyhow::{ensure, Context, Result},
+};
+
+/// Node hierarchies deeper than this are assumed to be cyclic.
+pub const MAX_NODE_DEPTH: usize = 256;
+
+/// Scale, then rotation, then translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Trs {
+    pub translation: [f32; 3],
+    /// Unit quaternion `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for Trs {
+    fn default() -> Self {
+        Trs {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Trs {
+    pub fn matrix(&self) -> Mat4 {
+        let [tx, ty, tz] = self.translation;
+        let [x, y, z, w] = self.rotation;
+        let [sx, sy, sz] = self.scale;
+        // Columns of the rotation matrix of the unit quaternion, scaled.
+        [
+            [(1.0 - 2.0 * (y * y + z * z)) * sx, 2.0 * (x * y + z * w) * sx, 2.0 * (x * z - y * w) * sx, 0.0],
+            [2.0 * (x * y - z * w) * sy, (1.0 - 2.0 * (x * x + z * z)) * sy, 2.0 * (y * z + x * w) * sy, 0.0],
+            [2.0 * (x * z + y * w) * sz, 2.0 * (y * z - x * w) * sz, (1.0 - 2.0 * (x * x + y * y)) * sz, 0.0],
+            [tx, ty, tz, 1.0],
+        ]
+    }
+
+    /// Splits an affine matrix without shear, as glTF requires of node
+    /// matrices. Mirroring matrices get a negative x scale.
+    pub fn from_matrix(m: &Mat4) -> Trs {
+        let col = |i: usize| Vec3::new(m[i][0], m[i][1], m[i][2]);
+        let mut scale = [0, 1, 2].map(|i| col(i).length());
+        if gltf::determinant(m) < 0.0 {
+            scale[0] = -scale[0];
+        }
+        let [a, b, c] = [0, 1, 2].map(|i| if scale[i] == 0.0 { Vec3::new(0.0, 0.0, 0.0) } else { col(i) / scale[i] });
+        // Shepperd's method, dividing by the largest of the four components.
+        let trace = a.x() + b.y() + c.z();
+        let rotation = if trace > 0.0 {
+            let s = (1.0 + trace).sqrt() * 2.0;
+            [(b.z() - c.y()) / s, (c.x() - a.z()) / s, (a.y() - b.x()) / s, s / 4.0]
+        } else if a.x() > b.y() && a.x() > c.z() {
+            let s = (1.0 + a.x() - b.y() - c.z()).sqrt() * 2.0;
+            [s / 4.0, (b.x() + a.y()) / s, (c.x() + a.z()) / s, (b.z() - c.y()) / s]
+        } else if b.y() > c.z() {
+            let s = (1.0 + b.y() - a.x() - c.z()).sqrt() * 2.0;
+            [(b.x() + a.y()) / s, s / 4.0, (c.y() + b.z()) / s, (c.x() - a.z()) / s]
+        } else {
+            let s = (1.0 + c.z() - a.x() - b.y()).sqrt() * 2.0;
+            [(c.x() + a.z()) / s, (c.y() + b.z()) / s, s / 4.0, (a.y() - b.x()) / s]
+        };
+        Trs {
+            translation: [m[3][0], m[3][1], m[3][2]],
+            rotation,
+            scale,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SceneNode {
+    pub name: String,
+    /// Node to parent, or to world space for roots.
+    pub transform: Trs,
+    /// Index into `Scene::meshes` of a mesh placed at the node.
+    pub mesh: Option<u32>,
+    /// Replaces the materials of the node's mesh, but not of its children.
+    pub material: Option<u32>,
+    /// Indices into `Scene::nodes`.
+    pub children: Vec<u32>,
+}
+
+impl Scene {
+    /// Adds `node` under `parent`, or as a root without one, and returns its
+    /// index. Its children are added afterwards, with it as their parent.
+    pub fn add_node(&mut self, parent: Option<u32>, node: SceneNode) -> Result<u32> {
+        ensure!(node.children.is_empty(), "children are added after their parent");
+        ensure!(
+            node.mesh.is_none_or(|mesh| (mesh as usize) < self.meshes.len()),
+            "node places a missing mesh"
+        );
+        ensure!(
+            node.material.is_none_or(|m| (m as usize) < self.materials.len()),
+            "node uses a missing material"
+        );
+        let index = self.nodes.len() as u32;
+        match parent {
+            Some(parent) => {
+                let parent = self.nodes.get_mut(parent as usize).with_context(|| format!("no node {parent}"))?;
+                parent.children.push(index);
+            }
+            None => self.root_nodes.push(index),
+        }
+        self.nodes.push(node);
+        Ok(index)
+    }
+
+    /// Node to world transform of every node reached from a root, indexed
+    /// like `nodes`. Nodes listed more than once are placed where they are
+    /// first reached.
+    pub fn node_transforms(&self) -> Vec<Option<Mat4>> {
+        let mut transforms = vec![None; self.nodes.len()];
+        let mut stack: Vec<(u32, Mat4, usize)> = self.root_nodes.iter().rev().map(|&i| (i, IDENTITY, 0)).collect();
+        while let Some((index, parent, depth)) = stack.pop() {
+            let Some(node) = self.nodes.get(index as usize) else { continue };
+            if depth >= MAX_NODE_DEPTH || transforms[index as usize].is_some() {
+                continue;
+            }
+            let transform = gltf::mat_mul(&parent, &node.transform.matrix());
+            transforms[index as usize] = Some(transform);
+            stack.extend(node.children.iter().rev().map(|&child| (child, transform, depth + 1)));
+        }
+        transforms
+    }
+
+    /// World-space placements of the meshes of the node hierarchy. Nodes
+    /// scaled to nothing are left out.
+    pub fn node_instances(&self) -> Vec<Instance> {
+        self.node_transforms()
+            .into_iter()
+            .zip(&self.nodes)
+            .filter_map(|(transform, node)| {
+                let transform = transform?;
+                (gltf::determinant(&transform) != 0.0).then_some(Instance {
+                    mesh: node.mesh?,
+                    transform,
+                    material: node.material,
+                    visibility: Visibility::ALL,
+                })
+            })
+            .collect()
+    }
+
+    /// Bakes the meshes placed by the node hierarchy into world-space
+    /// triangles and drops the hierarchy, along with meshes that no
+    /// instance places and that are no other mesh's LOD.
+    pub fn flatten_nodes(&mut self) {
+        for instance in self.node_instances() {
+            let mesh = &self.meshes[instance.mesh as usize];
+            let base = self.positions.len() as u32;
+            if !mesh.colors.is_empty() {
+                self.colors.resize(base as usize, None);
+                self.colors.extend_from_slice(&mesh.colors);
+            }
+            if !mesh.uvs.is_empty() {
+                self.uvs.resize(base as usize, [0.0; 2]);
+                self.uvs.extend_from_slice(&mesh.uvs);
+            }
+            if !mesh.tangents.is_empty() {
+                self.tangents.resize(base as usize, [0.0; 4]);
+                self.tangents
+                    .extend(mesh.tangents.iter().map(|&t| gltf::transform_tangent(&instance.transform, t)));
+            }
+            self.positions
+                .extend(mesh.positions.iter().map(|&p| gltf::transform_point(&instance.transform, p)));
+            self.normals
+                .extend(mesh.normals.iter().map(|&n| gltf::transform_normal(&instance.transform, n)));
+            self.triangles.extend(mesh.triangles.iter().map(|tri| Triangle {
+                indices: tri.indices.map(|i| base + i),
+                material: instance.material.unwrap_or(tri.material),
+            }));
+        }
+        self.nodes.clear();
+        self.root_nodes.clear();
+
+        let mut remap = vec![None; self.meshes.len()];
+        let lods = self.meshes.iter().flat_map(|mesh| &mesh.lods).map(|lod| lod.mesh);
+        for mesh in self.instances.iter().map(|instance| instance.mesh).chain(lods) {
+            remap[mesh as usize] = Some(0);
+        }
+        for (kept, slot) in remap.iter_mut().filter(|slot| slot.is_some()).enumerate() {
+            *slot = Some(kept as u32);
+        }
+        let mut index = 0;
+        self.meshes.retain(|_| {
+            index += 1;
+            remap[index - 1].is_some()
+        });
+        for instance in &mut self.instances {
+            instance.mesh = remap[instance.mesh as usize].unwrap();
+        }
+        for lod in self.meshes.iter_mut().flat_map(|mesh| &mut mesh.lods) {
+            lod.mesh = remap[lod.mesh as usize].unwrap();
+        }
+    }
+}
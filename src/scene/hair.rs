@@ -0,0 +1,103 @@
+//! Cem Yuksel's binary `.hair` format, imported as round curves.
+//!
+//! Each strand becomes a Catmull-Rom spline through its points, with the
+//! file's thickness as the diameter. All strands share one diffuse material
+//! whose color is the mean of the point colors, or the file's default color;
+//! transparency is ignored.
+
+use {
+    super::{
+        curve::{self, CurveShape},
+        Material, Scene,
+    },
+    anyhow::{ensure, Context, Result},
+    std::{fs, path::Path},
+};
+
+const HAS_SEGMENTS: u32 = 1 << 0;
+const HAS_POINTS: u32 = 1 << 1;
+const HAS_THICKNESS: u32 = 1 << 2;
+const HAS_TRANSPARENCY: u32 = 1 << 3;
+const HAS_COLOR: u32 = 1 << 4;
+
+const HEADER_SIZE: usize = 128;
+
+pub fn load(path: &Path) -> Result<Scene> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse(&bytes).with_context(|| format!("failed to import {}", path.display()))
+}
+
+fn parse(bytes: &[u8]) -> Result<Scene> {
+    ensure!(bytes.len() >= HEADER_SIZE && bytes.starts_with(b"HAIR"), "not a .hair file");
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let f32_at = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let strand_count = u32_at(4) as usize;
+    let point_count = u32_at(8) as usize;
+    let flags = u32_at(12);
+    let default_segments = u32_at(16) as usize;
+    let default_thickness = f32_at(20);
+    let default_color = [f32_at(28), f32_at(32), f32_at(36)];
+    ensure!(flags & HAS_POINTS != 0, "file has no points");
+
+    // Arrays follow the header in flag order, each present only if flagged.
+    let mut offset = HEADER_SIZE;
+    let mut take = |flag: u32, size: usize| -> Result<Option<&[u8]>> {
+        if flags & flag == 0 {
+            return Ok(None);
+        }
+        let array = bytes.get(offset..offset + size).context("file is truncated")?;
+        offset += size;
+        Ok(Some(array))
+    };
+    let segments = take(HAS_SEGMENTS, strand_count * 2)?;
+    let points = take(HAS_POINTS, point_count * 12)?.unwrap();
+    let thickness = take(HAS_THICKNESS, point_count * 4)?;
+    take(HAS_TRANSPARENCY, point_count * 4)?;
+    let colors = take(HAS_COLOR, point_count * 12)?;
+
+    let f32s = |array: &[u8]| -> Vec<f32> {
+        array
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    };
+    let positions: Vec<[f32; 3]> = f32s(points).chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect();
+    let radii: Vec<f32> = match thickness {
+        Some(thickness) => f32s(thickness).into_iter().map(|t| t * 0.5).collect(),
+        None => vec![default_thickness * 0.5; point_count],
+    };
+    let base_color = match colors {
+        Some(colors) if point_count > 0 => {
+            let colors = f32s(colors);
+            [0, 1, 2].map(|i| colors.iter().skip(i).step_by(3).sum::<f32>() / point_count as f32)
+        }
+        _ => default_color,
+    };
+
+    let mut scene = Scene {
+        materials: vec![Material {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            ..Material::default()
+        }],
+        ..Scene::default()
+    };
+    let mut first = 0;
+    for i in 0..strand_count {
+        let segment_count = match segments {
+            Some(segments) => u16::from_le_bytes([segments[i * 2], segments[i * 2 + 1]]) as usize,
+            None => default_segments,
+        };
+        let end = first + segment_count + 1;
+        ensure!(end <= point_count, "strand {i} runs past the last point");
+        scene.curves.extend(curve::strand(
+            &positions[first..end],
+            &radii[first..end],
+            CurveShape::Round,
+            0,
+        ));
+        first = end;
+    }
+    Ok(scene)
+}
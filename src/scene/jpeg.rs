@@ -0,0 +1,572 @@
+//! Baseline and progressive JPEG images, decoded to 8-bit RGBA:
+//! Huffman-coded 8-bit grayscale or YCbCr (or Adobe RGB) scans with any
+//! chroma subsampling and restart intervals. Lossless, hierarchical,
+//! arithmetic-coded and CMYK files are rejected with an error naming them.
+//! Colors are taken to be sRGB.
+
+use {
+    super::texture::Texture,
+    anyhow::{bail, ensure, Context, Result},
+    std::f32::consts::PI,
+};
+
+pub const MAGIC: &[u8] = &[0xff, 0xd8, 0xff];
+
+/// Natural (row-major) position of each coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61,
+    54, 47, 55, 62, 63,
+];
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc_table: usize,
+    ac_table: usize,
+    /// Quantized coefficients of each block in zigzag order, over a grid
+    /// padded out to whole MCUs. Progressive scans fill them in over
+    /// several passes, so nothing is transformed until the image ends.
+    blocks: Vec<[i16; 64]>,
+    /// Width of `blocks`, in blocks.
+    blocks_wide: usize,
+}
+
+/// The components of a scan and the part of each coefficient it codes:
+/// the zigzag band `start..=end`, from bit `low` up, refining bits above
+/// `low` if `high` is not zero.
+struct Scan {
+    components: Vec<usize>,
+    start: usize,
+    end: usize,
+    high: u8,
+    low: u8,
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Texture> {
+    ensure!(bytes.starts_with(&MAGIC[..2]), "not a JPEG file");
+    let mut pos = 2;
+    let mut quant = [[0u16; 64]; 4];
+    let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+    let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+    let mut components: Vec<Component> = Vec::new();
+    let (mut width, mut height) = (0, 0);
+    let mut progressive = false;
+    let mut restart_interval = 0;
+    let mut adobe_transform = None;
+    loop {
+        // Markers may be padded with any number of 0xff bytes.
+        while bytes.get(pos) != Some(&0xff) {
+            ensure!(pos < bytes.len(), "missing end of image");
+            pos += 1;
+        }
+        while bytes.get(pos) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *bytes.get(pos).context("missing end of image")?;
+        pos += 1;
+        match marker {
+            // Stuffed bytes, restarts outside a scan and markers without a
+            // segment carry nothing.
+            0x00 | 0x01 | 0xd0..=0xd8 => continue,
+            0xd9 => break,
+            _ => (),
+        }
+        let length = u16::from_be_bytes([
+            *bytes.get(pos).context("truncated segment")?,
+            *bytes.get(pos + 1).context("truncated segment")?,
+        ]) as usize;
+        let segment = bytes.get(pos + 2..pos + length).context("truncated segment")?;
+        pos += length;
+        match marker {
+            0xdb => {
+                let mut rest = segment;
+                while !rest.is_empty() {
+                    let (precision, table) = (rest[0] >> 4, (rest[0] & 15) as usize);
+                    ensure!(table < 4, "quantization table {table} out of range");
+                    let size = if precision == 0 { 64 } else { 128 };
+                    let values = rest.get(1..1 + size).context("truncated quantization table")?;
+                    for (k, q) in quant[table].iter_mut().enumerate() {
+                        *q = match precision {
+                            0 => values[k] as u16,
+                            _ => u16::from_be_bytes([values[2 * k], values[2 * k + 1]]),
+                        };
+                    }
+                    rest = &rest[1 + size..];
+                }
+            }
+            0xc4 => {
+                let mut rest = segment;
+                while !rest.is_empty() {
+                    let (class, table) = (rest[0] >> 4, (rest[0] & 15) as usize);
+                    ensure!(table < 4, "Huffman table {table} out of range");
+                    let counts: [u8; 16] = rest.get(1..17).context("truncated Huffman table")?.try_into()?;
+                    let total: usize = counts.iter().map(|&c| c as usize).sum();
+                    let symbols = rest.get(17..17 + total).context("truncated Huffman table")?;
+                    let huffman = Some(Huffman::new(&counts, symbols));
+                    match class {
+                        0 => dc_tables[table] = huffman,
+                        _ => ac_tables[table] = huffman,
+                    }
+                    rest = &rest[17 + total..];
+                }
+            }
+            0xc0..=0xc2 => {
+                ensure!(components.is_empty(), "more than one frame header");
+                ensure!(segment.len() >= 6, "truncated frame header");
+                ensure!(segment[0] == 8, "{}-bit JPEG is not supported", segment[0]);
+                progressive = marker == 0xc2;
+                height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+                width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+                ensure!(width > 0 && height > 0, "image has no pixels, or its height comes later");
+                let count = segment[5] as usize;
+                ensure!(count == 1 || count == 3, "JPEG with {count} components is not supported");
+                for c in 0..count {
+                    let spec = segment.get(6 + 3 * c..9 + 3 * c).context("truncated frame header")?;
+                    let (h, v) = ((spec[1] >> 4) as usize, (spec[1] & 15) as usize);
+                    ensure!((1..=4).contains(&h) && (1..=4).contains(&v), "invalid sampling factors");
+                    components.push(Component {
+                        id: spec[0],
+                        h,
+                        v,
+                        quant: (spec[2] & 3) as usize,
+                        dc_table: 0,
+                        ac_table: 0,
+                        blocks: Vec::new(),
+                        blocks_wide: 0,
+                    });
+                }
+                let (h_max, v_max) = max_sampling(&components);
+                let mcus = [width.div_ceil(8 * h_max), height.div_ceil(8 * v_max)];
+                for component in &mut components {
+                    component.blocks_wide = mcus[0] * component.h;
+                    component.blocks = vec![[0; 64]; component.blocks_wide * mcus[1] * component.v];
+                }
+            }
+            0xc3 => bail!("lossless JPEG is not supported"),
+            0xc5..=0xc7 => bail!("hierarchical JPEG is not supported"),
+            0xc9..=0xcb | 0xcd..=0xcf => bail!("arithmetic-coded JPEG is not supported"),
+            0xdd => {
+                ensure!(segment.len() >= 2, "truncated restart interval");
+                restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as usize;
+            }
+            0xee if segment.starts_with(b"Adobe") && segment.len() >= 12 => adobe_transform = Some(segment[11]),
+            0xda => {
+                ensure!(!components.is_empty(), "scan before the frame header");
+                let count = *segment.first().context("truncated scan header")? as usize;
+                let mut scan_components = Vec::with_capacity(count);
+                for i in 0..count {
+                    let spec = segment.get(1 + 2 * i..3 + 2 * i).context("truncated scan header")?;
+                    let c = components
+                        .iter()
+                        .position(|component| component.id == spec[0])
+                        .context("scan names an unknown component")?;
+                    components[c].dc_table = (spec[1] >> 4) as usize & 3;
+                    components[c].ac_table = (spec[1] & 15) as usize & 3;
+                    scan_components.push(c);
+                }
+                let band = segment.get(1 + 2 * count..4 + 2 * count).context("truncated scan header")?;
+                let scan = match progressive {
+                    true => Scan {
+                        components: scan_components,
+                        start: band[0] as usize,
+                        end: band[1] as usize,
+                        high: band[2] >> 4,
+                        low: band[2] & 15,
+                    },
+                    // Sequential scans code whole coefficients, whatever
+                    // the header says.
+                    false => Scan {
+                        components: scan_components,
+                        start: 0,
+                        end: 63,
+                        high: 0,
+                        low: 0,
+                    },
+                };
+                ensure!(
+                    scan.start <= scan.end && scan.end < 64 && (!progressive || (scan.start == 0) == (scan.end == 0)),
+                    "invalid spectral selection"
+                );
+                ensure!(
+                    scan.start == 0 || scan.components.len() == 1,
+                    "AC scan of more than one component"
+                );
+                ensure!(scan.low < 14, "invalid successive approximation");
+                let tables = Tables {
+                    dc: &dc_tables,
+                    ac: &ac_tables,
+                };
+                pos = decode_scan(bytes, pos, &mut components, &scan, &tables, restart_interval, [width, height])?;
+            }
+            _ => (),
+        }
+    }
+    ensure!(!components.is_empty(), "missing frame header");
+
+    let cosines = idct_cosines();
+    let planes: Vec<Vec<u8>> = components
+        .iter()
+        .map(|component| samples(component, &quant[component.quant], &cosines))
+        .collect();
+    let (h_max, v_max) = max_sampling(&components);
+    // Nearest-neighbor upsampling of subsampled components.
+    let sample = |c: usize, x: usize, y: usize| {
+        let component = &components[c];
+        let stride = component.blocks_wide * 8;
+        planes[c][y * component.v / v_max * stride + x * component.h / h_max]
+    };
+    let rgb = components.len() == 3 && adobe_transform == Some(0);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = if components.len() == 1 {
+                let g = sample(0, x, y);
+                [g, g, g, 255]
+            } else if rgb {
+                [sample(0, x, y), sample(1, x, y), sample(2, x, y), 255]
+            } else {
+                let l = sample(0, x, y) as f32;
+                let cb = sample(1, x, y) as f32 - 128.0;
+                let cr = sample(2, x, y) as f32 - 128.0;
+                let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+                [
+                    clamp(l + 1.402 * cr),
+                    clamp(l - 0.344136 * cb - 0.714136 * cr),
+                    clamp(l + 1.772 * cb),
+                    255,
+                ]
+            };
+            pixels.push(pixel);
+        }
+    }
+    Ok(Texture {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+        blocks: None,
+        source: None,
+    })
+}
+
+fn max_sampling(components: &[Component]) -> (usize, usize) {
+    let h = components.iter().map(|c| c.h).max().unwrap_or(1);
+    let v = components.iter().map(|c| c.v).max().unwrap_or(1);
+    (h, v)
+}
+
+struct Tables<'a> {
+    dc: &'a [Option<Huffman>; 4],
+    ac: &'a [Option<Huffman>; 4],
+}
+
+/// Decodes the entropy-coded data of a scan starting at `pos` into the
+/// coefficients of its components, returning where the data ends.
+fn decode_scan(
+    bytes: &[u8],
+    pos: usize,
+    components: &mut [Component],
+    scan: &Scan,
+    tables: &Tables,
+    restart_interval: usize,
+    [width, height]: [usize; 2],
+) -> Result<usize> {
+    let (h_max, v_max) = max_sampling(components);
+    // A scan of one component codes its blocks in plain raster order,
+    // covering only the image; an interleaved scan codes whole MCUs.
+    let single = scan.components.len() == 1;
+    let (mcus_x, mcus_y) = match single {
+        true => {
+            let c = &components[scan.components[0]];
+            (
+                (width * c.h).div_ceil(h_max).div_ceil(8),
+                (height * c.v).div_ceil(v_max).div_ceil(8),
+            )
+        }
+        false => (width.div_ceil(8 * h_max), height.div_ceil(8 * v_max)),
+    };
+    let mut bits = Bits { data: bytes, pos, buffer: 0, count: 0 };
+    let mut predictions = vec![0i32; components.len()];
+    // Blocks still to skip in an AC band after an end-of-band run.
+    let mut eob_run = 0;
+    for mcu in 0..mcus_x * mcus_y {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            bits.restart();
+            predictions.fill(0);
+            eob_run = 0;
+        }
+        let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+        for &c in &scan.components {
+            let component = &mut components[c];
+            let (blocks_x, blocks_y) = if single { (1, 1) } else { (component.h, component.v) };
+            let missing = || anyhow::anyhow!("scan uses a missing Huffman table");
+            let dc = tables.dc[component.dc_table].as_ref();
+            let ac = tables.ac[component.ac_table].as_ref();
+            for by in 0..blocks_y {
+                for bx in 0..blocks_x {
+                    let x = mx * blocks_x + bx;
+                    let y = my * blocks_y + by;
+                    let block = &mut component.blocks[y * component.blocks_wide + x];
+                    if scan.start == 0 {
+                        if scan.high == 0 {
+                            let size = dc.ok_or_else(missing)?.decode(&mut bits)? as u32;
+                            predictions[c] += extend(bits.take(size)?, size);
+                            block[0] = (predictions[c] << scan.low) as i16;
+                        } else if bits.take(1)? == 1 {
+                            block[0] |= 1 << scan.low;
+                        }
+                    }
+                    if scan.end > 0 {
+                        let ac = ac.ok_or_else(missing)?;
+                        let band = scan.start.max(1)..=scan.end;
+                        if scan.high == 0 {
+                            decode_ac(&mut bits, ac, block, band, scan.low, &mut eob_run)?;
+                        } else {
+                            refine_ac(&mut bits, ac, block, band, scan.low, &mut eob_run)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(bits.pos)
+}
+
+/// Decodes the first bits of the AC coefficients `band` of a block, or of a
+/// whole sequential block past its DC coefficient.
+fn decode_ac(
+    bits: &mut Bits,
+    ac: &Huffman,
+    block: &mut [i16; 64],
+    band: std::ops::RangeInclusive<usize>,
+    low: u8,
+    eob_run: &mut u32,
+) -> Result<()> {
+    if *eob_run > 0 {
+        *eob_run -= 1;
+        return Ok(());
+    }
+    let (mut k, end) = band.into_inner();
+    while k <= end {
+        let symbol = ac.decode(bits)?;
+        let (run, size) = ((symbol >> 4) as u32, (symbol & 15) as u32);
+        if size == 0 {
+            if run == 15 {
+                k += 16;
+                continue;
+            }
+            // This block ends the band, and so do the next blocks of the run.
+            *eob_run = (1 << run) + bits.take(run)? - 1;
+            break;
+        }
+        k += run as usize;
+        ensure!(k <= end, "coefficient run overruns its band");
+        block[k] = (extend(bits.take(size)?, size) << low) as i16;
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Decodes one more bit of the AC coefficients `band` of a block: a bit
+/// for each coefficient already nonzero, and the coefficients that become
+/// nonzero at this bit, each preceded by a run of the ones still zero.
+fn refine_ac(
+    bits: &mut Bits,
+    ac: &Huffman,
+    block: &mut [i16; 64],
+    band: std::ops::RangeInclusive<usize>,
+    low: u8,
+    eob_run: &mut u32,
+) -> Result<()> {
+    let bit = 1i16 << low;
+    let (mut k, end) = band.into_inner();
+    if *eob_run == 0 {
+        while k <= end {
+            let symbol = ac.decode(bits)?;
+            let (run, size) = ((symbol >> 4) as u32, symbol & 15);
+            let value = match size {
+                0 if run < 15 => {
+                    // The rest of the band is refined below.
+                    *eob_run = (1 << run) + bits.take(run)?;
+                    break;
+                }
+                // A run of 16 zeros and no new coefficient.
+                0 => 0,
+                1 if bits.take(1)? == 1 => bit,
+                1 => -bit,
+                _ => bail!("invalid refinement of a coefficient"),
+            };
+            let mut zeros = run as i32;
+            while k <= end {
+                if block[k] != 0 {
+                    refine(bits, &mut block[k], bit)?;
+                } else {
+                    zeros -= 1;
+                    if zeros < 0 {
+                        break;
+                    }
+                }
+                k += 1;
+            }
+            if value != 0 {
+                ensure!(k <= end, "coefficient run overruns its band");
+                block[k] = value;
+            }
+            k += 1;
+        }
+    }
+    if *eob_run > 0 {
+        for coefficient in &mut block[k..=end] {
+            if *coefficient != 0 {
+                refine(bits, coefficient, bit)?;
+            }
+        }
+        *eob_run -= 1;
+    }
+    Ok(())
+}
+
+/// Adds the next bit of a coefficient that is already nonzero, away from
+/// zero.
+fn refine(bits: &mut Bits, coefficient: &mut i16, bit: i16) -> Result<()> {
+    if bits.take(1)? == 1 && *coefficient & bit == 0 {
+        *coefficient += if *coefficient >= 0 { bit } else { -bit };
+    }
+    Ok(())
+}
+
+/// Samples of a component, dequantized and transformed from its blocks.
+fn samples(component: &Component, quant: &[u16; 64], cosines: &[[f32; 8]; 8]) -> Vec<u8> {
+    let stride = component.blocks_wide * 8;
+    let mut samples = vec![0; component.blocks.len() * 64];
+    let mut coefficients = [0i32; 64];
+    for (i, block) in component.blocks.iter().enumerate() {
+        for k in 0..64 {
+            coefficients[ZIGZAG[k]] = block[k] as i32 * quant[k] as i32;
+        }
+        let (x, y) = (i % component.blocks_wide * 8, i / component.blocks_wide * 8);
+        idct(cosines, &coefficients, &mut samples[y * stride + x..], stride);
+    }
+    samples
+}
+
+/// Value of a `size`-bit magnitude category code.
+fn extend(value: u32, size: u32) -> i32 {
+    if size == 0 {
+        0
+    } else if value < 1 << (size - 1) {
+        value as i32 - (1 << size) + 1
+    } else {
+        value as i32
+    }
+}
+
+/// Basis of the inverse DCT: the weight of frequency `u` at sample `x`.
+fn idct_cosines() -> [[f32; 8]; 8] {
+    std::array::from_fn(|x| {
+        std::array::from_fn(|u| {
+            let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+            scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos()
+        })
+    })
+}
+
+/// Inverse DCT of one block of natural-order coefficients into 8 rows of
+/// `out`, `stride` apart.
+fn idct(cosines: &[[f32; 8]; 8], coefficients: &[i32; 64], out: &mut [u8], stride: usize) {
+    // Rows first, then columns.
+    let mut rows = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| cosines[x][u] * coefficients[v * 8 + u] as f32).sum();
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let value: f32 = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+            out[y * stride + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Most significant bit first reader of entropy-coded data, which removes
+/// stuffed zero bytes and reads zeros once it reaches a marker.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            if self.count == 0 {
+                self.fill()?;
+            }
+            self.count -= 1;
+            value = value << 1 | (self.buffer >> self.count) & 1;
+        }
+        Ok(value)
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        let byte = *self.data.get(self.pos).context("scan data ends early")?;
+        self.buffer = match byte {
+            0xff if self.data.get(self.pos + 1) == Some(&0) => {
+                self.pos += 2;
+                0xff
+            }
+            // A marker: stay on it.
+            0xff => 0,
+            _ => {
+                self.pos += 1;
+                byte as u32
+            }
+        };
+        self.count = 8;
+        Ok(())
+    }
+
+    /// Drops the bits left in the current byte and steps over the restart
+    /// marker that follows.
+    fn restart(&mut self) {
+        self.count = 0;
+        if self.data.get(self.pos) == Some(&0xff) && matches!(self.data.get(self.pos + 1), Some(0xd0..=0xd7)) {
+            self.pos += 2;
+        }
+    }
+}
+
+/// Canonical Huffman code from the code counts of each length, 1 to 16.
+struct Huffman {
+    counts: [u8; 16],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> Huffman {
+        Huffman {
+            counts: *counts,
+            symbols: symbols.to_vec(),
+        }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u8> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts {
+            code |= bits.take(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied().context("invalid Huffman code");
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code")
+    }
+}
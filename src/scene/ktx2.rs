@@ -0,0 +1,265 @@
+//! KTX 2.0 textures (`.ktx2`).
+//!
+//! Only the first layer and face is read. BC1 to BC7 blocks are kept with
+//! their mip chain, for the renderer to upload as they are. Other formats
+//! have their largest mip level decoded to 8-bit RGBA: 8-bit formats as
+//! stored and 16- and 32-bit float formats clamped to `0..=1`. `_UNORM` and
+//! float formats hold linear values, which are encoded as sRGB like every
+//! scene texture. One-channel formats are grey. Basis Universal payloads
+//! (ETC1S and UASTC), ETC2, ASTC, signed BC formats and Zstandard or zlib
+//! supercompression are rejected with an error naming them.
+
+use {
+    super::{
+        bcn::BlockFormat,
+        texture::{BlockImage, Texture},
+    },
+    crate::color,
+    anyhow::{bail, ensure, Context, Result},
+};
+
+pub const IDENTIFIER: &[u8; 12] = b"\xabKTX 20\xbb\r\n\x1a\n";
+
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+/// Data format descriptor color models of Basis Universal payloads, which
+/// have no Vulkan format.
+const KHR_DF_MODEL_ETC1S: u8 = 163;
+const KHR_DF_MODEL_UASTC: u8 = 166;
+
+/// How texels of a Vulkan format are stored.
+enum Encoding {
+    /// `channels` components per texel, blue first if `bgr`.
+    Plain {
+        channels: usize,
+        kind: Component,
+        bgr: bool,
+    },
+    Blocks(BlockFormat),
+}
+
+#[derive(Copy, Clone)]
+enum Component {
+    U8,
+    F16,
+    F32,
+}
+
+impl Component {
+    fn size(self) -> usize {
+        match self {
+            Component::U8 => 1,
+            Component::F16 => 2,
+            Component::F32 => 4,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f32 {
+        match self {
+            Component::U8 => bytes[0] as f32 / 255.0,
+            Component::F16 => half_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])),
+            Component::F32 => f32::from_le_bytes(bytes[..4].try_into().unwrap()),
+        }
+    }
+}
+
+/// Reads the first layer and face of a KTX2 file.
+///
+/// ```
+/// use raytracer::scene::{bcn::BlockFormat, ktx2};
+///
+/// // A 4x4 BC4_UNORM texture with two levels of a block each, the second
+/// // `last` bytes long.
+/// let ktx2 = |last: u64| {
+///     let mut file = ktx2::IDENTIFIER.to_vec();
+///     // Vulkan format, type size, width, height, depth, layers, faces, levels
+///     // and supercompression, then empty descriptors.
+///     for value in [139u32, 1, 4, 4, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0] {
+///         file.extend_from_slice(&value.to_le_bytes());
+///     }
+///     // The level index: offset, length and uncompressed length.
+///     for (offset, length) in [(128u64, 8), (136, last)] {
+///         for value in [offset, length, length] {
+///             file.extend_from_slice(&value.to_le_bytes());
+///         }
+///     }
+///     file.extend_from_slice(&[0; 16]);
+///     file
+/// };
+///
+/// let texture = ktx2::parse(&ktx2(8)).unwrap();
+/// let blocks = texture.blocks.unwrap();
+/// assert_eq!((blocks.format, blocks.srgb, blocks.levels.len()), (BlockFormat::Bc4, false, 2));
+/// let err = ktx2::parse(&ktx2(4)).unwrap_err();
+/// assert_eq!(err.to_string(), "truncated mip level 1");
+/// ```
+pub fn parse(bytes: &[u8]) -> Result<Texture> {
+    ensure!(bytes.starts_with(IDENTIFIER), "not a KTX2 file");
+    ensure!(bytes.len() >= HEADER_SIZE, "truncated header");
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let [vk_format, _type_size, width, height, depth, _layers, faces, levels, supercompression] =
+        std::array::from_fn(|i| u32_at(12 + 4 * i));
+    let (dfd_offset, dfd_length) = (u32_at(48) as usize, u32_at(52) as usize);
+
+    ensure!(width > 0, "texture has no width");
+    ensure!(depth == 0, "3D textures are not supported");
+    ensure!(faces == 1 || faces == 6, "invalid face count {faces}");
+    let height = height.max(1);
+    match supercompression {
+        0 => (),
+        SUPERCOMPRESSION_BASIS_LZ => bail!("Basis Universal ETC1S textures are not supported; transcode them to BC1-BC5 or RGBA8"),
+        SUPERCOMPRESSION_ZSTD => bail!("Zstandard supercompression is not supported"),
+        SUPERCOMPRESSION_ZLIB => bail!("zlib supercompression is not supported"),
+        other => bail!("unknown supercompression scheme {other}"),
+    }
+
+    let (encoding, srgb) = match vk_format {
+        0 => {
+            // The color model follows the descriptor's total size, vendor,
+            // type, version and block size fields.
+            let model = (dfd_length >= 16)
+                .then(|| bytes.get(dfd_offset + 12))
+                .flatten()
+                .copied();
+            match model {
+                Some(KHR_DF_MODEL_ETC1S | KHR_DF_MODEL_UASTC) => {
+                    bail!("Basis Universal textures are not supported; transcode them to BC1-BC5 or RGBA8")
+                }
+                _ => bail!("texture has no format"),
+            }
+        }
+        9..=50 => {
+            let (base, channels, bgr) = match vk_format {
+                9..=15 => (9, 1, false),
+                16..=22 => (16, 2, false),
+                23..=29 => (23, 3, false),
+                30..=36 => (30, 3, true),
+                37..=43 => (37, 4, false),
+                _ => (44, 4, true),
+            };
+            // Each size comes as UNORM, SNORM, USCALED, SSCALED, UINT, SINT
+            // and SRGB.
+            let srgb = match vk_format - base {
+                0 => false,
+                6 => true,
+                _ => bail!("only UNORM and SRGB 8-bit formats are supported, not Vulkan format {vk_format}"),
+            };
+            let kind = Component::U8;
+            (Encoding::Plain { channels, kind, bgr }, srgb)
+        }
+        76 | 83 | 90 | 97 => {
+            let channels = (vk_format as usize - 76) / 7 + 1;
+            (Encoding::Plain { channels, kind: Component::F16, bgr: false }, false)
+        }
+        100 | 103 | 106 | 109 => {
+            let channels = (vk_format as usize - 100) / 3 + 1;
+            (Encoding::Plain { channels, kind: Component::F32, bgr: false }, false)
+        }
+        131..=139 | 141 | 143 | 145 | 146 => {
+            let format = match vk_format {
+                131..=134 => BlockFormat::Bc1,
+                135 | 136 => BlockFormat::Bc2,
+                137 | 138 => BlockFormat::Bc3,
+                139 => BlockFormat::Bc4,
+                141 => BlockFormat::Bc5,
+                143 => BlockFormat::Bc6h,
+                _ => BlockFormat::Bc7,
+            };
+            (Encoding::Blocks(format), matches!(vk_format, 132 | 134 | 136 | 138 | 146))
+        }
+        140 | 142 | 144 => bail!("signed BC4, BC5 and BC6H textures are not supported"),
+        147..=156 => bail!("ETC2 and EAC textures are not supported"),
+        157..=184 => bail!("ASTC textures are not supported"),
+        other => bail!("unsupported Vulkan format {other}"),
+    };
+
+    // The level index starts with the largest level. Each level's data
+    // starts with the first layer's first face.
+    let level = |index: usize| -> Result<&[u8]> {
+        let at = HEADER_SIZE + index * LEVEL_INDEX_ENTRY_SIZE;
+        let entry = bytes.get(at..at + LEVEL_INDEX_ENTRY_SIZE).context("truncated level index")?;
+        let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+        let length = u64::from_le_bytes(entry[8..16].try_into().unwrap()) as usize;
+        offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .context("mip level exceeds the file")
+    };
+
+    let (channels, kind, bgr) = match encoding {
+        Encoding::Plain { channels, kind, bgr } => (channels, kind, bgr),
+        Encoding::Blocks(format) => {
+            let levels = (0..levels.clamp(1, 32))
+                .map(|i| {
+                    let size = format.image_size((width >> i).max(1), (height >> i).max(1));
+                    let data = level(i as usize)?;
+                    ensure!(data.len() >= size, "truncated mip level {i}");
+                    Ok(data[..size].to_vec())
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Texture {
+                width,
+                height,
+                pixels: Vec::new(),
+                blocks: Some(BlockImage { format, srgb, levels }),
+                source: None,
+            });
+        }
+    };
+
+    let data = level(0)?;
+    let texels = width as usize * height as usize;
+    let texel_size = channels * kind.size();
+    ensure!(data.len() >= texels * texel_size, "truncated texel data");
+    let mut pixels: Vec<[f32; 4]> = data
+        .chunks_exact(texel_size)
+        .take(texels)
+        .map(|texel| {
+            let mut rgba = [0.0, 0.0, 0.0, 1.0];
+            for (c, value) in rgba.iter_mut().take(channels).enumerate() {
+                *value = kind.read(&texel[c * kind.size()..]);
+            }
+            if bgr {
+                rgba.swap(0, 2);
+            }
+            if channels == 1 {
+                rgba = [rgba[0], rgba[0], rgba[0], 1.0];
+            }
+            rgba
+        })
+        .collect();
+    if !srgb {
+        for rgba in &mut pixels {
+            for c in &mut rgba[..3] {
+                *c = color::linear_to_srgb(c.clamp(0.0, 1.0));
+            }
+        }
+    }
+    Ok(Texture {
+        width,
+        height,
+        pixels: pixels
+            .into_iter()
+            .map(|rgba| rgba.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+            .collect(),
+        blocks: None,
+        source: None,
+    })
+}
+
+pub(super) fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,130 @@
+//! Analytic area lights, and the triangles of emissive materials. The
+//! shader samples both directly at diffuse hits instead of waiting for
+//! paths to stumble onto them.
+
+use super::{gltf, Scene};
+
+/// Must match `MAX_LIGHTS` in shader.wgsl.
+pub const MAX_LIGHTS: usize = 64;
+
+#[derive(Copy, Clone, Debug)]
+pub struct AreaLight {
+    pub shape: LightShape,
+    /// Emitted radiance, linear RGB.
+    pub emission: [f32; 3],
+    /// Index into `Scene::textures` of an image modulating the emission of a
+    /// rect, stretched so that its columns run along `u` and its rows along
+    /// `v`.
+    pub gobo: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum LightShape {
+    /// Rectangle spanned by the perpendicular edges `u` and `v` from `corner`,
+    /// emitting towards `u × v` only.
+    Rect { corner: [f32; 3], u: [f32; 3], v: [f32; 3] },
+    /// Emits towards `normal` only.
+    Disc { center: [f32; 3], normal: [f32; 3], radius: f32 },
+    Sphere { center: [f32; 3], radius: f32 },
+    /// Open cylinder around the segment from `a` to `b`.
+    Tube { a: [f32; 3], b: [f32; 3], radius: f32 },
+}
+
+impl AreaLight {
+    /// Applies `rotate`, which must preserve lengths, followed by a uniform
+    /// `scale`.
+    pub fn transformed(self, rotate: impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> AreaLight {
+        let scaled = |p| rotate(p).map(|c| c * scale);
+        let shape = match self.shape {
+            LightShape::Rect { corner, u, v } => LightShape::Rect {
+                corner: scaled(corner),
+                u: scaled(u),
+                v: scaled(v),
+            },
+            LightShape::Disc { center, normal, radius } => LightShape::Disc {
+                center: scaled(center),
+                normal: rotate(normal),
+                radius: radius * scale.abs(),
+            },
+            LightShape::Sphere { center, radius } => LightShape::Sphere {
+                center: scaled(center),
+                radius: radius * scale.abs(),
+            },
+            LightShape::Tube { a, b, radius } => LightShape::Tube {
+                a: scaled(a),
+                b: scaled(b),
+                radius: radius * scale.abs(),
+            },
+        };
+        AreaLight { shape, ..self }
+    }
+
+    pub fn translated(self, offset: [f32; 3]) -> AreaLight {
+        let moved = |p: [f32; 3]| [0, 1, 2].map(|i| p[i] + offset[i]);
+        let shape = match self.shape {
+            LightShape::Rect { corner, u, v } => LightShape::Rect { corner: moved(corner), u, v },
+            LightShape::Disc { center, normal, radius } => LightShape::Disc {
+                center: moved(center),
+                normal,
+                radius,
+            },
+            LightShape::Sphere { center, radius } => LightShape::Sphere {
+                center: moved(center),
+                radius,
+            },
+            LightShape::Tube { a, b, radius } => LightShape::Tube {
+                a: moved(a),
+                b: moved(b),
+                radius,
+            },
+        };
+        AreaLight { shape, ..self }
+    }
+}
+
+/// A world-space triangle of a material with emission, lighting the scene
+/// like an area light from both sides.
+#[derive(Copy, Clone, Debug)]
+pub struct EmissiveTriangle {
+    pub positions: [[f32; 3]; 3],
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+impl Scene {
+    /// The emissive world-space triangles and those of every mesh placed
+    /// where diffuse rays see it, at full detail whatever level the renderer
+    /// draws.
+    pub fn emissive_triangles(&self) -> Vec<EmissiveTriangle> {
+        let emissive = |material: u32| {
+            self.materials
+                .get(material as usize)
+                .is_some_and(|m| m.emission.iter().any(|&c| c > 0.0))
+        };
+        let mut out: Vec<EmissiveTriangle> = self
+            .triangles
+            .iter()
+            .filter(|tri| emissive(tri.material))
+            .map(|tri| EmissiveTriangle {
+                positions: tri.indices.map(|i| self.positions[i as usize]),
+                material: tri.material,
+            })
+            .collect();
+        let placed = self.instances.iter().copied().chain(self.node_instances());
+        for instance in placed.filter(|instance| instance.visibility.diffuse) {
+            let mesh = &self.meshes[instance.mesh as usize];
+            for tri in &mesh.triangles {
+                let material = instance.material.unwrap_or(tri.material);
+                if emissive(material) {
+                    out.push(EmissiveTriangle {
+                        positions: tri
+                            .indices
+                            .map(|i| gltf::transform_point(&instance.transform, mesh.positions[i as usize])),
+                        material,
+                    });
+                }
+            }
+        }
+        out
+    }
+}
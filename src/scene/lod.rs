@@ -0,0 +1,115 @@
+//! Levels of detail: coarser meshes drawn in place of an instanced mesh
+//! where its instances are far from the camera or small on screen, chosen
+//! again as the camera moves.
+
+use {
+    super::{gltf, Instance},
+    crate::math::Vec3,
+};
+
+/// One coarser level of a mesh.
+#[derive(Copy, Clone, Debug)]
+pub struct Lod {
+    /// Index into `Scene::meshes`.
+    pub mesh: u32,
+    pub switch: LodSwitch,
+}
+
+/// When a level is drawn instead of the finer ones before it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LodSwitch {
+    /// Beyond this distance from the camera to the center of the instance's
+    /// bounding sphere, in world units.
+    Distance(f32),
+    /// Once the instance's bounding sphere spans less than this fraction of
+    /// the view height.
+    ScreenSize(f32),
+}
+
+/// Where levels are chosen from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodView {
+    pub eye: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub vfov: f32,
+}
+
+/// Center and radius.
+pub type BoundingSphere = ([f32; 3], f32);
+
+/// The sphere around a `(min, max)` box.
+pub fn bounding_sphere((min, max): ([f32; 3], [f32; 3])) -> BoundingSphere {
+    let center = [0, 1, 2].map(|i| (min[i] + max[i]) * 0.5);
+    let [x, y, z] = [0, 1, 2].map(|i| (max[i] - min[i]) * 0.5);
+    (center, (x * x + y * y + z * z).sqrt())
+}
+
+impl Instance {
+    /// Mesh drawn for this instance seen from `view`: the coarsest level of
+    /// `chain` whose switch holds, or the instance's own mesh. `sphere`
+    /// bounds the mesh in object space.
+    ///
+    /// ```
+    /// use raytracer::scene::{
+    ///     gltf::IDENTITY,
+    ///     lod::{Lod, LodSwitch, LodView},
+    ///     Instance, Visibility,
+    /// };
+    ///
+    /// let instance = Instance {
+    ///     mesh: 0,
+    ///     transform: IDENTITY,
+    ///     material: None,
+    ///     visibility: Visibility::ALL,
+    /// };
+    /// let chain = [
+    ///     Lod { mesh: 1, switch: LodSwitch::Distance(10.0) },
+    ///     Lod { mesh: 2, switch: LodSwitch::ScreenSize(0.05) },
+    /// ];
+    /// // With a 90 degree field of view, a unit sphere spans 1 / distance of
+    /// // the view height.
+    /// let from = |x: f32| LodView { eye: [x, 0.0, 0.0], vfov: 90.0 };
+    /// let unit = ([0.0; 3], 1.0);
+    /// assert_eq!(instance.choose_lod(&chain, unit, &from(5.0)), 0);
+    /// assert_eq!(instance.choose_lod(&chain, unit, &from(15.0)), 1);
+    /// assert_eq!(instance.choose_lod(&chain, unit, &from(30.0)), 2);
+    /// // The coarsest level whose switch holds wins, even where the finer
+    /// // ones' do not: a small sphere near the camera.
+    /// assert_eq!(instance.choose_lod(&chain, ([0.0; 3], 0.1), &from(5.0)), 2);
+    /// assert_eq!(instance.choose_lod(&[], unit, &from(30.0)), 0);
+    ///
+    /// // Moved 100 along x and scaled by 4, the sphere is 30 away and still
+    /// // spans more than 0.05 of the view.
+    /// let mut transform = IDENTITY;
+    /// (0..3).for_each(|i| transform[i][i] = 4.0);
+    /// transform[3][0] = 100.0;
+    /// let scaled = Instance { transform, ..instance };
+    /// assert_eq!(scaled.choose_lod(&chain, unit, &from(130.0)), 1);
+    /// ```
+    pub fn choose_lod(&self, chain: &[Lod], sphere: BoundingSphere, view: &LodView) -> u32 {
+        if chain.is_empty() {
+            return self.mesh;
+        }
+        let m = &self.transform;
+        let (center, radius) = sphere;
+        let center = gltf::transform_point(m, center);
+        // Radius of the sphere around the scaled one, for any scaling.
+        let scale = (0..3).map(|i| Vec3::new(m[i][0], m[i][1], m[i][2]).length()).fold(0.0, f32::max);
+        let radius = radius * scale;
+        let offset = Vec3::new(center[0] - view.eye[0], center[1] - view.eye[1], center[2] - view.eye[2]);
+        let distance = offset.length();
+        let screen_size = if distance > radius {
+            radius / (distance * (view.vfov.to_radians() * 0.5).tan())
+        } else {
+            f32::INFINITY
+        };
+        chain
+            .iter()
+            .rev()
+            .find(|lod| match lod.switch {
+                LodSwitch::Distance(d) => distance > d,
+                LodSwitch::ScreenSize(s) => screen_size < s,
+            })
+            .map_or(self.mesh, |lod| lod.mesh)
+    }
+}
@@ -0,0 +1,120 @@
+//! Mesh files read in place through a memory map instead of being copied
+//! into memory first, so that a scan several gigabytes large does not need
+//! twice that while its vertices are parsed out of it. Progress through
+//! large files goes to the handler set with [`set_progress_handler`], if
+//! any.
+
+use {
+    anyhow::{Context, Result},
+    memmap2::Mmap,
+    std::{fs::File, ops::Deref, path::Path, sync::RwLock},
+};
+
+/// Files smaller than this load quickly enough not to report progress.
+const PROGRESS_MIN_LEN: usize = 64 << 20;
+
+type ProgressHandler = Box<dyn Fn(&LoadProgress) + Send + Sync>;
+
+static PROGRESS_HANDLER: RwLock<Option<ProgressHandler>> = RwLock::new(None);
+
+/// How far parsing of a large mesh file has got.
+#[derive(Copy, Clone, Debug)]
+pub struct LoadProgress<'a> {
+    pub file_name: &'a str,
+    /// Share of the file parsed, in steps of 10.
+    pub percent: u32,
+    /// Set once parsing has ended, whether it finished or failed part way.
+    pub done: bool,
+}
+
+/// Sends the progress of every large mesh file loaded from now on to
+/// `handler`, replacing the one set before. Without one, loading is silent.
+pub fn set_progress_handler(handler: impl Fn(&LoadProgress) + Send + Sync + 'static) {
+    *PROGRESS_HANDLER.write().unwrap_or_else(|err| err.into_inner()) = Some(Box::new(handler));
+}
+
+/// The bytes of a file, mapped rather than read.
+///
+/// The slice it derefs to is the file itself, not a copy, so it is only
+/// sound while nothing else writes to or truncates the file. Another
+/// process changing the file while it is mapped is undefined behavior
+/// rather than an error, and truncating it kills this process with a bus
+/// error on reads past the new end.
+pub struct MappedFile {
+    /// `None` for an empty file, which cannot be mapped.
+    map: Option<Mmap>,
+}
+
+impl MappedFile {
+    pub fn open(path: &Path) -> Result<MappedFile> {
+        let context = || format!("failed to read {}", path.display());
+        let file = File::open(path).with_context(context)?;
+        if file.metadata().with_context(context)?.len() == 0 {
+            return Ok(MappedFile { map: None });
+        }
+        // SAFETY: none is guaranteed. The map stays valid after `file` is
+        // closed, but `&[u8]` promises bytes that never change, which holds
+        // only while no other process writes to or truncates the file. That
+        // cannot be enforced, and is assumed of scene files while they load,
+        // as the docs of `MappedFile` say.
+        let map = unsafe { Mmap::map(&file) }.with_context(context)?;
+        Ok(MappedFile { map: Some(map) })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+}
+
+/// Reports how far parsing of a large file has got, in tenths.
+pub struct Progress {
+    name: String,
+    len: usize,
+    /// Tenths reported so far, or `None` if the file is too small to report.
+    shown: Option<usize>,
+}
+
+impl Progress {
+    pub fn new(path: &Path, len: usize) -> Progress {
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+        Progress {
+            name,
+            len,
+            shown: (len >= PROGRESS_MIN_LEN).then_some(0),
+        }
+    }
+
+    /// Notes that parsing has reached byte `pos`.
+    pub fn update(&mut self, pos: usize) {
+        let Some(shown) = &mut self.shown else {
+            return;
+        };
+        let tenths = pos.min(self.len) * 10 / self.len;
+        if tenths > *shown {
+            *shown = tenths;
+            self.report(false);
+        }
+    }
+
+    fn report(&self, done: bool) {
+        let handler = PROGRESS_HANDLER.read().unwrap_or_else(|err| err.into_inner());
+        if let (Some(handler), Some(shown)) = (&*handler, self.shown) {
+            handler(&LoadProgress {
+                file_name: &self.name,
+                percent: shown as u32 * 10,
+                done,
+            });
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// Reports the end, whether parsing finished or failed part way.
+    fn drop(&mut self) {
+        self.report(true);
+    }
+}
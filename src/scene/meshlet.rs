@@ -0,0 +1,178 @@
+//! Meshlets: small clusters of a mesh's triangles, each with a bounding
+//! sphere and a cone bounding its normals, for culling whole clusters at a
+//! time. They are cut from the leaf order of the mesh's hierarchy, which
+//! already keeps nearby triangles together.
+
+use {
+    super::{bvh::Bvh, Triangle},
+    crate::math::Vec3,
+    std::ops::Range,
+};
+
+/// Most vertices in one meshlet.
+pub const MAX_VERTICES: usize = 64;
+
+/// Most triangles in one meshlet.
+pub const MAX_TRIANGLES: usize = 124;
+
+/// Normals within this angle of the cone axis, as its cosine, are too widely
+/// spread for the cone to cull anything.
+const MIN_CONE_COS: f32 = 0.1;
+
+#[derive(Clone, Debug)]
+pub struct Meshlet {
+    /// Run of [`Bvh::order`] holding the meshlet's triangles.
+    pub triangles: Range<u32>,
+    /// Mesh vertices the triangles use, in order of first use.
+    pub vertices: Vec<u32>,
+    pub center: [f32; 3],
+    pub radius: f32,
+    /// `None` when the normals face too many ways for any view to see only
+    /// the backs of them all.
+    pub cone: Option<NormalCone>,
+}
+
+/// Bounds the normals of a meshlet's triangles, placed so that a point sees
+/// only their backs when it looks at `apex` from within the opposite cone.
+#[derive(Copy, Clone, Debug)]
+pub struct NormalCone {
+    pub apex: [f32; 3],
+    pub axis: [f32; 3],
+    /// Sine of the angle between the axis and the widest normal.
+    pub cutoff: f32,
+}
+
+impl Meshlet {
+    /// Whether every triangle faces away from `eye`, so that rays from there
+    /// can only reach their backs.
+    pub fn backfacing(&self, eye: [f32; 3]) -> bool {
+        let Some(cone) = self.cone else {
+            return false;
+        };
+        let to_apex = vec(cone.apex) - vec(eye);
+        let length = to_apex.length();
+        length > 0.0 && to_apex.dot(&vec(cone.axis)) >= cone.cutoff * length
+    }
+}
+
+/// Cuts the triangles of `bvh`, built over `triangles` indexing into
+/// `positions`, into meshlets of consecutive leaf-order runs.
+///
+/// ```
+/// use raytracer::scene::{bvh::Bvh, meshlet, Triangle};
+///
+/// // A 16 by 16 grid of quads facing up, too many triangles for one meshlet.
+/// let n = 16;
+/// let positions: Vec<[f32; 3]> = (0..=n)
+///     .flat_map(|z| (0..=n).map(move |x| [x as f32, 0.0, z as f32]))
+///     .collect();
+/// let triangles: Vec<Triangle> = (0..n)
+///     .flat_map(|z| (0..n).map(move |x| z * (n + 1) + x))
+///     .flat_map(|v| [[v, v + n + 1, v + 1], [v + 1, v + n + 1, v + n + 2]])
+///     .map(|indices| Triangle { indices, material: 0 })
+///     .collect();
+/// let bvh = Bvh::build(&positions, &triangles, 4);
+/// let meshlets = meshlet::build(&positions, &triangles, &bvh);
+/// assert!(meshlets.len() > 1);
+///
+/// // The runs cover every triangle once, in order.
+/// let mut next = 0;
+/// for m in &meshlets {
+///     assert_eq!(m.triangles.start, next);
+///     next = m.triangles.end;
+///     assert!(m.vertices.len() <= meshlet::MAX_VERTICES);
+///     assert!(m.triangles.len() <= meshlet::MAX_TRIANGLES);
+///     // The sphere holds every vertex.
+///     for &v in &m.vertices {
+///         let d: f32 = (0..3).map(|i| (positions[v as usize][i] - m.center[i]).powi(2)).sum();
+///         assert!(d.sqrt() <= m.radius * 1.0001);
+///     }
+/// }
+/// assert_eq!(next as usize, triangles.len());
+///
+/// // Seen from below, every meshlet shows only its backs; from above, none.
+/// assert!(meshlets.iter().all(|m| m.backfacing([8.0, -5.0, 8.0])));
+/// assert!(meshlets.iter().all(|m| !m.backfacing([8.0, 5.0, 8.0])));
+/// ```
+pub fn build(positions: &[[f32; 3]], triangles: &[Triangle], bvh: &Bvh) -> Vec<Meshlet> {
+    let mut meshlets = Vec::new();
+    let mut first = 0;
+    let mut vertices: Vec<u32> = Vec::new();
+    for (i, &tri) in bvh.order.iter().enumerate() {
+        let indices = triangles[tri as usize].indices;
+        let new = indices.iter().filter(|v| !vertices.contains(v)).count();
+        if i - first == MAX_TRIANGLES || vertices.len() + new > MAX_VERTICES {
+            meshlets.push(meshlet(positions, triangles, bvh, first..i, std::mem::take(&mut vertices)));
+            first = i;
+        }
+        for v in indices {
+            if !vertices.contains(&v) {
+                vertices.push(v);
+            }
+        }
+    }
+    if first < bvh.order.len() {
+        meshlets.push(meshlet(positions, triangles, bvh, first..bvh.order.len(), vertices));
+    }
+    meshlets
+}
+
+fn meshlet(positions: &[[f32; 3]], triangles: &[Triangle], bvh: &Bvh, run: Range<usize>, vertices: Vec<u32>) -> Meshlet {
+    let points: Vec<Vec3> = vertices.iter().map(|&v| vec(positions[v as usize])).collect();
+    let (mut min, mut max) = (points[0], points[0]);
+    for p in &points {
+        min = Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+        max = Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+    }
+    let center = (min + max) * 0.5;
+    let radius = points.iter().map(|&p| (p - center).length()).fold(0.0, f32::max);
+
+    // Unit normal and first corner of every triangle with an area.
+    let faces: Vec<(Vec3, Vec3)> = bvh.order[run.clone()]
+        .iter()
+        .filter_map(|&tri| {
+            let [a, b, c] = triangles[tri as usize].indices.map(|v| vec(positions[v as usize]));
+            let normal = (b - a).cross(&(c - a));
+            (normal.length() > 0.0).then(|| (normal.normalized(), a))
+        })
+        .collect();
+    let cone = normal_cone(&faces, center);
+
+    Meshlet {
+        triangles: run.start as u32..run.end as u32,
+        vertices,
+        center: [center.x(), center.y(), center.z()],
+        radius,
+        cone,
+    }
+}
+
+/// The cone around the unit normals of `faces`, each given with a corner,
+/// of a meshlet centered on `center`.
+fn normal_cone(faces: &[(Vec3, Vec3)], center: Vec3) -> Option<NormalCone> {
+    let sum = faces.iter().fold(Vec3::zero(), |sum, &(normal, _)| sum + normal);
+    if sum.length() == 0.0 {
+        return None;
+    }
+    let axis = sum.normalized();
+    let min_cos = faces.iter().map(|(normal, _)| normal.dot(&axis)).fold(1.0, f32::min);
+    if min_cos < MIN_CONE_COS {
+        return None;
+    }
+    // Back the apex off along the axis until it is behind every triangle's
+    // plane.
+    let offset = faces
+        .iter()
+        .map(|&(normal, corner)| (center - corner).dot(&normal) / normal.dot(&axis))
+        .fold(0.0, f32::max);
+    let apex = center - axis * offset;
+    Some(NormalCone {
+        apex: [apex.x(), apex.y(), apex.z()],
+        axis: [axis.x(), axis.y(), axis.z()],
+        cutoff: (1.0 - min_cos * min_cos).max(0.0).sqrt(),
+    })
+}
+
+fn vec([x, y, z]: [f32; 3]) -> Vec3 {
+    Vec3::new(x, y, z)
+}
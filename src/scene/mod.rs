@@ -0,0 +1,813 @@
+//! Geometry and materials loaded from files, flattened for the GPU.
+
+use {
+    self::{
+        curve::Curve,
+        gltf::{Mat4, IDENTITY},
+        graph::{SceneNode, Trs},
+        light::{AreaLight, MAX_LIGHTS},
+        lod::Lod,
+        procedural::Procedural,
+        sdf::{Sdf, MAX_SDF_NODES, SDF_STACK_SIZE},
+        splat::Splat,
+        texture::Texture,
+        volume::{Volume, MAX_VOLUMES},
+    },
+    crate::{camera::Camera, export, math::Vec3},
+    anyhow::{bail, ensure, Result},
+    std::{path::Path, str::FromStr},
+};
+
+pub mod alembic;
+pub mod bcn;
+pub mod builder;
+pub mod bvh;
+pub mod curve;
+pub mod dds;
+pub mod description;
+pub mod edit;
+pub mod gltf;
+pub mod graph;
+pub mod hair;
+pub mod jpeg;
+pub mod ktx2;
+pub mod light;
+pub mod lod;
+pub mod mapped;
+pub mod meshlet;
+pub mod nanovdb;
+pub mod obj;
+pub mod ply;
+pub mod png;
+pub mod procedural;
+pub mod sdf;
+pub mod splat;
+pub mod subdivide;
+pub mod tangent;
+pub mod texture;
+pub mod usd;
+pub mod volume;
+pub mod xyz;
+
+/// Triangles, analytic shapes, curves, point splats and area lights in world
+/// space, plus placed copies of instanced meshes and a hierarchy of nodes
+/// placing more of them.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub positions: Vec<[f32; 3]>,
+    /// Shading normal of each vertex, parallel to `positions`. A zero normal
+    /// makes triangles using the vertex flat-shaded.
+    pub normals: Vec<[f32; 3]>,
+    /// Linear color of each vertex replacing the base color of its
+    /// triangles' material, parallel to `positions` as far as it goes.
+    /// Triangles are colored only if all their vertices are.
+    pub colors: Vec<Option<[f32; 3]>>,
+    /// Texture coordinates of each vertex, parallel to `positions` as far as
+    /// it goes, with (0, 0) at the top left of the image as in glTF. Vertices
+    /// past its end are at (0, 0).
+    pub uvs: Vec<[f32; 2]>,
+    /// Tangent of each vertex, orienting normal maps, parallel to
+    /// `positions` as far as it goes. See [`tangent::generate`] for the
+    /// convention. Vertices past its end, or with a zero tangent, ignore
+    /// normal maps.
+    pub tangents: Vec<[f32; 4]>,
+    pub triangles: Vec<Triangle>,
+    /// Runs of `positions` that move over time, for motion blur.
+    pub vertex_animations: Vec<VertexAnimation>,
+    pub materials: Vec<Material>,
+    pub spheres: Vec<Sphere>,
+    pub shapes: Vec<Shape>,
+    pub sdfs: Vec<Sdf>,
+    pub procedurals: Vec<Procedural>,
+    pub curves: Vec<Curve>,
+    pub splats: Vec<Splat>,
+    pub volumes: Vec<Volume>,
+    pub lights: Vec<AreaLight>,
+    pub textures: Vec<Texture>,
+    pub meshes: Vec<Mesh>,
+    pub instances: Vec<Instance>,
+    pub nodes: Vec<SceneNode>,
+    /// Nodes without a parent, indices into `nodes`.
+    pub root_nodes: Vec<u32>,
+    pub camera: Option<SceneCamera>,
+}
+
+/// Object-space triangles with their own vertices, drawn once per
+/// [`Instance`] that names them. Triangle materials index into
+/// `Scene::materials`.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    /// As in `Scene::normals`.
+    pub normals: Vec<[f32; 3]>,
+    /// As in `Scene::colors`.
+    pub colors: Vec<Option<[f32; 3]>>,
+    /// As in `Scene::uvs`.
+    pub uvs: Vec<[f32; 2]>,
+    /// As in `Scene::tangents`.
+    pub tangents: Vec<[f32; 4]>,
+    pub triangles: Vec<Triangle>,
+    /// Coarser meshes drawn in its place, from finest to coarsest.
+    pub lods: Vec<Lod>,
+}
+
+/// Positions of a run of `Scene::positions` at successive times, such as a
+/// baked simulation. The run itself holds the first.
+#[derive(Clone, Debug)]
+pub struct VertexAnimation {
+    /// First vertex of the run.
+    pub first: u32,
+    /// Seconds.
+    pub times: Vec<f32>,
+    /// Positions of the run's vertices at each of `times`.
+    pub positions: Vec<Vec<[f32; 3]>>,
+}
+
+/// One placement of a mesh, sharing its vertices with every other placement.
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    /// Index into `Scene::meshes`.
+    pub mesh: u32,
+    /// Object to world. Must be invertible.
+    pub transform: Mat4,
+    /// Replaces the materials of all the mesh's triangles.
+    pub material: Option<u32>,
+    pub visibility: Visibility,
+}
+
+/// Which rays see an instance. Rays that do not pass through it, so an
+/// instance hidden from the camera still casts shadows and shows in
+/// reflections, and one that casts no shadows still shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Visibility {
+    /// Rays from the camera.
+    pub camera: bool,
+    /// Rays towards lights and the sun, and ambient occlusion rays.
+    pub shadow: bool,
+    /// Rays leaving diffuse surfaces and scattering in volumes.
+    pub diffuse: bool,
+    /// Rays leaving mirrors, metals and glass.
+    pub specular: bool,
+}
+
+impl Visibility {
+    pub const ALL: Visibility = Visibility {
+        camera: true,
+        shadow: true,
+        diffuse: true,
+        specular: true,
+    };
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::ALL
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    pub indices: [u32; 3],
+    pub material: u32,
+}
+
+/// A negative radius turns the normals inwards, for hollow glass.
+#[derive(Copy, Clone, Debug)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: SphereMaterial,
+}
+
+/// Spheres can also use the fixed materials of the demo scene.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SphereMaterial {
+    /// Index into `Scene::materials`.
+    Scene(u32),
+    /// Grey checkerboard diffuse.
+    Checker,
+    /// Polished brass-colored metal.
+    Brass,
+    /// Red diffuse.
+    RedDiffuse,
+    /// Clear glass with an index of refraction of 1.5.
+    Glass,
+}
+
+/// A quad, box or plane, intersected exactly rather than triangulated.
+#[derive(Copy, Clone, Debug)]
+pub struct Shape {
+    pub kind: ShapeKind,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ShapeKind {
+    /// Parallelogram spanned by `u` and `v` from `corner`, hit from both
+    /// sides.
+    Quad { corner: [f32; 3], u: [f32; 3], v: [f32; 3] },
+    /// Axis-aligned box, closed so that it can hold glass.
+    Box { min: [f32; 3], max: [f32; 3] },
+    /// Infinite plane through `point`, hit from both sides.
+    Plane { point: [f32; 3], normal: [f32; 3] },
+}
+
+impl Shape {
+    /// Applies `rotate`, which must map axes onto axes so that boxes stay
+    /// axis-aligned, followed by a uniform `scale`.
+    pub fn transformed(self, rotate: impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> Shape {
+        let scaled = |p| rotate(p).map(|c| c * scale);
+        let kind = match self.kind {
+            ShapeKind::Quad { corner, u, v } => ShapeKind::Quad {
+                corner: scaled(corner),
+                u: scaled(u),
+                v: scaled(v),
+            },
+            ShapeKind::Box { min, max } => {
+                let (a, b) = (scaled(min), scaled(max));
+                ShapeKind::Box {
+                    min: [0, 1, 2].map(|i| a[i].min(b[i])),
+                    max: [0, 1, 2].map(|i| a[i].max(b[i])),
+                }
+            }
+            ShapeKind::Plane { point, normal } => ShapeKind::Plane {
+                point: scaled(point),
+                normal: rotate(normal),
+            },
+        };
+        Shape { kind, ..self }
+    }
+
+    pub fn translated(self, offset: [f32; 3]) -> Shape {
+        let moved = |p: [f32; 3]| [0, 1, 2].map(|i| p[i] + offset[i]);
+        let kind = match self.kind {
+            ShapeKind::Quad { corner, u, v } => ShapeKind::Quad { corner: moved(corner), u, v },
+            ShapeKind::Box { min, max } => ShapeKind::Box {
+                min: moved(min),
+                max: moved(max),
+            },
+            ShapeKind::Plane { point, normal } => ShapeKind::Plane {
+                point: moved(point),
+                normal,
+            },
+        };
+        Shape { kind, ..self }
+    }
+}
+
+/// Metallic-roughness material, as in glTF, or one of the classic materials
+/// picked by `kind`.
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    /// Linear RGB.
+    pub base_color: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Emitted radiance, linear RGB.
+    pub emission: [f32; 3],
+    /// Index of refraction of the specular layer of non-metals, which sets
+    /// how much it reflects head-on, or of a dielectric.
+    pub ior: f32,
+    pub kind: MaterialKind,
+    /// Index into `Scene::textures` of an sRGB image multiplying
+    /// `base_color`, looked up at the triangles' `uvs` and repeated outside
+    /// 0 to 1. Other primitives have no texture coordinates and ignore it.
+    pub base_color_texture: Option<u32>,
+    /// Index into `Scene::textures` of a tangent-space normal map, looked up
+    /// like `base_color_texture` and bending the shading normal of triangles
+    /// with `tangents`. Its pixels hold linear values, X in red and Y in
+    /// green, with +Y up the image as in glTF.
+    pub normal_texture: Option<u32>,
+    /// Multiplies the X and Y of the normal map.
+    pub normal_scale: f32,
+}
+
+/// How a material scatters light. The classic kinds use `base_color` as
+/// their albedo and ignore `metallic` and `roughness`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum MaterialKind {
+    /// GGX specular layer over a Lambertian base.
+    #[default]
+    MetallicRoughness,
+    /// Ideal diffuse.
+    Lambertian,
+    /// Mirror whose reflections are scattered within a sphere of radius
+    /// `fuzz`, from 0 (polished) to 1.
+    Metal { fuzz: f32 },
+    /// Clear glass that refracts by `ior` and reflects by Fresnel.
+    Dielectric,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        // The glTF default material.
+        Self {
+            base_color: [1.0; 3],
+            metallic: 1.0,
+            roughness: 1.0,
+            emission: [0.0; 3],
+            ior: 1.5,
+            kind: MaterialKind::MetallicRoughness,
+            base_color_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SceneCamera {
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vup: Vec3,
+    /// Vertical field of view in degrees.
+    pub vfov: f32,
+}
+
+impl Mesh {
+    /// Object-space bounds of the vertices used by triangles, or `None` if
+    /// there are none.
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut points = self.triangles.iter().flat_map(|tri| tri.indices.map(|i| self.positions[i as usize]));
+        let first = points.next()?;
+        Some(points.fold((first, first), |(lo, hi), p| {
+            ([0, 1, 2].map(|i| lo[i].min(p[i])), [0, 1, 2].map(|i| hi[i].max(p[i])))
+        }))
+    }
+}
+
+impl Instance {
+    /// World-space box around an object-space `(min, max)` box.
+    pub fn world_bounds(&self, (min, max): ([f32; 3], [f32; 3])) -> ([f32; 3], [f32; 3]) {
+        let corners = (0..8).map(|i| {
+            let corner = [0, 1, 2].map(|axis| if i >> axis & 1 == 0 { min[axis] } else { max[axis] });
+            gltf::transform_point(&self.transform, corner)
+        });
+        corners.fold(([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]), |(lo, hi), p| {
+            ([0, 1, 2].map(|i| lo[i].min(p[i])), [0, 1, 2].map(|i| hi[i].max(p[i])))
+        })
+    }
+
+    /// Rows of the affine inverse of `transform`.
+    pub fn world_to_object(&self) -> [[f32; 4]; 3] {
+        let m = &self.transform;
+        let col = |i: usize| Vec3::new(m[i][0], m[i][1], m[i][2]);
+        let (a, b, c) = (col(0), col(1), col(2));
+        let det = a.dot(&b.cross(&c));
+        let translation = col(3);
+        [b.cross(&c), c.cross(&a), a.cross(&b)].map(|row| {
+            let row = row / det;
+            [row.x(), row.y(), row.z(), -row.dot(&translation)]
+        })
+    }
+}
+
+impl SceneCamera {
+    pub fn to_camera(self) -> Camera {
+        Camera::new(self.lookfrom, self.lookat, self.vup, self.vfov)
+    }
+}
+
+/// Loads a scene, choosing the format from the file extension.
+pub fn load(path: &Path) -> Result<Scene> {
+    let extension = path
+        .extension()
+        .and_then(|it| it.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gltf" | "glb") => gltf::load(path),
+        Some("obj") => obj::load(path),
+        Some("hair") => hair::load(path),
+        Some("ply") => ply::load(path),
+        Some("xyz") => xyz::load(path),
+        Some("usda" | "usd" | "usdz") => usd::load(path),
+        Some("abc") => alembic::load(path),
+        Some("nvdb") => Ok(Scene {
+            volumes: vec![nanovdb::load(path)?],
+            ..Scene::default()
+        }),
+        Some("json") => description::load(path),
+        _ => bail!(
+            "unsupported scene format `{}` (expected .json, .gltf, .glb, .obj, .hair, .ply, .xyz, .usda, .usdz, .abc or .nvdb)",
+            path.display()
+        ),
+    }
+}
+
+/// Up axis of an imported asset; scenes are Y-up internally.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+impl FromStr for UpAxis {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "y" | "Y" => UpAxis::Y,
+            "z" | "Z" => UpAxis::Z,
+            _ => bail!("unknown up axis `{s}` (expected y or z)"),
+        })
+    }
+}
+
+impl Scene {
+    /// The glass, metal and diffuse spheres on a checkered ground shown when
+    /// no scene file is given.
+    pub fn demo() -> Scene {
+        let sphere = |center, radius, material| Sphere {
+            center,
+            radius,
+            material,
+        };
+        Scene {
+            spheres: vec![
+                sphere([0.0, 0.0, -1.0], 0.5, SphereMaterial::Glass),
+                sphere([0.0, 0.0, -1.0], -0.45, SphereMaterial::Glass),
+                sphere([-1.1, 0.0, -1.0], 0.5, SphereMaterial::RedDiffuse),
+                sphere([1.1, 0.0, -1.0], 0.5, SphereMaterial::Brass),
+                sphere([0.0, -100.5, -1.0], 100.0, SphereMaterial::Checker),
+            ],
+            ..Scene::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+            && self.spheres.is_empty()
+            && self.shapes.is_empty()
+            && self.sdfs.is_empty()
+            && self.procedurals.is_empty()
+            && self.curves.is_empty()
+            && self.splats.is_empty()
+            && self.volumes.is_empty()
+            && self.lights.is_empty()
+            && self.instances.is_empty()
+            && self.nodes.iter().all(|node| node.mesh.is_none())
+    }
+
+    /// Checks that the SDF shapes, lights and volumes fit the shader's
+    /// fixed-size tables, and that procedural primitives can be spliced into
+    /// it.
+    pub fn check_limits(&self) -> Result<()> {
+        for (i, sdf) in self.sdfs.iter().enumerate() {
+            ensure!(
+                sdf.root.stack_depth() <= SDF_STACK_SIZE,
+                "SDF shape {i} nests too deeply (at most {SDF_STACK_SIZE} pending distances)"
+            );
+        }
+        let sdf_nodes: usize = self.sdfs.iter().map(|sdf| sdf.root.node_count()).sum();
+        ensure!(sdf_nodes <= MAX_SDF_NODES, "more than {MAX_SDF_NODES} SDF primitives and operations");
+        ensure!(self.lights.len() <= MAX_LIGHTS, "more than {MAX_LIGHTS} lights");
+        ensure!(self.volumes.len() <= MAX_VOLUMES, "more than {MAX_VOLUMES} volumes");
+        procedural::check(&self.procedurals)
+    }
+
+    /// Appends triangles indexing into `positions`, all using `material`.
+    /// Without `normals` the mesh is flat-shaded.
+    pub fn add_mesh(
+        &mut self,
+        positions: &[[f32; 3]],
+        normals: Option<&[[f32; 3]]>,
+        indices: &[[u32; 3]],
+        material: u32,
+    ) {
+        let base = self.positions.len() as u32;
+        self.positions.extend_from_slice(positions);
+        match normals {
+            Some(normals) => self.normals.extend_from_slice(normals),
+            None => self.normals.resize(self.positions.len(), [0.0; 3]),
+        }
+        self.triangles.extend(indices.iter().map(|tri| Triangle {
+            indices: tri.map(|i| base + i),
+            material,
+        }));
+    }
+
+    /// Appends another scene's geometry, instances, nodes, materials, lights
+    /// and textures. Its triangles keep their own materials unless `material`
+    /// overrides them all. The camera of `other` is dropped.
+    pub fn append(&mut self, other: Scene, material: Option<u32>) {
+        let vertex_base = self.positions.len() as u32;
+        let material_base = self.materials.len() as u32;
+        if !other.colors.is_empty() {
+            self.colors.resize(vertex_base as usize, None);
+            self.colors.extend(other.colors);
+        }
+        if !other.uvs.is_empty() {
+            self.uvs.resize(vertex_base as usize, [0.0; 2]);
+            self.uvs.extend(other.uvs);
+        }
+        if !other.tangents.is_empty() {
+            self.tangents.resize(vertex_base as usize, [0.0; 4]);
+            self.tangents.extend(other.tangents);
+        }
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.triangles.extend(other.triangles.into_iter().map(|tri| Triangle {
+            indices: tri.indices.map(|i| vertex_base + i),
+            material: material.unwrap_or(material_base + tri.material),
+        }));
+        self.vertex_animations.extend(other.vertex_animations.into_iter().map(|animation| VertexAnimation {
+            first: vertex_base + animation.first,
+            ..animation
+        }));
+        self.spheres.extend(other.spheres.into_iter().map(|sphere| Sphere {
+            material: match sphere.material {
+                SphereMaterial::Scene(i) => SphereMaterial::Scene(material.unwrap_or(material_base + i)),
+                builtin => builtin,
+            },
+            ..sphere
+        }));
+        self.shapes.extend(other.shapes.into_iter().map(|shape| Shape {
+            material: material.unwrap_or(material_base + shape.material),
+            ..shape
+        }));
+        self.sdfs.extend(other.sdfs.into_iter().map(|sdf| Sdf {
+            material: material.unwrap_or(material_base + sdf.material),
+            ..sdf
+        }));
+        self.procedurals.extend(other.procedurals.into_iter().map(|procedural| Procedural {
+            material: material.unwrap_or(material_base + procedural.material),
+            ..procedural
+        }));
+        self.curves.extend(other.curves.into_iter().map(|curve| Curve {
+            material: material.unwrap_or(material_base + curve.material),
+            ..curve
+        }));
+        self.splats.extend(other.splats.into_iter().map(|splat| Splat {
+            material: material.unwrap_or(material_base + splat.material),
+            ..splat
+        }));
+        self.volumes.extend(other.volumes);
+        let mesh_base = self.meshes.len() as u32;
+        self.meshes.extend(other.meshes.into_iter().map(|mesh| Mesh {
+            triangles: mesh
+                .triangles
+                .into_iter()
+                .map(|tri| Triangle {
+                    material: material.unwrap_or(material_base + tri.material),
+                    ..tri
+                })
+                .collect(),
+            lods: mesh
+                .lods
+                .into_iter()
+                .map(|lod| Lod {
+                    mesh: mesh_base + lod.mesh,
+                    ..lod
+                })
+                .collect(),
+            ..mesh
+        }));
+        self.instances.extend(other.instances.into_iter().map(|instance| Instance {
+            mesh: mesh_base + instance.mesh,
+            material: material.or(instance.material.map(|i| material_base + i)),
+            ..instance
+        }));
+        let node_base = self.nodes.len() as u32;
+        self.root_nodes.extend(other.root_nodes.into_iter().map(|i| node_base + i));
+        self.nodes.extend(other.nodes.into_iter().map(|node| SceneNode {
+            mesh: node.mesh.map(|i| mesh_base + i),
+            material: material.or(node.material.map(|i| material_base + i)),
+            children: node.children.into_iter().map(|i| node_base + i).collect(),
+            ..node
+        }));
+        let texture_base = self.textures.len() as u32;
+        if material.is_none() {
+            self.materials.extend(other.materials.into_iter().map(|m| Material {
+                base_color_texture: m.base_color_texture.map(|i| texture_base + i),
+                normal_texture: m.normal_texture.map(|i| texture_base + i),
+                ..m
+            }));
+        }
+        self.lights.extend(other.lights.into_iter().map(|light| AreaLight {
+            gobo: light.gobo.map(|i| texture_base + i),
+            ..light
+        }));
+        self.textures.extend(other.textures);
+    }
+
+    /// Converts an asset authored in other units or Z-up into the internal
+    /// Y-up frame. `unit_scale` is the size of one asset unit in scene units.
+    pub fn convert_frame(&mut self, unit_scale: f32, up: UpAxis) {
+        let convert = |[x, y, z]: [f32; 3]| match up {
+            UpAxis::Y => [x, y, z],
+            UpAxis::Z => [x, z, -y],
+        };
+        for p in &mut self.positions {
+            *p = convert(*p).map(|c| c * unit_scale);
+        }
+        for p in self.vertex_animations.iter_mut().flat_map(|it| &mut it.positions).flatten() {
+            *p = convert(*p).map(|c| c * unit_scale);
+        }
+        for n in &mut self.normals {
+            *n = convert(*n);
+        }
+        for t in &mut self.tangents {
+            let [x, y, z] = convert([t[0], t[1], t[2]]);
+            *t = [x, y, z, t[3]];
+        }
+        for sphere in &mut self.spheres {
+            sphere.center = convert(sphere.center).map(|c| c * unit_scale);
+            sphere.radius *= unit_scale.abs();
+        }
+        for shape in &mut self.shapes {
+            *shape = shape.transformed(convert, unit_scale);
+        }
+        for sdf in &mut self.sdfs {
+            sdf.root = sdf.root.transformed(&convert, unit_scale);
+        }
+        for curve in &mut self.curves {
+            *curve = curve.transformed(convert, unit_scale);
+        }
+        for splat in &mut self.splats {
+            *splat = splat.transformed(convert, unit_scale);
+        }
+        for light in &mut self.lights {
+            *light = light.transformed(convert, unit_scale);
+        }
+        // Meshes stay in object space; only their placements move.
+        let mut frame = IDENTITY;
+        for (i, axis) in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]].into_iter().enumerate() {
+            let [x, y, z] = convert(axis).map(|c| c * unit_scale);
+            frame[i] = [x, y, z, 0.0];
+        }
+        for instance in &mut self.instances {
+            instance.transform = gltf::mat_mul(&frame, &instance.transform);
+        }
+        for volume in &mut self.volumes {
+            volume.transform = gltf::mat_mul(&frame, &volume.transform);
+        }
+        // The frame is a rotation and a uniform scale, so roots stay TRS.
+        for &root in &self.root_nodes {
+            let node = &mut self.nodes[root as usize];
+            node.transform = Trs::from_matrix(&gltf::mat_mul(&frame, &node.transform.matrix()));
+        }
+        if let Some(camera) = &mut self.camera {
+            let vec = |v: Vec3| {
+                let [x, y, z] = convert([v.x(), v.y(), v.z()]);
+                Vec3::new(x, y, z)
+            };
+            camera.lookfrom = vec(camera.lookfrom) * unit_scale;
+            camera.lookat = vec(camera.lookat) * unit_scale;
+            camera.vup = vec(camera.vup);
+        }
+    }
+
+    /// CRC-32 over the geometry and materials, to tell scenes apart in reports.
+    pub fn content_hash(&self) -> u32 {
+        let mut crc = export::crc32(0, bytemuck::cast_slice(&self.positions));
+        crc = export::crc32(crc, bytemuck::cast_slice(&self.normals));
+        crc = hash_colors(crc, &self.colors);
+        crc = export::crc32(crc, bytemuck::cast_slice(&self.uvs));
+        crc = export::crc32(crc, bytemuck::cast_slice(&self.tangents));
+        for tri in &self.triangles {
+            crc = export::crc32(crc, bytemuck::cast_slice(&tri.indices));
+            crc = export::crc32(crc, &tri.material.to_le_bytes());
+        }
+        for animation in &self.vertex_animations {
+            crc = export::crc32(crc, &animation.first.to_le_bytes());
+            crc = export::crc32(crc, bytemuck::cast_slice(&animation.times));
+            for positions in &animation.positions {
+                crc = export::crc32(crc, bytemuck::cast_slice(positions));
+            }
+        }
+        for m in &self.materials {
+            let values = [m.base_color, [m.metallic, m.roughness, m.ior], m.emission];
+            crc = export::crc32(crc, bytemuck::cast_slice(&values));
+            let textures = (m.base_color_texture, m.normal_texture, m.normal_scale);
+            crc = export::crc32(crc, format!("{:?} {textures:?}", m.kind).as_bytes());
+        }
+        for sphere in &self.spheres {
+            crc = export::crc32(crc, format!("{sphere:?}").as_bytes());
+        }
+        for shape in &self.shapes {
+            crc = export::crc32(crc, format!("{shape:?}").as_bytes());
+        }
+        for sdf in &self.sdfs {
+            crc = export::crc32(crc, format!("{sdf:?}").as_bytes());
+        }
+        for procedural in &self.procedurals {
+            crc = export::crc32(crc, format!("{procedural:?}").as_bytes());
+        }
+        for curve in &self.curves {
+            crc = export::crc32(crc, format!("{curve:?}").as_bytes());
+        }
+        for splat in &self.splats {
+            crc = export::crc32(crc, format!("{splat:?}").as_bytes());
+        }
+        for volume in &self.volumes {
+            crc = export::crc32(crc, bytemuck::cast_slice(&volume.densities));
+            let Volume { size, transform, density_scale, albedo, .. } = volume;
+            crc = export::crc32(crc, format!("{size:?} {transform:?} {density_scale} {albedo:?}").as_bytes());
+        }
+        for light in &self.lights {
+            crc = export::crc32(crc, format!("{light:?}").as_bytes());
+        }
+        for texture in &self.textures {
+            crc = export::crc32(crc, bytemuck::cast_slice(&texture.pixels));
+            for level in texture.blocks.iter().flat_map(|blocks| &blocks.levels) {
+                crc = export::crc32(crc, level);
+            }
+        }
+        for mesh in &self.meshes {
+            crc = export::crc32(crc, bytemuck::cast_slice(&mesh.positions));
+            crc = export::crc32(crc, bytemuck::cast_slice(&mesh.normals));
+            crc = hash_colors(crc, &mesh.colors);
+            crc = export::crc32(crc, bytemuck::cast_slice(&mesh.uvs));
+            crc = export::crc32(crc, bytemuck::cast_slice(&mesh.tangents));
+            for tri in &mesh.triangles {
+                crc = export::crc32(crc, bytemuck::cast_slice(&tri.indices));
+                crc = export::crc32(crc, &tri.material.to_le_bytes());
+            }
+            crc = export::crc32(crc, format!("{:?}", mesh.lods).as_bytes());
+        }
+        for instance in &self.instances {
+            crc = export::crc32(crc, format!("{instance:?}").as_bytes());
+        }
+        for node in &self.nodes {
+            crc = export::crc32(crc, format!("{node:?}").as_bytes());
+        }
+        crc = export::crc32(crc, bytemuck::cast_slice(&self.root_nodes));
+        crc
+    }
+
+    /// Axis-aligned bounds of all vertices, instances, quads, boxes, SDF
+    /// shapes, procedural primitives, curves, splats and volumes, or `None`
+    /// if there are none.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let node_instances = self.node_instances();
+        let instance_corners = self.instances.iter().chain(&node_instances).flat_map(|instance| {
+            let bounds = self.meshes[instance.mesh as usize].bounds();
+            bounds.into_iter().flat_map(|bounds| {
+                let (lo, hi) = instance.world_bounds(bounds);
+                [lo, hi]
+            })
+        });
+        let shape_corners = self.shapes.iter().flat_map(|shape| match shape.kind {
+            ShapeKind::Quad { corner, u, v } => {
+                let add = |a: [f32; 3], b: [f32; 3]| [0, 1, 2].map(|i| a[i] + b[i]);
+                vec![corner, add(corner, u), add(corner, v), add(add(corner, u), v)]
+            }
+            ShapeKind::Box { min, max } => vec![min, max],
+            ShapeKind::Plane { .. } => vec![],
+        });
+        let mut points = self
+            .positions
+            .iter()
+            .copied()
+            .chain(instance_corners)
+            .chain(shape_corners)
+            .chain(self.sdfs.iter().flat_map(|sdf| sdf.root.bounds()).flat_map(|(lo, hi)| [lo, hi]))
+            .chain(self.procedurals.iter().flat_map(|procedural| {
+                let (lo, hi) = procedural.primitive.bounds();
+                [lo, hi]
+            }))
+            .chain(self.curves.iter().flat_map(|curve| {
+                let (lo, hi) = curve.bounds();
+                [lo, hi]
+            }))
+            .chain(self.splats.iter().flat_map(|splat| {
+                let (lo, hi) = splat.bounds();
+                [lo, hi]
+            }))
+            .chain(self.volumes.iter().flat_map(|volume| {
+                let (lo, hi) = volume.bounds();
+                [lo, hi]
+            }));
+        let first = points.next()?;
+        let (lo, hi) = points.fold((first, first), |(lo, hi), p| {
+            ([0, 1, 2].map(|i| lo[i].min(p[i])), [0, 1, 2].map(|i| hi[i].max(p[i])))
+        });
+        Some((Vec3::new(lo[0], lo[1], lo[2]), Vec3::new(hi[0], hi[1], hi[2])))
+    }
+
+    /// The scene's own camera, or one looking at the whole scene from the
+    /// front-left and slightly above.
+    pub fn camera(&self) -> Option<SceneCamera> {
+        if self.camera.is_some() {
+            return self.camera;
+        }
+        let (lo, hi) = self.bounds()?;
+        let center = (lo + hi) * 0.5;
+        let radius = (hi - lo).length().max(1e-3) * 0.5;
+        let vfov: f32 = 40.0;
+        let distance = radius / (vfov.to_radians() * 0.5).sin();
+        Some(SceneCamera {
+            lookfrom: center + Vec3::new(-0.5, 0.4, 1.0).normalized() * distance,
+            lookat: center,
+            vup: Vec3::Y,
+            vfov,
+        })
+    }
+}
+
+fn hash_colors(crc: u32, colors: &[Option<[f32; 3]>]) -> u32 {
+    colors.iter().fold(crc, |crc, color| {
+        export::crc32(crc, bytemuck::cast_slice(&color.unwrap_or([-1.0; 3])))
+    })
+}
@@ -0,0 +1,341 @@
+//! NanoVDB density grids (`.nvdb`), either files written by
+//! `nanovdb::io::writeGrid` or bare grid buffers.
+//!
+//! The first uncompressed `float` grid is read into a dense block over its
+//! index bounding box, averaged down to [`MAX_VOLUME_SIZE`] voxels a side if
+//! it is larger. Voxels in leaves and active tiles take their values and the
+//! rest of the box the background. Root tiles are read with the default
+//! 64-bit keys.
+
+use {
+    super::{
+        gltf::Mat4,
+        volume::{Volume, MAX_VOLUME_SIZE},
+    },
+    anyhow::{bail, ensure, Context, Result},
+    std::{fs, path::Path},
+};
+
+const MAGIC_LEGACY: &[u8] = b"NanoVDB0";
+const MAGIC_GRID: &[u8] = b"NanoVDB1";
+const MAGIC_FILE: &[u8] = b"NanoVDB2";
+const MAJOR_VERSION: u32 = 32;
+const GRID_TYPE_FLOAT: u32 = 1;
+
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_METADATA_SIZE: usize = 176;
+const GRID_DATA_SIZE: usize = 672;
+/// Root data is padded to the 32-byte alignment of its tiles.
+const ROOT_DATA_SIZE: usize = 64;
+const ROOT_TILE_SIZE: usize = 32;
+const LEAF_VALUES: usize = 96;
+
+/// Index space edge of a root tile.
+const ROOT_TILE_DIM: i64 = 4096;
+
+pub fn load(path: &Path) -> Result<Volume> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    find_grid(&bytes)
+        .and_then(decode)
+        .with_context(|| format!("failed to import {}", path.display()))
+}
+
+/// Little-endian reads that fail past the end of the data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, at: usize, len: usize) -> Result<&'a [u8]> {
+        at.checked_add(len)
+            .and_then(|end| self.0.get(at..end))
+            .context("unexpected end of data")
+    }
+
+    fn array<const N: usize>(&self, at: usize) -> Result<[u8; N]> {
+        Ok(self.bytes(at, N)?.try_into().unwrap())
+    }
+
+    fn u16(&self, at: usize) -> Result<u16> {
+        self.array(at).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, at: usize) -> Result<u32> {
+        self.array(at).map(u32::from_le_bytes)
+    }
+
+    fn i32(&self, at: usize) -> Result<i32> {
+        self.array(at).map(i32::from_le_bytes)
+    }
+
+    fn u64(&self, at: usize) -> Result<u64> {
+        self.array(at).map(u64::from_le_bytes)
+    }
+
+    fn i64(&self, at: usize) -> Result<i64> {
+        self.array(at).map(i64::from_le_bytes)
+    }
+
+    fn f32(&self, at: usize) -> Result<f32> {
+        self.array(at).map(f32::from_le_bytes)
+    }
+
+    fn f64(&self, at: usize) -> Result<f64> {
+        self.array(at).map(f64::from_le_bytes)
+    }
+
+    fn coord(&self, at: usize) -> Result<[i64; 3]> {
+        Ok([self.i32(at)?, self.i32(at + 4)?, self.i32(at + 8)?].map(i64::from))
+    }
+
+    /// `base` moved by a signed byte offset stored at `at`.
+    fn offset(&self, base: usize, at: usize) -> Result<usize> {
+        let offset = self.i64(at)?;
+        base.checked_add_signed(offset as isize)
+            .filter(|&it| it < self.0.len())
+            .context("node offset out of range")
+    }
+}
+
+fn major_version(version: u32) -> u32 {
+    version >> 21
+}
+
+/// The first float grid of a file, or the buffer itself if it is a grid.
+fn find_grid(bytes: &[u8]) -> Result<&[u8]> {
+    let file = Reader(bytes);
+    let magic = file.bytes(0, 8).context("not a NanoVDB file")?;
+    ensure!(
+        [MAGIC_LEGACY, MAGIC_GRID, MAGIC_FILE].contains(&magic),
+        "not a NanoVDB file"
+    );
+    // Legacy files and grids share a magic number; a grid has its checksum
+    // where a file has its version.
+    if magic == MAGIC_GRID || (magic == MAGIC_LEGACY && major_version(file.u32(8)?) != MAJOR_VERSION) {
+        return Ok(bytes);
+    }
+
+    // Segments of a header, the metadata and name of each grid, and then
+    // the grids.
+    let mut at = 0;
+    while at < bytes.len() {
+        let version = file.u32(at + 8)?;
+        ensure!(
+            major_version(version) == MAJOR_VERSION,
+            "unsupported NanoVDB version {}",
+            major_version(version)
+        );
+        let grid_count = file.u16(at + 12)?;
+        ensure!(file.u16(at + 14)? == 0, "compressed NanoVDB files are not supported");
+        at += FILE_HEADER_SIZE;
+        let mut grids = Vec::with_capacity(grid_count.into());
+        for _ in 0..grid_count {
+            let grid_size = file.u64(at)? as usize;
+            let grid_type = file.u32(at + 32)?;
+            let name_size = file.u32(at + 136)? as usize;
+            grids.push((grid_size, grid_type));
+            at += FILE_METADATA_SIZE + name_size;
+        }
+        for (grid_size, grid_type) in grids {
+            if grid_type == GRID_TYPE_FLOAT {
+                return file.bytes(at, grid_size);
+            }
+            at += grid_size;
+        }
+    }
+    bail!("no float grid found")
+}
+
+fn decode(grid: &[u8]) -> Result<Volume> {
+    let r = Reader(grid);
+    let magic = r.bytes(0, 8)?;
+    ensure!(magic == MAGIC_LEGACY || magic == MAGIC_GRID, "not a NanoVDB grid");
+    let version = r.u32(16)?;
+    ensure!(
+        major_version(version) == MAJOR_VERSION,
+        "unsupported NanoVDB version {}",
+        major_version(version)
+    );
+    let grid_type = r.u32(636)?;
+    ensure!(grid_type == GRID_TYPE_FLOAT, "grid type {grid_type} is not float");
+
+    // Index-to-world map: a row-major 3x3 matrix and a translation.
+    let mut matrix = [0.0; 9];
+    for (i, m) in matrix.iter_mut().enumerate() {
+        *m = r.f64(384 + 8 * i)?;
+    }
+    let translation = [r.f64(528)?, r.f64(536)?, r.f64(544)?];
+
+    let tree = GRID_DATA_SIZE;
+    let root = tree + r.u64(tree + 24)? as usize;
+    let min = r.coord(root)?;
+    let max = r.coord(root + 12)?;
+    ensure!((0..3).all(|i| min[i] <= max[i]), "grid has no active voxels");
+    let extent = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+    let largest = extent.into_iter().max().unwrap_or(1);
+    let factor = (largest + i64::from(MAX_VOLUME_SIZE) - 1) / i64::from(MAX_VOLUME_SIZE);
+    let mut dense = Dense::new(min, extent, factor);
+
+    let table_size = r.u32(root + 24)? as usize;
+    let background = r.f32(root + 28)?;
+    for tile in 0..table_size {
+        let at = root + ROOT_DATA_SIZE + ROOT_TILE_SIZE * tile;
+        if r.i64(at + 8)? != 0 {
+            internal(&r, r.offset(root, at + 8)?, 5, 7, &mut dense)?;
+        } else if r.u32(at + 16)? != 0 {
+            // Keys pack the top 21 bits of each coordinate, x highest.
+            let key = r.u64(at)?;
+            let origin = [key >> 42, key >> 21, key].map(|bits| i64::from((((bits & 0x1f_ffff) as u32) << 12) as i32));
+            dense.fill(origin, ROOT_TILE_DIM, r.f32(at + 20)?);
+        }
+    }
+
+    // Grid coordinates start at the corner of the first voxel, half a voxel
+    // below its index, and step `factor` voxels.
+    let k = factor as f64;
+    let corner = [0, 1, 2].map(|i| min[i] as f64 - 0.5);
+    let mut transform: Mat4 = [[0.0; 4]; 4];
+    for (j, column) in transform.iter_mut().take(3).enumerate() {
+        *column = [matrix[j] * k, matrix[3 + j] * k, matrix[6 + j] * k, 0.0].map(|it| it as f32);
+    }
+    let offset = [0, 1, 2].map(|i| (0..3).map(|j| matrix[3 * i + j] * corner[j]).sum::<f64>() + translation[i]);
+    transform[3] = [offset[0] as f32, offset[1] as f32, offset[2] as f32, 1.0];
+
+    let (size, densities) = dense.finish(background);
+    Ok(Volume {
+        size,
+        densities,
+        transform,
+        density_scale: 1.0,
+        albedo: [1.0; 3],
+    })
+}
+
+/// Reads an upper (`log2_dim` 5) or lower (4) internal node whose children
+/// span `2^child_log2` voxels a side.
+fn internal(r: &Reader, node: usize, log2_dim: u32, child_log2: u32, dense: &mut Dense) -> Result<()> {
+    let entries = 1usize << (3 * log2_dim);
+    let words = entries / 64;
+    let value_mask = node + 32;
+    let child_mask = value_mask + 8 * words;
+    // Past the masks and four statistics, aligned to 32 bytes.
+    let table = node + (32 + 16 * words + 16).next_multiple_of(32);
+    let origin = r.coord(node)?.map(|c| c & !((1 << (log2_dim + child_log2)) - 1));
+    let dim_mask = (1 << log2_dim) - 1;
+    for word in 0..words {
+        let children = r.u64(child_mask + 8 * word)?;
+        let active = r.u64(value_mask + 8 * word)?;
+        let mut bits = children | active;
+        while bits != 0 {
+            let bit = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            let n = word * 64 + bit;
+            let entry = table + 8 * n;
+            if children >> bit & 1 != 0 {
+                let child = r.offset(node, entry)?;
+                if log2_dim == 5 {
+                    internal(r, child, 4, 3, dense)?;
+                } else {
+                    leaf(r, child, dense)?;
+                }
+            } else {
+                let local = [n >> (2 * log2_dim), n >> log2_dim & dim_mask, n & dim_mask];
+                let tile = [0, 1, 2].map(|i| origin[i] + ((local[i] as i64) << child_log2));
+                dense.fill(tile, 1 << child_log2, r.f32(entry)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn leaf(r: &Reader, node: usize, dense: &mut Dense) -> Result<()> {
+    let origin = r.coord(node)?.map(|c| c & !7);
+    let values = r.bytes(node + LEAF_VALUES, 512 * 4)?;
+    for (n, value) in values.chunks_exact(4).enumerate() {
+        let value = f32::from_le_bytes(value.try_into().unwrap());
+        let voxel = [n >> 6, n >> 3 & 7, n & 7].map(|c| c as i64);
+        dense.add([0, 1, 2].map(|i| origin[i] + voxel[i]), value);
+    }
+    Ok(())
+}
+
+/// Sums of voxel values over cells of `factor` voxels a side, and how many
+/// voxels each sum holds.
+struct Dense {
+    min: [i64; 3],
+    extent: [i64; 3],
+    factor: i64,
+    size: [usize; 3],
+    sums: Vec<f32>,
+    counts: Vec<f32>,
+}
+
+impl Dense {
+    fn new(min: [i64; 3], extent: [i64; 3], factor: i64) -> Self {
+        let size = extent.map(|e| ((e + factor - 1) / factor) as usize);
+        let cells = size.iter().product();
+        Self {
+            min,
+            extent,
+            factor,
+            size,
+            sums: vec![0.0; cells],
+            counts: vec![0.0; cells],
+        }
+    }
+
+    fn index(&self, cell: [usize; 3]) -> usize {
+        (cell[2] * self.size[1] + cell[1]) * self.size[0] + cell[0]
+    }
+
+    fn add(&mut self, voxel: [i64; 3], value: f32) {
+        let local = [0, 1, 2].map(|i| voxel[i] - self.min[i]);
+        if (0..3).all(|i| (0..self.extent[i]).contains(&local[i])) {
+            let i = self.index(local.map(|c| (c / self.factor) as usize));
+            self.sums[i] += value;
+            self.counts[i] += 1.0;
+        }
+    }
+
+    /// Adds `value` over the cube of `dim` voxels at `origin`.
+    fn fill(&mut self, origin: [i64; 3], dim: i64, value: f32) {
+        let lo = [0, 1, 2].map(|i| (origin[i] - self.min[i]).max(0));
+        let hi = [0, 1, 2].map(|i| (origin[i] + dim - self.min[i]).min(self.extent[i]));
+        if (0..3).any(|i| lo[i] >= hi[i]) {
+            return;
+        }
+        let k = self.factor;
+        // Voxels of `lo..hi` within cell `c` along one axis.
+        let overlap = |axis: usize, c: i64| (hi[axis].min((c + 1) * k) - lo[axis].max(c * k)) as f32;
+        for z in lo[2] / k..=(hi[2] - 1) / k {
+            for y in lo[1] / k..=(hi[1] - 1) / k {
+                for x in lo[0] / k..=(hi[0] - 1) / k {
+                    let count = overlap(0, x) * overlap(1, y) * overlap(2, z);
+                    let i = self.index([x, y, z].map(|c| c as usize));
+                    self.sums[i] += value * count;
+                    self.counts[i] += count;
+                }
+            }
+        }
+    }
+
+    /// The grid size and mean density of each cell, with `background` in
+    /// voxels no node covered and negative means read as empty.
+    fn finish(self, background: f32) -> ([u32; 3], Vec<f32>) {
+        let k = self.factor;
+        let voxels = |axis: usize, c: usize| {
+            let c = c as i64;
+            (self.extent[axis].min((c + 1) * k) - c * k) as f32
+        };
+        let mut densities = Vec::with_capacity(self.sums.len());
+        for z in 0..self.size[2] {
+            for y in 0..self.size[1] {
+                for x in 0..self.size[0] {
+                    let i = self.index([x, y, z]);
+                    let total = voxels(0, x) * voxels(1, y) * voxels(2, z);
+                    let mean = (self.sums[i] + (total - self.counts[i]) * background) / total;
+                    densities.push(mean.max(0.0));
+                }
+            }
+        }
+        (self.size.map(|n| n as u32), densities)
+    }
+}
@@ -0,0 +1,349 @@
+//! Wavefront OBJ import with MTL materials.
+//!
+//! Only geometry, vertex normals, texture coordinates, the constant
+//! material terms, diffuse color maps (`map_Kd`) and normal maps (`norm`, or
+//! `map_Bump` and `bump` as Blender writes them, scaled by `-bm`) are read;
+//! other texture maps, smoothing groups and free-form surfaces are ignored.
+//! Faces without normals are flat-shaded. Textured faces get tangents
+//! generated from their texture coordinates.
+
+use {
+    super::{
+        mapped::{MappedFile, Progress},
+        subdivide::Polygon,
+        tangent,
+        texture::Texture,
+        Material, Scene, Triangle,
+    },
+    crate::{crash, math::Vec3},
+    anyhow::{bail, Context, Result},
+    std::{collections::HashMap, fs, path::Path},
+};
+
+pub fn load(path: &Path) -> Result<Scene> {
+    load_polygons(path).map(|(scene, _)| scene)
+}
+
+/// Also returns the faces before triangulation, over the scene's vertices,
+/// for subdivision.
+pub fn load_polygons(path: &Path) -> Result<(Scene, Vec<Polygon>)> {
+    let file = MappedFile::open(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let mut progress = Progress::new(path, file.len());
+    parse(&file, base_dir, &mut progress).with_context(|| format!("failed to import {}", path.display()))
+}
+
+/// Parses the file a line at a time straight out of `bytes`, which may be
+/// far larger than the scene built from them.
+fn parse(bytes: &[u8], base_dir: &Path, progress: &mut Progress) -> Result<(Scene, Vec<Polygon>)> {
+    let mut scene = Scene::default();
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut texcoords: Vec<[f32; 2]> = Vec::new();
+    // Scene vertex of each position, texture coordinate and normal used
+    // together by a face.
+    let mut vertex_ids: HashMap<FaceVertex, u32> = HashMap::new();
+    let mut material_ids: HashMap<String, u32> = HashMap::new();
+    // Faces before any `usemtl`, or naming an unknown material, use a plain
+    // grey diffuse appended at the end.
+    let mut pending_default: Vec<usize> = Vec::new();
+    let mut current: Option<u32> = None;
+    let mut polygon = Vec::new();
+    let mut polygons: Vec<Polygon> = Vec::new();
+
+    let mut pos = 0;
+    for (number, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        pos += line.len() + 1;
+        progress.update(pos);
+        let context = || format!("line {}", number + 1);
+        let line = std::str::from_utf8(line).with_context(context)?;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        match keyword {
+            "v" => positions.push(floats(&mut words).with_context(context)?),
+            "vn" => normals.push(floats(&mut words).with_context(context)?),
+            "vt" => {
+                let [u] = floats(&mut words).with_context(context)?;
+                let v = words.next().map_or(Ok(0.0), str::parse).with_context(context)?;
+                // OBJ puts v = 0 at the bottom of the image.
+                texcoords.push([u, 1.0 - v]);
+            }
+            "f" => {
+                polygon.clear();
+                for word in words {
+                    let counts = [positions.len(), texcoords.len(), normals.len()];
+                    polygon.push(face_vertex(word, counts).with_context(context)?);
+                }
+                if polygon.len() < 3 {
+                    bail!("{}: face with fewer than three vertices", context());
+                }
+                let corners: Vec<u32> = polygon
+                    .iter()
+                    .map(|&vertex @ (position, texcoord, normal)| {
+                        *vertex_ids.entry(vertex).or_insert_with(|| {
+                            if let Some(t) = texcoord {
+                                scene.uvs.resize(scene.positions.len(), [0.0; 2]);
+                                scene.uvs.push(texcoords[t as usize]);
+                            }
+                            scene.positions.push(positions[position as usize]);
+                            scene.normals.push(normal.map_or([0.0; 3], |n| normals[n as usize]));
+                            scene.positions.len() as u32 - 1
+                        })
+                    })
+                    .collect();
+                if current.is_none() {
+                    pending_default.push(polygons.len());
+                }
+                polygons.push(Polygon {
+                    corners,
+                    material: current.unwrap_or(0),
+                });
+            }
+            "mtllib" => {
+                // File names may contain spaces.
+                let name = line.trim_start()[keyword.len()..].trim();
+                let path = base_dir.join(name);
+                match fs::read_to_string(&path) {
+                    Ok(mtl) => {
+                        let materials = parse_mtl(&mtl, base_dir, &mut scene.textures)
+                            .with_context(|| format!("in {}", path.display()))?;
+                        for (name, material) in materials {
+                            material_ids.insert(name, scene.materials.len() as u32);
+                            scene.materials.push(material);
+                        }
+                    }
+                    Err(err) => crash::log!("warning: skipping material library {}: {err}", path.display()),
+                }
+            }
+            "usemtl" => current = words.next().and_then(|name| material_ids.get(name).copied()),
+            _ => (),
+        }
+    }
+
+    if !pending_default.is_empty() {
+        let default = scene.materials.len() as u32;
+        scene.materials.push(Material {
+            base_color: [0.8; 3],
+            metallic: 0.0,
+            ..Material::default()
+        });
+        for polygon in pending_default {
+            polygons[polygon].material = default;
+        }
+    }
+    for polygon in &polygons {
+        let points: Vec<Vec3> = polygon
+            .corners
+            .iter()
+            .map(|&i| {
+                let [x, y, z] = scene.positions[i as usize];
+                Vec3::new(x, y, z)
+            })
+            .collect();
+        scene.triangles.extend(triangulate(&points).into_iter().map(|[a, b, c]| Triangle {
+            indices: [a, b, c].map(|k| polygon.corners[k]),
+            material: polygon.material,
+        }));
+    }
+    if !scene.uvs.is_empty() {
+        scene.tangents = tangent::generate(&scene.positions, &scene.normals, &scene.uvs, &scene.triangles);
+    }
+    Ok((scene, polygons))
+}
+
+/// An MTL material while its statements are being read.
+struct MtlEntry {
+    name: String,
+    material: Material,
+    illum: Option<usize>,
+    specular: Option<[f32; 3]>,
+}
+
+/// Materials of an MTL file mapped to metallic-roughness, in file order.
+/// Their diffuse and normal maps are added to `textures`.
+fn parse_mtl(text: &str, base_dir: &Path, textures: &mut Vec<Texture>) -> Result<Vec<(String, Material)>> {
+    let mut materials: Vec<MtlEntry> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let context = || format!("line {}", number + 1);
+        if keyword == "newmtl" {
+            let name = words.next().with_context(context)?.to_string();
+            let material = Material {
+                base_color: [0.8; 3],
+                metallic: 0.0,
+                ..Material::default()
+            };
+            materials.push(MtlEntry {
+                name,
+                material,
+                illum: None,
+                specular: None,
+            });
+            continue;
+        }
+        let Some(MtlEntry {
+            material,
+            illum,
+            specular,
+            ..
+        }) = materials.last_mut()
+        else {
+            continue;
+        };
+        match keyword {
+            "Kd" => material.base_color = floats(&mut words).with_context(context)?,
+            "Ks" => *specular = Some(floats(&mut words).with_context(context)?),
+            "Ke" => material.emission = floats(&mut words).with_context(context)?,
+            // Phong exponent to an equivalent GGX-like roughness.
+            "Ns" => {
+                let [ns] = floats(&mut words).with_context(context)?;
+                material.roughness = (2.0 / (ns.max(0.0) + 2.0)).sqrt();
+            }
+            // PBR extension written by Blender and others.
+            "Pr" => [material.roughness] = floats(&mut words).with_context(context)?,
+            "Pm" => [material.metallic] = floats(&mut words).with_context(context)?,
+            "Ni" => {
+                let [ni] = floats(&mut words).with_context(context)?;
+                material.ior = ni.max(1.0);
+            }
+            "illum" => *illum = words.next().and_then(|it| it.parse().ok()),
+            "map_Kd" | "norm" | "map_Bump" | "bump" => {
+                // Options come before the file name, which may otherwise
+                // contain spaces.
+                let rest = line.trim_start()[keyword.len()..].trim();
+                let options: Vec<&str> = words.collect();
+                let name = if rest.starts_with('-') { options.last().copied().unwrap_or_default() } else { rest };
+                let path = base_dir.join(name);
+                let texture = match Texture::load(&path) {
+                    Ok(texture) => texture,
+                    Err(err) => {
+                        crash::log!("warning: skipping texture {}: {err:#}", path.display());
+                        continue;
+                    }
+                };
+                textures.push(texture);
+                let id = Some(textures.len() as u32 - 1);
+                if keyword == "map_Kd" {
+                    material.base_color_texture = id;
+                } else {
+                    material.normal_texture = id;
+                    if let Some(i) = options.iter().position(|&option| option == "-bm") {
+                        material.normal_scale = options.get(i + 1).map_or(Ok(1.0), |it| it.parse()).with_context(context)?;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(materials
+        .into_iter()
+        .map(|MtlEntry { name, mut material, illum, specular }| {
+            // Illumination models 3, 5 and 7 trace mirror reflections, tinted
+            // by the specular color.
+            if matches!(illum, Some(3 | 5 | 7)) && material.metallic == 0.0 {
+                material.metallic = 1.0;
+                material.base_color = specular.unwrap_or(material.base_color);
+            }
+            (name, material)
+        })
+        .collect())
+}
+
+fn floats<const N: usize>(words: &mut std::str::SplitWhitespace) -> Result<[f32; N]> {
+    let mut out = [0.0; N];
+    for out in &mut out {
+        let word = words.next().context("missing number")?;
+        *out = word.parse().with_context(|| format!("invalid number `{word}`"))?;
+    }
+    Ok(out)
+}
+
+/// Position, texture coordinate and normal index of a face vertex.
+type FaceVertex = (u32, Option<u32>, Option<u32>);
+
+/// Indices of a face vertex (`v`, `v/vt`, `v//vn` or `v/vt/vn`), given how
+/// many positions, texture coordinates and normals precede it; negative
+/// indices count back from the latest element.
+fn face_vertex(word: &str, [position_count, texcoord_count, normal_count]: [usize; 3]) -> Result<FaceVertex> {
+    let mut parts = word.split('/');
+    let position = resolve_index(parts.next().unwrap_or_default(), position_count)
+        .with_context(|| format!("invalid face vertex `{word}`"))?;
+    let mut optional = |count| match parts.next() {
+        Some(index) if !index.is_empty() => {
+            resolve_index(index, count).map(Some).with_context(|| format!("invalid face vertex `{word}`"))
+        }
+        _ => Ok(None),
+    };
+    let texcoord = optional(texcoord_count)?;
+    let normal = optional(normal_count)?;
+    Ok((position, texcoord, normal))
+}
+
+fn resolve_index(word: &str, count: usize) -> Result<u32> {
+    let index: i64 = word.parse().with_context(|| format!("invalid index `{word}`"))?;
+    let resolved = match index {
+        1.. => index - 1,
+        ..=-1 => count as i64 + index,
+        0 => bail!("index 0"),
+    };
+    if !(0..count as i64).contains(&resolved) {
+        bail!("index {index} out of range");
+    }
+    Ok(resolved as u32)
+}
+
+/// Splits a polygon into triangles of corner indices by ear clipping in its
+/// best-fit plane, so concave faces come out right. Falls back to a fan if
+/// the polygon is not simple.
+pub(super) fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+    let fan = || (1..points.len() - 1).map(|i| [0, i, i + 1]).collect();
+
+    // Newell's method gives a robust normal for non-planar polygons.
+    let mut normal = Vec3::zero();
+    for (i, &p) in points.iter().enumerate() {
+        let q = points[(i + 1) % points.len()];
+        normal += Vec3::new(
+            (p.y() - q.y()) * (p.z() + q.z()),
+            (p.z() - q.z()) * (p.x() + q.x()),
+            (p.x() - q.x()) * (p.y() + q.y()),
+        );
+    }
+    if normal.length_squared() == 0.0 {
+        return fan();
+    }
+
+    let cross = |a: usize, b: usize, c: usize| (points[b] - points[a]).cross(&(points[c] - points[a])).dot(&normal);
+    let inside = |p: usize, [a, b, c]: [usize; 3]| cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0;
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let tri = [remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]];
+            cross(tri[0], tri[1], tri[2]) > 0.0
+                && remaining
+                    .iter()
+                    .filter(|v| !tri.contains(v))
+                    .all(|&v| !inside(v, tri))
+        });
+        let Some(i) = ear else {
+            return fan();
+        };
+        triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
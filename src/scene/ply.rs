@@ -0,0 +1,326 @@
+//! Stanford PLY meshes and point clouds, in ASCII or binary of either byte
+//! order.
+//!
+//! The `vertex` element gives positions with optional `nx`/`ny`/`nz`
+//! normals and `red`/`green`/`blue` sRGB colors. If a `face` element lists
+//! `vertex_indices`, they are the corners of polygons and the file loads as
+//! a mesh with vertex colors. Otherwise the vertices are points, which may
+//! also have a `radius`; points without one get [`splat::default_radius`].
+//! Other elements are skipped.
+
+use {
+    super::{
+        mapped::{MappedFile, Progress},
+        splat::{self, Splat, SplatShape},
+        subdivide::Polygon,
+        Material, Scene, Triangle,
+    },
+    crate::color,
+    anyhow::{bail, ensure, Context, Result},
+    std::path::Path,
+};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => bail!("unknown property type `{name}`"),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Largest value of an integer type, which colors are normalized by.
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    /// Count type of a list property, which is skipped.
+    list: Option<Scalar>,
+    ty: Scalar,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn index(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name == name && p.list.is_none())
+    }
+}
+
+/// Values of the body, read one at a time in the file's format.
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Body<'_> {
+    fn value(&mut self, ty: Scalar) -> Result<f64> {
+        if self.format == Format::Ascii {
+            while self.bytes.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+                self.pos += 1;
+            }
+            let start = self.pos;
+            while self.bytes.get(self.pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                self.pos += 1;
+            }
+            ensure!(start < self.pos, "file is truncated");
+            let token = std::str::from_utf8(&self.bytes[start..self.pos])?;
+            return token.parse().with_context(|| format!("invalid number `{token}`"));
+        }
+        let size = ty.size();
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(self.bytes.get(self.pos..self.pos + size).context("file is truncated")?);
+        self.pos += size;
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        let [b0, b1, b2, b3, ..] = raw;
+        Ok(match ty {
+            Scalar::I8 => b0 as i8 as f64,
+            Scalar::U8 => b0 as f64,
+            Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F64 => f64::from_le_bytes(raw),
+        })
+    }
+
+    /// Reads one row of `element`, leaving scalar properties in `row` and
+    /// the items of property `list`, if any, in `items`. Other lists are
+    /// skipped.
+    fn row(&mut self, element: &Element, row: &mut Vec<f64>, list: Option<usize>, items: &mut Vec<f64>) -> Result<()> {
+        row.clear();
+        items.clear();
+        for (i, property) in element.properties.iter().enumerate() {
+            match property.list {
+                Some(count_ty) => {
+                    let count = self.value(count_ty)? as usize;
+                    for _ in 0..count {
+                        let item = self.value(property.ty)?;
+                        if list == Some(i) {
+                            items.push(item);
+                        }
+                    }
+                    row.push(0.0);
+                }
+                None => row.push(self.value(property.ty)?),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load(path: &Path) -> Result<Scene> {
+    load_polygons(path).map(|(scene, _)| scene)
+}
+
+/// Also returns the faces before triangulation, over the scene's vertices,
+/// for subdivision. A point cloud has none.
+pub fn load_polygons(path: &Path) -> Result<(Scene, Vec<Polygon>)> {
+    let file = MappedFile::open(path)?;
+    let mut progress = Progress::new(path, file.len());
+    parse(&file, &mut progress).with_context(|| format!("failed to import {}", path.display()))
+}
+
+/// Reads rows straight out of `bytes`, which may be far larger than the
+/// scene built from them.
+fn parse(bytes: &[u8], progress: &mut Progress) -> Result<(Scene, Vec<Polygon>)> {
+    ensure!(bytes.starts_with(b"ply"), "not a PLY file");
+    let header_end = bytes
+        .windows(10)
+        .position(|w| w == b"end_header")
+        .context("header has no `end_header`")?;
+    let header = std::str::from_utf8(&bytes[..header_end]).context("header is not text")?;
+    // The body starts after the line ending of `end_header`.
+    let mut body_start = header_end + 10;
+    while bytes.get(body_start).is_some_and(|&b| b == b'\r' || b == b' ') {
+        body_start += 1;
+    }
+    ensure!(bytes.get(body_start) == Some(&b'\n'), "`end_header` is not on its own line");
+    body_start += 1;
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (number, line) in header.lines().enumerate().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let context = || format!("header line {}", number + 1);
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["format", ..] => bail!("{}: unsupported format", context()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().with_context(context)?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, ty, name] => {
+                let element = elements.last_mut().with_context(context)?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    list: Some(Scalar::parse(count_ty).with_context(context)?),
+                    ty: Scalar::parse(ty).with_context(context)?,
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().with_context(context)?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    list: None,
+                    ty: Scalar::parse(ty).with_context(context)?,
+                });
+            }
+            ["comment" | "obj_info", ..] | [] => (),
+            _ => bail!("{}: unexpected `{line}`", context()),
+        }
+    }
+    let mut body = Body {
+        format: format.context("header has no `format`")?,
+        bytes: &bytes[body_start..],
+        pos: 0,
+    };
+
+    let mut scene = Scene {
+        materials: vec![Material {
+            base_color: [0.8; 3],
+            metallic: 0.0,
+            ..Material::default()
+        }],
+        ..Scene::default()
+    };
+    let mut polygons = Vec::new();
+    // Vertices are read as points until faces show that they form a mesh.
+    let mut points = Vec::new();
+    let (mut row, mut items) = (Vec::new(), Vec::new());
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let index = |names: [&str; 3]| -> Option<[usize; 3]> {
+                    let [a, b, c] = names.map(|name| element.index(name));
+                    Some([a?, b?, c?])
+                };
+                let position = index(["x", "y", "z"]).context("vertices have no `x`, `y` and `z`")?;
+                let normal = index(["nx", "ny", "nz"]);
+                let color = index(["red", "green", "blue"]);
+                let radius = element.index("radius");
+                for i in 0..element.count {
+                    body.row(element, &mut row, None, &mut items).with_context(|| format!("in vertex {i}"))?;
+                    progress.update(body_start + body.pos);
+                    points.push(Splat {
+                        center: position.map(|k| row[k] as f32),
+                        normal: normal.map_or([0.0; 3], |n| n.map(|k| row[k] as f32)),
+                        radius: radius.map_or(0.0, |k| row[k] as f32),
+                        color: color.map(|c| {
+                            c.map(|k| color::srgb_to_linear((row[k] / element.properties[k].ty.color_scale()) as f32))
+                        }),
+                        shape: SplatShape::Disk,
+                        material: 0,
+                    });
+                }
+            }
+            "face" => {
+                let list = element
+                    .properties
+                    .iter()
+                    .position(|p| p.list.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"))
+                    .context("faces have no `vertex_indices` list")?;
+                for i in 0..element.count {
+                    body.row(element, &mut row, Some(list), &mut items).with_context(|| format!("in face {i}"))?;
+                    progress.update(body_start + body.pos);
+                    ensure!(items.len() >= 3, "face {i} has fewer than three vertices");
+                    ensure!(items.iter().all(|&v| v >= 0.0), "face {i} has a negative vertex index");
+                    polygons.push(Polygon {
+                        corners: items.iter().map(|&v| v as u32).collect(),
+                        material: 0,
+                    });
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.row(element, &mut row, None, &mut items)?;
+                    progress.update(body_start + body.pos);
+                }
+            }
+        }
+    }
+
+    if polygons.is_empty() {
+        if !elements.iter().any(|element| element.name == "vertex" && element.index("radius").is_some()) {
+            let centers: Vec<[f32; 3]> = points.iter().map(|s| s.center).collect();
+            let radius = splat::default_radius(&centers);
+            points.iter_mut().for_each(|s| s.radius = radius);
+        }
+        scene.splats = points;
+        return Ok((scene, polygons));
+    }
+    for (i, polygon) in polygons.iter().enumerate() {
+        ensure!(
+            polygon.corners.iter().all(|&v| (v as usize) < points.len()),
+            "face {i} refers to a missing vertex"
+        );
+    }
+    scene.positions = points.iter().map(|p| p.center).collect();
+    scene.normals = points.iter().map(|p| p.normal).collect();
+    if points.iter().any(|p| p.color.is_some()) {
+        scene.colors = points.iter().map(|p| p.color).collect();
+    }
+    scene.triangles = polygons
+        .iter()
+        .flat_map(|polygon| {
+            let c = &polygon.corners;
+            (1..c.len() - 1).map(move |i| Triangle {
+                indices: [c[0], c[i], c[i + 1]],
+                material: 0,
+            })
+        })
+        .collect();
+    Ok((scene, polygons))
+}
@@ -0,0 +1,368 @@
+//! PNG images, decoded to 8-bit RGBA. Every color type and bit depth is
+//! read, with 16-bit samples cut to their high byte and `tRNS` transparency
+//! applied; interlaced images are rejected. Colors are taken to be sRGB.
+
+use {
+    super::texture::Texture,
+    anyhow::{bail, ensure, Context, Result},
+};
+
+pub const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn parse(bytes: &[u8]) -> Result<Texture> {
+    ensure!(bytes.starts_with(SIGNATURE), "not a PNG file");
+    let mut pos = SIGNATURE.len();
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    loop {
+        let length = read_u32(bytes, pos)? as usize;
+        let kind = bytes.get(pos + 4..pos + 8).context("truncated chunk")?;
+        let data = bytes.get(pos + 8..pos + 8 + length).context("truncated chunk")?;
+        pos += 12 + length;
+        match kind {
+            b"IHDR" => header = Some(Header::parse(data)?),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // Ancillary chunks have a lowercase first letter.
+            _ if kind[0].is_ascii_lowercase() => (),
+            _ => bail!("unknown critical chunk `{}`", String::from_utf8_lossy(kind)),
+        }
+    }
+    let header = header.context("missing IHDR chunk")?;
+    let raw = zlib(&compressed).context("corrupt image data")?;
+    let samples = unfilter(&header, &raw)?;
+    let pixels = header.to_rgba(&samples, palette, transparency)?;
+    Ok(Texture {
+        width: header.width,
+        height: header.height,
+        pixels,
+        blocks: None,
+        source: None,
+    })
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header> {
+        ensure!(data.len() == 13, "IHDR chunk has the wrong length");
+        let header = Header {
+            width: read_u32(data, 0)?,
+            height: read_u32(data, 4)?,
+            depth: data[8],
+            color_type: data[9],
+        };
+        ensure!(header.width > 0 && header.height > 0, "image has no pixels");
+        let depth_allowed: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            other => bail!("unknown color type {other}"),
+        };
+        ensure!(
+            depth_allowed.contains(&header.depth),
+            "bit depth {} is not allowed for color type {}",
+            header.depth,
+            header.color_type
+        );
+        ensure!(data[10] == 0 && data[11] == 0, "unknown compression or filter method");
+        ensure!(data[12] == 0, "interlaced PNG is not supported");
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes in one row of samples, without its filter byte.
+    fn stride(&self) -> usize {
+        (self.width as usize * self.channels() * self.depth as usize).div_ceil(8)
+    }
+
+    /// Pixels from unfiltered rows of samples.
+    fn to_rgba(&self, samples: &[u8], palette: &[u8], transparency: &[u8]) -> Result<Vec<[u8; 4]>> {
+        let channels = self.channels();
+        let depth = self.depth as usize;
+        // Sample `i` of a row, at its own depth.
+        let sample = |row: &[u8], i: usize| -> u16 {
+            match depth {
+                16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]),
+                8 => row[i] as u16,
+                _ => {
+                    let bit = i * depth;
+                    (row[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1)
+                }
+            }
+        };
+        // To 8 bits, replicating low bit depths across the byte.
+        let scale = |value: u16| -> u8 {
+            match depth {
+                16 => (value >> 8) as u8,
+                _ => (value as u32 * 255 / ((1 << depth) - 1)) as u8,
+            }
+        };
+        // Sample values of the single fully transparent color, if any.
+        let key: Option<Vec<u16>> = match self.color_type {
+            0 | 2 if transparency.len() >= 2 * channels => {
+                Some((0..channels).map(|c| u16::from_be_bytes([transparency[2 * c], transparency[2 * c + 1]])).collect())
+            }
+            _ => None,
+        };
+
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize);
+        for row in samples.chunks_exact(self.stride()) {
+            for x in 0..self.width as usize {
+                let mut values = [0u16; 4];
+                for (c, value) in values[..channels].iter_mut().enumerate() {
+                    *value = sample(row, x * channels + c);
+                }
+                let pixel = match self.color_type {
+                    3 => {
+                        let i = values[0] as usize;
+                        let rgb = palette.get(3 * i..3 * i + 3).context("palette index out of range")?;
+                        [rgb[0], rgb[1], rgb[2], transparency.get(i).copied().unwrap_or(255)]
+                    }
+                    0 | 4 => {
+                        let g = scale(values[0]);
+                        let a = if channels == 2 { scale(values[1]) } else { 255 };
+                        [g, g, g, a]
+                    }
+                    _ => {
+                        let a = if channels == 4 { scale(values[3]) } else { 255 };
+                        [scale(values[0]), scale(values[1]), scale(values[2]), a]
+                    }
+                };
+                let transparent = key.as_ref().is_some_and(|key| key[..] == values[..channels]);
+                pixels.push(if transparent { [pixel[0], pixel[1], pixel[2], 0] } else { pixel });
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+/// Undoes the per-row filters, returning the rows without filter bytes.
+fn unfilter(header: &Header, raw: &[u8]) -> Result<Vec<u8>> {
+    let stride = header.stride();
+    let height = header.height as usize;
+    ensure!(raw.len() >= (stride + 1) * height, "image data is too short");
+    // Bytes per complete pixel, or 1 below 8 bits per pixel.
+    let bpp = (header.channels() * header.depth as usize).div_ceil(8);
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..][..stride];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prior = if y > 0 { &done[(y - 1) * stride..] } else { &[][..] };
+        let current = &mut rest[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = prior.get(i).copied().unwrap_or(0);
+            let c = if i >= bpp { prior.get(i - bpp).copied().unwrap_or(0) } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                other => bail!("unknown filter type {other} in row {y}"),
+            };
+            current[i] = line[i].wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    let b = bytes.get(at..at + 4).context("unexpected end of file")?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Inflates a zlib stream, without checking its Adler-32 checksum.
+fn zlib(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.len() >= 2, "zlib stream is too short");
+    ensure!(data[0] & 0x0f == 8, "unknown zlib compression method");
+    ensure!((data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31), "corrupt zlib header");
+    ensure!(data[1] & 0x20 == 0, "zlib preset dictionaries are not supported");
+    let mut bits = Bits { data, pos: 2, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                // Stored: byte-aligned length, its complement and the bytes.
+                bits.buffer = 0;
+                bits.count = 0;
+                let header = data.get(bits.pos..bits.pos + 4).context("deflate stream ends early")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                ensure!(length == !u16::from_le_bytes([header[2], header[3]]) as usize, "corrupt stored block");
+                let stored = data.get(bits.pos + 4..bits.pos + 4 + length).context("deflate stream ends early")?;
+                out.extend_from_slice(stored);
+                bits.pos += 4 + length;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => bail!("invalid deflate block type"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// Order of the code length code lengths in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_length_count = bits.take(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[i] = bits.take(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(bits)? {
+            length @ 0..=15 => (length as u8, 1),
+            16 => (*lengths.last().context("repeat with no previous length")?, 3 + bits.take(2)?),
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    ensure!(lengths.len() == literal_count + distance_count, "code lengths overrun their tables");
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                ensure!(i < LENGTH_BASE.len(), "invalid length symbol");
+                let length = LENGTH_BASE[i] as usize + bits.take(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(bits)? as usize;
+                ensure!(d < DISTANCE_BASE.len(), "invalid distance symbol");
+                let distance = DISTANCE_BASE[d] as usize + bits.take(DISTANCE_EXTRA[d] as u32)? as usize;
+                ensure!(distance <= out.len(), "distance reaches before the start of the stream");
+                // Byte by byte, since a match may overlap its own output.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+/// Least significant bit first reader of a deflate stream.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).context("deflate stream ends early")?;
+            self.pos += 1;
+            self.buffer |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1 << n) - 1)) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+}
+
+/// Canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    /// Codes of each length, 0 to 15.
+    counts: [u16; 16],
+    /// Symbols ordered by code length, then by value.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            symbols.extend((0..lengths.len() as u16).filter(|&s| lengths[s as usize] == length));
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        // First code and first symbol index of the current length.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code")
+    }
+}
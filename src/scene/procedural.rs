@@ -0,0 +1,156 @@
+//! Analytic shapes defined outside the crate. A [`ProceduralPrimitive`]
+//! gives its bounds on the CPU and an intersection function in WGSL, which
+//! is spliced into shader.wgsl when the renderer is built, so new kinds of
+//! shape need no changes to the shader.
+//!
+//! ```no_run
+//! use raytracer::scene::{
+//!     builder::SceneBuilder,
+//!     procedural::{ProceduralParameters, ProceduralPrimitive},
+//! };
+//!
+//! /// Disk of radius `radius` around `center`, facing `normal`.
+//! #[derive(Debug)]
+//! struct Disk {
+//!     center: [f32; 3],
+//!     normal: [f32; 3],
+//!     radius: f32,
+//! }
+//!
+//! impl ProceduralPrimitive for Disk {
+//!     fn name(&self) -> &'static str {
+//!         "hit_custom_disk"
+//!     }
+//!
+//!     fn wgsl(&self) -> &'static str {
+//!         "fn hit_custom_disk(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+//!             var rec = hit_plane(shape.position, shape.u, r, t_min, t_max);
+//!             rec.hit = rec.hit && length(rec.p - shape.position) <= shape.radius;
+//!             return rec;
+//!         }"
+//!     }
+//!
+//!     fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+//!         (self.center.map(|c| c - self.radius), self.center.map(|c| c + self.radius))
+//!     }
+//!
+//!     fn parameters(&self) -> ProceduralParameters {
+//!         ProceduralParameters {
+//!             position: self.center,
+//!             u: self.normal,
+//!             radius: self.radius,
+//!             ..ProceduralParameters::default()
+//!         }
+//!     }
+//! }
+//!
+//! let mut builder = SceneBuilder::new();
+//! builder.add_procedural(Disk {
+//!     center: [0.0, 1.0, 0.0],
+//!     normal: [0.0, 0.0, 1.0],
+//!     radius: 0.5,
+//! });
+//! let scene = builder.build().unwrap();
+//! ```
+
+use {
+    anyhow::{ensure, Result},
+    std::{fmt, sync::Arc},
+};
+
+/// A kind of shape intersected by its own WGSL function.
+pub trait ProceduralPrimitive: fmt::Debug + Send + Sync {
+    /// Name of the function [`wgsl`](Self::wgsl) defines. Every primitive
+    /// with the same name shares one copy of the function, so the name
+    /// stands for the type; it must be a WGSL identifier that no function of
+    /// shader.wgsl already uses.
+    fn name(&self) -> &'static str;
+
+    /// WGSL source of `fn <name>(shape: Shape, r: Ray, t_min: f32, t_max:
+    /// f32) -> HitRecord`, which sets the record's `hit`, `t`, `p` and
+    /// `normal` for the nearest hit between `t_min` and `t_max`. It reads
+    /// the primitive's [`parameters`](Self::parameters) from `shape` and may
+    /// call the functions of shader.wgsl, such as `hit_plane`; the material
+    /// is filled in afterwards. If it does not compile, creating the
+    /// renderer or updating its scene fails with an error naming the
+    /// primitive.
+    fn wgsl(&self) -> &'static str;
+
+    /// World-space minimum and maximum corners.
+    fn bounds(&self) -> ([f32; 3], [f32; 3]);
+
+    fn parameters(&self) -> ProceduralParameters;
+}
+
+/// Values handed to a primitive's intersection function as the fields of
+/// the same names of its `Shape`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ProceduralParameters {
+    pub position: [f32; 3],
+    pub u: [f32; 3],
+    pub v: [f32; 3],
+    pub radius: f32,
+}
+
+/// One placed procedural primitive.
+#[derive(Clone, Debug)]
+pub struct Procedural {
+    pub primitive: Arc<dyn ProceduralPrimitive>,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+/// The first of `procedurals` with each distinct name, in order. A
+/// primitive's kind in the shader is the position of its name here.
+pub fn kinds(procedurals: &[Procedural]) -> Vec<&dyn ProceduralPrimitive> {
+    let mut kinds: Vec<&dyn ProceduralPrimitive> = Vec::new();
+    for procedural in procedurals {
+        if kinds.iter().all(|kind| kind.name() != procedural.primitive.name()) {
+            kinds.push(&*procedural.primitive);
+        }
+    }
+    kinds
+}
+
+/// Checks that names are WGSL identifiers and that primitives sharing a
+/// name share their source.
+pub fn check(procedurals: &[Procedural]) -> Result<()> {
+    let kinds = kinds(procedurals);
+    for kind in &kinds {
+        let name = kind.name();
+        ensure!(
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "procedural primitive name `{name}` is not a WGSL identifier"
+        );
+    }
+    for procedural in procedurals {
+        let name = procedural.primitive.name();
+        let kind = kinds.iter().find(|kind| kind.name() == name).unwrap();
+        ensure!(
+            kind.wgsl() == procedural.primitive.wgsl(),
+            "procedural primitives named `{name}` have different WGSL"
+        );
+    }
+    Ok(())
+}
+
+/// WGSL replacing the stand-in `hit_procedural` of shader.wgsl: the
+/// functions of `kinds`, and a `hit_procedural` calling the one of the
+/// shape's kind.
+pub fn hit_function(kinds: &[&dyn ProceduralPrimitive]) -> String {
+    let mut source = String::new();
+    for kind in kinds {
+        source += kind.wgsl();
+        source += "\n\n";
+    }
+    source += "fn hit_procedural(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {\n";
+    for (i, kind) in kinds.iter().enumerate() {
+        source += &format!(
+            "    if (shape.kind == SHAPE_PROCEDURAL + {i}u) {{ return {}(shape, r, t_min, t_max); }}\n",
+            kind.name()
+        );
+    }
+    source += "    var rec: HitRecord;\n    rec.hit = false;\n    return rec;\n}\n";
+    source
+}
@@ -0,0 +1,209 @@
+//! Signed distance field shapes, sphere traced in shader.wgsl. Each shape
+//! combines its primitives with union, intersection and difference, which
+//! lets a few numbers describe cut-outs and organic forms that would take
+//! many triangles.
+
+/// Primitives and operations across all SDF shapes. Must match
+/// `MAX_SDF_NODES` in shader.wgsl.
+pub const MAX_SDF_NODES: usize = 64;
+
+/// Distances a shape may need at once while being evaluated. Must match
+/// `SDF_STACK_SIZE` in shader.wgsl.
+pub const SDF_STACK_SIZE: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct Sdf {
+    pub root: SdfNode,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum SdfNode {
+    Primitive(SdfPrimitive),
+    /// Combines `children` from left to right. A nonzero `smoothness` blends
+    /// every seam over that radius.
+    Operation {
+        op: SdfOp,
+        children: Vec<SdfNode>,
+        smoothness: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SdfOp {
+    Union,
+    Intersection,
+    /// The first child minus all the others.
+    Difference,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SdfPrimitive {
+    Sphere { center: [f32; 3], radius: f32 },
+    /// Axis-aligned box extending `half_size` from `center` along each axis.
+    Box { center: [f32; 3], half_size: [f32; 3] },
+    /// Ring of radius `major_radius` around `axis`, with a tube of radius
+    /// `minor_radius`.
+    Torus {
+        center: [f32; 3],
+        axis: [f32; 3],
+        major_radius: f32,
+        minor_radius: f32,
+    },
+}
+
+type Bounds = ([f32; 3], [f32; 3]);
+
+impl SdfPrimitive {
+    /// Axis-aligned `(min, max)` bounds.
+    pub fn bounds(&self) -> Bounds {
+        let (center, extent) = match *self {
+            SdfPrimitive::Sphere { center, radius } => (center, [radius; 3]),
+            SdfPrimitive::Box { center, half_size } => (center, half_size),
+            SdfPrimitive::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            } => {
+                // The ring reaches `major_radius * sqrt(1 - axis[i]^2)` along
+                // axis i.
+                (center, axis.map(|a| major_radius * (1.0 - a * a).max(0.0).sqrt() + minor_radius))
+            }
+        };
+        (
+            [0, 1, 2].map(|i| center[i] - extent[i]),
+            [0, 1, 2].map(|i| center[i] + extent[i]),
+        )
+    }
+
+    fn transformed(self, rotate: impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> SdfPrimitive {
+        let scaled = |p| rotate(p).map(|c| c * scale);
+        match self {
+            SdfPrimitive::Sphere { center, radius } => SdfPrimitive::Sphere {
+                center: scaled(center),
+                radius: radius * scale.abs(),
+            },
+            SdfPrimitive::Box { center, half_size } => SdfPrimitive::Box {
+                center: scaled(center),
+                half_size: scaled(half_size).map(f32::abs),
+            },
+            SdfPrimitive::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            } => SdfPrimitive::Torus {
+                center: scaled(center),
+                axis: rotate(axis),
+                major_radius: major_radius * scale.abs(),
+                minor_radius: minor_radius * scale.abs(),
+            },
+        }
+    }
+
+    fn translated(self, offset: [f32; 3]) -> SdfPrimitive {
+        let moved = |p: [f32; 3]| [0, 1, 2].map(|i| p[i] + offset[i]);
+        match self {
+            SdfPrimitive::Sphere { center, radius } => SdfPrimitive::Sphere {
+                center: moved(center),
+                radius,
+            },
+            SdfPrimitive::Box { center, half_size } => SdfPrimitive::Box {
+                center: moved(center),
+                half_size,
+            },
+            SdfPrimitive::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            } => SdfPrimitive::Torus {
+                center: moved(center),
+                axis,
+                major_radius,
+                minor_radius,
+            },
+        }
+    }
+}
+
+impl SdfNode {
+    /// Axis-aligned `(min, max)` bounds, or `None` if the node is empty.
+    /// Smooth unions are grown by the most they can bulge.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let (op, children, smoothness) = match self {
+            SdfNode::Primitive(primitive) => return Some(primitive.bounds()),
+            SdfNode::Operation { op, children, smoothness } => (op, children, smoothness),
+        };
+        let mut bounds = children.iter().map(SdfNode::bounds);
+        match op {
+            SdfOp::Union => {
+                let mut bounds = bounds.flatten();
+                let first = bounds.next()?;
+                let (lo, hi) = bounds.fold(first, |(lo, hi), (min, max)| {
+                    ([0, 1, 2].map(|i| lo[i].min(min[i])), [0, 1, 2].map(|i| hi[i].max(max[i])))
+                });
+                let margin = smoothness * 0.25;
+                Some((lo.map(|c| c - margin), hi.map(|c| c + margin)))
+            }
+            SdfOp::Intersection => {
+                let first = bounds.next()??;
+                bounds.try_fold(first, |(lo, hi), child| {
+                    let (min, max) = child?;
+                    let both = ([0, 1, 2].map(|i| lo[i].max(min[i])), [0, 1, 2].map(|i| hi[i].min(max[i])));
+                    (0..3).all(|i| both.0[i] <= both.1[i]).then_some(both)
+                })
+            }
+            SdfOp::Difference => bounds.next()?,
+        }
+    }
+
+    /// Primitives and operations once flattened for the shader.
+    pub fn node_count(&self) -> usize {
+        match self {
+            SdfNode::Primitive(_) => 1,
+            SdfNode::Operation { children, .. } => {
+                children.iter().map(SdfNode::node_count).sum::<usize>() + children.len().saturating_sub(1)
+            }
+        }
+    }
+
+    /// Distances held at once while evaluating the node.
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            SdfNode::Primitive(_) => 1,
+            SdfNode::Operation { children, .. } => children
+                .iter()
+                .enumerate()
+                .map(|(i, child)| child.stack_depth() + usize::from(i > 0))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Applies `rotate`, which must map axes onto axes so that boxes stay
+    /// axis-aligned, followed by a uniform `scale`.
+    pub fn transformed(&self, rotate: &impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> SdfNode {
+        match self {
+            SdfNode::Primitive(primitive) => SdfNode::Primitive(primitive.transformed(rotate, scale)),
+            SdfNode::Operation { op, children, smoothness } => SdfNode::Operation {
+                op: *op,
+                children: children.iter().map(|child| child.transformed(rotate, scale)).collect(),
+                smoothness: smoothness * scale.abs(),
+            },
+        }
+    }
+
+    pub fn translated(&self, offset: [f32; 3]) -> SdfNode {
+        match self {
+            SdfNode::Primitive(primitive) => SdfNode::Primitive(primitive.translated(offset)),
+            SdfNode::Operation { op, children, smoothness } => SdfNode::Operation {
+                op: *op,
+                children: children.iter().map(|child| child.translated(offset)).collect(),
+                smoothness: *smoothness,
+            },
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Point clouds drawn as small disks, for scanned data. Each point is a
+//! splat intersected in shader.wgsl, either as a solid disk or as a Gaussian
+//! whose opacity fades towards its rim.
+
+use super::gltf::{self, Mat4};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Splat {
+    pub center: [f32; 3],
+    /// Facing direction of the disk, or zero to turn it towards every ray.
+    pub normal: [f32; 3],
+    pub radius: f32,
+    /// Linear RGB replacing the material's base color.
+    pub color: Option<[f32; 3]>,
+    pub shape: SplatShape,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SplatShape {
+    /// Opaque out to `radius`.
+    Disk,
+    /// Opacity `exp(-d² / 2σ²)` at distance `d` from the center, with
+    /// `radius` at 3σ, where it is cut off.
+    Gaussian,
+}
+
+impl Splat {
+    /// Axis-aligned `(min, max)` bounds.
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        (self.center.map(|c| c - self.radius), self.center.map(|c| c + self.radius))
+    }
+
+    /// Applies `rotate`, which must preserve lengths, followed by a uniform
+    /// `scale`.
+    pub fn transformed(self, rotate: impl Fn([f32; 3]) -> [f32; 3], scale: f32) -> Splat {
+        Splat {
+            center: rotate(self.center).map(|c| c * scale),
+            normal: rotate(self.normal),
+            radius: self.radius * scale.abs(),
+            ..self
+        }
+    }
+
+    /// Applies an affine transform. Radii scale with the cube root of the
+    /// volume change, so non-uniform scales only approximately keep them.
+    pub fn transformed_by(self, m: &Mat4) -> Splat {
+        Splat {
+            center: gltf::transform_point(m, self.center),
+            normal: gltf::transform_normal(m, self.normal),
+            radius: self.radius * gltf::determinant(m).abs().cbrt(),
+            ..self
+        }
+    }
+}
+
+/// A radius for points that come without one: about the spacing they would
+/// have if they covered the largest face of their bounding box evenly.
+pub fn default_radius(points: &[[f32; 3]]) -> f32 {
+    let Some(&first) = points.first() else {
+        return 0.0;
+    };
+    let (lo, hi) = points.iter().fold((first, first), |(lo, hi), p| {
+        ([0, 1, 2].map(|i| lo[i].min(p[i])), [0, 1, 2].map(|i| hi[i].max(p[i])))
+    });
+    let mut extent = [0, 1, 2].map(|i| hi[i] - lo[i]);
+    extent.sort_by(f32::total_cmp);
+    let area = extent[1] * extent[2];
+    let radius = 0.7 * (area / points.len() as f32).sqrt();
+    if radius > 0.0 {
+        radius
+    } else {
+        // Collinear or coincident points.
+        (extent[2] / points.len() as f32).max(1e-3)
+    }
+}
@@ -0,0 +1,281 @@
+//! Subdivision surfaces, tessellated at load time: Catmull-Clark for meshes
+//! with quads or larger polygons, Loop for meshes of triangles only. Each
+//! level splits every face into four, and the result is smooth-shaded.
+
+use {
+    super::Triangle,
+    crate::math::Vec3,
+    std::collections::HashMap,
+};
+
+/// Most levels accepted by scene files; each one quadruples the face count.
+pub const MAX_LEVEL: u32 = 6;
+
+/// A face with any number of corners, in winding order.
+#[derive(Clone, Debug)]
+pub struct Polygon {
+    pub corners: Vec<u32>,
+    /// Index into `Scene::materials`.
+    pub material: u32,
+}
+
+/// Subdivides `polygons` over `positions` `level` times and splits the
+/// result into triangles, returning new positions, normals and triangles.
+/// Corners at the same position are welded first, so meshes split at normal
+/// seams stay one surface. Open boundaries follow the B-spline of their
+/// edges. Boundary vertices of a single face, or where boundaries meet
+/// other than in pairs, stay in place, which keeps the corners of a sheet.
+pub fn subdivide(positions: &[[f32; 3]], polygons: &[Polygon], level: u32) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<Triangle>) {
+    let (mut points, mut faces) = weld(positions, polygons);
+    for _ in 0..level {
+        (points, faces) = if faces.iter().all(|face| face.corners.len() == 3) {
+            loop_step(&points, &faces)
+        } else {
+            catmull_clark_step(&points, &faces)
+        };
+    }
+
+    let triangles: Vec<Triangle> = faces
+        .iter()
+        .flat_map(|face| {
+            let c = &face.corners;
+            (1..c.len() - 1).map(move |i| Triangle {
+                indices: [c[0], c[i], c[i + 1]],
+                material: face.material,
+            })
+        })
+        .collect();
+    // Area-weighted face normals, summed at each vertex.
+    let mut normals = vec![Vec3::zero(); points.len()];
+    for triangle in &triangles {
+        let [a, b, c] = triangle.indices.map(|i| points[i as usize]);
+        let normal = (b - a).cross(&(c - a));
+        for i in triangle.indices {
+            normals[i as usize] += normal;
+        }
+    }
+    let array = |v: Vec3| [v.x(), v.y(), v.z()];
+    let normals = normals
+        .into_iter()
+        .map(|n| if n.length_squared() > 0.0 { array(n.normalized()) } else { [0.0; 3] })
+        .collect();
+    (points.into_iter().map(array).collect(), normals, triangles)
+}
+
+/// Merges corners at equal positions and drops faces left with fewer than
+/// three distinct corners in a row.
+fn weld(positions: &[[f32; 3]], polygons: &[Polygon]) -> (Vec<Vec3>, Vec<Polygon>) {
+    let mut points = Vec::new();
+    let mut ids: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut faces = Vec::with_capacity(polygons.len());
+    for polygon in polygons {
+        let mut corners: Vec<u32> = polygon
+            .corners
+            .iter()
+            .map(|&i| {
+                let [x, y, z] = positions[i as usize];
+                // Adding zero folds -0.0 into 0.0.
+                *ids.entry([x, y, z].map(|c| (c + 0.0).to_bits())).or_insert_with(|| {
+                    points.push(Vec3::new(x, y, z));
+                    points.len() as u32 - 1
+                })
+            })
+            .collect();
+        corners.dedup();
+        while corners.len() > 1 && corners.first() == corners.last() {
+            corners.pop();
+        }
+        if corners.len() >= 3 {
+            faces.push(Polygon {
+                corners,
+                material: polygon.material,
+            });
+        }
+    }
+    (points, faces)
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+/// Edges of `face` as pairs of consecutive corners.
+fn face_edges(face: &Polygon) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let c = &face.corners;
+    (0..c.len()).map(move |i| (c[i], c[(i + 1) % c.len()]))
+}
+
+/// Faces around every edge.
+fn edge_faces(faces: &[Polygon]) -> HashMap<(u32, u32), Vec<usize>> {
+    let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (i, face) in faces.iter().enumerate() {
+        for (a, b) in face_edges(face) {
+            edges.entry(edge_key(a, b)).or_default().push(i);
+        }
+    }
+    edges
+}
+
+/// Sums gathered at a vertex for its smoothing rule.
+#[derive(Copy, Clone, Default)]
+struct Neighborhood {
+    /// Sum and count of the vertices across every edge.
+    sum: Vec3,
+    valence: u32,
+    /// Sum and count of the surrounding face points, for Catmull-Clark.
+    faces: Vec3,
+    face_count: u32,
+    /// Sum and count of the vertices across edges with other than two faces.
+    boundary: Vec3,
+    boundary_count: u32,
+}
+
+/// Gathers the neighbors of every vertex across `edges`.
+fn neighborhoods(points: &[Vec3], edges: &HashMap<(u32, u32), Vec<usize>>) -> Vec<Neighborhood> {
+    let mut around = vec![Neighborhood::default(); points.len()];
+    for (&(a, b), faces) in edges {
+        for (v, other) in [(a, b), (b, a)] {
+            let n = &mut around[v as usize];
+            n.sum += points[other as usize];
+            n.valence += 1;
+            if faces.len() != 2 {
+                n.boundary += points[other as usize];
+                n.boundary_count += 1;
+            }
+        }
+    }
+    around
+}
+
+/// Boundary rule shared by both schemes, or `None` for interior vertices.
+fn boundary_point(p: Vec3, n: &Neighborhood) -> Option<Vec3> {
+    match n.boundary_count {
+        0 if n.valence > 0 => None,
+        2 if n.valence > 2 => Some(0.75 * p + 0.125 * n.boundary),
+        // Corners of a lone face, isolated vertices and non-manifold
+        // junctions.
+        _ => Some(p),
+    }
+}
+
+/// One Catmull-Clark step. New vertices are the moved old ones, then one
+/// per edge in order of first use, then one per face; every face becomes a
+/// quad per corner.
+fn catmull_clark_step(points: &[Vec3], faces: &[Polygon]) -> (Vec<Vec3>, Vec<Polygon>) {
+    let face_points: Vec<Vec3> = faces
+        .iter()
+        .map(|face| {
+            let sum = face.corners.iter().fold(Vec3::zero(), |sum, &i| sum + points[i as usize]);
+            sum / face.corners.len() as f32
+        })
+        .collect();
+    let edges = edge_faces(faces);
+
+    let mut around = neighborhoods(points, &edges);
+    for (face, &point) in faces.iter().zip(&face_points) {
+        for &i in &face.corners {
+            around[i as usize].faces += point;
+            around[i as usize].face_count += 1;
+        }
+    }
+    let mut out: Vec<Vec3> = points
+        .iter()
+        .zip(&around)
+        .map(|(&p, n)| {
+            boundary_point(p, n).unwrap_or_else(|| {
+                // (F + 2R + (n - 3)P) / n, with R the mean edge midpoint.
+                let valence = n.valence as f32;
+                let f = n.faces / n.face_count as f32;
+                let r = (p * valence + n.sum) / (2.0 * valence);
+                (f + 2.0 * r + (valence - 3.0) * p) / valence
+            })
+        })
+        .collect();
+
+    let mut edge_ids: HashMap<(u32, u32), u32> = HashMap::with_capacity(edges.len());
+    for face in faces {
+        for (a, b) in face_edges(face) {
+            let key = edge_key(a, b);
+            edge_ids.entry(key).or_insert_with(|| {
+                let (a, b) = (points[a as usize], points[b as usize]);
+                out.push(match edges[&key][..] {
+                    [f0, f1] => (a + b + face_points[f0] + face_points[f1]) / 4.0,
+                    _ => (a + b) / 2.0,
+                });
+                out.len() as u32 - 1
+            });
+        }
+    }
+
+    let face_base = out.len() as u32;
+    out.extend(&face_points);
+    let mut quads = Vec::with_capacity(faces.iter().map(|face| face.corners.len()).sum());
+    for (i, face) in faces.iter().enumerate() {
+        let c = &face.corners;
+        let k = c.len();
+        for j in 0..k {
+            let (prev, here, next) = (c[(j + k - 1) % k], c[j], c[(j + 1) % k]);
+            quads.push(Polygon {
+                corners: vec![
+                    here,
+                    edge_ids[&edge_key(here, next)],
+                    face_base + i as u32,
+                    edge_ids[&edge_key(prev, here)],
+                ],
+                material: face.material,
+            });
+        }
+    }
+    (out, quads)
+}
+
+/// One Loop step over triangles. New vertices are the moved old ones, then
+/// one per edge in order of first use; every triangle becomes four.
+fn loop_step(points: &[Vec3], faces: &[Polygon]) -> (Vec<Vec3>, Vec<Polygon>) {
+    let edges = edge_faces(faces);
+    let around = neighborhoods(points, &edges);
+    let mut out: Vec<Vec3> = points
+        .iter()
+        .zip(&around)
+        .map(|(&p, n)| {
+            boundary_point(p, n).unwrap_or_else(|| {
+                let valence = n.valence as f32;
+                let beta = if n.valence == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * valence) };
+                (1.0 - valence * beta) * p + beta * n.sum
+            })
+        })
+        .collect();
+
+    // The corner of a triangle that is not on edge `(a, b)`.
+    let opposite = |face: usize, (a, b): (u32, u32)| {
+        let corner = faces[face].corners.iter().find(|&&c| c != a && c != b);
+        points[*corner.unwrap() as usize]
+    };
+    let mut edge_ids: HashMap<(u32, u32), u32> = HashMap::with_capacity(edges.len());
+    for face in faces {
+        for (a, b) in face_edges(face) {
+            let key = edge_key(a, b);
+            edge_ids.entry(key).or_insert_with(|| {
+                let ends = points[a as usize] + points[b as usize];
+                out.push(match edges[&key][..] {
+                    [f0, f1] => 0.375 * ends + 0.125 * (opposite(f0, key) + opposite(f1, key)),
+                    _ => ends / 2.0,
+                });
+                out.len() as u32 - 1
+            });
+        }
+    }
+
+    let mut triangles = Vec::with_capacity(faces.len() * 4);
+    for face in faces {
+        let [a, b, c] = [0, 1, 2].map(|i| face.corners[i]);
+        let [ab, bc, ca] = [(a, b), (b, c), (c, a)].map(|(x, y)| edge_ids[&edge_key(x, y)]);
+        for corners in [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]] {
+            triangles.push(Polygon {
+                corners: corners.to_vec(),
+                material: face.material,
+            });
+        }
+    }
+    (out, triangles)
+}
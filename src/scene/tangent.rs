@@ -0,0 +1,59 @@
+//! Tangents of textured meshes, which orient their normal maps.
+
+use {super::Triangle, crate::math::Vec3};
+
+/// Tangent of each vertex, parallel to `positions`, averaged over the
+/// triangles using it. `xyz` points along increasing u, orthogonal to the
+/// vertex normal, and `w` is ±1 so that `cross(normal, xyz) * w` points up
+/// the image, toward decreasing v, as glTF defines it. Vertices of no
+/// triangle with texture coordinates at all three corners keep a zero
+/// tangent.
+pub fn generate(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: &[[f32; 2]],
+    triangles: &[Triangle],
+) -> Vec<[f32; 4]> {
+    let vec = |[x, y, z]: [f32; 3]| Vec3::new(x, y, z);
+    // Sums of the u and v directions and the face normals around each
+    // vertex, weighted by area in texture space.
+    let mut sums = vec![(Vec3::zero(), Vec3::zero(), Vec3::zero()); positions.len()];
+    for tri in triangles {
+        let [a, b, c] = tri.indices.map(|i| i as usize);
+        let (Some(&uv0), Some(&uv1), Some(&uv2)) = (uvs.get(a), uvs.get(b), uvs.get(c)) else { continue };
+        let e1 = vec(positions[b]) - vec(positions[a]);
+        let e2 = vec(positions[c]) - vec(positions[a]);
+        let [du1, dv1] = [uv1[0] - uv0[0], uv1[1] - uv0[1]];
+        let [du2, dv2] = [uv2[0] - uv0[0], uv2[1] - uv0[1]];
+        let det = du1 * dv2 - du2 * dv1;
+        if det == 0.0 || !det.is_finite() {
+            continue;
+        }
+        let dp_du = (e1 * dv2 - e2 * dv1) * det.signum();
+        let dp_dv = (e2 * du1 - e1 * du2) * det.signum();
+        let face = e1.cross(&e2);
+        for i in [a, b, c] {
+            sums[i].0 += dp_du;
+            sums[i].1 += dp_dv;
+            sums[i].2 += face;
+        }
+    }
+    sums.iter()
+        .enumerate()
+        .map(|(i, &(dp_du, dp_dv, face))| {
+            let normal = normals.get(i).map_or(Vec3::zero(), |&n| vec(n));
+            let normal = if normal.length_squared() > 0.0 { normal } else { face };
+            if normal.length_squared() == 0.0 {
+                return [0.0; 4];
+            }
+            let normal = normal.normalized();
+            let tangent = dp_du - normal * normal.dot(&dp_du);
+            if tangent.length_squared() == 0.0 || !tangent.length_squared().is_finite() {
+                return [0.0; 4];
+            }
+            let tangent = tangent.normalized();
+            let w = if normal.cross(&tangent).dot(&dp_dv) > 0.0 { -1.0 } else { 1.0 };
+            [tangent.x(), tangent.y(), tangent.z(), w]
+        })
+        .collect()
+}
@@ -0,0 +1,244 @@
+//! Images referenced by the scene, such as light gobos and base color
+//! textures.
+
+use {
+    super::{
+        bcn::{self, BlockFormat},
+        dds, jpeg, ktx2, png,
+    },
+    crate::{color, texture_cache::{self, TextureCache}},
+    anyhow::{bail, ensure, Context, Result},
+    std::{fmt, fs, path::Path},
+};
+
+#[derive(Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    /// sRGB-encoded 8-bit RGBA pixels, row by row from the top, or for data
+    /// such as normal maps the values themselves. Empty for block-compressed
+    /// textures, which are decoded when needed.
+    pub pixels: Vec<[u8; 4]>,
+    /// Block-compressed mip chain as stored in the file, which the renderer
+    /// uploads without decoding where the GPU samples the format.
+    pub blocks: Option<BlockImage>,
+    /// Key of the file the texture was decoded from in the installed
+    /// [`TextureCache`], under which the renderer caches its layers.
+    pub source: Option<u64>,
+}
+
+/// Changes whenever a decoder decodes the same file differently, so that
+/// images cached by an older one are not read.
+pub const DECODER_VERSION: u32 = 1;
+
+#[derive(Clone)]
+pub struct BlockImage {
+    pub format: BlockFormat,
+    /// Whether the blocks hold sRGB-encoded colors rather than linear ones.
+    pub srgb: bool,
+    /// Largest level first, each padded out to whole blocks.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.blocks {
+            Some(blocks) => write!(f, "Texture({}x{} {:?})", self.width, self.height, blocks.format),
+            None => write!(f, "Texture({}x{})", self.width, self.height),
+        }
+    }
+}
+
+impl Texture {
+    /// Reads a PNG, baseline JPEG, binary PPM (`P6`) or PGM (`P5`) file,
+    /// whose samples deeper than 8 bits are reduced to 8, a KTX2 file or a
+    /// DDS file.
+    pub fn load(path: &Path) -> Result<Texture> {
+        let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Texture::parse(&bytes).with_context(|| format!("failed to decode {}", path.display()))
+    }
+
+    /// Decodes an image file held in memory, of any format `load` reads.
+    /// With a cache [installed](texture_cache::install), an image decoded
+    /// before is read back from it instead.
+    ///
+    /// ```
+    /// use raytracer::scene::texture::Texture;
+    ///
+    /// let texture = Texture::parse(b"P5 2 1 255\n\x00\xff").unwrap();
+    /// assert_eq!(texture.pixels, [[0, 0, 0, 255], [255, 255, 255, 255]]);
+    /// // Short pixel data, and sizes that overflow, are errors.
+    /// assert!(Texture::parse(b"P6 2 1 255\n\x00\xff").is_err());
+    /// assert!(Texture::parse(b"P6 4294967295 4294967295 65535\n").is_err());
+    /// ```
+    pub fn parse(bytes: &[u8]) -> Result<Texture> {
+        let cache = texture_cache::installed();
+        let key = cache.as_ref().map(|_| TextureCache::key(bytes));
+        if let Some(texture) = cache.as_ref().zip(key).and_then(|(cache, key)| cache.get_image(key)) {
+            return Ok(texture);
+        }
+        let texture = Texture {
+            source: key,
+            ..Texture::decode(bytes)?
+        };
+        // Block-compressed files are copied rather than decoded, so only
+        // their layers are worth caching.
+        if let Some((cache, key)) = cache.zip(key).filter(|_| texture.blocks.is_none()) {
+            cache.put_image(key, &texture);
+        }
+        Ok(texture)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Texture> {
+        if bytes.starts_with(png::SIGNATURE) {
+            png::parse(bytes)
+        } else if bytes.starts_with(jpeg::MAGIC) {
+            jpeg::parse(bytes)
+        } else if bytes.starts_with(ktx2::IDENTIFIER) {
+            ktx2::parse(bytes)
+        } else if bytes.starts_with(dds::MAGIC) {
+            dds::parse(bytes)
+        } else {
+            parse_netpbm(bytes)
+        }
+    }
+
+    /// The texture with its blocks decoded to pixels on the CPU, which
+    /// fails for BC6H and BC7. With `srgb`, linear colors are encoded as
+    /// sRGB; without, for data, the values are kept as stored.
+    pub fn decoded(&self, srgb: bool) -> Result<Texture> {
+        let Some(blocks) = &self.blocks else {
+            return Ok(self.clone());
+        };
+        let pixels = bcn::decode(blocks.format, self.width, self.height, &blocks.levels[0])?;
+        let grey = blocks.format == BlockFormat::Bc4;
+        let pixels = pixels
+            .into_iter()
+            .map(|[r, g, b, a]| {
+                let [r, g, b] = if grey { [r; 3] } else { [r, g, b] };
+                let [r, g, b] = if blocks.srgb || !srgb { [r, g, b] } else { [r, g, b].map(linear_to_srgb8) };
+                [r, g, b, a]
+            })
+            .collect();
+        Ok(Texture {
+            width: self.width,
+            height: self.height,
+            pixels,
+            blocks: None,
+            source: self.source,
+        })
+    }
+
+    /// Bilinear resampling of the pixels to `width` by `height`, with texel
+    /// centers aligned.
+    pub fn resized(&self, width: u32, height: u32) -> Texture {
+        if (width, height) == (self.width, self.height) {
+            return Texture {
+                blocks: None,
+                ..self.clone()
+            };
+        }
+        let texel = |x: u32, y: u32| self.pixels[(y * self.width + x) as usize].map(f32::from);
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let sy = ((y as f32 + 0.5) * self.height as f32 / height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+            for x in 0..width {
+                let sx = ((x as f32 + 0.5) * self.width as f32 / width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+                let (x0, y0) = (sx as u32, sy as u32);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let (fx, fy) = (sx.fract(), sy.fract());
+                let [a, b, c, d] = [texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1)];
+                pixels.push([0, 1, 2, 3].map(|i| {
+                    let top = a[i] + (b[i] - a[i]) * fx;
+                    let bottom = c[i] + (d[i] - c[i]) * fx;
+                    (top + (bottom - top) * fy).round() as u8
+                }));
+            }
+        }
+        Texture {
+            width,
+            height,
+            pixels,
+            blocks: None,
+            source: self.source,
+        }
+    }
+}
+
+/// Encodes a linear 8-bit channel as sRGB.
+pub(super) fn linear_to_srgb8(c: u8) -> u8 {
+    (color::linear_to_srgb(c as f32 / 255.0) * 255.0).round() as u8
+}
+
+fn parse_netpbm(bytes: &[u8]) -> Result<Texture> {
+    let channels = match bytes.get(..2) {
+        Some(b"P5") => 1,
+        Some(b"P6") => 3,
+        _ => bail!("not a binary PGM or PPM file"),
+    };
+
+    // Width, height and maximum value, separated by whitespace and comments.
+    let mut pos = 2;
+    let mut header = [0u32; 3];
+    for value in &mut header {
+        loop {
+            match bytes.get(pos) {
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                _ => break,
+            }
+        }
+        let start = pos;
+        while bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        *value = std::str::from_utf8(&bytes[start..pos])
+            .ok()
+            .and_then(|it| it.parse().ok())
+            .context("truncated header")?;
+    }
+    // A single whitespace byte precedes the samples.
+    pos += 1;
+
+    let [width, height, max] = header;
+    ensure!(width > 0 && height > 0, "image is empty");
+    ensure!((1..=u16::MAX as u32).contains(&max), "invalid maximum value {max}");
+    let sample_size = if max > 255 { 2 } else { 1 };
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|it| it.checked_mul(channels * sample_size))
+        .with_context(|| format!("{width}x{height} image is too large"))?;
+    let data = bytes.get(pos..).unwrap_or_default();
+    ensure!(
+        data.len() >= size,
+        "truncated pixel data ({} of {size} bytes)",
+        data.len()
+    );
+    let sample = |i: usize| {
+        let value = match sample_size {
+            1 => data[i] as u32,
+            _ => u16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as u32,
+        };
+        ((value.min(max) * 255 + max / 2) / max) as u8
+    };
+    let pixels = (0..width as usize * height as usize)
+        .map(|i| match channels {
+            1 => {
+                let v = sample(i);
+                [v, v, v, 255]
+            }
+            _ => [sample(3 * i), sample(3 * i + 1), sample(3 * i + 2), 255],
+        })
+        .collect();
+    Ok(Texture {
+        width,
+        height,
+        pixels,
+        blocks: None,
+        source: None,
+    })
+}
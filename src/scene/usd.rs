@@ -0,0 +1,954 @@
+//! Universal Scene Description import from `.usda` text layers, and from
+//! `.usdz` packages whose root layer is one.
+//!
+//! `Mesh` prims are flattened into world space through the `xformOp`s of
+//! their ancestors and shaded with the constant inputs of the
+//! `UsdPreviewSurface` bound to them, per face where a `GeomSubset` has its
+//! own binding. Unbound meshes take their `displayColor`. The first
+//! `Camera` sets the view. The stage's `upAxis` and `metersPerUnit` are
+//! applied, so scenes come in Y-up and in meters. References, payloads,
+//! sublayers, variants, instancing, subdivision and animation past the
+//! first time sample are not composed, and binary `.usdc` layers are
+//! rejected.
+
+use {
+    super::{
+        gltf::{self, Mat4, IDENTITY},
+        obj,
+        subdivide::Polygon,
+        Material, MaterialKind, Scene, SceneCamera, Triangle,
+    },
+    crate::{crash, math::Vec3},
+    anyhow::{bail, ensure, Context, Result},
+    std::{collections::HashMap, fs, path::Path},
+};
+
+const CRATE_MAGIC: &[u8] = b"PXR-USDC";
+const ZIP_LOCAL_HEADER: &[u8; 4] = b"PK\x03\x04";
+
+/// Prim hierarchies deeper than this are rejected rather than recursed into.
+const MAX_PRIM_DEPTH: usize = 256;
+
+/// Defaults of the `UsdPreviewSurface` inputs, also used for unbound meshes.
+const PREVIEW_SURFACE: Material = Material {
+    base_color: [0.18; 3],
+    metallic: 0.0,
+    roughness: 0.5,
+    emission: [0.0; 3],
+    ior: 1.5,
+    kind: MaterialKind::MetallicRoughness,
+    base_color_texture: None,
+    normal_texture: None,
+    normal_scale: 1.0,
+};
+
+pub fn load(path: &Path) -> Result<Scene> {
+    load_polygons(path).map(|(scene, _)| scene)
+}
+
+/// Also returns the faces before triangulation, over the scene's vertices,
+/// for subdivision.
+pub fn load_polygons(path: &Path) -> Result<(Scene, Vec<Polygon>)> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let import = || -> Result<(Scene, Vec<Polygon>)> {
+        let layer = if bytes.starts_with(ZIP_LOCAL_HEADER) {
+            root_layer(&bytes)?
+        } else {
+            &bytes[..]
+        };
+        ensure!(
+            !layer.starts_with(CRATE_MAGIC),
+            "binary USD layers are not supported; export the stage as .usda"
+        );
+        let text = std::str::from_utf8(layer).context("USD layer is not valid UTF-8")?;
+        import(&parse(text)?)
+    };
+    import().with_context(|| format!("failed to import {}", path.display()))
+}
+
+/// The root layer of a `.usdz` package, which is its first file. Package
+/// files are stored uncompressed.
+fn root_layer(bytes: &[u8]) -> Result<&[u8]> {
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize;
+    ensure!(bytes.len() >= 30, "truncated package");
+    ensure!(u16_at(8) == 0, "package files must be stored uncompressed");
+    let size = u32_at(18);
+    let name_start = 30;
+    let data_start = name_start + u16_at(26) + u16_at(28);
+    let name = bytes.get(name_start..name_start + u16_at(26)).context("truncated package")?;
+    let name = String::from_utf8_lossy(name);
+    let data = bytes.get(data_start..data_start + size).context("truncated package")?;
+    let extension = Path::new(&*name).extension().and_then(|it| it.to_str()).map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("usda") => Ok(data),
+        Some("usd") if !data.starts_with(CRATE_MAGIC) => Ok(data),
+        Some("usd" | "usdc") => bail!("root layer `{name}` is binary; export the stage as .usda"),
+        _ => bail!("root layer `{name}` is not a USD layer"),
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Str(String),
+    /// `<...>`, a path to a prim or property.
+    Path(String),
+    /// `@...@`, a path to a file, which is not followed.
+    Asset,
+    Punct(char),
+}
+
+/// Splits a layer into tokens, each with its line number.
+fn tokenize(text: &str) -> Result<Vec<(Token, u32)>> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let (mut i, mut line) = (0, 1);
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let token = match c {
+            b'\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'"' | b'\'' => {
+                let quote = if bytes[i..].starts_with(&[c; 3]) { &[c; 3][..] } else { &[c; 1][..] };
+                let first_line = line;
+                i += quote.len();
+                while !bytes[i..].starts_with(quote) {
+                    ensure!(i < bytes.len(), "line {first_line}: unterminated string");
+                    line += u32::from(bytes[i] == b'\n');
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                    i = i.min(bytes.len());
+                }
+                i += quote.len();
+                Token::Str(unescape(&text[start + quote.len()..i - quote.len()]))
+            }
+            b'<' | b'@' => {
+                let close = if c == b'<' { b'>' } else { b'@' };
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|&b| b == close || b == b'\n')
+                    .filter(|&end| bytes[i + 1 + end] == close)
+                    .with_context(|| format!("line {line}: unterminated path"))?;
+                i += end + 2;
+                if c == b'<' {
+                    Token::Path(text[start + 1..i - 1].to_string())
+                } else {
+                    Token::Asset
+                }
+            }
+            b'(' | b')' | b'[' | b']' | b'{' | b'}' | b'=' | b',' | b':' | b';' => {
+                i += 1;
+                Token::Punct(c as char)
+            }
+            _ if c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.') => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'-' | b'+' | b'.')) {
+                    i += 1;
+                }
+                let word = &text[start..i];
+                Token::Number(word.parse().with_context(|| format!("line {line}: invalid number `{word}`"))?)
+            }
+            _ if c.is_ascii_alphabetic() || c == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'_' | b':' | b'.')) {
+                    i += 1;
+                }
+                Token::Word(text[start..i].to_string())
+            }
+            _ => bail!("line {line}: unexpected `{}`", text[start..].chars().next().unwrap_or_default()),
+        };
+        tokens.push((token, line));
+    }
+    Ok(tokens)
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        });
+    }
+    out
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    None,
+    Number(f64),
+    /// Strings, tokens and keywords such as `true`.
+    Str(String),
+    Path(String),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            Value::Number(n) => Some(*n as f32),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The target of a relationship or connection, or its first target.
+    fn as_path(&self) -> Option<&str> {
+        match self {
+            Value::Path(path) => Some(path),
+            Value::List(items) => items.first()?.as_path(),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_tuple<const N: usize>(&self) -> Option<[f32; N]> {
+        match self {
+            Value::Tuple(items) if items.len() == N => {
+                let mut out = [0.0; N];
+                for (out, item) in out.iter_mut().zip(items) {
+                    *out = item.as_f32()?;
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+
+    fn as_vec3_list(&self) -> Option<Vec<[f32; 3]>> {
+        self.as_list()?.iter().map(Value::as_tuple::<3>).collect()
+    }
+
+    fn as_index_list(&self) -> Option<Vec<u32>> {
+        self.as_list()?
+            .iter()
+            .map(|item| match item {
+                Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u32),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+struct Property {
+    name: String,
+    value: Value,
+    metadata: Vec<(String, Value)>,
+}
+
+struct Prim {
+    specifier: String,
+    type_name: String,
+    name: String,
+    metadata: Vec<(String, Value)>,
+    properties: Vec<Property>,
+    children: Vec<Prim>,
+}
+
+impl Prim {
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().rev().find(|it| it.name == name)
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.property(name).map(|it| &it.value)
+    }
+
+    fn token(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+
+    fn binding(&self) -> Option<&str> {
+        self.get("material:binding").and_then(Value::as_path)
+    }
+}
+
+struct Stage {
+    metadata: Vec<(String, Value)>,
+    prims: Vec<Prim>,
+}
+
+fn meta<'a>(metadata: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    metadata.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value)
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+}
+
+fn parse(text: &str) -> Result<Stage> {
+    ensure!(text.starts_with("#usda"), "not a USD text layer (no `#usda` header)");
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let metadata = if parser.eat('(') { parser.metadata()? } else { Vec::new() };
+    let (_, prims) = parser.body(0)?;
+    Ok(Stage { metadata, prims })
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(1, |&(_, line)| line)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let (token, _) = self.tokens.get(self.pos).context("unexpected end of file")?;
+        self.pos += 1;
+        Ok(token.clone())
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(c)) if *c == punct);
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, punct: char) -> Result<()> {
+        ensure!(self.eat(punct), "line {}: expected `{punct}`", self.line());
+        Ok(())
+    }
+
+    /// Statements up to the `}` closing a prim, or to the end of the layer.
+    fn body(&mut self, depth: usize) -> Result<(Vec<Property>, Vec<Prim>)> {
+        let (mut properties, mut children) = (Vec::new(), Vec::new());
+        loop {
+            match self.peek() {
+                None if depth == 0 => break,
+                Some(Token::Punct('}')) if depth > 0 => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Token::Punct(';')) => self.pos += 1,
+                Some(Token::Word(word)) if matches!(word.as_str(), "def" | "over" | "class") => {
+                    children.push(self.prim(depth)?);
+                }
+                Some(Token::Word(word)) if word == "variantSet" => {
+                    while !self.eat('{') {
+                        self.next()?;
+                    }
+                    self.skip_group()?;
+                }
+                _ => properties.push(self.property()?),
+            }
+        }
+        Ok((properties, children))
+    }
+
+    fn prim(&mut self, depth: usize) -> Result<Prim> {
+        ensure!(depth < MAX_PRIM_DEPTH, "line {}: prims are nested too deeply", self.line());
+        let Token::Word(specifier) = self.next()? else {
+            unreachable!()
+        };
+        let type_name = match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                word
+            }
+            _ => String::new(),
+        };
+        let line = self.line();
+        let Token::Str(name) = self.next()? else {
+            bail!("line {line}: expected a prim name");
+        };
+        let metadata = if self.eat('(') { self.metadata()? } else { Vec::new() };
+        self.expect('{')?;
+        let (properties, children) = self.body(depth + 1)?;
+        Ok(Prim {
+            specifier,
+            type_name,
+            name,
+            metadata,
+            properties,
+            children,
+        })
+    }
+
+    /// An attribute or relationship: qualifiers and a type name, all on the
+    /// line of the property name, then an optional value and metadata.
+    fn property(&mut self) -> Result<Property> {
+        let line = self.line();
+        let mut words: Vec<String> = Vec::new();
+        while self.line() == line {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                Some(Token::Punct('[')) if !words.is_empty() => {
+                    self.pos += 1;
+                    self.expect(']')?;
+                }
+                _ => break,
+            }
+        }
+        let Some(name) = words.pop() else {
+            match self.peek() {
+                Some(token) => bail!("line {line}: unexpected {token:?}"),
+                None => bail!("unexpected end of file"),
+            }
+        };
+        let value = match self.eat('=') {
+            true if name.ends_with(".timeSamples") => self.time_samples()?,
+            true => self.value()?,
+            false => Value::None,
+        };
+        let metadata = if self.eat('(') { self.metadata()? } else { Vec::new() };
+        Ok(Property {
+            name: name.strip_suffix(".timeSamples").unwrap_or(&name).to_string(),
+            value,
+            metadata,
+        })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let line = self.line();
+        Ok(match self.next()? {
+            Token::Number(n) => Value::Number(n),
+            Token::Str(s) => Value::Str(s),
+            Token::Word(word) if word == "None" => Value::None,
+            Token::Word(word) => Value::Str(word),
+            Token::Path(path) => Value::Path(path),
+            // References and payloads, with an optional prim path.
+            Token::Asset => {
+                if let Some(Token::Path(_)) = self.peek() {
+                    self.pos += 1;
+                }
+                Value::None
+            }
+            Token::Punct('(') => Value::Tuple(self.sequence(')')?),
+            Token::Punct('[') => Value::List(self.sequence(']')?),
+            Token::Punct('{') => {
+                self.skip_group()?;
+                Value::None
+            }
+            token => bail!("line {line}: unexpected {token:?}"),
+        })
+    }
+
+    fn sequence(&mut self, close: char) -> Result<Vec<Value>> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(self.value()?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// The value at the earliest time of `{ time: value, ... }`.
+    fn time_samples(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut first: Option<(f64, Value)> = None;
+        while !self.eat('}') {
+            let line = self.line();
+            let Token::Number(time) = self.next()? else {
+                bail!("line {line}: expected a sample time");
+            };
+            self.expect(':')?;
+            let value = self.value()?;
+            if first.as_ref().is_none_or(|(earliest, _)| time < *earliest) {
+                first = Some((time, value));
+            }
+            self.eat(',');
+        }
+        Ok(first.map_or(Value::None, |(_, value)| value))
+    }
+
+    /// `key = value` entries up to the closing `)`; doc strings and list
+    /// edit qualifiers are dropped.
+    fn metadata(&mut self) -> Result<Vec<(String, Value)>> {
+        let mut entries = Vec::new();
+        loop {
+            let line = self.line();
+            match self.next()? {
+                Token::Punct(')') => return Ok(entries),
+                Token::Punct(';') | Token::Str(_) => (),
+                Token::Word(word) if matches!(word.as_str(), "prepend" | "append" | "add" | "delete" | "reorder") => (),
+                Token::Word(key) => {
+                    self.expect('=')?;
+                    entries.push((key, self.value()?));
+                }
+                token => bail!("line {line}: unexpected {token:?}"),
+            }
+        }
+    }
+
+    /// Skips to the `}` matching one already read.
+    fn skip_group(&mut self) -> Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::Punct('{') => depth += 1,
+                Token::Punct('}') => depth -= 1,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a primvar's values map onto a mesh.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Interpolation {
+    Constant,
+    Uniform,
+    Vertex,
+    FaceVarying,
+}
+
+struct Primvar {
+    values: Vec<[f32; 3]>,
+    indices: Option<Vec<u32>>,
+    interpolation: Interpolation,
+}
+
+impl Primvar {
+    fn read(prim: &Prim, name: &str, default: Interpolation) -> Option<Primvar> {
+        let property = prim.property(name)?;
+        let values = property.value.as_vec3_list().filter(|it| !it.is_empty())?;
+        let interpolation = match meta(&property.metadata, "interpolation").and_then(Value::as_str) {
+            Some("constant") => Interpolation::Constant,
+            Some("uniform") => Interpolation::Uniform,
+            Some("vertex" | "varying") => Interpolation::Vertex,
+            Some("faceVarying") => Interpolation::FaceVarying,
+            _ => default,
+        };
+        Some(Primvar {
+            values,
+            indices: prim.get(&format!("{name}:indices")).and_then(Value::as_index_list),
+            interpolation,
+        })
+    }
+
+    /// The value at corner `corner` of face `face`, which is point `point`.
+    fn at(&self, face: usize, corner: usize, point: usize) -> Option<[f32; 3]> {
+        let i = match self.interpolation {
+            Interpolation::Constant => 0,
+            Interpolation::Uniform => face,
+            Interpolation::Vertex => point,
+            Interpolation::FaceVarying => corner,
+        };
+        let i = match &self.indices {
+            Some(indices) => *indices.get(i)? as usize,
+            None => i,
+        };
+        self.values.get(i).copied()
+    }
+}
+
+struct Importer<'a> {
+    /// Defined prims by path, for material bindings.
+    prims: HashMap<String, &'a Prim>,
+    /// Stage frame, which `!resetXformStack!` returns to.
+    root: Mat4,
+    scene: Scene,
+    polygons: Vec<Polygon>,
+    materials: HashMap<String, u32>,
+    /// Materials of unbound meshes by the bits of their display color.
+    display_colors: HashMap<[u32; 3], u32>,
+}
+
+fn import(stage: &Stage) -> Result<(Scene, Vec<Polygon>)> {
+    let mut root = IDENTITY;
+    if let Some(meters) = meta(&stage.metadata, "metersPerUnit").and_then(Value::as_f32) {
+        ensure!(meters > 0.0, "`metersPerUnit` must be positive");
+        for (i, column) in root.iter_mut().take(3).enumerate() {
+            column[i] = meters;
+        }
+    }
+    match meta(&stage.metadata, "upAxis").and_then(Value::as_str) {
+        None | Some("Y") => (),
+        // (x, y, z) to (x, z, -y), as `Scene::convert_frame`.
+        Some("Z") => root = gltf::mat_mul(&[[1.0, 0.0, 0.0, 0.0], [0.0, 0.0, -1.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0]], &root),
+        Some(axis) => bail!("unsupported `upAxis` {axis:?}"),
+    }
+
+    let mut prims = HashMap::new();
+    index(&stage.prims, "", &mut prims);
+    let mut importer = Importer {
+        prims,
+        root,
+        scene: Scene::default(),
+        polygons: Vec::new(),
+        materials: HashMap::new(),
+        display_colors: HashMap::new(),
+    };
+    for prim in &stage.prims {
+        importer.visit(prim, &format!("/{}", prim.name), &root, None)?;
+    }
+    Ok((importer.scene, importer.polygons))
+}
+
+fn index<'a>(prims: &'a [Prim], parent: &str, out: &mut HashMap<String, &'a Prim>) {
+    for prim in prims.iter().filter(|it| it.specifier == "def") {
+        let path = format!("{parent}/{}", prim.name);
+        index(&prim.children, &path, out);
+        out.insert(path, prim);
+    }
+}
+
+impl Importer<'_> {
+    fn visit(&mut self, prim: &Prim, path: &str, parent: &Mat4, binding: Option<&str>) -> Result<()> {
+        let inactive = matches!(meta(&prim.metadata, "active"), Some(Value::Str(active)) if active == "false");
+        let hidden = prim.token("visibility") == Some("invisible") || matches!(prim.token("purpose"), Some("guide" | "proxy"));
+        if prim.specifier != "def" || inactive || hidden || matches!(prim.type_name.as_str(), "Material" | "Shader") {
+            return Ok(());
+        }
+        let (local, reset) = transform(prim).with_context(|| format!("prim `{path}`"))?;
+        let transform = gltf::mat_mul(if reset { &self.root } else { parent }, &local);
+        let binding = prim.binding().or(binding);
+        match prim.type_name.as_str() {
+            "Mesh" => self.mesh(prim, &transform, binding).with_context(|| format!("mesh `{path}`"))?,
+            "Camera" if self.scene.camera.is_none() => self.scene.camera = Some(camera(prim, &transform)),
+            _ => (),
+        }
+        for child in &prim.children {
+            self.visit(child, &format!("{path}/{}", child.name), &transform, binding)?;
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, prim: &Prim, transform: &Mat4, binding: Option<&str>) -> Result<()> {
+        let points = prim
+            .get("points")
+            .context("missing `points`")?
+            .as_vec3_list()
+            .context("`points` must be a list of 3-vectors")?;
+        let index_list = |name: &str| {
+            prim.get(name)
+                .map_or(Some(Vec::new()), Value::as_index_list)
+                .with_context(|| format!("`{name}` must be a list of non-negative integers"))
+        };
+        let counts = index_list("faceVertexCounts")?;
+        let indices = index_list("faceVertexIndices")?;
+        let holes = index_list("holeIndices")?;
+        ensure!(
+            counts.iter().map(|&n| n as usize).sum::<usize>() == indices.len(),
+            "`faceVertexCounts` does not add up to the {} face vertex indices",
+            indices.len()
+        );
+        if let Some(&i) = indices.iter().find(|&&i| i as usize >= points.len()) {
+            bail!("face vertex index {i} is out of range for {} points", points.len());
+        }
+        let left_handed = prim.token("orientation") == Some("leftHanded");
+
+        let normals = Primvar::read(prim, "primvars:normals", Interpolation::Vertex)
+            .or_else(|| Primvar::read(prim, "normals", Interpolation::Vertex));
+        // Display colors only stand in for a material.
+        let colors = match binding {
+            Some(_) => None,
+            None => Primvar::read(prim, "primvars:displayColor", Interpolation::Constant),
+        };
+        let mut face_materials = vec![
+            match (binding, &colors) {
+                (Some(binding), _) => self.material(binding),
+                (None, Some(colors)) if matches!(colors.interpolation, Interpolation::Constant) => {
+                    self.display_color(colors.values[0])
+                }
+                _ => self.display_color(PREVIEW_SURFACE.base_color),
+            };
+            counts.len()
+        ];
+        for subset in prim.children.iter().filter(|it| it.specifier == "def" && it.type_name == "GeomSubset") {
+            let element = subset.token("elementType").unwrap_or("face");
+            let (Some(binding), "face") = (subset.binding(), element) else {
+                continue;
+            };
+            let material = self.material(binding);
+            for face in subset.get("indices").and_then(Value::as_index_list).unwrap_or_default() {
+                if let Some(slot) = face_materials.get_mut(face as usize) {
+                    *slot = material;
+                }
+            }
+        }
+        let per_vertex_colors = colors.filter(|it| it.interpolation != Interpolation::Constant);
+        // Vertices are shared between faces unless a primvar varies per
+        // face or per corner.
+        let shared = [&normals, &per_vertex_colors]
+            .iter()
+            .all(|it| it.as_ref().is_none_or(|it| it.interpolation == Interpolation::Vertex));
+
+        let mut point_vertices = vec![u32::MAX; points.len()];
+        let mut start = 0;
+        for (face, &count) in counts.iter().enumerate() {
+            let range = start..start + count as usize;
+            start = range.end;
+            if count < 3 || holes.contains(&(face as u32)) {
+                continue;
+            }
+            let mut corners: Vec<u32> = range
+                .map(|corner| {
+                    let point = indices[corner] as usize;
+                    if shared && point_vertices[point] != u32::MAX {
+                        return point_vertices[point];
+                    }
+                    let vertex = self.scene.positions.len() as u32;
+                    self.scene.positions.push(gltf::transform_point(transform, points[point]));
+                    let normal = normals.as_ref().and_then(|it| it.at(face, corner, point));
+                    self.scene
+                        .normals
+                        .push(normal.map_or([0.0; 3], |it| gltf::transform_normal(transform, it)));
+                    if let Some(color) = per_vertex_colors.as_ref().and_then(|it| it.at(face, corner, point)) {
+                        self.scene.colors.resize(vertex as usize, None);
+                        self.scene.colors.push(Some(color));
+                    }
+                    point_vertices[point] = vertex;
+                    vertex
+                })
+                .collect();
+            if left_handed {
+                corners.reverse();
+            }
+            let positions: Vec<Vec3> = corners
+                .iter()
+                .map(|&i| {
+                    let [x, y, z] = self.scene.positions[i as usize];
+                    Vec3::new(x, y, z)
+                })
+                .collect();
+            let material = face_materials[face];
+            self.scene.triangles.extend(obj::triangulate(&positions).into_iter().map(|[a, b, c]| Triangle {
+                indices: [a, b, c].map(|k| corners[k]),
+                material,
+            }));
+            self.polygons.push(Polygon { corners, material });
+        }
+        Ok(())
+    }
+
+    /// Index of the material at `path`, converted on first use.
+    fn material(&mut self, path: &str) -> u32 {
+        if let Some(&index) = self.materials.get(path) {
+            return index;
+        }
+        let material = match self.prims.get(path).and_then(|it| surface_shader(it, &self.prims)) {
+            Some(shader) => preview_surface(shader),
+            None => {
+                crash::log!("warning: no UsdPreviewSurface found for material `{path}`");
+                PREVIEW_SURFACE
+            }
+        };
+        let index = self.scene.materials.len() as u32;
+        self.scene.materials.push(material);
+        self.materials.insert(path.to_string(), index);
+        index
+    }
+
+    fn display_color(&mut self, color: [f32; 3]) -> u32 {
+        *self.display_colors.entry(color.map(f32::to_bits)).or_insert_with(|| {
+            self.scene.materials.push(Material {
+                base_color: color,
+                ..PREVIEW_SURFACE
+            });
+            self.scene.materials.len() as u32 - 1
+        })
+    }
+}
+
+/// The prim's local transform from its `xformOpOrder`, and whether it
+/// replaces its parent's transform instead of following it.
+fn transform(prim: &Prim) -> Result<(Mat4, bool)> {
+    let (mut m, mut reset) = (IDENTITY, false);
+    for op in prim.get("xformOpOrder").and_then(Value::as_list).unwrap_or_default() {
+        let op = op.as_str().context("`xformOpOrder` must list op names")?;
+        if op == "!resetXformStack!" {
+            (m, reset) = (IDENTITY, true);
+            continue;
+        }
+        let (invert, name) = match op.strip_prefix("!invert!") {
+            Some(name) => (true, name),
+            None => (false, op),
+        };
+        let value = prim.get(name).with_context(|| format!("`{name}` has no value"))?;
+        let mut op_matrix = xform_op(name, value).with_context(|| format!("unsupported or invalid `{name}`"))?;
+        if invert {
+            op_matrix = affine_inverse(&op_matrix).with_context(|| format!("`{name}` is not invertible"))?;
+        }
+        m = gltf::mat_mul(&m, &op_matrix);
+    }
+    Ok((m, reset))
+}
+
+fn xform_op(name: &str, value: &Value) -> Option<Mat4> {
+    // Ops may carry a suffix, as in `xformOp:translate:pivot`.
+    let kind = name.strip_prefix("xformOp:")?.split(':').next()?;
+    let mut m = IDENTITY;
+    match kind {
+        "translate" => {
+            let [x, y, z] = value.as_tuple::<3>()?;
+            m[3] = [x, y, z, 1.0];
+        }
+        "scale" => {
+            for (i, s) in value.as_tuple::<3>()?.into_iter().enumerate() {
+                m[i][i] = s;
+            }
+        }
+        "rotateX" | "rotateY" | "rotateZ" => {
+            m = rotation(usize::from(kind.as_bytes()[6] - b'X'), value.as_f32()?);
+        }
+        // Rotations about the named axes in order, in degrees.
+        "rotateXYZ" | "rotateXZY" | "rotateYXZ" | "rotateYZX" | "rotateZXY" | "rotateZYX" => {
+            let angles = value.as_tuple::<3>()?;
+            for axis in kind[6..].bytes().map(|b| usize::from(b - b'X')) {
+                m = gltf::mat_mul(&rotation(axis, angles[axis]), &m);
+            }
+        }
+        "orient" => {
+            let [w, x, y, z] = value.as_tuple::<4>()?;
+            let length = (w * w + x * x + y * y + z * z).sqrt();
+            let [w, x, y, z] = [w, x, y, z].map(|c| c / length);
+            m = [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w), 0.0],
+                [2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w), 0.0],
+                [2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ];
+        }
+        // Rows of a matrix that transforms row vectors, which are the
+        // columns of one that transforms column vectors.
+        "transform" => match value {
+            Value::Tuple(rows) if rows.len() == 4 => {
+                for (column, row) in m.iter_mut().zip(rows) {
+                    *column = row.as_tuple::<4>()?;
+                }
+            }
+            _ => return None,
+        },
+        _ => return None,
+    }
+    Some(m)
+}
+
+/// Rotation about `axis` by `degrees`, counterclockwise looking down it.
+fn rotation(axis: usize, degrees: f32) -> Mat4 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut m = IDENTITY;
+    m[a][a] = cos;
+    m[a][b] = sin;
+    m[b][a] = -sin;
+    m[b][b] = cos;
+    m
+}
+
+fn affine_inverse(m: &Mat4) -> Option<Mat4> {
+    let col = |i: usize| Vec3::new(m[i][0], m[i][1], m[i][2]);
+    let (a, b, c) = (col(0), col(1), col(2));
+    let det = a.dot(&b.cross(&c));
+    if det == 0.0 {
+        return None;
+    }
+    let rows = [b.cross(&c), c.cross(&a), a.cross(&b)].map(|row| row / det);
+    let t = col(3);
+    let mut out = IDENTITY;
+    for (i, row) in rows.iter().enumerate() {
+        for (j, column) in out.iter_mut().take(3).enumerate() {
+            column[i] = [row.x(), row.y(), row.z()][j];
+        }
+        out[3][i] = -row.dot(&t);
+    }
+    Some(out)
+}
+
+/// The `UsdPreviewSurface` shader feeding a material's surface output, or
+/// failing a connection, the first one under the material.
+fn surface_shader<'a>(material: &'a Prim, prims: &HashMap<String, &'a Prim>) -> Option<&'a Prim> {
+    let is_preview = |prim: &Prim| prim.type_name == "Shader" && prim.token("info:id") == Some("UsdPreviewSurface");
+    let connected = material
+        .get("outputs:surface.connect")
+        .and_then(Value::as_path)
+        .and_then(|target| prims.get(target.split('.').next().unwrap_or_default()))
+        .copied();
+    connected.filter(|it| is_preview(it)).or_else(|| {
+        let mut stack: Vec<&Prim> = material.children.iter().collect();
+        while let Some(prim) = stack.pop() {
+            if is_preview(prim) {
+                return Some(prim);
+            }
+            stack.extend(&prim.children);
+        }
+        None
+    })
+}
+
+/// Constant inputs of a `UsdPreviewSurface`; inputs driven by textures keep
+/// their fallback value.
+fn preview_surface(shader: &Prim) -> Material {
+    let input = |name: &str| shader.get(&format!("inputs:{name}"));
+    let mut material = PREVIEW_SURFACE;
+    if let Some(color) = input("diffuseColor").and_then(Value::as_tuple::<3>) {
+        material.base_color = color;
+    }
+    if let Some(metallic) = input("metallic").and_then(Value::as_f32) {
+        material.metallic = metallic.clamp(0.0, 1.0);
+    }
+    if let Some(roughness) = input("roughness").and_then(Value::as_f32) {
+        material.roughness = roughness.clamp(0.0, 1.0);
+    }
+    if let Some(emission) = input("emissiveColor").and_then(Value::as_tuple::<3>) {
+        material.emission = emission;
+    }
+    if let Some(ior) = input("ior").and_then(Value::as_f32) {
+        material.ior = ior.max(1.0);
+    }
+    material
+}
+
+/// A camera looking down its local -Z with +Y up. The vertical field of
+/// view follows from the aperture and focal length, which share a unit.
+fn camera(prim: &Prim, transform: &Mat4) -> SceneCamera {
+    let number = |name: &str, default: f32| prim.get(name).and_then(Value::as_f32).unwrap_or(default);
+    let aperture = number("verticalAperture", 15.2908);
+    let focal_length = number("focalLength", 50.0);
+    let vec = |[x, y, z]: [f32; 3]| Vec3::new(x, y, z);
+    let eye = vec(gltf::transform_point(transform, [0.0; 3]));
+    let forward = vec(gltf::transform_direction(transform, [0.0, 0.0, -1.0]));
+    let up = vec(gltf::transform_direction(transform, [0.0, 1.0, 0.0]));
+    SceneCamera {
+        lookfrom: eye,
+        lookat: eye + forward.normalized(),
+        vup: up.normalized(),
+        vfov: (2.0 * (aperture / (2.0 * focal_length)).atan()).to_degrees(),
+    }
+}
@@ -0,0 +1,63 @@
+//! Heterogeneous participating media, as dense density grids placed in the
+//! scene. The shader finds collisions in them by delta tracking and scatters
+//! isotropically.
+
+use super::{
+    gltf::{self, Mat4},
+    Instance, Visibility,
+};
+
+/// Must match `MAX_VOLUMES` in shader.wgsl.
+pub const MAX_VOLUMES: usize = 8;
+
+/// Largest grid edge in voxels. Larger grids are averaged down to fit, so
+/// that every grid fits one 3D texture stacked along z.
+pub const MAX_VOLUME_SIZE: u32 = 255;
+
+#[derive(Clone, Debug)]
+pub struct Volume {
+    /// Voxels along x, y and z.
+    pub size: [u32; 3],
+    /// One density per voxel, x varying fastest, then y.
+    pub densities: Vec<f32>,
+    /// Takes grid coordinates, in voxels from the corner of the first one,
+    /// to world space.
+    pub transform: Mat4,
+    /// Extinction per world unit of a density of 1.
+    pub density_scale: f32,
+    /// Fraction of extinction that scatters rather than absorbs, per channel.
+    pub albedo: [f32; 3],
+}
+
+impl Volume {
+    /// Largest extinction anywhere in the grid, per world unit.
+    pub fn majorant(&self) -> f32 {
+        self.densities.iter().fold(0.0f32, |max, &d| max.max(d)) * self.density_scale
+    }
+
+    /// World-space bounds of the grid box.
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        self.placement().world_bounds(([0.0; 3], self.size.map(|n| n as f32)))
+    }
+
+    /// Rows of the affine inverse of `transform`.
+    pub fn world_to_grid(&self) -> [[f32; 4]; 3] {
+        self.placement().world_to_object()
+    }
+
+    /// The grid placed like a mesh instance, for its transform helpers.
+    fn placement(&self) -> Instance {
+        Instance {
+            mesh: 0,
+            transform: self.transform,
+            material: None,
+            visibility: Visibility::ALL,
+        }
+    }
+
+    /// The grid moved by `m` after its own transform.
+    pub fn transformed(mut self, m: &Mat4) -> Self {
+        self.transform = gltf::mat_mul(m, &self.transform);
+        self
+    }
+}
@@ -0,0 +1,69 @@
+//! Plain-text XYZ point clouds: one point per line as `x y z`, optionally
+//! followed by an sRGB color `r g b` (0-255, or 0-1 if every channel in the
+//! file is at most 1) and then a normal `nx ny nz`. Lines starting with `#`
+//! or `//` are comments. Points get [`splat::default_radius`].
+
+use {
+    super::{
+        splat::{self, Splat, SplatShape},
+        Material, Scene,
+    },
+    crate::color,
+    anyhow::{bail, Context, Result},
+    std::{fs, path::Path},
+};
+
+pub fn load(path: &Path) -> Result<Scene> {
+    let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse(&text).with_context(|| format!("failed to import {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Scene> {
+    let mut splats = Vec::new();
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let values = line
+            .split(|c: char| c.is_ascii_whitespace() || c == ',' || c == ';')
+            .filter(|word| !word.is_empty())
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("line {}: invalid number", number + 1))?;
+        let triple = |i: usize| [values[i], values[i + 1], values[i + 2]];
+        let (color, normal) = match values.len() {
+            3 => (None, [0.0; 3]),
+            6 => (Some(triple(3)), [0.0; 3]),
+            9 => (Some(triple(3)), triple(6)),
+            n => bail!("line {}: expected 3, 6 or 9 values, found {n}", number + 1),
+        };
+        colors.push(color);
+        splats.push(Splat {
+            center: triple(0),
+            normal,
+            radius: 0.0,
+            color: None,
+            shape: SplatShape::Disk,
+            material: 0,
+        });
+    }
+
+    let scale = if colors.iter().flatten().flatten().all(|&c| c <= 1.0) { 1.0 } else { 255.0 };
+    let centers: Vec<[f32; 3]> = splats.iter().map(|s| s.center).collect();
+    let radius = splat::default_radius(&centers);
+    for (splat, color) in splats.iter_mut().zip(colors) {
+        splat.radius = radius;
+        splat.color = color.map(|rgb| rgb.map(|c| color::srgb_to_linear(c / scale)));
+    }
+    Ok(Scene {
+        materials: vec![Material {
+            base_color: [0.8; 3],
+            metallic: 0.0,
+            ..Material::default()
+        }],
+        splats,
+        ..Scene::default()
+    })
+}
@@ -0,0 +1,458 @@
+use crate::color;
+use crate::photometry;
+use crate::scene::UpAxis;
+use crate::shake::CameraShake;
+use crate::sun::{self, Sun};
+use anyhow::{bail, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub struct RenderSettings {
+    /// Scene file to render instead of the built-in spheres.
+    pub scene_path: Option<PathBuf>,
+    /// Size of one scene-file unit in world units.
+    pub unit_scale: f32,
+    /// Up axis the scene file was authored with.
+    pub up_axis: UpAxis,
+    /// Triangle count below which BVH nodes may stop splitting.
+    pub bvh_leaf_size: usize,
+    /// Bytes of GPU memory for triangle meshes, which are split into chunks
+    /// and streamed in and out by how many rays reach them and how near the
+    /// camera is, with coarse proxies drawn in place of the chunks that are
+    /// out; `None` uploads them all.
+    pub geometry_budget: Option<u64>,
+    /// Directory that decoded scene textures, and the layers and mips made
+    /// of them, are kept in between runs, by the files they come from.
+    pub texture_cache: Option<PathBuf>,
+    /// Name stamped into exported frames and used for their file names.
+    pub scene_name: String,
+    /// Directory exported frames are written to.
+    pub output_dir: PathBuf,
+    /// File name of exported frames within `output_dir`. `{scene}` becomes
+    /// the scene name and the run of `#` the zero-padded frame number; the
+    /// format's extension is added if the pattern has none.
+    pub output_pattern: String,
+    /// Number of the first exported frame.
+    pub start_frame: u32,
+    /// Frame rate recorded in exported frames along with their SMPTE
+    /// timecode.
+    pub fps: Option<f64>,
+    /// Stops under and over the current exposure of the outer frames of a
+    /// bracketed export.
+    pub bracket_stops: f32,
+    /// Composite a burn-in strip (scene, frame, spp, date) into exported frames.
+    pub burn_in: bool,
+    /// File format exported frames are written in.
+    pub image_format: ImageFormat,
+    /// How each sample is shaded.
+    pub integrator: Integrator,
+    /// Length of sky occlusion rays in world units; `None` is unbounded.
+    pub ao_distance: Option<f32>,
+    /// Reconstruction filter the sub-pixel offsets are drawn from.
+    pub filter: PixelFilter,
+    /// Filter radius in pixels; `None` uses the filter's default.
+    pub filter_radius: Option<f32>,
+    /// Shade primary diffuse hits from a hashed irradiance cache while the
+    /// accumulation is young.
+    pub irradiance_cache: bool,
+    /// Number of accumulated frames over which the cached preview fades out.
+    pub preview_frames: u32,
+    /// Edge length of an irradiance cache cell in world units.
+    pub cache_cell_size: f32,
+    /// Directional sun placed from a location and time, if requested.
+    pub sun: Option<Sun>,
+    /// Scale applied to the sky gradient, whose brightest point has radiance 1.
+    pub sky_radiance: f32,
+    /// Names of the post passes run on the displayed image, in order.
+    pub post_passes: Vec<String>,
+    /// Physical camera exposure; `None` displays radiance unscaled.
+    pub exposure_ev100: Option<f32>,
+    /// Color temperature in kelvin that the display renders as neutral.
+    pub white_balance: Option<f32>,
+    /// Lens radius in world units; 0 keeps the pinhole camera.
+    pub aperture: f32,
+    /// Distance to the plane in focus; `None` focuses on the look-at point.
+    pub focus_distance: Option<f32>,
+    /// Aperture blade count for polygonal bokeh; fewer than 3 is a circle.
+    pub aperture_blades: u32,
+    /// Rotation of the aperture polygon in degrees.
+    pub aperture_rotation: f32,
+    /// Continuously focus on the surfaces around the center of the screen,
+    /// weighted toward the center.
+    pub autofocus: bool,
+    /// Time constant of autofocus pulls in seconds.
+    pub focus_pull: f32,
+    /// Handheld noise applied to the camera on every turntable frame.
+    pub shake: Option<CameraShake>,
+    /// Enable backend validation layers and debug labels.
+    pub gpu_validation: bool,
+    /// Directory to record a wgpu API trace into.
+    pub wgpu_trace: Option<PathBuf>,
+    /// Render a few samples, write a state snapshot zip here and exit.
+    pub dump_state: Option<PathBuf>,
+    /// Hide console output and ignore all input except Escape.
+    pub present: bool,
+    /// Print the frame rate to the console every frame, with the traversal
+    /// counters under the `debug-stats` feature.
+    pub frame_stats: bool,
+    /// Orbit the camera around its target once every this many seconds.
+    pub turntable: Option<f32>,
+    /// Seed of the random numbers. Each animation frame, such as each step
+    /// of a turntable, derives its own from it and its frame number.
+    pub seed: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Integrator {
+    /// Full path tracing.
+    Path = 0,
+    /// Albedo lit by the sky along one occlusion ray per sample, for
+    /// judging composition in a handful of frames.
+    SkyAo = 1,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 2] = [Integrator::Path, Integrator::SkyAo];
+
+    /// Name taken by `--integrator`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Integrator::Path => "path",
+            Integrator::SkyAo => "sky-ao",
+        }
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "path" => Integrator::Path,
+            "sky-ao" => Integrator::SkyAo,
+            _ => bail!("unknown integrator `{s}` (expected path or sky-ao)"),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8-bit PNG with the display transform applied.
+    Png,
+    /// 16-bit PNG with the display transform applied.
+    Png16,
+    /// 16-bit uncompressed TIFF with the display transform applied.
+    Tiff16,
+    /// Linear floating-point radiance after exposure and white balance.
+    Pfm,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png | ImageFormat::Png16 => "png",
+            ImageFormat::Tiff16 => "tif",
+            ImageFormat::Pfm => "pfm",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "png" => ImageFormat::Png,
+            "png16" => ImageFormat::Png16,
+            "tiff16" => ImageFormat::Tiff16,
+            "pfm" => ImageFormat::Pfm,
+            _ => bail!("unknown image format `{s}` (expected png, png16, tiff16 or pfm)"),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PixelFilter {
+    Box = 0,
+    Tent = 1,
+    Gaussian = 2,
+    BlackmanHarris = 3,
+}
+
+impl PixelFilter {
+    pub fn default_radius(self) -> f32 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+            PixelFilter::BlackmanHarris => 2.0,
+        }
+    }
+}
+
+impl FromStr for PixelFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "box" => PixelFilter::Box,
+            "tent" => PixelFilter::Tent,
+            "gaussian" => PixelFilter::Gaussian,
+            "blackman-harris" => PixelFilter::BlackmanHarris,
+            _ => bail!("unknown pixel filter `{s}` (expected box, tent, gaussian or blackman-harris)"),
+        })
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            scene_path: None,
+            unit_scale: 1.0,
+            up_axis: UpAxis::Y,
+            bvh_leaf_size: 4,
+            geometry_budget: None,
+            texture_cache: None,
+            scene_name: "default".to_string(),
+            output_dir: PathBuf::from("."),
+            output_pattern: "{scene}_####".into(),
+            start_frame: 0,
+            fps: None,
+            bracket_stops: 2.0,
+            burn_in: false,
+            image_format: ImageFormat::Png,
+            integrator: Integrator::Path,
+            ao_distance: None,
+            filter: PixelFilter::Box,
+            filter_radius: None,
+            irradiance_cache: false,
+            preview_frames: 32,
+            cache_cell_size: 0.05,
+            sun: None,
+            sky_radiance: 1.0,
+            post_passes: Vec::new(),
+            exposure_ev100: None,
+            white_balance: None,
+            aperture: 0.0,
+            focus_distance: None,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
+            autofocus: false,
+            focus_pull: 0.3,
+            shake: None,
+            gpu_validation: false,
+            wgpu_trace: None,
+            dump_state: None,
+            present: false,
+            frame_stats: true,
+            turntable: None,
+            seed: 0,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn from_args() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut settings = Self::default();
+        let mut sun_location = None;
+        let mut sun_time = None;
+        let mut shake = CameraShake {
+            rotation: 0.0,
+            translation: 0.0,
+            frequency: 1.0,
+        };
+        let mut sun_irradiance = 3.0;
+        let mut sun_temperature = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--unit-scale" => settings.unit_scale = parse(&mut args, &arg)?,
+                "--up-axis" => settings.up_axis = parse(&mut args, &arg)?,
+                "--bvh-leaf-size" => settings.bvh_leaf_size = parse(&mut args, &arg)?,
+                "--geometry-budget" => {
+                    let mib: u64 = parse(&mut args, &arg)?;
+                    settings.geometry_budget = Some(mib << 20);
+                }
+                "--texture-cache" => settings.texture_cache = Some(value(&mut args, &arg)?.into()),
+                "--bracket-stops" => settings.bracket_stops = parse(&mut args, &arg)?,
+                "--burn-in" => settings.burn_in = true,
+                "--scene-name" => settings.scene_name = value(&mut args, &arg)?,
+                "--output-dir" => settings.output_dir = value(&mut args, &arg)?.into(),
+                "--output-pattern" => settings.output_pattern = value(&mut args, &arg)?,
+                "--start-frame" => settings.start_frame = parse(&mut args, &arg)?,
+                "--fps" => settings.fps = Some(parse_fps(&value(&mut args, &arg)?)?),
+                "--image-format" => settings.image_format = parse(&mut args, &arg)?,
+                "--integrator" => settings.integrator = parse(&mut args, &arg)?,
+                "--ao-distance" => settings.ao_distance = Some(parse(&mut args, &arg)?),
+                "--filter" => settings.filter = parse(&mut args, &arg)?,
+                "--filter-radius" => settings.filter_radius = Some(parse(&mut args, &arg)?),
+                "--irradiance-cache" => settings.irradiance_cache = true,
+                "--preview-frames" => settings.preview_frames = parse(&mut args, &arg)?,
+                "--cache-cell-size" => settings.cache_cell_size = parse(&mut args, &arg)?,
+                "--sun-location" => sun_location = Some(parse_location(&value(&mut args, &arg)?)?),
+                "--sun-time" => sun_time = Some(sun::parse_utc(&value(&mut args, &arg)?)?),
+                "--sun-irradiance" => sun_irradiance = parse(&mut args, &arg)?,
+                "--sun-illuminance" => {
+                    sun_irradiance = photometry::lux_to_irradiance(parse(&mut args, &arg)?)
+                }
+                "--sun-temperature" => sun_temperature = Some(parse(&mut args, &arg)?),
+                "--sky-radiance" => settings.sky_radiance = parse(&mut args, &arg)?,
+                "--sky-luminance" => {
+                    settings.sky_radiance = photometry::nits_to_radiance(parse(&mut args, &arg)?)
+                }
+                "--post" => {
+                    let names = value(&mut args, &arg)?;
+                    settings.post_passes = names.split(',').map(|name| name.trim().to_string()).collect();
+                }
+                "--exposure-ev100" => settings.exposure_ev100 = Some(parse(&mut args, &arg)?),
+                "--white-balance" => settings.white_balance = Some(parse(&mut args, &arg)?),
+                "--aperture" => settings.aperture = parse(&mut args, &arg)?,
+                "--focus-distance" => settings.focus_distance = Some(parse(&mut args, &arg)?),
+                "--aperture-blades" => settings.aperture_blades = parse(&mut args, &arg)?,
+                "--aperture-rotation" => settings.aperture_rotation = parse(&mut args, &arg)?,
+                "--autofocus" => settings.autofocus = true,
+                "--focus-pull" => settings.focus_pull = parse(&mut args, &arg)?,
+                "--shake-rotation" => shake.rotation = parse(&mut args, &arg)?,
+                "--shake-translation" => shake.translation = parse(&mut args, &arg)?,
+                "--shake-frequency" => shake.frequency = parse(&mut args, &arg)?,
+                "--gpu-validation" => settings.gpu_validation = true,
+                "--wgpu-trace" => settings.wgpu_trace = Some(value(&mut args, &arg)?.into()),
+                "--dump-state" => settings.dump_state = Some(value(&mut args, &arg)?.into()),
+                "--present" => settings.present = true,
+                "--no-frame-stats" => settings.frame_stats = false,
+                "--turntable" => settings.turntable = Some(parse(&mut args, &arg)?),
+                "--seed" => settings.seed = parse(&mut args, &arg)?,
+                _ if !arg.starts_with('-') && settings.scene_path.is_none() => {
+                    settings.scene_path = Some(arg.into())
+                }
+                _ => bail!("unrecognized argument `{arg}`"),
+            }
+        }
+
+        if let Some((latitude, longitude)) = sun_location {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |it| it.as_secs() as i64);
+            settings.sun = Some(Sun {
+                latitude,
+                longitude,
+                unix_time: sun_time.unwrap_or(now),
+                irradiance: sun_irradiance,
+                color: sun_temperature.map_or([1.0; 3], color::blackbody_rgb),
+            });
+        } else if sun_time.is_some() || sun_temperature.is_some() {
+            bail!("`--sun-time` and `--sun-temperature` require `--sun-location`");
+        }
+        if shake.rotation != 0.0 || shake.translation != 0.0 {
+            settings.shake = Some(shake);
+        }
+        if settings.wgpu_trace.is_some() && !cfg!(feature = "wgpu-trace") {
+            bail!("`--wgpu-trace` requires building with `--features wgpu-trace`");
+        }
+        if settings.bvh_leaf_size == 0 {
+            bail!("`--bvh-leaf-size` must be at least 1");
+        }
+        if settings.geometry_budget == Some(0) {
+            bail!("`--geometry-budget` expects a positive size in MiB");
+        }
+        if settings.burn_in && settings.image_format == ImageFormat::Pfm {
+            bail!("`--burn-in` cannot be stamped into linear `pfm` frames");
+        }
+        let runs = settings.output_pattern.split(|c| c != '#').filter(|run| !run.is_empty()).count();
+        if runs != 1 {
+            bail!("`--output-pattern` must contain one run of `#` for the frame number");
+        }
+        if let Some(extension) = Path::new(&settings.output_pattern).extension() {
+            let expected = settings.image_format.extension();
+            if !extension.eq_ignore_ascii_case(expected) {
+                bail!(
+                    "`--output-pattern` ends in `.{}` but frames are written as `.{expected}`",
+                    extension.to_string_lossy()
+                );
+            }
+        }
+        if !(settings.bracket_stops > 0.0 && settings.bracket_stops.is_finite()) {
+            bail!("`--bracket-stops` must be positive");
+        }
+        if settings.filter_radius.is_some_and(|radius| !(radius > 0.0 && radius.is_finite())) {
+            bail!("`--filter-radius` must be positive");
+        }
+        if settings.ao_distance.is_some_and(|distance| distance <= 0.0) {
+            bail!("`--ao-distance` must be positive");
+        }
+        if settings.turntable.is_some_and(|period| period <= 0.0) {
+            bail!("`--turntable` expects a positive period in seconds");
+        }
+        Ok(settings)
+    }
+
+    /// Scale applied to radiance before tone mapping.
+    pub fn exposure(&self) -> f32 {
+        self.exposure_ev100
+            .map_or(1.0, photometry::exposure_from_ev100)
+    }
+
+    pub fn white_balance(&self) -> color::Mat3 {
+        self.white_balance
+            .map_or(color::IDENTITY, color::white_balance)
+    }
+
+    pub fn filter_radius(&self) -> f32 {
+        self.filter_radius
+            .unwrap_or_else(|| self.filter.default_radius())
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next()
+        .with_context(|| format!("`{flag}` expects a value"))
+}
+
+/// Parses `LAT,LON` in degrees.
+fn parse_location(s: &str) -> Result<(f64, f64)> {
+    let invalid = || format!("invalid location `{s}` (expected LAT,LON in degrees)");
+    let (lat, lon) = s.split_once(',').with_context(invalid)?;
+    let lat: f64 = lat.trim().parse().with_context(invalid)?;
+    let lon: f64 = lon.trim().parse().with_context(invalid)?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        bail!(invalid());
+    }
+    Ok((lat, lon))
+}
+
+/// Parses a frame rate such as `24`, `29.97` or `30000/1001`.
+fn parse_fps(s: &str) -> Result<f64> {
+    let invalid = || format!("invalid frame rate `{s}` (expected e.g. 24, 29.97 or 30000/1001)");
+    let fps = match s.split_once('/') {
+        Some((num, den)) => {
+            num.trim().parse::<f64>().with_context(invalid)? / den.trim().parse::<f64>().with_context(invalid)?
+        }
+        None => s.trim().parse().with_context(invalid)?,
+    };
+    if !(fps.is_finite() && fps >= 1.0) {
+        bail!(invalid());
+    }
+    Ok(fps)
+}
+
+fn parse<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    let value = value(args, flag)?;
+    value
+        .parse()
+        .map_err(Into::into)
+        .with_context(|| format!("invalid value `{value}` for `{flag}`"))
+}
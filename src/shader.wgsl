@@ -3,17 +3,285 @@ struct CameraUniforms {
     u: vec3<f32>,
     v: vec3<f32>,
     w: vec3<f32>,
+    aperture: f32,
+    focus_distance: f32,
+    aperture_blades: u32,
+    aperture_rotation: f32,
 }
 
 struct Uniforms {
     width: u32,
     height: u32,
     frame_count: u32,
+    filter_type: u32,
+    filter_radius: f32,
+    cache_cell_size: f32,
+    preview_frames: u32,
+    light_count: u32,
     camera: CameraUniforms, 
+    sun: vec4<f32>,
+    exposure: f32,
+    sky_radiance: f32,
+    instance_count: u32,
+    shape_count: u32,
+    white_balance: mat3x3<f32>,
+    sun_color: vec3<f32>,
+    integrator: u32,
+    ao_distance: f32,
+    seed: u32,
+    animation_frame: u32,
+    volume_count: u32,
+    emitter_count: u32,
 }
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
 @group(0) @binding(1) var radiance_samples: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(2) var<storage, read_write> irradiance_cache: array<atomic<u32>>;
+@group(0) @binding(3) var<storage, read_write> debug_stats: DebugStats;
+// Sum of the odd-numbered samples only; the even half is the full sum minus it.
+@group(0) @binding(4) var odd_samples: texture_storage_2d<rgba32float, read_write>;
+// Depth along the view axis of the surfaces around the screen center, as
+// `focus_depth` measures it, or -1.
+@group(0) @binding(5) var<storage, read_write> focus_probe: f32;
+
+struct SceneMaterial {
+    base_color: vec3<f32>,
+    metallic: f32,
+    emission: vec3<f32>,
+    roughness: f32,
+    ior: f32,
+    // One of the `SHADING_` codes below.
+    kind: u32,
+    fuzz: f32,
+    // One more than the `sample_texture` slot multiplying `base_color`
+    // at triangle hits, or 0 for none.
+    base_color_texture: u32,
+    // Likewise for the tangent-space normal map bending the shading normal.
+    normal_texture: u32,
+    normal_scale: f32,
+}
+
+const SHADING_METALLIC_ROUGHNESS: u32 = 0u;
+const SHADING_LAMBERTIAN: u32 = 1u;
+const SHADING_METAL: u32 = 2u;
+const SHADING_DIELECTRIC: u32 = 3u;
+
+// Loaded scene: world-space positions with a packed color in w (see
+// `vertex_color`), triangles as three vertex indices and a material index,
+// and metallic-roughness materials. Curves use
+// four positions as control points with their radius in w, and a record of
+// first control point, shape, 0 and material among the triangles. Splats use
+// two vertices, the center with the radius in w and then the linear color,
+// the normal alongside the first, and a record of first vertex, shape,
+// whether the color is set and material.
+@group(0) @binding(6) var<storage, read> scene_vertices: array<vec4<f32>>;
+@group(0) @binding(7) var<storage, read> scene_triangles: array<vec4<u32>>;
+@group(0) @binding(8) var<storage, read> scene_materials: array<SceneMaterial>;
+// Per-vertex shading normals, zero for flat-shaded vertices.
+@group(0) @binding(9) var<storage, read> scene_normals: array<vec4<f32>>;
+// The top-level hierarchy over `instances` from node 0, then one hierarchy
+// per resident chunk of a mesh and one each over the curves and splats.
+@group(0) @binding(10) var<storage, read> bvh_nodes: array<BvhNode>;
+// The first `uniforms.light_count` entries are in use.
+@group(0) @binding(11) var<uniform> lights: array<AreaLight, MAX_LIGHTS>;
+// The first `uniforms.shape_count` entries are in use.
+@group(0) @binding(12) var<storage, read> shapes: array<Shape>;
+// Scene images such as light gobos and base color textures, one per layer,
+// with mip chains, in arrays of 256 to 4096 texels a side; see
+// `sample_texture`.
+@group(0) @binding(13) var textures_256: texture_2d_array<f32>;
+@group(0) @binding(14) var scene_sampler: sampler;
+// The first `uniforms.instance_count` entries are in use.
+@group(0) @binding(15) var<storage, read> instances: array<Instance>;
+// Ranges of these are named by `SHAPE_SDF` shapes.
+@group(0) @binding(16) var<uniform> sdf_nodes: array<SdfNode, MAX_SDF_NODES>;
+// The first `uniforms.volume_count` entries are in use.
+@group(0) @binding(17) var<uniform> volumes: array<Volume, MAX_VOLUMES>;
+// Densities of every volume, stacked along z.
+@group(0) @binding(18) var volume_densities: texture_3d<f32>;
+// Where each chunk placed by `instances` is, if resident, and how many rays
+// reached it while streaming.
+@group(0) @binding(19) var<storage, read_write> chunks: array<Chunk>;
+// Per-vertex tangents alongside `scene_normals`, with the handedness of the
+// bitangent in w, or zero for none.
+@group(0) @binding(20) var<storage, read> scene_tangents: array<vec4<f32>>;
+// The first `uniforms.emitter_count` entries are in use.
+@group(0) @binding(21) var<storage, read> emitters: array<Emitter>;
+// Per-vertex texture coordinates alongside `scene_normals`.
+@group(0) @binding(22) var<storage, read> scene_uvs: array<vec2<f32>>;
+@group(0) @binding(23) var textures_512: texture_2d_array<f32>;
+@group(0) @binding(24) var textures_1k: texture_2d_array<f32>;
+@group(0) @binding(25) var textures_2k: texture_2d_array<f32>;
+@group(0) @binding(26) var textures_4k: texture_2d_array<f32>;
+// The same arrays read as plain 8-bit values, for data such as normal maps,
+// whose layers have mips averaged as they are; see `sample_data_texture`.
+@group(0) @binding(27) var data_textures_256: texture_2d_array<f32>;
+@group(0) @binding(28) var data_textures_512: texture_2d_array<f32>;
+@group(0) @binding(29) var data_textures_1k: texture_2d_array<f32>;
+@group(0) @binding(30) var data_textures_2k: texture_2d_array<f32>;
+@group(0) @binding(31) var data_textures_4k: texture_2d_array<f32>;
+
+// Interior nodes have `count == 0` and their children at `first` and
+// `first + 1`; leaves hold `count` triangles starting at `first`.
+struct BvhNode {
+    min: vec3<f32>,
+    first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+// A placed mesh. `vec4(p, 1.0) * world_to_object` takes a point into object
+// space. `material` is `MATERIAL_SCENE` plus a material replacing the mesh's,
+// or 0. The hierarchy at `root` holds the `PRIMITIVE_` kind `primitive`.
+// Only rays of the `VISIBLE_` kinds in `visibility` hit it.
+struct Instance {
+    world_to_object: mat3x4<f32>,
+    chunk: u32,
+    material: u32,
+    primitive: u32,
+    visibility: u32,
+}
+
+// Kinds of rays, for instance visibility.
+const VISIBLE_CAMERA: u32 = 1u;
+const VISIBLE_SHADOW: u32 = 2u;
+const VISIBLE_DIFFUSE: u32 = 4u;
+const VISIBLE_SPECULAR: u32 = 8u;
+
+// Object-space bounds of a chunk, with its root node or `NOT_RESIDENT`.
+struct Chunk {
+    min: vec3<f32>,
+    root: u32,
+    max: vec3<f32>,
+    traffic: atomic<u32>,
+}
+
+const NOT_RESIDENT: u32 = 0xffffffffu;
+
+// Switched on when meshes are streamed through a memory budget, so that
+// rays count the chunks they reach.
+const STREAM_GEOMETRY: bool = false;
+
+const PRIMITIVE_TRIANGLES: u32 = 0u;
+const PRIMITIVE_CURVES: u32 = 1u;
+const PRIMITIVE_SPLATS: u32 = 2u;
+
+const SHAPE_SPHERE: u32 = 0u;
+const SHAPE_QUAD: u32 = 1u;
+const SHAPE_BOX: u32 = 2u;
+const SHAPE_PLANE: u32 = 3u;
+const SHAPE_SDF: u32 = 4u;
+// Kinds from here on are procedural primitives, in the order of
+// `procedural::kinds`.
+const SHAPE_PROCEDURAL: u32 = 5u;
+
+// `position` is a sphere center, quad corner, box minimum or point on a
+// plane; `u` a quad edge, box maximum or plane normal; `v` the second quad
+// edge. SDF shapes keep their bounds in `position` and `u`, and their first
+// node and node count in `v`. Procedural primitives hold whatever their
+// intersection function reads.
+// `material` is one of the fixed material codes below `MATERIAL_SCENE`, or a
+// scene material.
+struct Shape {
+    position: vec3<f32>,
+    kind: u32,
+    u: vec3<f32>,
+    radius: f32,
+    v: vec3<f32>,
+    material: u32,
+}
+
+const SDF_SPHERE: u32 = 0u;
+const SDF_BOX: u32 = 1u;
+const SDF_TORUS: u32 = 2u;
+const SDF_UNION: u32 = 3u;
+const SDF_INTERSECTION: u32 = 4u;
+const SDF_DIFFERENCE: u32 = 5u;
+
+// Must match `sdf::MAX_SDF_NODES`.
+const MAX_SDF_NODES: u32 = 64u;
+// Must match `sdf::SDF_STACK_SIZE`.
+const SDF_STACK_SIZE: u32 = 8u;
+
+// Shapes are stored in postfix order: primitives push their distance and
+// operations combine the top two. `size` holds a sphere radius in x, box half
+// size, torus major and minor radii, or the smoothness of an operation in x;
+// `axis` is the torus axis.
+struct SdfNode {
+    center: vec3<f32>,
+    kind: u32,
+    size: vec3<f32>,
+    axis: vec3<f32>,
+}
+
+// Must match `volume::MAX_VOLUMES`.
+const MAX_VOLUMES: u32 = 8u;
+// Tracking steps through one volume before a ray is let through.
+const MAX_VOLUME_STEPS: u32 = 1024u;
+
+// A density grid. `vec4(p, 1.0) * world_to_grid` takes a point to voxel
+// coordinates from the corner of the first voxel, whose density is at
+// `first_slice` in `volume_densities`. `majorant` is the largest extinction
+// in the grid, per world unit.
+struct Volume {
+    world_to_grid: mat3x4<f32>,
+    size: vec3<u32>,
+    first_slice: u32,
+    albedo: vec3<f32>,
+    majorant: f32,
+    density_scale: f32,
+}
+
+const LIGHT_RECT: u32 = 0u;
+const LIGHT_DISC: u32 = 1u;
+const LIGHT_SPHERE: u32 = 2u;
+const LIGHT_TUBE: u32 = 3u;
+
+// Must match `light::MAX_LIGHTS`.
+const MAX_LIGHTS: u32 = 64u;
+
+// `u` is the first edge of a rect, the unit normal of a disc or the axis of a
+// tube from its end at `position`; `v` is the second edge of a rect.
+struct AreaLight {
+    position: vec3<f32>,
+    shape: u32,
+    u: vec3<f32>,
+    radius: f32,
+    v: vec3<f32>,
+    // Texture slot + 1 tinting a rect, or 0 for none.
+    gobo: u32,
+    emission: vec3<f32>,
+}
+
+// A world-space triangle of an emissive scene material.
+struct Emitter {
+    position: vec3<f32>,
+    material: u32,
+    e1: vec3<f32>,
+    // Chance of picking this emitter or one before it.
+    cdf: f32,
+    e2: vec3<f32>,
+    probability: f32,
+}
+
+// Hits on area lights carry `MATERIAL_LIGHT + light index`.
+const MATERIAL_LIGHT: u32 = 0x80000000u;
+
+// Must be at least `bvh::MAX_DEPTH`.
+const BVH_STACK_SIZE: u32 = 64u;
+
+// Hits on scene materials carry `MATERIAL_SCENE + material index`; lower
+// values are the fixed materials of the demo scene.
+const MATERIAL_SCENE: u32 = 4u;
+
+// Switched on by the `debug-stats` cargo feature when the module is built.
+const DEBUG_STATS: bool = false;
+
+struct DebugStats {
+    rays: atomic<u32>,
+    node_visits: atomic<u32>,
+    primitive_tests: atomic<u32>,
+}
 
 struct VertexInput {
     @location(0) index: u32,
@@ -40,15 +308,25 @@ fn vs_main(input: VertexInput) -> VertexOutput {
 
 var<private> rng_state: u32;
 
-fn init_rng(pixel: vec2<u32>, frame: u32) {
-    rng_state = (pixel.x + pixel.y * uniforms.width) ^ (frame * 719393u);
+fn pcg_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Each pixel draws from a stream hashed from the job's seed, the animation
+// frame and the sample, so leftover noise does not stay fixed on screen from
+// frame to frame, and a frame renders the same however it was reached.
+fn init_rng(pixel: vec2<u32>, sample: u32) {
+    let stream = pcg_hash(uniforms.seed ^ pcg_hash(uniforms.animation_frame ^ pcg_hash(sample)));
+    rng_state = pcg_hash((pixel.x + pixel.y * uniforms.width) ^ stream);
 }
 
+// Uniform in [0, 1): the top 24 bits are exact in an f32, where dividing all
+// 32 by 2^32 - 1 rounded up to 1.0 and let `rand() < p` pass at p = 1.
 fn rand() -> f32 {
-    let state = rng_state * 747796405u + 2891336453u;
-    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
-    rng_state = (word >> 22u) ^ word;
-    return f32(rng_state) / 4294967295.0;
+    rng_state = pcg_hash(rng_state);
+    return f32(rng_state >> 8u) * 0x1p-24f;
 }
 
 fn random_in_unit_sphere() -> vec3<f32> {
@@ -61,6 +339,86 @@ fn random_in_unit_sphere() -> vec3<f32> {
     return normalize(vec3<f32>(rand(), rand(), rand()));
 }
 
+const FILTER_BOX: u32 = 0u;
+const FILTER_TENT: u32 = 1u;
+const FILTER_GAUSSIAN: u32 = 2u;
+const FILTER_BLACKMAN_HARRIS: u32 = 3u;
+
+const PI: f32 = 3.14159265359;
+
+fn sample_tent() -> f32 {
+    let u = 2.0 * rand();
+    if (u < 1.0) {
+        return sqrt(u) - 1.0;
+    }
+    return 1.0 - sqrt(2.0 - u);
+}
+
+fn blackman_harris(x: f32) -> f32 {
+    return 0.35875 + 0.48829 * cos(PI * x) + 0.14128 * cos(2.0 * PI * x) + 0.01168 * cos(3.0 * PI * x);
+}
+
+// The window peaks at 1 so plain rejection against it is exact.
+fn sample_blackman_harris() -> f32 {
+    var x = 0.0;
+    for (var i = 0; i < 16; i++) {
+        x = 2.0 * rand() - 1.0;
+        if (rand() < blackman_harris(x)) {
+            break;
+        }
+    }
+    return x;
+}
+
+// Draws a sub-pixel offset distributed like the reconstruction filter, so each
+// sample can be accumulated with equal weight (filter importance sampling).
+fn sample_pixel_filter() -> vec2<f32> {
+    let radius = uniforms.filter_radius;
+    switch uniforms.filter_type {
+        case FILTER_TENT: {
+            return radius * vec2<f32>(sample_tent(), sample_tent());
+        }
+        case FILTER_GAUSSIAN: {
+            // Isotropic Gaussian truncated to the filter radius, sampled in
+            // polar form through its inverse CDF.
+            let sigma = radius / 3.0;
+            let tail = 1.0 - exp(-radius * radius / (2.0 * sigma * sigma));
+            let r = sigma * sqrt(-2.0 * log(1.0 - rand() * tail));
+            let phi = 2.0 * PI * rand();
+            return r * vec2<f32>(cos(phi), sin(phi));
+        }
+        case FILTER_BLACKMAN_HARRIS: {
+            return radius * vec2<f32>(sample_blackman_harris(), sample_blackman_harris());
+        }
+        case FILTER_BOX, default: {
+            return radius * vec2<f32>(2.0 * rand() - 1.0, 2.0 * rand() - 1.0);
+        }
+    }
+}
+
+// Uniform point on the unit disk, or on a regular polygon inscribed in it when
+// the aperture has blades.
+fn sample_aperture() -> vec2<f32> {
+    let cam = uniforms.camera;
+    if (cam.aperture_blades < 3u) {
+        let r = sqrt(rand());
+        let phi = 2.0 * PI * rand();
+        return r * vec2<f32>(cos(phi), sin(phi));
+    }
+
+    let n = f32(cam.aperture_blades);
+    let side = min(floor(rand() * n), n - 1.0);
+    let a0 = cam.aperture_rotation + side * 2.0 * PI / n;
+    let a1 = a0 + 2.0 * PI / n;
+    var b0 = rand();
+    var b1 = rand();
+    if (b0 + b1 > 1.0) {
+        b0 = 1.0 - b0;
+        b1 = 1.0 - b1;
+    }
+    return b0 * vec2<f32>(cos(a0), sin(a0)) + b1 * vec2<f32>(cos(a1), sin(a1));
+}
+
 fn aces_tone_map(x: vec3<f32>) -> vec3<f32> {
     let a = 2.51;
     let b = 0.03;
@@ -70,6 +428,44 @@ fn aces_tone_map(x: vec3<f32>) -> vec3<f32> {
     return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
 }
 
+const IRRADIANCE_CACHE_CELLS: u32 = 262144u;
+const CACHE_SCALE: f32 = 256.0;
+const CACHE_MAX_RADIANCE: f32 = 64.0;
+
+// Cells are keyed on the quantized position, hashed as in the hash grid,
+// and the dominant normal axis so that surfaces meeting at a corner do not
+// share irradiance.
+fn cache_cell(p: vec3<f32>, n: vec3<f32>) -> u32 {
+    let c = vec3<i32>(floor(p / uniforms.cache_cell_size));
+    let a = abs(n);
+    var axis = 0u;
+    if (a.y > a.x && a.y >= a.z) { axis = 1u; } else if (a.z > a.x && a.z > a.y) { axis = 2u; }
+    if (dot(n, vec3<f32>(1.0)) < 0.0) { axis += 3u; }
+    let h = spatial_hash(c) ^ (axis * 2654435761u);
+    return h % IRRADIANCE_CACHE_CELLS;
+}
+
+fn cache_insert(cell: u32, radiance: vec3<f32>) {
+    let v = vec3<u32>(clamp(radiance, vec3<f32>(0.0), vec3<f32>(CACHE_MAX_RADIANCE)) * CACHE_SCALE);
+    atomicAdd(&irradiance_cache[cell * 4u], v.x);
+    atomicAdd(&irradiance_cache[cell * 4u + 1u], v.y);
+    atomicAdd(&irradiance_cache[cell * 4u + 2u], v.z);
+    atomicAdd(&irradiance_cache[cell * 4u + 3u], 1u);
+}
+
+fn cache_lookup(cell: u32) -> vec3<f32> {
+    let count = atomicLoad(&irradiance_cache[cell * 4u + 3u]);
+    if (count == 0u) {
+        return vec3<f32>(0.0);
+    }
+    let sum = vec3<f32>(
+        f32(atomicLoad(&irradiance_cache[cell * 4u])),
+        f32(atomicLoad(&irradiance_cache[cell * 4u + 1u])),
+        f32(atomicLoad(&irradiance_cache[cell * 4u + 2u])),
+    );
+    return sum / (CACHE_SCALE * f32(count));
+}
+
 struct Ray {
     origin: vec3<f32>,
     direction: vec3<f32>,
@@ -79,11 +475,29 @@ struct HitRecord {
     t: f32,
     p: vec3<f32>,
     normal: vec3<f32>,
+    // Normal of the surface itself on the side of `normal`, which smooth
+    // shading and normal maps may tilt away from it. Rays leave on this
+    // side.
+    geo_normal: vec3<f32>,
     mat_type: u32,
     hit: bool,
+    // Replaces the material's base color where `color.w` is 1.
+    color: vec4<f32>,
+    // Texture coordinates, set by triangle hits only.
+    uv: vec2<f32>,
+    textured: bool,
+    // Set by triangle hits, the only surfaces `sample_emitters` samples.
+    is_triangle: bool,
+    // Tangent for normal maps, with `cross(normal, tangent.xyz) * tangent.w`
+    // pointing up the texture, or zero where there is none.
+    tangent: vec4<f32>,
+    // Width of the ray cone at the hit in texture coordinates, which picks
+    // the mip level of texture lookups.
+    uv_width: f32,
 }
 
 fn hit_sphere(center: vec3<f32>, radius: f32, r: Ray, t_min: f32, t_max: f32, mat_type: u32) -> HitRecord {
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
     var rec: HitRecord;
     rec.hit = false;
     
@@ -117,75 +531,1306 @@ fn hit_sphere(center: vec3<f32>, radius: f32, r: Ray, t_min: f32, t_max: f32, ma
     return rec;
 }
 
-fn world_hit(r: Ray) -> HitRecord {
+// A plane through `p` with normal `n`, facing the incoming ray.
+fn hit_plane(p: vec3<f32>, n: vec3<f32>, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    var rec: HitRecord;
+    rec.hit = false;
+    let denom = dot(n, r.direction);
+    if (abs(denom) < 1e-12) {
+        return rec;
+    }
+    let t = dot(n, p - r.origin) / denom;
+    if (t <= t_min || t >= t_max) {
+        return rec;
+    }
+    rec.t = t;
+    rec.p = r.origin + t * r.direction;
+    rec.normal = normalize(select(n, -n, denom > 0.0));
+    rec.hit = true;
+    return rec;
+}
+
+// Slab test. The normal points out of the box, also when the ray starts
+// inside it, so glass boxes refract like spheres.
+fn hit_box(lo: vec3<f32>, hi: vec3<f32>, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    var rec: HitRecord;
+    rec.hit = false;
+    let inv_dir = 1.0 / r.direction;
+    let t0 = (lo - r.origin) * inv_dir;
+    let t1 = (hi - r.origin) * inv_dir;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let t_near = max(max(near.x, near.y), near.z);
+    let t_far = min(min(far.x, far.y), far.z);
+    if (t_near > t_far) {
+        return rec;
+    }
+    // Entering through the face that was crossed last, or leaving through the
+    // one crossed first.
+    var t = t_near;
+    var axis = select(select(2u, 1u, near.y == t_near), 0u, near.x == t_near);
+    var outward = -sign(r.direction);
+    if (t <= t_min) {
+        t = t_far;
+        axis = select(select(2u, 1u, far.y == t_far), 0u, far.x == t_far);
+        outward = sign(r.direction);
+    }
+    if (t <= t_min || t >= t_max) {
+        return rec;
+    }
+    rec.t = t;
+    rec.p = r.origin + t * r.direction;
+    rec.normal = vec3<f32>(0.0);
+    rec.normal[axis] = outward[axis];
+    rec.hit = true;
+    return rec;
+}
+
+fn sdf_primitive(primitive: SdfNode, p: vec3<f32>) -> f32 {
+    let q = p - primitive.center;
+    if (primitive.kind == SDF_SPHERE) {
+        return length(q) - primitive.size.x;
+    }
+    if (primitive.kind == SDF_BOX) {
+        let d = abs(q) - primitive.size;
+        return length(max(d, vec3<f32>(0.0))) + min(max(d.x, max(d.y, d.z)), 0.0);
+    }
+    let height = dot(q, primitive.axis);
+    let ring = length(q - height * primitive.axis) - primitive.size.x;
+    return length(vec2<f32>(ring, height)) - primitive.size.y;
+}
+
+// Polynomial smooth minimum, which undercuts `min` by at most `k / 4`.
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if (k <= 0.0) {
+        return min(a, b);
+    }
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+
+fn sdf_distance(shape: Shape, p: vec3<f32>) -> f32 {
+    var stack: array<f32, SDF_STACK_SIZE>;
+    var depth = 0u;
+    let first = u32(shape.v.x);
+    for (var i = first; i < first + u32(shape.v.y); i++) {
+        let node = sdf_nodes[i];
+        if (node.kind < SDF_UNION) {
+            stack[depth] = sdf_primitive(node, p);
+            depth++;
+            continue;
+        }
+        depth--;
+        let a = stack[depth - 1u];
+        let b = stack[depth];
+        let k = node.size.x;
+        // Intersection and difference are unions of the complements.
+        if (node.kind == SDF_UNION) {
+            stack[depth - 1u] = smooth_min(a, b, k);
+        } else if (node.kind == SDF_INTERSECTION) {
+            stack[depth - 1u] = -smooth_min(-a, -b, k);
+        } else {
+            stack[depth - 1u] = -smooth_min(-a, b, k);
+        }
+    }
+    return stack[0];
+}
+
+const SDF_MAX_STEPS: u32 = 128u;
+const SDF_EPSILON: f32 = 1e-4;
+
+// Sphere tracing within the bounds of the shape. Rays that start inside
+// march towards the surface from within, and the normal always points out, so
+// SDF glass refracts like spheres.
+fn hit_sdf(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    var rec: HitRecord;
+    rec.hit = false;
+    let inv_dir = 1.0 / r.direction;
+    let t0 = (shape.position - r.origin) * inv_dir;
+    let t1 = (shape.u - r.origin) * inv_dir;
+    var t = max(max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z)), t_min);
+    let t_far = min(min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z)), t_max);
+    if (t > t_far) {
+        return rec;
+    }
+    let speed = length(r.direction);
+    let side = select(1.0, -1.0, sdf_distance(shape, r.origin + t * r.direction) < 0.0);
+    for (var step = 0u; step < SDF_MAX_STEPS; step++) {
+        let d = side * sdf_distance(shape, r.origin + t * r.direction);
+        if (d < SDF_EPSILON) {
+            rec.t = t;
+            rec.p = r.origin + t * r.direction;
+            // Tetrahedral central differences.
+            let e = vec2<f32>(1.0, -1.0) * 1e-4;
+            rec.normal = normalize(
+                e.xyy * sdf_distance(shape, rec.p + e.xyy) + e.yyx * sdf_distance(shape, rec.p + e.yyx)
+                    + e.yxy * sdf_distance(shape, rec.p + e.yxy) + e.xxx * sdf_distance(shape, rec.p + e.xxx)
+            );
+            rec.hit = true;
+            return rec;
+        }
+        t += d / speed;
+        if (t >= t_far) {
+            break;
+        }
+    }
+    return rec;
+}
+
+// Stand-in replaced by `procedural::hit_function` with the intersection
+// functions of the scene's procedural primitives.
+fn hit_procedural(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    var rec: HitRecord;
+    rec.hit = false;
+    return rec;
+}
+
+fn hit_shape(shape: Shape, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    if (shape.kind == SHAPE_SPHERE) {
+        var rec = hit_sphere(shape.position, shape.radius, r, t_min, t_max, shape.material);
+        rec.geo_normal = rec.normal;
+        return rec;
+    }
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
+    var rec: HitRecord;
+    if (shape.kind >= SHAPE_PROCEDURAL) {
+        rec = hit_procedural(shape, r, t_min, t_max);
+    } else if (shape.kind == SHAPE_SDF) {
+        rec = hit_sdf(shape, r, t_min, t_max);
+    } else if (shape.kind == SHAPE_BOX) {
+        rec = hit_box(shape.position, shape.u, r, t_min, t_max);
+    } else if (shape.kind == SHAPE_PLANE) {
+        rec = hit_plane(shape.position, shape.u, r, t_min, t_max);
+    } else {
+        let n = cross(shape.u, shape.v);
+        rec = hit_plane(shape.position, n, r, t_min, t_max);
+        // Coordinates of the hit along the (not necessarily perpendicular)
+        // edges.
+        let q = rec.p - shape.position;
+        let w = n / dot(n, n);
+        let a = dot(w, cross(q, shape.v));
+        let b = dot(w, cross(shape.u, q));
+        rec.hit = rec.hit && a >= 0.0 && a <= 1.0 && b >= 0.0 && b <= 1.0;
+    }
+    // Shapes are not smoothed.
+    rec.geo_normal = rec.normal;
+    rec.mat_type = shape.material;
+    return rec;
+}
+
+// Moller-Trumbore intersection. The normal faces the incoming ray, so meshes
+// render two-sided.
+// Linear color packed into the w of a mesh vertex by `pack_color` in
+// render.rs, as squared 8-bit channels; w is negative for none.
+fn vertex_color(w: f32) -> vec3<f32> {
+    let packed = u32(w);
+    let root = vec3<f32>(vec3<u32>(packed >> 16u, packed >> 8u, packed) & vec3<u32>(255u)) / 255.0;
+    return root * root;
+}
+
+fn hit_triangle(index: u32, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
+    var rec: HitRecord;
+    rec.hit = false;
+
+    let tri = scene_triangles[index];
+    let p0 = scene_vertices[tri.x].xyz;
+    let e1 = scene_vertices[tri.y].xyz - p0;
+    let e2 = scene_vertices[tri.z].xyz - p0;
+
+    let pvec = cross(r.direction, e2);
+    let det = dot(e1, pvec);
+    if (abs(det) < 1e-12) {
+        return rec;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = r.origin - p0;
+    let u = dot(tvec, pvec) * inv_det;
+    if (u < 0.0 || u > 1.0) {
+        return rec;
+    }
+    let qvec = cross(tvec, e1);
+    let v = dot(r.direction, qvec) * inv_det;
+    if (v < 0.0 || u + v > 1.0) {
+        return rec;
+    }
+    let t = dot(e2, qvec) * inv_det;
+    if (t <= t_min || t >= t_max) {
+        return rec;
+    }
+
+    rec.t = t;
+    rec.p = r.origin + t * r.direction;
+    rec.normal = normalize(cross(e1, e2));
+    // The normal on the side the tangents were made for.
+    var front = rec.normal;
+    if (dot(rec.normal, r.direction) > 0.0) {
+        rec.normal = -rec.normal;
+    }
+    rec.geo_normal = rec.normal;
+    // Smooth shading interpolates the vertex normals, kept on the side of the
+    // surface the ray arrived from.
+    let n0 = scene_normals[tri.x].xyz;
+    let n1 = scene_normals[tri.y].xyz;
+    let n2 = scene_normals[tri.z].xyz;
+    if (dot(n0, n0) > 0.0 && dot(n1, n1) > 0.0 && dot(n2, n2) > 0.0) {
+        let shading = (1.0 - u - v) * n0 + u * n1 + v * n2;
+        if (dot(shading, shading) > 0.0) {
+            front = normalize(shading);
+            rec.normal = select(front, -front, dot(front, rec.normal) < 0.0);
+        }
+    }
+    let t0 = scene_tangents[tri.x];
+    let t1 = scene_tangents[tri.y];
+    let t2 = scene_tangents[tri.z];
+    if (t0.w != 0.0 && t1.w != 0.0 && t2.w != 0.0) {
+        // The handedness flips with the normal, so that the bitangent still
+        // points up the texture from the back.
+        let w = select(t0.w, -t0.w, dot(front, rec.normal) < 0.0);
+        rec.tangent = vec4<f32>((1.0 - u - v) * t0.xyz + u * t1.xyz + v * t2.xyz, w);
+    }
+    let w = vec3<f32>(scene_vertices[tri.x].w, scene_vertices[tri.y].w, scene_vertices[tri.z].w);
+    if (all(w >= vec3<f32>(0.0))) {
+        let color = (1.0 - u - v) * vertex_color(w.x) + u * vertex_color(w.y) + v * vertex_color(w.z);
+        rec.color = vec4<f32>(color, 1.0);
+    }
+    let uv0 = scene_uvs[tri.x];
+    let uv1 = scene_uvs[tri.y] - uv0;
+    let uv2 = scene_uvs[tri.z] - uv0;
+    rec.uv = uv0 + u * uv1 + v * uv2;
+    // Texture coordinates per unit of object space for now, the square root
+    // of the ratio of the triangle's areas; `hit_instance` widens it to the
+    // ray cone.
+    rec.uv_width = sqrt(abs(uv1.x * uv2.y - uv2.x * uv1.y) / max(length(cross(e1, e2)), 1e-20));
+    rec.textured = true;
+    rec.is_triangle = true;
+    rec.mat_type = MATERIAL_SCENE + tri.w;
+    rec.hit = true;
+    return rec;
+}
+
+const CURVE_ROUND: u32 = 0u;
+const CURVE_RIBBON: u32 = 1u;
+// Most linear pieces a curve is split into per ray.
+const CURVE_MAX_SEGMENTS: u32 = 16u;
+
+fn bezier(p0: vec3<f32>, p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>, u: f32) -> vec3<f32> {
+    let s = 1.0 - u;
+    return s * s * s * p0 + 3.0 * s * s * u * p1 + 3.0 * s * u * u * p2 + u * u * u * p3;
+}
+
+fn bezier_tangent(p0: vec3<f32>, p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>, u: f32) -> vec3<f32> {
+    let s = 1.0 - u;
+    return 3.0 * (s * s * (p1 - p0) + 2.0 * s * u * (p2 - p1) + u * u * (p3 - p2));
+}
+
+// Cubic Bezier curve, after pbrt: in a frame where the ray runs along +z
+// from the origin, the curve is split into straight pieces fine enough for
+// its width, and the ray hits where it passes within the radius of the
+// nearest point of a piece. Ribbons face the ray; round curves bend the
+// normal across their width like a tube.
+fn hit_curve(index: u32, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
+    var rec: HitRecord;
+    rec.hit = false;
+
+    let curve = scene_triangles[index];
+    let c0 = scene_vertices[curve.x];
+    let c1 = scene_vertices[curve.x + 1u].xyz;
+    let c2 = scene_vertices[curve.x + 2u].xyz;
+    let c3 = scene_vertices[curve.x + 3u];
+    let radius0 = c0.w;
+    let radius1 = c3.w;
+    let max_radius = max(radius0, radius1);
+
+    let scale = length(r.direction);
+    let dz = r.direction / scale;
+    let dx = normalize(cross(dz, select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(dz.y) > 0.9)));
+    let dy = cross(dz, dx);
+    let to_ray = transpose(mat3x3<f32>(dx, dy, dz));
+    let p0 = to_ray * (c0.xyz - r.origin);
+    let p1 = to_ray * (c1 - r.origin);
+    let p2 = to_ray * (c2 - r.origin);
+    let p3 = to_ray * (c3.xyz - r.origin);
+
+    let lo = min(min(p0, p1), min(p2, p3)) - max_radius;
+    let hi = max(max(p0, p1), max(p2, p3)) + max_radius;
+    if (lo.x > 0.0 || hi.x < 0.0 || lo.y > 0.0 || hi.y < 0.0 || hi.z < t_min * scale || lo.z > t_max * scale) {
+        return rec;
+    }
+
+    // Enough pieces to keep the chord error below a tenth of the radius.
+    let flatness = max(length((p0 - 2.0 * p1 + p2).xy), length((p1 - 2.0 * p2 + p3).xy));
+    let segments = clamp(u32(ceil(sqrt(1.0607 * flatness / (0.1 * max(max_radius, 1e-8))))), 1u, CURVE_MAX_SEGMENTS);
+
+    var closest_t = t_max;
+    var hit_u = 0.0;
+    var hit_offset = vec2<f32>(0.0);
+    var hit_radius = 0.0;
+    var a = p0;
+    for (var i = 1u; i <= segments; i++) {
+        let u1 = f32(i) / f32(segments);
+        let b = bezier(p0, p1, p2, p3, u1);
+        let edge = (b - a).xy;
+        let length2 = dot(edge, edge);
+        let w = select(0.0, clamp(-dot(a.xy, edge) / length2, 0.0, 1.0), length2 > 0.0);
+        let u = (f32(i - 1u) + w) / f32(segments);
+        a = b;
+
+        let pc = bezier(p0, p1, p2, p3, u);
+        let radius = mix(radius0, radius1, u);
+        let distance2 = dot(pc.xy, pc.xy);
+        if (distance2 > radius * radius) {
+            continue;
+        }
+        var z = pc.z;
+        if (curve.y == CURVE_ROUND) {
+            z -= sqrt(radius * radius - distance2);
+        }
+        let t = z / scale;
+        if (t > t_min && t < closest_t) {
+            closest_t = t;
+            hit_u = u;
+            hit_offset = -pc.xy;
+            hit_radius = radius;
+            rec.hit = true;
+        }
+    }
+    if (!rec.hit) {
+        return rec;
+    }
+
+    let tangent = normalize(bezier_tangent(c0.xyz, c1, c2, c3.xyz, hit_u));
+    let across = dz - dot(dz, tangent) * tangent;
+    var facing = -dz;
+    if (dot(across, across) > 1e-12) {
+        facing = -normalize(across);
+    }
+    rec.normal = facing;
+    rec.geo_normal = facing;
+    if (curve.y == CURVE_ROUND && hit_radius > 0.0) {
+        let side = cross(tangent, facing);
+        let s = clamp(dot(hit_offset.x * dx + hit_offset.y * dy, side) / hit_radius, -1.0, 1.0);
+        rec.normal = normalize(facing * sqrt(1.0 - s * s) + side * s);
+    }
+    rec.t = closest_t;
+    rec.p = r.origin + closest_t * r.direction;
+    rec.mat_type = MATERIAL_SCENE + curve.w;
+    return rec;
+}
+
+const SPLAT_DISK: u32 = 0u;
+const SPLAT_GAUSSIAN: u32 = 1u;
+
+// Disk splat facing its normal, or the ray if it has none. Gaussian splats
+// are hit with probability exp(-d^2 / 2 sigma^2) at distance d from the
+// center, with sigma a third of the radius, so they blend as more samples
+// are taken.
+fn hit_splat(index: u32, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
+    var rec: HitRecord;
+    rec.hit = false;
+
+    let splat = scene_triangles[index];
+    let center = scene_vertices[splat.x];
+    let radius = center.w;
+    var normal = scene_normals[splat.x].xyz;
+    if (dot(normal, normal) == 0.0) {
+        normal = r.direction;
+    }
+    let denom = dot(r.direction, normal);
+    if (abs(denom) < 1e-12) {
+        return rec;
+    }
+    let t = dot(center.xyz - r.origin, normal) / denom;
+    if (t <= t_min || t >= t_max) {
+        return rec;
+    }
+    let q = r.origin + t * r.direction - center.xyz;
+    let distance2 = dot(q, q);
+    if (distance2 > radius * radius) {
+        return rec;
+    }
+    if (splat.y == SPLAT_GAUSSIAN && rand() > exp(-4.5 * distance2 / (radius * radius))) {
+        return rec;
+    }
+    rec.t = t;
+    rec.p = center.xyz + q;
+    rec.normal = normalize(select(normal, -normal, denom > 0.0));
+    rec.geo_normal = rec.normal;
+    rec.mat_type = MATERIAL_SCENE + splat.w;
+    if (splat.z != 0u) {
+        rec.color = vec4<f32>(scene_vertices[splat.x + 1u].xyz, 1.0);
+    }
+    rec.hit = true;
+    return rec;
+}
+
+// Area lights are visible and cast shadows. Rects and discs are hit from both
+// sides; `light_emission` leaves the back black.
+fn hit_light(index: u32, r: Ray, t_min: f32, t_max: f32) -> HitRecord {
+    let light = lights[index];
+    if (light.shape == LIGHT_SPHERE) {
+        var rec = hit_sphere(light.position, light.radius, r, t_min, t_max, MATERIAL_LIGHT + index);
+        rec.geo_normal = rec.normal;
+        return rec;
+    }
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.primitive_tests, 1u); }
+    var rec: HitRecord;
+    rec.hit = false;
+    rec.mat_type = MATERIAL_LIGHT + index;
+
+    if (light.shape == LIGHT_TUBE) {
+        let height = length(light.u);
+        let axis = light.u / height;
+        let oc = r.origin - light.position;
+        let d_perp = r.direction - dot(r.direction, axis) * axis;
+        let o_perp = oc - dot(oc, axis) * axis;
+        let a = dot(d_perp, d_perp);
+        let b = 2.0 * dot(d_perp, o_perp);
+        let c = dot(o_perp, o_perp) - light.radius * light.radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if (a < 1e-12 || discriminant < 0.0) {
+            return rec;
+        }
+        let root = sqrt(discriminant);
+        for (var i = 0; i < 2; i++) {
+            let t = (-b + select(-root, root, i == 1)) / (2.0 * a);
+            let h = dot(oc + t * r.direction, axis);
+            if (t > t_min && t < t_max && h >= 0.0 && h <= height) {
+                rec.t = t;
+                rec.p = r.origin + t * r.direction;
+                rec.normal = (o_perp + t * d_perp) / light.radius;
+                rec.geo_normal = rec.normal;
+                rec.hit = true;
+                return rec;
+            }
+        }
+        return rec;
+    }
+
+    let normal = select(light.u, normalize(cross(light.u, light.v)), light.shape == LIGHT_RECT);
+    let denom = dot(r.direction, normal);
+    if (abs(denom) < 1e-12) {
+        return rec;
+    }
+    let t = dot(light.position - r.origin, normal) / denom;
+    if (t <= t_min || t >= t_max) {
+        return rec;
+    }
+    let q = r.origin + t * r.direction - light.position;
+    if (light.shape == LIGHT_RECT) {
+        let a = dot(q, light.u) / dot(light.u, light.u);
+        let b = dot(q, light.v) / dot(light.v, light.v);
+        if (a < 0.0 || a > 1.0 || b < 0.0 || b > 1.0) {
+            return rec;
+        }
+    } else if (dot(q, q) > light.radius * light.radius) {
+        return rec;
+    }
+    rec.t = t;
+    rec.p = light.position + q;
+    rec.normal = normal;
+    rec.geo_normal = normal;
+    rec.hit = true;
+    return rec;
+}
+
+// Emission of a rect at `uv`, its coordinates along `u` and `v` in [0, 1],
+// averaged over `uv_width` of them.
+fn rect_emission(light: AreaLight, uv: vec2<f32>, uv_width: f32) -> vec3<f32> {
+    if (light.gobo == 0u) {
+        return light.emission;
+    }
+    return light.emission * sample_texture(light.gobo - 1u, uv, uv_width).rgb;
+}
+
+// Radiance leaving a light towards `-dir` at point `p` with `normal`, seen
+// through a ray cone `width` wide there.
+fn light_emission(index: u32, p: vec3<f32>, normal: vec3<f32>, dir: vec3<f32>, width: f32) -> vec3<f32> {
+    let light = lights[index];
+    let one_sided = light.shape == LIGHT_RECT || light.shape == LIGHT_DISC;
+    if (one_sided && dot(dir, normal) >= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    if (light.shape == LIGHT_RECT) {
+        let q = p - light.position;
+        let uv_width = width / (min(length(light.u), length(light.v)) * max(abs(dot(dir, normal)), 0.05));
+        return rect_emission(light, vec2<f32>(dot(q, light.u) / dot(light.u, light.u), dot(q, light.v) / dot(light.v, light.v)), uv_width);
+    }
+    return light.emission;
+}
+
+// First surface seen from the camera, recorded by `ray_color` for the cache.
+struct PrimaryHit {
+    diffuse: bool,
+    p: vec3<f32>,
+    normal: vec3<f32>,
+    albedo: vec3<f32>,
+}
+
+var<private> primary: PrimaryHit;
+
+// Ray cone around the current path segment: its width where the segment
+// starts and its growth per unit of distance, after Akenine-Möller et al.,
+// "Texture Level of Detail Strategies for Real-Time Ray Tracing".
+var<private> ray_cone: vec2<f32>;
+
+// Width of the ray cone `distance` along the current segment.
+fn cone_width(distance: f32) -> f32 {
+    return ray_cone.x + ray_cone.y * distance;
+}
+
+// Mip level of a texture `size` texels wide whose texels match `uv_width`.
+fn texture_lod(uv_width: f32, size: f32) -> f32 {
+    return log2(max(uv_width * size, 1.0));
+}
+
+// The scene texture in `slot`, its layer shifted left by 3 above the index of
+// its array, at `uv` averaged over `uv_width` texture coordinates.
+fn sample_texture(slot: u32, uv: vec2<f32>, uv_width: f32) -> vec4<f32> {
+    let layer = slot >> 3u;
+    switch (slot & 7u) {
+        case 0u: { return textureSampleLevel(textures_256, scene_sampler, uv, layer, texture_lod(uv_width, 256.0)); }
+        case 1u: { return textureSampleLevel(textures_512, scene_sampler, uv, layer, texture_lod(uv_width, 512.0)); }
+        case 2u: { return textureSampleLevel(textures_1k, scene_sampler, uv, layer, texture_lod(uv_width, 1024.0)); }
+        case 3u: { return textureSampleLevel(textures_2k, scene_sampler, uv, layer, texture_lod(uv_width, 2048.0)); }
+        default: { return textureSampleLevel(textures_4k, scene_sampler, uv, layer, texture_lod(uv_width, 4096.0)); }
+    }
+}
+
+// As `sample_texture`, for a scene texture that holds data, whose values are
+// returned as stored rather than decoded from sRGB.
+fn sample_data_texture(slot: u32, uv: vec2<f32>, uv_width: f32) -> vec4<f32> {
+    let layer = slot >> 3u;
+    switch (slot & 7u) {
+        case 0u: { return textureSampleLevel(data_textures_256, scene_sampler, uv, layer, texture_lod(uv_width, 256.0)); }
+        case 1u: { return textureSampleLevel(data_textures_512, scene_sampler, uv, layer, texture_lod(uv_width, 512.0)); }
+        case 2u: { return textureSampleLevel(data_textures_1k, scene_sampler, uv, layer, texture_lod(uv_width, 1024.0)); }
+        case 3u: { return textureSampleLevel(data_textures_2k, scene_sampler, uv, layer, texture_lod(uv_width, 2048.0)); }
+        default: { return textureSampleLevel(data_textures_4k, scene_sampler, uv, layer, texture_lod(uv_width, 4096.0)); }
+    }
+}
+
+// Center-weighted depth along the view axis for autofocus: the mean over
+// the screen center and two rings of probes around it, offset in the units
+// of `cam.u` and `cam.v`, weighted toward the center. Probes that miss are left out, and
+// -1 means every one missed.
+fn focus_depth(cam: CameraUniforms) -> f32 {
+    var probes = array<vec3<f32>, 9>(
+        vec3<f32>(0.0, 0.0, 4.0),
+        vec3<f32>(0.05, 0.0, 2.0),
+        vec3<f32>(-0.05, 0.0, 2.0),
+        vec3<f32>(0.0, 0.05, 2.0),
+        vec3<f32>(0.0, -0.05, 2.0),
+        vec3<f32>(0.07, 0.07, 1.0),
+        vec3<f32>(-0.07, 0.07, 1.0),
+        vec3<f32>(0.07, -0.07, 1.0),
+        vec3<f32>(-0.07, -0.07, 1.0),
+    );
+    var depth = 0.0;
+    var weight = 0.0;
+    for (var i = 0u; i < 9u; i++) {
+        let probe = probes[i];
+        let dir = cam.w + cam.u * probe.x + cam.v * probe.y;
+        let hit = world_hit(Ray(cam.origin, normalize(dir)), VISIBLE_CAMERA);
+        if (hit.hit) {
+            // `w` has unit length, so depth along it is t over |dir|.
+            depth += probe.z * hit.t / length(dir);
+            weight += probe.z;
+        }
+    }
+    return select(-1.0, depth / weight, weight > 0.0);
+}
+
+// Closest hit of a ray of the `VISIBLE_` kind `kind`.
+fn world_hit(r: Ray, kind: u32) -> HitRecord {
+    return world_hit_within(r, 1e30, kind);
+}
+
+// Closest hit nearer than `t_max`.
+fn world_hit_within(r: Ray, t_max: f32, kind: u32) -> HitRecord {
+    if (DEBUG_STATS) { atomicAdd(&debug_stats.rays, 1u); }
     var closest: HitRecord;
     closest.hit = false;
-    closest.t = 1e30;
+    closest.t = t_max;
 
-    let rec1 = hit_sphere(vec3<f32>(0.0, 0.0, -1.0), 0.5, r, 0.001, closest.t, 3u);
-    if (rec1.hit) { closest = rec1; }
+    for (var i = 0u; i < uniforms.shape_count; i++) {
+        let rec = hit_shape(shapes[i], r, 0.001, closest.t);
+        if (rec.hit) { closest = rec; }
+    }
 
-    let rec2 = hit_sphere(vec3<f32>(0.0, 0.0, -1.0), -0.45, r, 0.001, closest.t, 3u);
-    if (rec2.hit) { closest = rec2; }
+    if (uniforms.instance_count > 0u) {
+        closest = hit_instances(r, closest, kind);
+    }
 
-    let rec3 = hit_sphere(vec3<f32>(-1.1, 0.0, -1.0), 0.5, r, 0.001, closest.t, 2u);
-    if (rec3.hit) { closest = rec3; }
+    for (var i = 0u; i < uniforms.light_count; i++) {
+        let rec = hit_light(i, r, 0.001, closest.t);
+        if (rec.hit) { closest = rec; }
+    }
 
-    let rec4 = hit_sphere(vec3<f32>(1.1, 0.0, -1.0), 0.5, r, 0.001, closest.t, 1u);
-    if (rec4.hit) { closest = rec4; }
+    return closest;
+}
 
-    let rec_g = hit_sphere(vec3<f32>(0.0, -100.5, -1.0), 100.0, r, 0.001, closest.t, 0u);
-    if (rec_g.hit) { closest = rec_g; }
+// Entry distance of a ray into a box, or 1e30 if it misses before `t_max`.
+fn hit_aabb(node: BvhNode, origin: vec3<f32>, inv_dir: vec3<f32>, t_max: f32) -> f32 {
+    let t0 = (node.min - origin) * inv_dir;
+    let t1 = (node.max - origin) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), max(min(t0.z, t1.z), 0.001));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), min(max(t0.z, t1.z), t_max));
+    return select(1e30, t_near, t_near <= t_far);
+}
 
+// Closest instance nearer than `closest` that rays of `kind` see, walking
+// the top-level hierarchy.
+fn hit_instances(r: Ray, closest_in: HitRecord, kind: u32) -> HitRecord {
+    var closest = closest_in;
+    let inv_dir = 1.0 / r.direction;
+    if (hit_aabb(bvh_nodes[0], r.origin, inv_dir, closest.t) == 1e30) {
+        return closest;
+    }
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var depth = 0u;
+    var index = 0u;
+    loop {
+        if (DEBUG_STATS) { atomicAdd(&debug_stats.node_visits, 1u); }
+        let node = bvh_nodes[index];
+        if (node.count > 0u) {
+            for (var i = node.first; i < node.first + node.count; i++) {
+                if ((instances[i].visibility & kind) != 0u) {
+                    closest = hit_instance(instances[i], r, closest);
+                }
+            }
+        } else {
+            var near = node.first;
+            var far = node.first + 1u;
+            var t_near = hit_aabb(bvh_nodes[near], r.origin, inv_dir, closest.t);
+            var t_far = hit_aabb(bvh_nodes[far], r.origin, inv_dir, closest.t);
+            if (t_far < t_near) {
+                let swap = near; near = far; far = swap;
+                let t_swap = t_near; t_near = t_far; t_far = t_swap;
+            }
+            if (t_near != 1e30) {
+                if (t_far != 1e30) {
+                    stack[depth] = far;
+                    depth++;
+                }
+                index = near;
+                continue;
+            }
+        }
+        if (depth == 0u) {
+            break;
+        }
+        depth--;
+        index = stack[depth];
+    }
     return closest;
 }
 
+// Traces the mesh of `instance` in object space. The direction is not
+// renormalized, so distances along the ray stay comparable with `closest`.
+fn hit_instance(instance: Instance, r: Ray, closest_in: HitRecord) -> HitRecord {
+    var local: Ray;
+    local.origin = vec4<f32>(r.origin, 1.0) * instance.world_to_object;
+    local.direction = vec4<f32>(r.direction, 0.0) * instance.world_to_object;
+    if (STREAM_GEOMETRY) {
+        let bounds = BvhNode(chunks[instance.chunk].min, 0u, chunks[instance.chunk].max, 0u);
+        if (hit_aabb(bounds, local.origin, 1.0 / local.direction, closest_in.t) != 1e30) {
+            atomicAdd(&chunks[instance.chunk].traffic, 1u);
+        }
+    }
+    // Rays pass through chunks that are not resident.
+    let root = chunks[instance.chunk].root;
+    if (root == NOT_RESIDENT) {
+        return closest_in;
+    }
+    var rec = hit_bvh(local, closest_in, root, instance.primitive);
+    if (rec.t == closest_in.t) {
+        return closest_in;
+    }
+    rec.p = r.origin + rec.t * r.direction;
+    if (instance.material != 0u) {
+        rec.mat_type = instance.material;
+    }
+    // The cone's width at the hit, scaled into object space, then spread
+    // over the surface it meets at a slant.
+    let scale = length(local.direction) / length(r.direction);
+    let slant = max(abs(dot(normalize(local.direction), rec.geo_normal)), 0.05);
+    rec.uv_width *= cone_width(rec.t * length(r.direction)) * scale / slant;
+    // Normal maps bend the normal in object space, where the tangents are.
+    rec.normal = mapped_normal(rec);
+    // Normals go back by the transpose of the inverse.
+    rec.normal = normalize((instance.world_to_object * rec.normal).xyz);
+    rec.geo_normal = normalize((instance.world_to_object * rec.geo_normal).xyz);
+    return rec;
+}
+
+// The shading normal bent by the normal map of the hit's scene material, if
+// it has one and the hit has a tangent.
+fn mapped_normal(rec: HitRecord) -> vec3<f32> {
+    if (rec.mat_type < MATERIAL_SCENE || rec.mat_type >= MATERIAL_LIGHT || rec.tangent.w == 0.0) {
+        return rec.normal;
+    }
+    let material = scene_materials[rec.mat_type - MATERIAL_SCENE];
+    let tangent = rec.tangent.xyz - rec.normal * dot(rec.normal, rec.tangent.xyz);
+    if (material.normal_texture == 0u || dot(tangent, tangent) == 0.0) {
+        return rec.normal;
+    }
+    let t = normalize(tangent);
+    let b = cross(rec.normal, t) * rec.tangent.w;
+    let texel = sample_data_texture(material.normal_texture - 1u, fract(rec.uv), rec.uv_width).rgb;
+    let m = (2.0 * texel - 1.0) * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    let bent = m.x * t + m.y * b + m.z * rec.normal;
+    return select(rec.normal, normalize(bent), dot(bent, bent) > 0.0);
+}
+
+// Closest primitive of kind `primitive` in the hierarchy at `root` nearer
+// than `closest`, visiting the nearer child first so that far subtrees are
+// culled by the hits found so far.
+fn hit_bvh(r: Ray, closest_in: HitRecord, root: u32, primitive: u32) -> HitRecord {
+    var closest = closest_in;
+    let inv_dir = 1.0 / r.direction;
+    if (hit_aabb(bvh_nodes[root], r.origin, inv_dir, closest.t) == 1e30) {
+        return closest;
+    }
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var depth = 0u;
+    var index = root;
+    loop {
+        if (DEBUG_STATS) { atomicAdd(&debug_stats.node_visits, 1u); }
+        let node = bvh_nodes[index];
+        if (node.count > 0u) {
+            for (var i = node.first; i < node.first + node.count; i++) {
+                var rec: HitRecord;
+                if (primitive == PRIMITIVE_CURVES) {
+                    rec = hit_curve(i, r, 0.001, closest.t);
+                } else if (primitive == PRIMITIVE_SPLATS) {
+                    rec = hit_splat(i, r, 0.001, closest.t);
+                } else {
+                    rec = hit_triangle(i, r, 0.001, closest.t);
+                }
+                if (rec.hit) { closest = rec; }
+            }
+        } else {
+            var near = node.first;
+            var far = node.first + 1u;
+            var t_near = hit_aabb(bvh_nodes[near], r.origin, inv_dir, closest.t);
+            var t_far = hit_aabb(bvh_nodes[far], r.origin, inv_dir, closest.t);
+            if (t_far < t_near) {
+                let swap = near; near = far; far = swap;
+                let t_swap = t_near; t_near = t_far; t_far = t_swap;
+            }
+            if (t_near != 1e30) {
+                if (t_far != 1e30) {
+                    stack[depth] = far;
+                    depth++;
+                }
+                index = near;
+                continue;
+            }
+        }
+        // Continue with the most recently deferred far child.
+        if (depth == 0u) {
+            break;
+        }
+        depth--;
+        index = stack[depth];
+    }
+    return closest;
+}
+
+// Extinction of a volume at voxel coordinates `g`, interpolated between
+// voxel centers.
+fn volume_extinction(volume: Volume, g: vec3<f32>) -> f32 {
+    let c = g - 0.5;
+    let base = floor(c);
+    let f = c - base;
+    let hi = vec3<i32>(volume.size) - 1;
+    var density = 0.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner = vec3<u32>(i & 1u, (i >> 1u) & 1u, i >> 2u);
+        let w = mix(1.0 - f, f, vec3<f32>(corner));
+        var texel = clamp(vec3<i32>(base) + vec3<i32>(corner), vec3<i32>(0), hi);
+        texel.z += i32(volume.first_slice);
+        density += w.x * w.y * w.z * textureLoad(volume_densities, texel, 0).r;
+    }
+    return density * volume.density_scale;
+}
+
+// Distances at which a ray in grid coordinates enters and leaves a volume,
+// clipped to `0..t_max`. They are in order only if it passes through.
+fn volume_span(volume: Volume, origin: vec3<f32>, dir: vec3<f32>, t_max: f32) -> vec2<f32> {
+    let inv_dir = 1.0 / dir;
+    let t0 = -origin * inv_dir;
+    let t1 = (vec3<f32>(volume.size) - origin) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), max(min(t0.z, t1.z), 0.0));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), min(max(t0.z, t1.z), t_max));
+    return vec2<f32>(t_near, t_far);
+}
+
+struct VolumeHit {
+    t: f32,
+    // Index into `volumes`, or -1 if the ray passed through them all.
+    volume: i32,
+}
+
+// The first collision of a unit-length ray with the volumes before `t_max`,
+// by delta tracking each of them.
+fn sample_volumes(r: Ray, t_max: f32) -> VolumeHit {
+    var hit = VolumeHit(t_max, -1);
+    for (var i = 0u; i < uniforms.volume_count; i++) {
+        let volume = volumes[i];
+        if (volume.majorant <= 0.0) {
+            continue;
+        }
+        let origin = vec4<f32>(r.origin, 1.0) * volume.world_to_grid;
+        let dir = vec4<f32>(r.direction, 0.0) * volume.world_to_grid;
+        let span = volume_span(volume, origin, dir, hit.t);
+        var t = span.x;
+        for (var step = 0u; step < MAX_VOLUME_STEPS; step++) {
+            t -= log(1.0 - rand()) / volume.majorant;
+            if (t >= span.y) {
+                break;
+            }
+            if (rand() * volume.majorant < volume_extinction(volume, origin + t * dir)) {
+                hit = VolumeHit(t, i32(i));
+                break;
+            }
+        }
+    }
+    return hit;
+}
+
+// Fraction of light that a unit-length ray carries through the volumes
+// before `t_max`, by ratio tracking.
+fn volume_transmittance(r: Ray, t_max: f32) -> f32 {
+    var transmittance = 1.0;
+    for (var i = 0u; i < uniforms.volume_count; i++) {
+        let volume = volumes[i];
+        if (volume.majorant <= 0.0) {
+            continue;
+        }
+        let origin = vec4<f32>(r.origin, 1.0) * volume.world_to_grid;
+        let dir = vec4<f32>(r.direction, 0.0) * volume.world_to_grid;
+        let span = volume_span(volume, origin, dir, t_max);
+        var t = span.x;
+        for (var step = 0u; step < MAX_VOLUME_STEPS; step++) {
+            t -= log(1.0 - rand()) / volume.majorant;
+            if (t >= span.y) {
+                break;
+            }
+            transmittance *= 1.0 - volume_extinction(volume, origin + t * dir) / volume.majorant;
+        }
+    }
+    return max(transmittance, 0.0);
+}
+
+// Angular radius of the sun disk (0.2666 degrees).
+const SUN_COS_RADIUS: f32 = 0.99998917;
+const SUN_TAN_RADIUS: f32 = 0.00465310;
+
+// Direct light from the sun at a Lambertian surface, with a shadow ray towards
+// a random point on the disk.
+fn sample_sun(p: vec3<f32>, normal: vec3<f32>, geo_normal: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    let l = normalize(uniforms.sun.xyz + SUN_TAN_RADIUS * random_in_unit_sphere());
+    let cos_theta = dot(normal, l);
+    if (cos_theta <= 0.0 || dot(geo_normal, l) <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let shadow_ray = Ray(offset_origin(p, geo_normal, l), l);
+    if (world_hit(shadow_ray, VISIBLE_SHADOW).hit) {
+        return vec3<f32>(0.0);
+    }
+    return albedo / PI * uniforms.sun.w * uniforms.sun_color * cos_theta * volume_transmittance(shadow_ray, 1e30);
+}
+
+// Start of a ray leaving a surface at `p` in `dir`, moved off the surface to
+// the side of `geo_normal` the ray leaves on, so that it does not hit the
+// surface again. The step grows with the distance from the origin, as the
+// rounding of `p` does.
+fn offset_origin(p: vec3<f32>, geo_normal: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
+    let scale = max(1.0, max(abs(p.x), max(abs(p.y), abs(p.z))));
+    return p + select(-geo_normal, geo_normal, dot(dir, geo_normal) > 0.0) * 1e-4 * scale;
+}
+
+// Any two unit vectors completing an orthonormal basis with `n`.
+fn basis(n: vec3<f32>) -> mat2x3<f32> {
+    let sign = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    return mat2x3<f32>(
+        vec3<f32>(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3<f32>(b, sign + n.y * n.y * a, -n.y),
+    );
+}
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Head-on reflectance of a dielectric with index of refraction `ior`.
+fn dielectric_f0(ior: f32) -> f32 {
+    let r = (ior - 1.0) / (ior + 1.0);
+    return r * r;
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Smith masking of GGX microfacets seen at `n_dot_x` from the normal.
+fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(a2 + (1.0 - a2) * n_dot_x * n_dot_x));
+}
+
+// A GGX microfacet normal around `n`, drawn in proportion to D(h) (h.n).
+fn sample_ggx(n: vec3<f32>, alpha: f32) -> vec3<f32> {
+    let u = rand();
+    let phi = 2.0 * PI * rand();
+    let cos_theta = sqrt((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u));
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let tangents = basis(n);
+    return normalize(sin_theta * (cos(phi) * tangents[0] + sin(phi) * tangents[1]) + cos_theta * n);
+}
+
+// A bounce off a scene material and the throughput it carries: the BSDF
+// times the cosine over the density it was drawn with, or zero if the path
+// ends. `diffuse` is set for the Lambertian lobe, whose weight is also its
+// albedo for light sampling.
+struct BsdfSample {
+    direction: vec3<f32>,
+    weight: vec3<f32>,
+    diffuse: bool,
+}
+
+// Metallic-roughness shading: a GGX specular layer over a Lambertian base.
+// Metals tint the layer with `base_color` and have no base; dielectrics
+// reflect by their index of refraction and pass the rest to the base.
+fn sample_scene_material(material: SceneMaterial, base_color: vec3<f32>, n: vec3<f32>, dir: vec3<f32>) -> BsdfSample {
+    let v = -normalize(dir);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let alpha = max(material.roughness * material.roughness, 1e-3);
+    let f0 = mix(vec3<f32>(dielectric_f0(material.ior)), base_color, material.metallic);
+    let fresnel = fresnel_schlick(f0, n_dot_v);
+    let base = base_color * (1.0 - material.metallic) * (1.0 - fresnel);
+    let specular_share = luminance(fresnel);
+    // Both lobes keep some chance of being picked, so neither weight below
+    // divides by zero, even for a fully metallic or a black material.
+    let p_specular = clamp(specular_share / max(specular_share + luminance(base), 1e-6), 0.05, 0.95);
+
+    if (rand() < p_specular) {
+        let h = sample_ggx(n, alpha);
+        let l = reflect(-v, h);
+        let n_dot_l = dot(n, l);
+        let v_dot_h = dot(v, h);
+        if (n_dot_l <= 0.0 || v_dot_h <= 0.0) {
+            return BsdfSample(l, vec3<f32>(0.0), false);
+        }
+        let n_dot_h = max(dot(n, h), 1e-4);
+        let weight = fresnel_schlick(f0, v_dot_h) * smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha)
+            * v_dot_h / (n_dot_v * n_dot_h * p_specular);
+        return BsdfSample(l, weight, false);
+    }
+    return BsdfSample(n + random_in_unit_sphere(), base / (1.0 - p_specular), true);
+}
+
+// Mirror reflection of `dir` about `n`, scattered within a sphere of radius
+// `fuzz`. Below the surface when the path is absorbed.
+fn scatter_metal(dir: vec3<f32>, n: vec3<f32>, fuzz: f32) -> vec3<f32> {
+    return reflect(normalize(dir), n) + fuzz * random_in_unit_sphere();
+}
+
+// Refraction of `dir` through a surface with outward normal `n` into or out
+// of glass with index of refraction `ir`, or its reflection, chosen by
+// Schlick's Fresnel and total internal reflection.
+fn scatter_dielectric(dir: vec3<f32>, n: vec3<f32>, ir: f32) -> vec3<f32> {
+    var refraction_ratio = ir;
+    var normal_vec = -n;
+    if (dot(dir, n) < 0.0) {
+        refraction_ratio = 1.0 / ir;
+        normal_vec = n;
+    }
+
+    let unit_dir = normalize(dir);
+    let cos_theta = min(dot(-unit_dir, normal_vec), 1.0);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+    let reflectance = fresnel_schlick(vec3<f32>(dielectric_f0(ir)), cos_theta).x;
+
+    if (cannot_refract || reflectance > rand()) {
+        return reflect(unit_dir, normal_vec);
+    }
+    let r_out_perp = refraction_ratio * (unit_dir + cos_theta * normal_vec);
+    let r_out_parallel = -sqrt(abs(1.0 - dot(r_out_perp, r_out_perp))) * normal_vec;
+    return r_out_perp + r_out_parallel;
+}
+
+// A direction towards a light, the distance to it along that direction, the
+// radiance arriving from it and the solid angle density it was drawn with
+// (0 if the light cannot be seen from the shading point).
+struct LightSample {
+    direction: vec3<f32>,
+    distance: f32,
+    radiance: vec3<f32>,
+    pdf: f32,
+}
+
+// Uniform within the cone the sphere subtends.
+fn sample_sphere_light(light: AreaLight, p: vec3<f32>) -> LightSample {
+    var s: LightSample;
+    s.pdf = 0.0;
+    let to_center = light.position - p;
+    let d2 = dot(to_center, to_center);
+    let r2 = light.radius * light.radius;
+    if (d2 <= r2) {
+        return s;
+    }
+    let d = sqrt(d2);
+    let w = to_center / d;
+    let sin2_max = r2 / d2;
+    // 1 - cos(theta_max) without cancellation for small, distant spheres.
+    let cone = sin2_max / (1.0 + sqrt(1.0 - sin2_max));
+    let cos_theta = 1.0 - rand() * cone;
+    let sin2_theta = max(0.0, 1.0 - cos_theta * cos_theta);
+    let phi = 2.0 * PI * rand();
+    s.direction = cos_theta * w + sqrt(sin2_theta) * (basis(w) * vec2<f32>(cos(phi), sin(phi)));
+    s.distance = d * cos_theta - sqrt(max(0.0, r2 - d2 * sin2_theta));
+    s.radiance = light.emission;
+    s.pdf = 1.0 / (2.0 * PI * cone);
+    return s;
+}
+
+// Uniform in the solid angle of the rectangle (Urena et al. 2013).
+fn sample_rect_light(light: AreaLight, p: vec3<f32>) -> LightSample {
+    var s: LightSample;
+    s.pdf = 0.0;
+    let ex_length = length(light.u);
+    let ey_length = length(light.v);
+    let ex = light.u / ex_length;
+    let ey = light.v / ey_length;
+    let ez = cross(ex, ey);
+    let corner = light.position - p;
+    let x0 = dot(corner, ex);
+    let y0 = dot(corner, ey);
+    let z0 = dot(corner, ez);
+    // Only the side facing `u x v` emits.
+    if (z0 >= 0.0) {
+        return s;
+    }
+    let x1 = x0 + ex_length;
+    let y1 = y0 + ey_length;
+
+    let n0 = normalize(vec3<f32>(0.0, z0, -y0));
+    let n1 = normalize(vec3<f32>(-z0, 0.0, x1));
+    let n2 = normalize(vec3<f32>(0.0, -z0, y1));
+    let n3 = normalize(vec3<f32>(z0, 0.0, -x0));
+    let g0 = acos(clamp(-n0.z * n1.z, -1.0, 1.0));
+    let g1 = acos(clamp(-n1.z * n2.z, -1.0, 1.0));
+    let g2 = acos(clamp(-n2.z * n3.z, -1.0, 1.0));
+    let g3 = acos(clamp(-n3.z * n0.z, -1.0, 1.0));
+    let k = 2.0 * PI - g2 - g3;
+    let solid_angle = g0 + g1 - k;
+    if (solid_angle <= 1e-7) {
+        return s;
+    }
+
+    let au = rand() * solid_angle + k;
+    let fu = (cos(au) * n0.z - n2.z) / sin(au);
+    let cu = clamp(sign(fu) / sqrt(fu * fu + n0.z * n0.z), -0.99999, 0.99999);
+    let xu = clamp(-cu * z0 / sqrt(1.0 - cu * cu), x0, x1);
+    let d = sqrt(xu * xu + z0 * z0);
+    let h0 = y0 / sqrt(d * d + y0 * y0);
+    let h1 = y1 / sqrt(d * d + y1 * y1);
+    let hv = h0 + rand() * (h1 - h0);
+    let yv = select(y1, hv * d / sqrt(1.0 - hv * hv), hv * hv < 0.99999);
+
+    let offset = xu * ex + yv * ey + z0 * ez;
+    s.distance = length(offset);
+    s.direction = offset / s.distance;
+    // A single point on the light, so the finest level.
+    s.radiance = rect_emission(light, vec2<f32>((xu - x0) / ex_length, (yv - y0) / ey_length), 0.0);
+    s.pdf = 1.0 / solid_angle;
+    return s;
+}
+
+// Uniform over the area of a disc or the side of a tube, converted to a
+// solid angle density.
+fn sample_light_area(light: AreaLight, p: vec3<f32>) -> LightSample {
+    var s: LightSample;
+    s.pdf = 0.0;
+    let phi = 2.0 * PI * rand();
+    var point: vec3<f32>;
+    var normal: vec3<f32>;
+    var area: f32;
+    if (light.shape == LIGHT_DISC) {
+        normal = light.u;
+        point = light.position + light.radius * sqrt(rand()) * (basis(normal) * vec2<f32>(cos(phi), sin(phi)));
+        area = PI * light.radius * light.radius;
+    } else {
+        let height = length(light.u);
+        normal = basis(light.u / height) * vec2<f32>(cos(phi), sin(phi));
+        point = light.position + rand() * light.u + light.radius * normal;
+        area = 2.0 * PI * light.radius * height;
+    }
+    let offset = point - p;
+    s.distance = length(offset);
+    s.direction = offset / s.distance;
+    let cos_light = -dot(s.direction, normal);
+    if (cos_light <= 0.0) {
+        return s;
+    }
+    s.radiance = light.emission;
+    s.pdf = s.distance * s.distance / (area * cos_light);
+    return s;
+}
+
+// Direct light from one randomly chosen area light at a Lambertian surface.
+fn sample_lights(p: vec3<f32>, normal: vec3<f32>, geo_normal: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    let count = uniforms.light_count;
+    let light = lights[min(u32(rand() * f32(count)), count - 1u)];
+    var s: LightSample;
+    if (light.shape == LIGHT_SPHERE) {
+        s = sample_sphere_light(light, p);
+    } else if (light.shape == LIGHT_RECT) {
+        s = sample_rect_light(light, p);
+    } else {
+        s = sample_light_area(light, p);
+    }
+    let cos_theta = dot(normal, s.direction);
+    if (s.pdf <= 0.0 || cos_theta <= 0.0 || dot(geo_normal, s.direction) <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let shadow_ray = Ray(offset_origin(p, geo_normal, s.direction), s.direction);
+    let shadow = world_hit(shadow_ray, VISIBLE_SHADOW);
+    if (shadow.hit && shadow.t < s.distance * 0.999) {
+        return vec3<f32>(0.0);
+    }
+    let transmittance = volume_transmittance(shadow_ray, s.distance);
+    return albedo / PI * s.radiance * cos_theta / s.pdf * f32(count) * transmittance;
+}
+
+// Direct light from one emissive triangle at a Lambertian surface, picked in
+// proportion to its power and sampled uniformly over its area.
+fn sample_emitters(p: vec3<f32>, normal: vec3<f32>, geo_normal: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    let u = rand();
+    var lo = 0u;
+    var hi = uniforms.emitter_count - 1u;
+    while (lo < hi) {
+        let mid = (lo + hi) / 2u;
+        if (emitters[mid].cdf < u) {
+            lo = mid + 1u;
+        } else {
+            hi = mid;
+        }
+    }
+    let emitter = emitters[lo];
+    let r = sqrt(rand());
+    let v = rand();
+    let offset = emitter.position + r * (1.0 - v) * emitter.e1 + r * v * emitter.e2 - p;
+    let distance = length(offset);
+    let direction = offset / distance;
+    let area_normal = cross(emitter.e1, emitter.e2);
+    let area = 0.5 * length(area_normal);
+    // Emissive surfaces glow on both sides.
+    let cos_light = abs(dot(direction, area_normal)) / (2.0 * area);
+    let cos_theta = dot(normal, direction);
+    if (cos_theta <= 0.0 || dot(geo_normal, direction) <= 0.0 || cos_light <= 0.0 || emitter.probability <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let shadow_ray = Ray(offset_origin(p, geo_normal, direction), direction);
+    let shadow = world_hit(shadow_ray, VISIBLE_SHADOW);
+    if (shadow.hit && shadow.t < distance * 0.999) {
+        return vec3<f32>(0.0);
+    }
+    let transmittance = volume_transmittance(shadow_ray, distance);
+    let pdf = emitter.probability * distance * distance / (area * cos_light);
+    return albedo / PI * scene_materials[emitter.material].emission * cos_theta / pdf * transmittance;
+}
+
+fn sky_color(dir: vec3<f32>, specular: bool) -> vec3<f32> {
+    let t = 0.5 * (dir.y + 1.0);
+    var sky = uniforms.sky_radiance * ((1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t * vec3<f32>(0.5, 0.7, 1.0));
+    // Diffuse bounces already gathered the sun through `sample_sun`.
+    if (specular && uniforms.sun.w > 0.0 && dot(dir, uniforms.sun.xyz) > SUN_COS_RADIUS) {
+        sky += uniforms.sun_color * uniforms.sun.w / (2.0 * PI * (1.0 - SUN_COS_RADIUS));
+    }
+    return sky;
+}
+
 fn ray_color(r_in: Ray) -> vec3<f32> {
     var cur_ray = r_in;
     var cur_attenuation = vec3<f32>(1.0, 1.0, 1.0);
+    var radiance = vec3<f32>(0.0);
+    var specular = true;
+    var kind = VISIBLE_CAMERA;
     
     for (var depth = 0; depth < 50; depth++) {
-        let rec = world_hit(cur_ray);
+        let rec = world_hit(cur_ray, kind);
+        let medium = sample_volumes(cur_ray, rec.t);
+        if (medium.volume >= 0) {
+            // Isotropic scattering; lights and the sun are only found by
+            // chance from here, so they count as specular.
+            cur_attenuation *= volumes[medium.volume].albedo;
+            ray_cone.x = cone_width(medium.t);
+            cur_ray = Ray(cur_ray.origin + medium.t * cur_ray.direction, normalize(random_in_unit_sphere()));
+            specular = true;
+            kind = VISIBLE_DIFFUSE;
+            continue;
+        }
         
+        if (rec.hit && rec.mat_type >= MATERIAL_LIGHT) {
+            // Diffuse bounces already gathered area lights through `sample_lights`.
+            if (specular) {
+                radiance += cur_attenuation * light_emission(rec.mat_type - MATERIAL_LIGHT, rec.p, rec.normal, cur_ray.direction, cone_width(rec.t));
+            }
+            return radiance;
+        }
         if (rec.hit) {
-            var scattered_origin = rec.p;
             var scattered_direction = vec3<f32>(0.0);
             var attenuation = vec3<f32>(0.0);
+            var diffuse = rec.mat_type == 0u || rec.mat_type == 2u;
             
-            if (rec.mat_type == 3u) {
-                let ir = 1.5;
-                var refraction_ratio = ir;
-                var normal_vec = -rec.normal;
-                
-                if (dot(cur_ray.direction, rec.normal) < 0.0) {
-                    refraction_ratio = 1.0 / ir;
-                    normal_vec = rec.normal;
+            if (rec.mat_type >= MATERIAL_SCENE) {
+                let material = scene_materials[rec.mat_type - MATERIAL_SCENE];
+                // Diffuse bounces already gathered emissive triangles
+                // through `sample_emitters`.
+                if (specular || !rec.is_triangle || uniforms.emitter_count == 0u) {
+                    radiance += cur_attenuation * material.emission;
                 }
-                
-                let unit_dir = normalize(cur_ray.direction);
-                let cos_theta = min(dot(-unit_dir, normal_vec), 1.0);
-                let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
-                
-                let cannot_refract = refraction_ratio * sin_theta > 1.0;
-                let r0 = (1.0 - ir) / (1.0 + ir);
-                let r0_sq = r0 * r0;
-                let reflectance = r0_sq + (1.0 - r0_sq) * pow(1.0 - cos_theta, 5.0);
-                
-                if (cannot_refract || reflectance > rand()) {
-                    scattered_direction = reflect(unit_dir, normal_vec);
-                } else {
-                    let r_out_perp = refraction_ratio * (unit_dir + cos_theta * normal_vec);
-                    let r_out_parallel = -sqrt(abs(1.0 - dot(r_out_perp, r_out_perp))) * normal_vec;
-                    scattered_direction = r_out_perp + r_out_parallel;
+                let base_color = surface_base_color(material, rec);
+                switch material.kind {
+                    case SHADING_LAMBERTIAN: {
+                        scattered_direction = rec.normal + random_in_unit_sphere();
+                        attenuation = base_color;
+                        diffuse = true;
+                    }
+                    case SHADING_METAL: {
+                        scattered_direction = scatter_metal(cur_ray.direction, rec.normal, material.fuzz);
+                        attenuation = base_color;
+                    }
+                    case SHADING_DIELECTRIC: {
+                        scattered_direction = scatter_dielectric(cur_ray.direction, rec.normal, material.ior);
+                        attenuation = base_color;
+                    }
+                    case SHADING_METALLIC_ROUGHNESS, default: {
+                        let bsdf = sample_scene_material(material, base_color, rec.normal, cur_ray.direction);
+                        if (all(bsdf.weight == vec3<f32>(0.0))) { return radiance; }
+                        scattered_direction = bsdf.direction;
+                        attenuation = bsdf.weight;
+                        diffuse = bsdf.diffuse;
+                    }
                 }
+            }
+            else if (rec.mat_type == 3u) {
+                scattered_direction = scatter_dielectric(cur_ray.direction, rec.normal, 1.5);
                 attenuation = vec3<f32>(1.0, 1.0, 1.0);
             } 
             else if (rec.mat_type == 1u) {
-                let fuzz = 0.0; 
-                let reflected = reflect(normalize(cur_ray.direction), rec.normal);
-                scattered_direction = reflected + fuzz * random_in_unit_sphere();
+                scattered_direction = scatter_metal(cur_ray.direction, rec.normal, 0.0);
                 attenuation = vec3<f32>(0.7, 0.6, 0.5); 
-                if (dot(scattered_direction, rec.normal) <= 0.0) { return vec3<f32>(0.0); }
             } 
             else if (rec.mat_type == 2u) {
                 let scatter_target = rec.p + rec.normal + random_in_unit_sphere();
@@ -200,16 +1845,105 @@ fn ray_color(r_in: Ray) -> vec3<f32> {
                 else { attenuation = vec3<f32>(0.9, 0.9, 0.9); }
             }
 
-            cur_ray = Ray(scattered_origin, normalize(scattered_direction));
+            if (diffuse && uniforms.sun.w > 0.0) {
+                radiance += cur_attenuation * sample_sun(rec.p, rec.normal, rec.geo_normal, attenuation);
+            }
+            if (diffuse && uniforms.light_count > 0u) {
+                radiance += cur_attenuation * sample_lights(rec.p, rec.normal, rec.geo_normal, attenuation);
+            }
+            if (diffuse && uniforms.emitter_count > 0u) {
+                radiance += cur_attenuation * sample_emitters(rec.p, rec.normal, rec.geo_normal, attenuation);
+            }
+            specular = !diffuse;
+            kind = select(VISIBLE_SPECULAR, VISIBLE_DIFFUSE, diffuse);
+
+            if (depth == 0) {
+                primary.diffuse = diffuse;
+                primary.p = rec.p;
+                primary.normal = rec.normal;
+                primary.albedo = attenuation;
+            }
+
+            // Only glass lets rays through. A reflection that a shading
+            // normal turned below the surface would pass through it.
+            let transmits = rec.mat_type == 3u
+                || (rec.mat_type >= MATERIAL_SCENE && scene_materials[rec.mat_type - MATERIAL_SCENE].kind == SHADING_DIELECTRIC);
+            if (!transmits && dot(scattered_direction, rec.geo_normal) <= 0.0) {
+                return radiance;
+            }
+
+            // The cone keeps its spread through bounces, which keeps
+            // textures seen in reflections sharp rather than blurring them.
+            ray_cone.x = cone_width(rec.t);
+            cur_ray = Ray(offset_origin(rec.p, rec.geo_normal, scattered_direction), normalize(scattered_direction));
             cur_attenuation = cur_attenuation * attenuation;
         } else {
-            let unit_dir = normalize(cur_ray.direction);
-            let t = 0.5 * (unit_dir.y + 1.0);
-            let sky = (1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t * vec3<f32>(0.5, 0.7, 1.0);
-            return cur_attenuation * sky;
+            return radiance + cur_attenuation * sky_color(normalize(cur_ray.direction), specular);
+        }
+    }
+    return radiance;
+}
+
+const INTEGRATOR_PATH: u32 = 0u;
+const INTEGRATOR_SKY_AO: u32 = 1u;
+
+// Base color of a scene material where `rec` hit it: the vertex color if
+// there is one, times the texture if the material has one and the hit has
+// texture coordinates, which repeat outside 0 to 1.
+fn surface_base_color(material: SceneMaterial, rec: HitRecord) -> vec3<f32> {
+    var base_color = select(material.base_color, rec.color.xyz, rec.color.w == 1.0);
+    if (material.base_color_texture > 0u && rec.textured) {
+        let texel = sample_texture(material.base_color_texture - 1u, fract(rec.uv), rec.uv_width);
+        base_color *= texel.rgb;
+    }
+    return base_color;
+}
+
+// Diffuse color of a surface for previews, ignoring its specular lobes.
+fn preview_albedo(rec: HitRecord) -> vec3<f32> {
+    if (rec.mat_type >= MATERIAL_SCENE) {
+        return surface_base_color(scene_materials[rec.mat_type - MATERIAL_SCENE], rec);
+    }
+    if (rec.color.w == 1.0) {
+        return rec.color.xyz;
+    }
+    switch (rec.mat_type) {
+        case 1u: { return vec3<f32>(0.7, 0.6, 0.5); }
+        case 2u: { return vec3<f32>(0.7, 0.3, 0.3); }
+        case 3u: { return vec3<f32>(1.0); }
+        default: {
+            let sines = sin(3.0 * rec.p.x) * sin(3.0 * rec.p.z);
+            return select(vec3<f32>(0.9), vec3<f32>(0.2), sines < 0.0);
         }
     }
-    return vec3<f32>(0.0, 0.0, 0.0);
+}
+
+// The first hit treated as diffuse and lit only by the sky, through one
+// occlusion ray that counts as open beyond `uniforms.ao_distance`.
+fn sky_ao_color(r: Ray) -> vec3<f32> {
+    let rec = world_hit(r, VISIBLE_CAMERA);
+    if (!rec.hit) {
+        return sky_color(r.direction, true);
+    }
+    if (rec.mat_type >= MATERIAL_LIGHT) {
+        return light_emission(rec.mat_type - MATERIAL_LIGHT, rec.p, rec.normal, r.direction, cone_width(rec.t));
+    }
+    var emission = vec3<f32>(0.0);
+    if (rec.mat_type >= MATERIAL_SCENE) {
+        emission = scene_materials[rec.mat_type - MATERIAL_SCENE].emission;
+    }
+    // Facing the camera, so that glass and two-sided surfaces shade alike.
+    let normal = select(rec.normal, -rec.normal, dot(rec.normal, r.direction) > 0.0);
+    let geo_normal = select(rec.geo_normal, -rec.geo_normal, dot(rec.geo_normal, r.direction) > 0.0);
+    let dir = normalize(normal + random_in_unit_sphere());
+    if (dot(dir, geo_normal) <= 0.0) {
+        return emission;
+    }
+    let occluder = world_hit_within(Ray(offset_origin(rec.p, geo_normal, dir), dir), uniforms.ao_distance, VISIBLE_SHADOW);
+    if (occluder.hit) {
+        return emission;
+    }
+    return emission + preview_albedo(rec) * sky_color(dir, false);
 }
 
 @fragment
@@ -220,17 +1954,38 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let resolution = vec2<f32>(f32(uniforms.width), f32(uniforms.height));
     let aspect_ratio = resolution.x / resolution.y;
     
-    let jitter = vec2<f32>(rand() - 0.5, rand() - 0.5);
+    let jitter = sample_pixel_filter();
     let uv = (in.position.xy + jitter) / resolution;
     
     let p = (uv * 2.0 - 1.0);
     let screen_p = vec2<f32>(p.x * aspect_ratio, -p.y); 
     
     let cam = uniforms.camera;
-    let ray_dir = normalize(cam.w + cam.u * screen_p.x + cam.v * screen_p.y);
-    let r = Ray(cam.origin, ray_dir);
+    var origin = cam.origin;
+    var ray_dir = cam.w + cam.u * screen_p.x + cam.v * screen_p.y;
+    if (cam.aperture > 0.0) {
+        // Thin lens: w has unit length, so the focus plane sits at
+        // focus_distance along every unnormalized ray direction.
+        let focus = origin + ray_dir * cam.focus_distance;
+        let lens = sample_aperture() * cam.aperture;
+        origin += normalize(cam.u) * lens.x + normalize(cam.v) * lens.y;
+        ray_dir = focus - origin;
+    }
+    let r = Ray(origin, normalize(ray_dir));
+    // `v` spans half the screen height at unit distance.
+    ray_cone = vec2<f32>(0.0, 2.0 * length(cam.v) / resolution.y);
+
+    if (all(coord == vec2<u32>(uniforms.width, uniforms.height) / 2u)) {
+        focus_probe = focus_depth(cam);
+    }
 
-    let color = ray_color(r);
+    primary.diffuse = false;
+    var color: vec3<f32>;
+    if (uniforms.integrator == INTEGRATOR_SKY_AO) {
+        color = sky_ao_color(r);
+    } else {
+        color = ray_color(r);
+    }
     
     var acc_color = vec4<f32>(0.0);
     if (uniforms.frame_count > 1u) {
@@ -242,10 +1997,31 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     let new_acc = acc_color + vec4<f32>(safe_color, 1.0);
     textureStore(radiance_samples, vec2<i32>(coord), new_acc);
+
+    if (uniforms.frame_count % 2u == 1u) {
+        var odd_acc = vec4<f32>(0.0);
+        if (uniforms.frame_count > 1u) {
+            odd_acc = textureLoad(odd_samples, vec2<i32>(coord));
+        }
+        textureStore(odd_samples, vec2<i32>(coord), odd_acc + vec4<f32>(safe_color, 1.0));
+    }
     
-    let accumulated_linear = new_acc.rgb / f32(uniforms.frame_count);
-    
-    let tone_mapped = aces_tone_map(accumulated_linear);
+    var accumulated_linear = new_acc.rgb / f32(uniforms.frame_count);
+
+    // While the accumulation is young, show primary diffuse hits lit by the
+    // cached irradiance of their cell and fade to the ground truth.
+    if (uniforms.frame_count <= uniforms.preview_frames && primary.diffuse) {
+        let cell = cache_cell(primary.p, primary.normal);
+        // Dark albedo channels would blow the irradiance up, or divide by
+        // zero; their preview stays dark whatever the cache holds.
+        cache_insert(cell, safe_color / max(primary.albedo, vec3<f32>(1e-3)));
+        let preview = primary.albedo * cache_lookup(cell);
+        let t = f32(uniforms.frame_count) / f32(uniforms.preview_frames);
+        accumulated_linear = mix(preview, accumulated_linear, t);
+    }
+
+    let balanced = uniforms.white_balance * (accumulated_linear * uniforms.exposure);
+    let tone_mapped = aces_tone_map(balanced);
     let gamma_corrected = pow(tone_mapped, vec3<f32>(1.0/2.2));
     
     return vec4<f32>(gamma_corrected, 1.0);
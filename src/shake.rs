@@ -0,0 +1,61 @@
+//! Procedural handheld camera noise.
+
+use crate::camera::Camera;
+
+/// Smooth random drift of the camera, as if it were held by hand.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraShake {
+    /// Peak rotation in degrees.
+    pub rotation: f32,
+    /// Peak translation in world units.
+    pub translation: f32,
+    /// Rate of the dominant wobble in Hz.
+    pub frequency: f32,
+}
+
+impl CameraShake {
+    /// Returns a copy of `camera` displaced by the noise at `time` seconds. The
+    /// offset is recomputed from scratch each time, so it never drifts away.
+    pub fn apply(&self, camera: &Camera, time: f32) -> Camera {
+        let t = time * self.frequency;
+        let noise = |seed| handheld_noise(t, seed);
+
+        let forward = camera.lookat - camera.lookfrom;
+        let distance = forward.length();
+        let w = forward.normalized();
+        let u = w.cross(&camera.vup).normalized();
+        let v = u.cross(&w);
+
+        let offset = (u * noise(0) + v * noise(1) + w * noise(2)) * self.translation;
+        let yaw = (noise(3) * self.rotation).to_radians();
+        let pitch = (noise(4) * self.rotation).to_radians();
+
+        let mut shaken = camera.clone();
+        shaken.lookfrom += offset;
+        shaken.lookat = shaken.lookfrom + (w + u * yaw.tan() + v * pitch.tan()) * distance;
+        shaken
+    }
+}
+
+/// Two octaves of 1D gradient noise, roughly within [-1, 1].
+fn handheld_noise(t: f32, seed: u32) -> f32 {
+    (perlin(t, seed) + 0.5 * perlin(2.03 * t, seed + 101)) * (2.0 / 1.5)
+}
+
+/// 1D Perlin noise in [-0.5, 0.5] with a hashed gradient per integer lattice point.
+fn perlin(x: f32, seed: u32) -> f32 {
+    let cell = x.floor();
+    let f = x - cell;
+    let gradient = |i: i32| hash(i as u32 ^ seed.wrapping_mul(0x9e37_79b9)) as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    let d0 = gradient(cell as i32) * f;
+    let d1 = gradient(cell as i32 + 1) * (f - 1.0);
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    d0 + (d1 - d0) * fade
+}
+
+/// PCG hash, the same one the shader's random numbers are built on.
+fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
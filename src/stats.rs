@@ -0,0 +1,11 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Per-frame counters written by the shader. Must match `DebugStats` in
+/// shader.wgsl.
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct TraversalStats {
+    pub rays: u32,
+    pub node_visits: u32,
+    pub primitive_tests: u32,
+}
@@ -0,0 +1,233 @@
+//! Which chunks of scene geometry stay on the GPU when they do not all fit
+//! its memory budget. The traversal counts the rays reaching each chunk's
+//! bounds, resident or not, and the counts are weighted by how near the
+//! camera is to each chunk; the chunks that weigh most are paged in, in
+//! place of those that weigh least. Chunks that are not resident are drawn
+//! as coarse proxies until they are paged in.
+
+use std::ops::Range;
+
+/// Fraction of a chunk's ray count carried over from one readback to the
+/// next.
+const HEAT_DECAY: f32 = 0.5;
+
+/// Weight of resident chunks' counts when ranking, so that chunks reached
+/// about as often as resident ones do not keep trading places with them.
+const RESIDENT_BONUS: f32 = 1.5;
+
+/// Chunks are placed in three pools, holding their vertices, their triangle
+/// records and their hierarchy nodes.
+pub const POOLS: usize = 3;
+
+/// Chunks resident within pool budgets, paged in and out as the ray counts
+/// and the camera move.
+///
+/// ```
+/// use raytracer::streaming::{Residency, POOLS};
+///
+/// // Three chunks of ten elements in each pool, of which two fit.
+/// let mut residency = Residency::new(vec![[10; POOLS]; 3], [20; POOLS]);
+/// // Before any rays arrive, chunks are paged in in order.
+/// assert_eq!(residency.resident_count(), 2);
+/// assert_eq!(residency.slot(2), None);
+///
+/// // Rays reaching chunk 2 page it in, in place of chunk 1 and into the
+/// // space it gave up.
+/// let freed = residency.slot(1);
+/// let paging = residency.update(&[0, 0, 8], &[1.0; 3]);
+/// assert_eq!((paging.paged_in, paging.paged_out), (vec![2], vec![1]));
+/// assert_eq!(residency.slot(2), freed);
+///
+/// // The counters only grow between uploads. Chunk 1 is now reached a
+/// // little more often than resident chunk 0, 5 rays to 4, which is not
+/// // enough to trade places with it.
+/// let paging = residency.update(&[4, 5, 12], &[1.0; 3]);
+/// assert!(paging.paged_in.is_empty() && paging.paged_out.is_empty());
+///
+/// // Counts decay by half at every readback, so chunk 2, which rays have
+/// // stopped reaching, falls behind chunk 1's steady stream.
+/// let paging = residency.update(&[8, 13, 12], &[1.0; 3]);
+/// assert_eq!((paging.paged_in, paging.paged_out), (vec![1], vec![2]));
+///
+/// // Chunks far from the camera weigh less, whatever reaches them.
+/// let paging = residency.update(&[8, 13, 12], &[1.0, 0.1, 1.0]);
+/// assert_eq!((paging.paged_in, paging.paged_out), (vec![2], vec![1]));
+/// assert_eq!(residency.resident_count(), 2);
+/// ```
+pub struct Residency {
+    pools: [Pool; POOLS],
+    /// Elements each chunk takes in each pool.
+    sizes: Vec<[u32; POOLS]>,
+    /// First element of each resident chunk in each pool.
+    slots: Vec<Option<[u32; POOLS]>>,
+    /// Ray counts decayed over the readbacks.
+    heat: Vec<f32>,
+    /// Ray counts of the previous readback, which only grow until the
+    /// counters are uploaded again.
+    counts: Vec<u32>,
+    /// How near the camera was to each chunk at the latest update.
+    nearness: Vec<f32>,
+}
+
+/// Chunks to upload and chunks whose space was given up after an update.
+#[derive(Default)]
+pub struct Paging {
+    pub paged_in: Vec<usize>,
+    pub paged_out: Vec<usize>,
+}
+
+impl Residency {
+    /// Pages in chunks in order for as long as they fit pools of `capacity`
+    /// elements.
+    pub fn new(sizes: Vec<[u32; POOLS]>, capacity: [u32; POOLS]) -> Self {
+        let count = sizes.len();
+        let mut residency = Residency {
+            pools: capacity.map(Pool::new),
+            sizes,
+            slots: vec![None; count],
+            heat: vec![0.0; count],
+            counts: vec![0; count],
+            nearness: vec![1.0; count],
+        };
+        residency.choose();
+        residency
+    }
+
+    /// Where `chunk` is placed in each pool, if it is resident.
+    pub fn slot(&self, chunk: usize) -> Option<[u32; POOLS]> {
+        self.slots[chunk]
+    }
+
+    pub fn resident_count(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Folds in the ray counts read back from the GPU and the [`nearness`]
+    /// of the camera, both indexed like the chunks, and pages chunks in and
+    /// out to follow them.
+    pub fn update(&mut self, counts: &[u32], nearness: &[f32]) -> Paging {
+        self.nearness.copy_from_slice(nearness);
+        for ((heat, last), &count) in self.heat.iter_mut().zip(&mut self.counts).zip(counts) {
+            // A smaller count means the counters were uploaded again since.
+            let delta = if count >= *last { count - *last } else { count };
+            *heat = *heat * HEAT_DECAY + delta as f32;
+            *last = count;
+        }
+        self.choose()
+    }
+
+    /// Ranks the chunks by heat weighted by nearness, or by nearness alone
+    /// before any rays have reached them, and keeps the leading ones that fit
+    /// the pools together.
+    fn choose(&mut self) -> Paging {
+        let priority = |i: usize| {
+            self.heat[i] * self.nearness[i] * if self.slots[i].is_some() { RESIDENT_BONUS } else { 1.0 }
+        };
+        let mut ranked: Vec<usize> = (0..self.sizes.len()).collect();
+        ranked.sort_by(|&a, &b| {
+            priority(b)
+                .total_cmp(&priority(a))
+                .then(self.nearness[b].total_cmp(&self.nearness[a]))
+                .then(self.slots[b].is_some().cmp(&self.slots[a].is_some()))
+        });
+        let mut used = [0u32; POOLS];
+        let mut wanted = vec![false; self.sizes.len()];
+        for &i in &ranked {
+            let size = self.sizes[i];
+            if (0..POOLS).all(|p| used[p] + size[p] <= self.pools[p].capacity) {
+                (0..POOLS).for_each(|p| used[p] += size[p]);
+                wanted[i] = true;
+            }
+        }
+
+        let mut paging = Paging::default();
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(first) = slot.take_if(|_| !wanted[i]) {
+                for ((pool, first), size) in self.pools.iter_mut().zip(first).zip(self.sizes[i]) {
+                    pool.free(first..first + size);
+                }
+                paging.paged_out.push(i);
+            }
+        }
+        for i in ranked {
+            if !wanted[i] || self.slots[i].is_some() {
+                continue;
+            }
+            // Free space may be split up so that a chunk does not fit after
+            // all, in which case it waits for a later update.
+            let size = self.sizes[i];
+            let first: [Option<u32>; POOLS] = std::array::from_fn(|p| self.pools[p].allocate(size[p]));
+            if let [Some(v), Some(r), Some(n)] = first {
+                self.slots[i] = Some([v, r, n]);
+                paging.paged_in.push(i);
+            } else {
+                for (p, first) in first.into_iter().enumerate() {
+                    if let Some(first) = first {
+                        self.pools[p].free(first..first + size[p]);
+                    }
+                }
+            }
+        }
+        paging
+    }
+}
+
+/// How near `eye` is to a chunk placed within `(min, max)`: 1 from inside
+/// the bounds, halving by the time the distance to them equals their radius.
+pub fn nearness(eye: [f32; 3], (min, max): ([f32; 3], [f32; 3])) -> f32 {
+    let [x, y, z] = [0, 1, 2].map(|i| (max[i] - min[i]) * 0.5);
+    let radius = (x * x + y * y + z * z).sqrt();
+    let [dx, dy, dz] = [0, 1, 2].map(|i| (min[i] - eye[i]).max(eye[i] - max[i]).max(0.0));
+    let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+    if distance == 0.0 {
+        return 1.0;
+    }
+    radius / (radius + distance)
+}
+
+/// First-fit allocation of element ranges within a fixed capacity.
+struct Pool {
+    capacity: u32,
+    /// Free ranges, sorted and never adjacent.
+    free: Vec<Range<u32>>,
+}
+
+impl Pool {
+    fn new(capacity: u32) -> Self {
+        Pool {
+            capacity,
+            free: std::iter::once(0..capacity).collect(),
+        }
+    }
+
+    fn allocate(&mut self, len: u32) -> Option<u32> {
+        if len == 0 {
+            return Some(0);
+        }
+        let i = self.free.iter().position(|range| range.len() as u32 >= len)?;
+        let first = self.free[i].start;
+        self.free[i].start += len;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+        Some(first)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let i = self.free.partition_point(|free| free.start < range.start);
+        let joins_previous = i > 0 && self.free[i - 1].end == range.start;
+        let joins_next = i < self.free.len() && self.free[i].start == range.end;
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            }
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+}
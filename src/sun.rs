@@ -0,0 +1,102 @@
+use {
+    crate::math::Vec3,
+    anyhow::{bail, Context, Result},
+    std::f64::consts::PI,
+};
+
+/// A sun placed by geographic location and UTC time.
+#[derive(Copy, Clone, Debug)]
+pub struct Sun {
+    /// Degrees, north positive.
+    pub latitude: f64,
+    /// Degrees, east positive.
+    pub longitude: f64,
+    /// Seconds since the Unix epoch, UTC.
+    pub unix_time: i64,
+    /// Irradiance on a surface facing the sun, in the units of the sky.
+    pub irradiance: f32,
+    /// Linear RGB tint at unit luminance.
+    pub color: [f32; 3],
+}
+
+impl Sun {
+    /// Unit vector towards the sun in world space (Y up, -Z north, +X east).
+    pub fn direction(&self) -> Vec3 {
+        let (elevation, azimuth) = solar_position(self.latitude, self.longitude, self.unix_time);
+        Vec3::new(
+            (azimuth.sin() * elevation.cos()) as f32,
+            elevation.sin() as f32,
+            (-azimuth.cos() * elevation.cos()) as f32,
+        )
+    }
+
+    pub fn advance(&mut self, seconds: i64) {
+        self.unix_time += seconds;
+    }
+}
+
+/// Solar (elevation, azimuth) in radians for a location in degrees. Azimuth is
+/// measured clockwise from north. Uses the low-precision formulas of the
+/// Astronomical Almanac, good to about 0.01 degrees for this century.
+pub fn solar_position(latitude: f64, longitude: f64, unix_time: i64) -> (f64, f64) {
+    let n = unix_time as f64 / 86400.0 - 10957.5; // days since J2000.0
+    let mean_longitude = (280.460 + 0.985_647_4 * n).to_radians();
+    let mean_anomaly = (357.528 + 0.985_600_3 * n).to_radians();
+    let ecliptic_longitude = mean_longitude
+        + 1.915f64.to_radians() * mean_anomaly.sin()
+        + 0.020f64.to_radians() * (2.0 * mean_anomaly).sin();
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_hours = 18.697_374_558 + 24.065_709_824_419_08 * n + longitude / 15.0;
+    let hour_angle = (sidereal_hours * 15.0).to_radians() - right_ascension;
+
+    let lat = latitude.to_radians();
+    let elevation = (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos()).asin();
+    let azimuth = (-hour_angle.sin())
+        .atan2(declination.tan() * lat.cos() - lat.sin() * hour_angle.cos())
+        .rem_euclid(2.0 * PI);
+    (elevation, azimuth)
+}
+
+/// Parses `YYYY-MM-DDTHH:MM[:SS]` as UTC into seconds since the Unix epoch.
+pub fn parse_utc(s: &str) -> Result<i64> {
+    let invalid = || format!("invalid time `{s}` (expected YYYY-MM-DDTHH:MM[:SS] in UTC)");
+    let (date, time) = s.split_once(['T', ' ']).with_context(invalid)?;
+
+    let date: Vec<i64> = date
+        .split('-')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(invalid)?;
+    let time: Vec<i64> = time
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(invalid)?;
+    let (&[year, month, day], &[hour, minute, ref second @ ..]) = (&date[..], &time[..]) else {
+        bail!(invalid());
+    };
+    let second = match second {
+        [] => 0,
+        &[second] => second,
+        _ => bail!(invalid()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        bail!(invalid());
+    }
+    Ok(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
@@ -0,0 +1,199 @@
+//! Scene textures kept on disk by a hash of the file they were decoded from,
+//! so that loading the same images again, in this run or a later one,
+//! skips the work. An entry holds either the decoded image, which
+//! [`Texture::parse`] returns in place of decoding the file, or the layer
+//! the renderer makes of it at one size, with its mip chain.
+
+use {
+    crate::{
+        crash,
+        gpu::mipmap,
+        scene::texture::{self, Texture},
+    },
+    std::{
+        fs,
+        io::{self, Write},
+        path::{Path, PathBuf},
+        sync::RwLock,
+    },
+};
+
+const IMAGE_MAGIC: &[u8; 4] = b"RTTI";
+const LAYER_MAGIC: &[u8; 4] = b"RTTL";
+
+/// Changes whenever layers are prepared differently, so that stale entries
+/// are never read.
+const VERSION: u32 = 2;
+
+static INSTALLED: RwLock<Option<TextureCache>> = RwLock::new(None);
+
+/// Makes `cache` the one textures are decoded and prepared through from
+/// now on, replacing the one installed before; `None` turns caching off.
+pub fn install(cache: Option<TextureCache>) {
+    *INSTALLED.write().unwrap_or_else(|err| err.into_inner()) = cache;
+}
+
+/// The cache set with [`install`], if any.
+pub fn installed() -> Option<TextureCache> {
+    INSTALLED.read().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// Decoded images and prepared layers in a directory, one file per entry.
+///
+/// ```
+/// use raytracer::{scene::texture::Texture, texture_cache::{self, TextureCache}};
+///
+/// let dir = std::env::temp_dir().join(format!("texture-cache-{}", std::process::id()));
+/// let cache = TextureCache::new(dir.clone());
+/// let file = b"P5 1 1 255\n\x0a";
+/// let key = TextureCache::key(file);
+/// assert_ne!(TextureCache::key(b"P5 1 1 255\n\x0b"), key);
+///
+/// // Once installed, parsing a file stores its image, and parsing it again
+/// // reads that back instead of decoding: here a different image put
+/// // under its key.
+/// texture_cache::install(Some(cache.clone()));
+/// let texture = Texture::parse(file).unwrap();
+/// assert_eq!(texture.source, Some(key));
+/// assert_eq!(cache.get_image(key).unwrap().pixels, [[10, 10, 10, 255]]);
+/// let stored = Texture { pixels: vec![[1, 2, 3, 4]], ..texture };
+/// cache.put_image(key, &stored);
+/// assert_eq!(Texture::parse(file).unwrap().pixels, [[1, 2, 3, 4]]);
+///
+/// // Layers are stored with their mips, apart for every size and encoding.
+/// let levels = vec![vec![[1, 2, 3, 4]; 4], vec![[5, 6, 7, 8]]];
+/// cache.put_layer(key, 2, true, &levels);
+/// assert_eq!(cache.get_layer(key, 2, true), Some(levels));
+/// assert_eq!(cache.get_layer(key, 4, true), None);
+/// assert_eq!(cache.get_layer(key, 2, false), None);
+///
+/// texture_cache::install(None);
+/// std::fs::remove_dir_all(dir).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TextureCache {
+    dir: PathBuf,
+}
+
+impl TextureCache {
+    /// Entries live in `dir`, which is created when the first is stored.
+    pub fn new(dir: PathBuf) -> Self {
+        TextureCache { dir }
+    }
+
+    /// Hash of the bytes of an image file, together with the version of the
+    /// decoders that read it.
+    pub fn key(bytes: &[u8]) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(&VERSION.to_le_bytes());
+        hash.write(&texture::DECODER_VERSION.to_le_bytes());
+        hash.write(bytes);
+        hash.0
+    }
+
+    /// The decoded image stored under `key`, if there is one.
+    pub fn get_image(&self, key: u64) -> Option<Texture> {
+        let bytes = fs::read(self.image_path(key)).ok()?;
+        let rest = bytes.strip_prefix([IMAGE_MAGIC.as_slice(), &VERSION.to_le_bytes()].concat().as_slice())?;
+        let (width, rest) = rest.split_first_chunk::<4>()?;
+        let (height, texels) = rest.split_first_chunk::<4>()?;
+        let (width, height) = (u32::from_le_bytes(*width), u32::from_le_bytes(*height));
+        if texels.len() as u64 != width as u64 * height as u64 * 4 {
+            return None;
+        }
+        Some(Texture {
+            width,
+            height,
+            pixels: texels_of(texels),
+            blocks: None,
+            source: Some(key),
+        })
+    }
+
+    /// Stores the pixels of a decoded image under `key`. Failing to is only
+    /// worth a warning, since the image can always be decoded again.
+    pub fn put_image(&self, key: u64, texture: &Texture) {
+        let header = [
+            IMAGE_MAGIC.as_slice(),
+            &VERSION.to_le_bytes(),
+            &texture.width.to_le_bytes(),
+            &texture.height.to_le_bytes(),
+        ]
+        .concat();
+        self.store(&self.image_path(key), &header, &[&texture.pixels]);
+    }
+
+    /// The `size` by `size` layer stored under `key`, level 0 first and then
+    /// each of its mips down to 1x1, if there is one. Layers whose mips
+    /// average sRGB colors and ones that average data are kept apart.
+    pub fn get_layer(&self, key: u64, size: u32, srgb: bool) -> Option<Vec<Vec<[u8; 4]>>> {
+        let bytes = fs::read(self.layer_path(key, size, srgb)).ok()?;
+        let header = [LAYER_MAGIC.as_slice(), &VERSION.to_le_bytes(), &size.to_le_bytes()].concat();
+        let mut texels = bytes.strip_prefix(header.as_slice())?;
+        let mut levels = Vec::new();
+        for level in 0..mipmap::mip_level_count(size, size) {
+            let len = ((size >> level) as usize).pow(2) * 4;
+            let (level, rest) = texels.split_at_checked(len)?;
+            levels.push(texels_of(level));
+            texels = rest;
+        }
+        texels.is_empty().then_some(levels)
+    }
+
+    /// Stores a `size` by `size` layer and its mips under `key`, as
+    /// [`get_layer`](Self::get_layer) returns them.
+    pub fn put_layer(&self, key: u64, size: u32, srgb: bool, levels: &[Vec<[u8; 4]>]) {
+        let header = [LAYER_MAGIC.as_slice(), &VERSION.to_le_bytes(), &size.to_le_bytes()].concat();
+        let levels: Vec<&[[u8; 4]]> = levels.iter().map(Vec::as_slice).collect();
+        self.store(&self.layer_path(key, size, srgb), &header, &levels);
+    }
+
+    fn store(&self, path: &Path, header: &[u8], texels: &[&[[u8; 4]]]) {
+        if let Err(err) = self.write(path, header, texels) {
+            crash::log!("warning: failed to cache texture in {}: {err}", self.dir.display());
+        }
+    }
+
+    fn write(&self, path: &Path, header: &[u8], texels: &[&[[u8; 4]]]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Written aside and renamed into place, so that other runs never read
+        // half an entry.
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        let mut file = fs::File::create(&partial)?;
+        file.write_all(header)?;
+        for texels in texels {
+            file.write_all(bytemuck::cast_slice(texels))?;
+        }
+        drop(file);
+        fs::rename(&partial, path)
+    }
+
+    fn image_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.rgba"))
+    }
+
+    fn layer_path(&self, key: u64, size: u32, srgb: bool) -> PathBuf {
+        let encoding = if srgb { "srgb" } else { "unorm" };
+        self.dir.join(format!("{key:016x}-{size}-{encoding}.mips"))
+    }
+}
+
+fn texels_of(bytes: &[u8]) -> Vec<[u8; 4]> {
+    bytes.chunks_exact(4).map(|texel| texel.try_into().unwrap()).collect()
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher stays the same
+/// from one build to the next.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
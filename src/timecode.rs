@@ -0,0 +1,47 @@
+//! SMPTE timecode for frame numbers, so exported sequences line up on an
+//! editorial timeline.
+
+/// `HH:MM:SS:FF` of `frame` at `fps`, counting from zero at frame zero.
+/// Fractional NTSC rates (23.976, 29.97, 59.94 and so on) count at their
+/// whole rate; 29.97 and 59.94 use drop-frame numbering, written with `;`
+/// before the frames, which skips frame labels to keep in step with the
+/// clock.
+///
+/// ```
+/// use raytracer::timecode::timecode;
+///
+/// assert_eq!(timecode(90, 24.0), "00:00:03:18");
+/// // 29.97 skips ;00 and ;01 at each minute, except every tenth.
+/// assert_eq!(timecode(1799, 29.97), "00:00:59;29");
+/// assert_eq!(timecode(1800, 29.97), "00:01:00;02");
+/// assert_eq!(timecode(17981, 29.97), "00:09:59;29");
+/// assert_eq!(timecode(17982, 29.97), "00:10:00;00");
+/// assert_eq!(timecode(3600, 59.94), "00:01:00;04");
+/// ```
+pub fn timecode(frame: u32, fps: f64) -> String {
+    let nominal = (fps.round() as u64).max(1);
+    let fractional = (fps - fps.round()).abs() > 1e-3;
+    let drop_frame = fractional && nominal.is_multiple_of(30);
+    let mut frame = u64::from(frame);
+    if drop_frame {
+        // Two labels at 29.97, or four at 59.94, are skipped at the start of
+        // every minute except each tenth.
+        let dropped = nominal / 15;
+        let per_minute = nominal * 60 - dropped;
+        let per_ten_minutes = per_minute * 10 + dropped;
+        let (tens, rest) = (frame / per_ten_minutes, frame % per_ten_minutes);
+        frame += dropped * 9 * tens;
+        if rest > dropped {
+            frame += dropped * ((rest - dropped) / per_minute);
+        }
+    }
+    let seconds = frame / nominal;
+    format!(
+        "{:02}:{:02}:{:02}{}{:02}",
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        if drop_frame { ';' } else { ':' },
+        frame % nominal
+    )
+}
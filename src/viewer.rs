@@ -0,0 +1,406 @@
+//! The interactive window, which embedding applications can run with their
+//! own [`ViewerHooks`] attached:
+//!
+//! ```no_run
+//! use raytracer::{settings::RenderSettings, viewer::{self, ViewerHooks}};
+//! use std::path::PathBuf;
+//!
+//! /// Lists every exported file.
+//! struct ExportLog;
+//!
+//! impl ViewerHooks for ExportLog {
+//!     fn on_export(&mut self, paths: &[PathBuf]) {
+//!         for path in paths {
+//!             eprintln!("exported {}", path.display());
+//!         }
+//!     }
+//! }
+//!
+//! let settings = RenderSettings::from_args().unwrap();
+//! pollster::block_on(viewer::run(settings, &mut ExportLog)).unwrap();
+//! ```
+
+use {
+    crate::{
+        camera::Camera,
+        crash,
+        dump,
+        export,
+        math::Vec3,
+        post::PostRegistry,
+        render::{self, PathTracer},
+        scene::{self, Scene},
+        settings::RenderSettings,
+        texture_cache::{self, TextureCache},
+    },
+    anyhow::{ensure, Context, Result},
+    std::{path::PathBuf, time::Instant},
+    winit::{
+        event::{DeviceEvent, ElementState, Event, MouseScrollDelta, WindowEvent},
+        event_loop::{ControlFlow, EventLoop},
+        window::{Window, WindowBuilder},
+    },
+};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+/// Callbacks from the viewer loop. Every one does nothing unless
+/// overridden; `()` overrides none. The loop prints the frame rate unless
+/// [`RenderSettings::frame_stats`] is off.
+pub trait ViewerHooks {
+    /// Before the passes named by [`RenderSettings::post_passes`] are
+    /// created, so that passes registered here can be named there. The
+    /// registry starts with the built-in passes.
+    fn register_post_passes(&mut self, registry: &mut PostRegistry) {
+        let _ = registry;
+    }
+
+    /// Once the scene is loaded and uploaded, before the first frame. The
+    /// viewer does not upload `scene` again, so a hook changing it calls
+    /// [`PathTracer::update_scene`] itself.
+    fn on_scene_loaded(&mut self, renderer: &mut PathTracer, scene: &mut Scene) {
+        let _ = (renderer, scene);
+    }
+
+    /// Before each frame is drawn. As with `on_scene_loaded`, a hook changing
+    /// `scene` uploads it itself, and one moving `camera` restarts
+    /// accumulation itself with [`PathTracer::reset_samples`]; neither
+    /// counts as a change reported to `on_camera_changed`.
+    fn on_frame_start(&mut self, renderer: &mut PathTracer, scene: &mut Scene, camera: &mut Camera) {
+        let _ = (renderer, scene, camera);
+    }
+
+    /// After the viewer moves the camera, from input or a turntable or
+    /// focus pull.
+    fn on_camera_changed(&mut self, camera: &Camera) {
+        let _ = camera;
+    }
+
+    /// After a frame or an exposure bracket is saved, with the files written.
+    fn on_export(&mut self, paths: &[PathBuf]) {
+        let _ = paths;
+    }
+}
+
+impl ViewerHooks for () {}
+
+/// Opens the window on the scene `settings` name and renders it until the
+/// window is closed, calling `hooks` along the way.
+pub async fn run(settings: RenderSettings, hooks: &mut dyn ViewerHooks) -> Result<()> {
+    texture_cache::install(settings.texture_cache.clone().map(TextureCache::new));
+    let mut scene = match &settings.scene_path {
+        Some(path) => scene::load(path)?,
+        None => Scene::demo(),
+    };
+    scene.convert_frame(settings.unit_scale, settings.up_axis);
+    let event_loop = EventLoop::new()?;
+    let window_size = winit::dpi::PhysicalSize::new(WIDTH, HEIGHT);
+    let window = WindowBuilder::new()
+        .with_inner_size(window_size)
+        .with_resizable(false)
+        .with_title("RayTracer".to_string())
+        .build(&event_loop)?;
+
+    if settings.present {
+        window.set_cursor_visible(false);
+    }
+
+    let (device, queue, surface) = connect_to_gpu(&window, &settings).await?;
+    let mut renderer = PathTracer::new(device, queue, WIDTH, HEIGHT, &settings, &scene)?;
+    let mut registry = PostRegistry::with_builtins();
+    hooks.register_post_passes(&mut registry);
+    renderer.set_post_passes(&registry, &settings.post_passes)?;
+    hooks.on_scene_loaded(&mut renderer, &mut scene);
+    let mut camera = match scene.camera() {
+        Some(camera) => camera.to_camera(),
+        None => Camera::new(
+            Vec3::new(-2.0, 2.0, 1.0), 
+            Vec3::new(0.0, 0.0, -1.0), 
+            Vec3::new(0.0, 1.0, 0.0),  
+            20.0                      
+        ),
+    };
+    camera.aperture = settings.aperture;
+    camera.focus_distance = settings.focus_distance;
+    camera.aperture_blades = settings.aperture_blades;
+    camera.aperture_rotation = settings.aperture_rotation;
+
+    let start = Instant::now();
+    let mut now = start;
+    let mut export_index = 0;
+    let mut animation_frame = settings.start_frame;
+    let mut sun = settings.sun;
+
+    event_loop.run(|event, control_handle| {
+        control_handle.set_control_flow(ControlFlow::Poll);
+        use winit::keyboard::KeyCode::*;
+        use winit::keyboard::PhysicalKey::Code;
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => control_handle.exit(),
+                WindowEvent::RedrawRequested => {
+                    let frame: wgpu::SurfaceTexture = surface
+                        .get_current_texture()
+                        .expect("failed to get current texture");
+
+                    let dt = now.elapsed().as_secs_f64();
+                    now = Instant::now();
+                    hooks.on_frame_start(&mut renderer, &mut scene, &mut camera);
+                    if settings.frame_stats && !settings.present {
+                        print!("\rFPS: {:.0}  ", dt.recip());
+                        #[cfg(feature = "debug-stats")]
+                        if let Some(stats) = renderer.stats() {
+                            print!(
+                                "rays: {}  node visits: {}  primitive tests: {}  ",
+                                stats.rays, stats.node_visits, stats.primitive_tests
+                            );
+                        }
+                    }
+                    if let Some(distance) = renderer.focus_probe() {
+                        if camera.pull_focus(distance, dt as f32, settings.focus_pull) {
+                            camera_changed(&mut renderer, hooks, &camera);
+                        }
+                    }
+                    // A state dump holds the camera still until its samples
+                    // have accumulated.
+                    let turntable = settings.turntable.filter(|_| settings.dump_state.is_none());
+                    if let Some(period) = turntable {
+                        // Every step is a frame of the animation.
+                        animation_frame = animation_frame.wrapping_add(1);
+                        renderer.set_animation_frame(animation_frame);
+                        camera.orbit(std::f32::consts::TAU * dt as f32 / period);
+                        camera_changed(&mut renderer, hooks, &camera);
+                    }
+                    let target = frame.texture.create_view(&wgpu::TextureViewDescriptor {
+                        label: Some("surface view"),
+                        ..Default::default()
+                    });
+                    // Shake only rides on turntable frames, which restart
+                    // accumulation anyway; a still camera stays still so its
+                    // samples converge.
+                    match settings.shake.filter(|_| turntable.is_some()) {
+                        Some(shake) => {
+                            let time = start.elapsed().as_secs_f32();
+                            renderer.render_frame(&target, &shake.apply(&camera, time));
+                        }
+                        None => renderer.render_frame(&target, &camera),
+                    }
+
+                    frame.present();
+
+                    if let Some(path) = &settings.dump_state {
+                        if renderer.sample_count() >= dump::DUMP_SAMPLES {
+                            match dump::write(path, &renderer, &camera, &scene) {
+                                Ok(()) => println!("\nwrote {}", path.display()),
+                                Err(err) => crash::log!("\nfailed to dump state: {err:#}"),
+                            }
+                            control_handle.exit();
+                        }
+                    }
+                    window.request_redraw();
+                }
+                WindowEvent::KeyboardInput { event, .. } if event.physical_key == Code(Escape) => {
+                    control_handle.exit()
+                }
+                WindowEvent::KeyboardInput { .. } if settings.present => (),
+                WindowEvent::KeyboardInput { event, .. } => match event.physical_key {
+                    Code(KeyZ) => {
+                        camera.zoom(0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(KeyX) => {
+                        camera.zoom(-0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(KeyW) => {
+                        camera.move_along_w(0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(KeyS) => {
+                        camera.move_along_w(-0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(KeyA) => {
+                        camera.move_along_u(0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(KeyD) => {
+                        camera.move_along_u(-0.1);
+                        camera_changed(&mut renderer, hooks, &camera)
+                    }
+                    Code(key @ (BracketLeft | BracketRight))
+                        if event.state == ElementState::Pressed =>
+                    {
+                        if let Some(sun) = sun.as_mut() {
+                            let step = if key == BracketLeft { -900 } else { 900 };
+                            sun.advance(step);
+                            renderer.set_sun(Some(sun));
+                        }
+                    }
+                    Code(KeyP) if event.state == ElementState::Pressed && !event.repeat => {
+                        match export::save_frame(&renderer, &settings, export_index) {
+                            Ok(path) => {
+                                println!("\nsaved {}", path.display());
+                                hooks.on_export(&[path]);
+                            }
+                            Err(err) => crash::log!("\nfailed to export frame: {err:#}"),
+                        }
+                        export_index += 1;
+                    }
+                    Code(KeyB) if event.state == ElementState::Pressed && !event.repeat => {
+                        match export::save_bracket(&renderer, &settings, export_index) {
+                            Ok(paths) => {
+                                for path in &paths {
+                                    println!("\nsaved {}", path.display());
+                                }
+                                hooks.on_export(&paths);
+                            }
+                            Err(err) => crash::log!("\nfailed to export bracket: {err:#}"),
+                        }
+                        export_index += 1;
+                    }
+                    #[cfg(feature = "renderdoc")]
+                    Code(F10) if event.state == ElementState::Pressed && !event.repeat => {
+                        renderer.capture_next_frame();
+                        println!("\ncapturing the next frame");
+                    }
+                    Code(KeyC) if event.state == ElementState::Pressed && !event.repeat => {
+                        match renderer.read_split_radiance() {
+                            Some(split) => println!(
+                                "\n{} spp, error {:.4}",
+                                renderer.sample_count(),
+                                split.error()
+                            ),
+                            None => println!("\nneed at least 2 samples to estimate error"),
+                        }
+                    }
+                    Code(KeyI) if event.state == ElementState::Pressed && !event.repeat => {
+                        let names = renderer.integrator_names();
+                        let current = names.iter().position(|&name| name == renderer.integrator_name());
+                        let next = names[current.map_or(0, |i| (i + 1) % names.len())].to_string();
+                        match renderer.set_integrator(&next) {
+                            Ok(()) => println!("\nintegrator: {next}"),
+                            Err(err) => crash::log!("\nfailed to switch integrator: {err:#}"),
+                        }
+                    }
+                    _ => (),
+                },
+                _ => (),
+            },
+            Event::DeviceEvent { .. } if settings.present => (),
+            Event::DeviceEvent { event, .. } => match event {
+                DeviceEvent::MouseWheel { delta } => {
+                    let delta = match delta {
+                        MouseScrollDelta::PixelDelta(delta) => 0.001 * delta.y as f32,
+                        MouseScrollDelta::LineDelta(_, y) => y * 0.001,
+                    };
+                    camera.zoom(delta);
+                    camera_changed(&mut renderer, hooks, &camera);
+                }
+                DeviceEvent::MouseMotion { delta: (dx, dy) } => {
+                    let sensitivity = 0.003;
+                    let dx = dx as f32 * sensitivity;
+                    let dy = dy as f32 * sensitivity;
+                    camera.rotate(dx, dy);
+                    camera_changed(&mut renderer, hooks, &camera)
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    })?;
+    Ok(())
+}
+
+/// Restarts accumulation after the viewer moved the camera.
+fn camera_changed(renderer: &mut PathTracer, hooks: &mut dyn ViewerHooks, camera: &Camera) {
+    renderer.reset_samples();
+    hooks.on_camera_changed(camera);
+}
+
+async fn connect_to_gpu<'w>(
+    window: &'w Window,
+    settings: &RenderSettings,
+) -> Result<(wgpu::Device, wgpu::Queue, wgpu::Surface<'w>)> {
+    use wgpu::TextureFormat::{Bgra8Unorm, Rgba8Unorm};
+
+
+    let mut flags = wgpu::InstanceFlags::from_build_config().with_env();
+    if settings.gpu_validation {
+        flags |= wgpu::InstanceFlags::VALIDATION | wgpu::InstanceFlags::DEBUG;
+    }
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        flags,
+        ..Default::default()
+    });
+
+    
+    let surface = instance.create_surface(window)?;
+
+   
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: Some(&surface),
+        })
+        .await
+        .context("failed to find a compatible adapter")?;
+    crash::record("adapter", format!("{:#?}", adapter.get_info()));
+    crash::record("limits", format!("{:#?}", adapter.limits()));
+    let info = adapter.get_info();
+    let storage_buffers = adapter.limits().max_storage_buffers_per_shader_stage;
+    ensure!(
+        storage_buffers >= render::STORAGE_BUFFERS_PER_STAGE,
+        "{} ({:?}) binds at most {storage_buffers} storage buffers per shader stage, and the path tracer needs {}",
+        info.name,
+        info.backend,
+        render::STORAGE_BUFFERS_PER_STAGE
+    );
+
+  
+    if let Some(dir) = &settings.wgpu_trace {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create trace directory {}", dir.display()))?;
+    }
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("device"),
+                required_limits: wgpu::Limits {
+                    max_storage_buffers_per_shader_stage: render::STORAGE_BUFFERS_PER_STAGE,
+                    ..wgpu::Limits::default()
+                },
+                required_features: wgpu::Features::default()
+                    | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | (adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC),
+            },
+            settings.wgpu_trace.as_deref(),
+        )
+        .await
+        .context("failed to connect to the GPU")?;
+
+   
+    let caps = surface.get_capabilities(&adapter);
+    let format = caps
+        .formats
+        .into_iter()
+        .find(|it| matches!(it, Rgba8Unorm | Bgra8Unorm))
+        .context("could not find preferred texture format (Rgba8Unorm or Bgra8Unorm)")?;
+
+    let size = window.inner_size();
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: size.width,
+        height: size.height,
+        present_mode: wgpu::PresentMode::AutoVsync,
+        alpha_mode: caps.alpha_modes[0],
+        view_formats: vec![],
+        desired_maximum_frame_latency: 1,
+    };
+    surface.configure(&device, &config);
+
+    Ok((device, queue, surface))
+}